@@ -0,0 +1,27 @@
+// Counts the raw depth readings of a frame per bin, see `src/histogram.rs`.
+// The readings come two to a word, low half first.
+
+@group(0) @binding(0)
+var<storage, read> depth: array<u32>;
+@group(0) @binding(1)
+var<storage, read_write> bins: array<atomic<u32>>;
+
+let NO_DEPTH: u32 = 1023u;
+let BIN_WIDTH: u32 = 4u;
+
+fn count(raw: u32) {
+    // holes are left out
+    if (raw < NO_DEPTH) {
+        atomicAdd(&bins[raw / BIN_WIDTH], 1u);
+    }
+}
+
+@compute @workgroup_size(256)
+fn count_depth(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= arrayLength(&depth)) {
+        return;
+    }
+    let word = depth[id.x];
+    count(word & 0xffffu);
+    count(word >> 16u);
+}
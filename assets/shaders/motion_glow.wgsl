@@ -0,0 +1,24 @@
+// Tints the view where the motion field says things are moving.
+
+struct MotionGlowMaterial {
+    color: vec4<f32>,
+};
+
+@group(1) @binding(0)
+var<uniform> material: MotionGlowMaterial;
+@group(1) @binding(1)
+var motion_texture: texture_2d<f32>;
+@group(1) @binding(2)
+var motion_sampler: sampler;
+
+struct FragmentInput {
+    #import bevy_sprite::mesh2d_vertex_output
+};
+
+@fragment
+fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
+    let motion = textureSample(motion_texture, motion_sampler, in.uv).r;
+    // ease in so small jitter stays dark
+    let glow = smoothstep(0.05, 0.6, motion);
+    return vec4<f32>(material.color.rgb, material.color.a * glow);
+}
@@ -0,0 +1,86 @@
+// Converts raw Kinect video (GRBG Bayer or packed UYVY) to RGB.
+
+struct RawVideoMaterial {
+    format: u32,
+};
+
+let FORMAT_BAYER: u32 = 0u;
+
+@group(1) @binding(0)
+var<uniform> material: RawVideoMaterial;
+@group(1) @binding(1)
+var raw_texture: texture_2d<f32>;
+
+struct FragmentInput {
+    #import bevy_sprite::mesh2d_vertex_output
+};
+
+// mirror at the borders so the Bayer neighbours keep the right color
+fn reflect_coord(p: vec2<i32>, size: vec2<i32>) -> vec2<i32> {
+    let q = abs(p);
+    return select(q, 2 * size - 2 - q, q >= size);
+}
+
+fn raw(p: vec2<i32>) -> f32 {
+    let size = vec2<i32>(textureDimensions(raw_texture));
+    return textureLoad(raw_texture, reflect_coord(p, size), 0).r;
+}
+
+fn debayer(p: vec2<i32>) -> vec3<f32> {
+    let c = raw(p);
+    let left = raw(p + vec2<i32>(-1, 0));
+    let right = raw(p + vec2<i32>(1, 0));
+    let up = raw(p + vec2<i32>(0, -1));
+    let down = raw(p + vec2<i32>(0, 1));
+    let horizontal = (left + right) * 0.5;
+    let vertical = (up + down) * 0.5;
+    let cross = (left + right + up + down) * 0.25;
+    let diagonal = (raw(p + vec2<i32>(-1, -1)) + raw(p + vec2<i32>(1, -1))
+        + raw(p + vec2<i32>(-1, 1)) + raw(p + vec2<i32>(1, 1))) * 0.25;
+
+    let parity = p & vec2<i32>(1, 1);
+    if (parity.y == 0) {
+        if (parity.x == 0) {
+            return vec3<f32>(horizontal, c, vertical);
+        }
+        return vec3<f32>(c, cross, diagonal);
+    }
+    if (parity.x == 0) {
+        return vec3<f32>(diagonal, cross, c);
+    }
+    return vec3<f32>(vertical, c, horizontal);
+}
+
+fn uyvy(p: vec2<i32>) -> vec3<f32> {
+    // every texel holds U Y0 V Y1 for two neighbouring pixels
+    let texel = textureLoad(raw_texture, vec2<i32>(p.x / 2, p.y), 0);
+    let y = select(texel.g, texel.a, (p.x & 1) == 1);
+    let u = texel.r - 0.5;
+    let v = texel.b - 0.5;
+    let rgb = vec3<f32>(y + 1.402 * v, y - 0.344 * u - 0.714 * v, y + 1.772 * u);
+    return clamp(rgb, vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+// the camera delivers sRGB encoded values, the render target expects linear
+fn srgb_to_linear(c: vec3<f32>) -> vec3<f32> {
+    let low = c / 12.92;
+    let high = pow((c + 0.055) / 1.055, vec3<f32>(2.4));
+    return select(high, low, c <= vec3<f32>(0.04045));
+}
+
+@fragment
+fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
+    var size = vec2<i32>(textureDimensions(raw_texture));
+    if (material.format != FORMAT_BAYER) {
+        size.x = size.x * 2;
+    }
+    let p = min(vec2<i32>(in.uv * vec2<f32>(size)), size - 1);
+
+    var rgb: vec3<f32>;
+    if (material.format == FORMAT_BAYER) {
+        rgb = debayer(p);
+    } else {
+        rgb = uyvy(p);
+    }
+    return vec4<f32>(srgb_to_linear(rgb), 1.0);
+}
@@ -0,0 +1,12 @@
+fn main() {
+    // libfreenect2 only has a C++ API, `src/kinect2.rs` goes through a shim
+    #[cfg(feature = "freenect2")]
+    {
+        println!("cargo:rerun-if-changed=kinect2/shim.cpp");
+        cc::Build::new()
+            .cpp(true)
+            .file("kinect2/shim.cpp")
+            .compile("kinect2_shim");
+        println!("cargo:rustc-link-lib=freenect2");
+    }
+}
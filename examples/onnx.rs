@@ -0,0 +1,67 @@
+//! Runs an image classifier on the Kinect's video and logs its best guess
+//! whenever it changes:
+//!
+//! ```text
+//! cargo run --example onnx --features onnx -- classifier.onnx
+//! ```
+//!
+//! The model takes `[1, 3, 480, 640]` RGB in 0..1, as
+//! `inference::video_tensor` makes it, and returns one score per class.
+
+use bevy::prelude::*;
+use bevy_kinect::inference::{self, FrameModel, FrameModelAppExt};
+use bevy_kinect::onnx::OnnxModel;
+use bevy_kinect::KinectPlugin;
+
+/// The class with the highest score and that score.
+#[derive(Resource)]
+struct BestGuess(usize, f32);
+
+fn main() {
+    let path = match std::env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: onnx <model.onnx>");
+            std::process::exit(2);
+        }
+    };
+    let model = match OnnxModel::load(&path, &[1, 3, 480, 640]) {
+        Ok(model) => model,
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+    };
+
+    App::new()
+        .add_plugins(DefaultPlugins)
+        .add_plugins(KinectPlugin::default())
+        .add_frame_model(FrameModel {
+            every: 5,
+            preprocess: Box::new(inference::video_tensor),
+            model: Box::new(model),
+            postprocess: Box::new(|output| {
+                let best = output
+                    .data
+                    .iter()
+                    .copied()
+                    .enumerate()
+                    .max_by(|a, b| a.1.total_cmp(&b.1));
+                let (class, score) = best.unwrap_or((0, 0.0));
+                BestGuess(class, score)
+            }),
+        })
+        .add_system(log_guess)
+        .run();
+}
+
+fn log_guess(guess: Option<Res<BestGuess>>, mut last: Local<Option<usize>>) {
+    let guess = match guess {
+        Some(guess) => guess,
+        None => return,
+    };
+    if *last != Some(guess.0) {
+        info!("Class {} ({:.2})", guess.0, guess.1);
+        *last = Some(guess.0);
+    }
+}
@@ -0,0 +1,70 @@
+// C interface to the parts of libfreenect2 src/kinect2.rs uses; libfreenect2
+// only has a C++ API. Built by build.rs with the `kinect2` feature.
+
+#include <libfreenect2/libfreenect2.hpp>
+#include <libfreenect2/frame_listener_impl.h>
+
+#include <cstring>
+
+struct k2_intrinsics {
+    float fx, fy, cx, cy;
+};
+
+struct k2_device {
+    libfreenect2::Freenect2 context;
+    libfreenect2::Freenect2Device *device = nullptr;
+    libfreenect2::SyncMultiFrameListener listener{
+        libfreenect2::Frame::Color | libfreenect2::Frame::Depth};
+};
+
+extern "C" {
+
+// Opens and starts the first Kinect v2, null if there is none or it fails.
+k2_device *k2_open(k2_intrinsics *depth, k2_intrinsics *color) {
+    k2_device *dev = new k2_device();
+    if (dev->context.enumerateDevices() == 0) {
+        delete dev;
+        return nullptr;
+    }
+    dev->device = dev->context.openDefaultDevice();
+    if (!dev->device) {
+        delete dev;
+        return nullptr;
+    }
+    dev->device->setColorFrameListener(&dev->listener);
+    dev->device->setIrAndDepthFrameListener(&dev->listener);
+    if (!dev->device->start()) {
+        dev->device->close();
+        delete dev;
+        return nullptr;
+    }
+
+    libfreenect2::Freenect2Device::IrCameraParams ir = dev->device->getIrCameraParams();
+    *depth = k2_intrinsics{ir.fx, ir.fy, ir.cx, ir.cy};
+    libfreenect2::Freenect2Device::ColorCameraParams rgb = dev->device->getColorCameraParams();
+    *color = k2_intrinsics{rgb.fx, rgb.fy, rgb.cx, rgb.cy};
+    return dev;
+}
+
+// Waits up to `timeout_ms` for the next frames and copies them out: depth as
+// 512x424 floats in millimeters, color as 1920x1080 BGRX. 1 with new frames,
+// 0 on timeout.
+int k2_wait_frames(k2_device *dev, int timeout_ms, float *depth, unsigned char *color) {
+    libfreenect2::FrameMap frames;
+    if (!dev->listener.waitForNewFrame(frames, timeout_ms)) {
+        return 0;
+    }
+    libfreenect2::Frame *d = frames[libfreenect2::Frame::Depth];
+    libfreenect2::Frame *c = frames[libfreenect2::Frame::Color];
+    std::memcpy(depth, d->data, d->width * d->height * sizeof(float));
+    std::memcpy(color, c->data, c->width * c->height * 4);
+    dev->listener.release(frames);
+    return 1;
+}
+
+void k2_close(k2_device *dev) {
+    dev->device->stop();
+    dev->device->close();
+    delete dev;
+}
+}
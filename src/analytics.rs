@@ -0,0 +1,261 @@
+//! Engagement numbers for exhibit owners. A session starts when someone comes
+//! close to the sensor and ends once nobody has been seen for a short grace
+//! period; other features bump named counters. Everything is written to one
+//! file per run, as CSV or JSON, on a schedule and again on exit.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use serde::Serialize;
+
+use crate::attract::AttractEvent;
+use crate::CloseBlob;
+
+/// Seconds nobody may be seen before a session counts as over, so someone
+/// stepping out of range for a moment doesn't split their visit in two.
+const SESSION_GRACE: f64 = 2.0;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Json,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(ExportFormat::Csv),
+            "json" => Ok(ExportFormat::Json),
+            _ => Err(format!("unknown export format '{s}', expected csv or json")),
+        }
+    }
+}
+
+#[derive(Resource, Clone, Debug)]
+pub struct AnalyticsSettings {
+    /// directory to export to, analytics are off without one
+    pub dir: Option<PathBuf>,
+    pub format: ExportFormat,
+    /// seconds between exports, besides the one on exit
+    pub interval: f32,
+}
+
+impl Default for AnalyticsSettings {
+    fn default() -> Self {
+        AnalyticsSettings {
+            dir: None,
+            format: ExportFormat::default(),
+            interval: 300.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Session {
+    /// unix time in seconds
+    pub started_at: f64,
+    pub dwell_seconds: f64,
+}
+
+#[derive(Resource, Debug, Serialize)]
+pub struct Analytics {
+    /// unix time in seconds
+    pub run_started_at: f64,
+    pub sessions: Vec<Session>,
+    pub counters: BTreeMap<String, u64>,
+    #[serde(skip)]
+    current: Option<CurrentSession>,
+}
+
+#[derive(Debug)]
+struct CurrentSession {
+    started_at: f64,
+    started: f64,
+    last_seen: f64,
+}
+
+impl Default for Analytics {
+    fn default() -> Self {
+        Analytics {
+            run_started_at: unix_now(),
+            sessions: Vec::new(),
+            counters: BTreeMap::new(),
+            current: None,
+        }
+    }
+}
+
+impl Analytics {
+    pub fn count(&mut self, name: &str) {
+        *self.counters.entry(name.to_string()).or_default() += 1;
+    }
+
+    fn end_session(&mut self) {
+        if let Some(session) = self.current.take() {
+            self.sessions.push(Session {
+                started_at: session.started_at,
+                dwell_seconds: session.last_seen - session.started,
+            });
+        }
+    }
+
+    fn to_csv(&self) -> String {
+        let mut csv = String::from("kind,name,started_at,value\n");
+        for session in &self.sessions {
+            let _ = writeln!(
+                csv,
+                "session,,{:.0},{:.1}",
+                session.started_at, session.dwell_seconds
+            );
+        }
+        for (name, count) in &self.counters {
+            let _ = writeln!(csv, "counter,{},,{count}", csv_field(name));
+        }
+        csv
+    }
+
+    fn export(&self, settings: &AnalyticsSettings) -> io::Result<()> {
+        let dir = match &settings.dir {
+            Some(dir) => dir,
+            None => return Ok(()),
+        };
+        fs::create_dir_all(dir)?;
+        let stem = format!("analytics-{:.0}", self.run_started_at);
+        match settings.format {
+            ExportFormat::Csv => fs::write(dir.join(stem + ".csv"), self.to_csv()),
+            ExportFormat::Json => {
+                let json = serde_json::to_string_pretty(self)?;
+                fs::write(dir.join(stem + ".json"), json)
+            }
+        }
+    }
+}
+
+/// `field` quoted as RFC 4180 has it when it holds a comma, a quote or a
+/// line break.
+fn csv_field(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn unix_now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |since| since.as_secs_f64())
+}
+
+/// The browser's clock, there is no system time on wasm32.
+#[cfg(target_arch = "wasm32")]
+pub fn unix_now() -> f64 {
+    js_sys::Date::now() / 1000.0
+}
+
+#[derive(Resource)]
+struct ExportTimer(Timer);
+
+pub struct AnalyticsPlugin;
+
+impl Plugin for AnalyticsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<AnalyticsSettings>()
+            .init_resource::<Analytics>()
+            .add_startup_system(setup_export_timer)
+            .add_system(track_sessions)
+            .add_system(count_attract_runs)
+            .add_system(export_on_schedule)
+            .add_system_to_stage(CoreStage::Last, export_on_exit);
+    }
+}
+
+fn setup_export_timer(mut commands: Commands, settings: Res<AnalyticsSettings>) {
+    commands.insert_resource(ExportTimer(Timer::from_seconds(
+        settings.interval,
+        TimerMode::Repeating,
+    )));
+}
+
+fn track_sessions(blob: Res<CloseBlob>, time: Res<Time>, mut analytics: ResMut<Analytics>) {
+    let now = time.elapsed_seconds_f64();
+    if blob.0.is_some() {
+        match &mut analytics.current {
+            Some(session) => session.last_seen = now,
+            None => {
+                analytics.current = Some(CurrentSession {
+                    started_at: unix_now(),
+                    started: now,
+                    last_seen: now,
+                })
+            }
+        }
+    } else if matches!(&analytics.current, Some(session) if now - session.last_seen > SESSION_GRACE)
+    {
+        analytics.end_session();
+    }
+}
+
+fn count_attract_runs(mut events: EventReader<AttractEvent>, mut analytics: ResMut<Analytics>) {
+    for event in events.iter() {
+        if *event == AttractEvent::Started {
+            analytics.count("attract_mode");
+        }
+    }
+}
+
+fn export_on_schedule(
+    settings: Res<AnalyticsSettings>,
+    analytics: Res<Analytics>,
+    time: Res<Time>,
+    mut timer: ResMut<ExportTimer>,
+) {
+    if timer.0.tick(time.delta()).just_finished() {
+        if let Err(err) = analytics.export(&settings) {
+            error!("Unable to export analytics: {err}");
+        }
+    }
+}
+
+fn export_on_exit(
+    exit: EventReader<AppExit>,
+    settings: Res<AnalyticsSettings>,
+    mut analytics: ResMut<Analytics>,
+) {
+    if exit.is_empty() {
+        return;
+    }
+    // whoever is still around counts as done
+    analytics.end_session();
+    if let Err(err) = analytics.export(&settings) {
+        error!("Unable to export analytics: {err}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counter_names_are_quoted() {
+        let mut analytics = Analytics::default();
+        analytics.count("waves");
+        analytics.count("hello, \"world\"\n");
+        let csv = analytics.to_csv();
+        assert!(csv.contains("counter,waves,,1\n"));
+        assert!(csv.contains("counter,\"hello, \"\"world\"\"\n\",,1\n"));
+    }
+}
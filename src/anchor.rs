@@ -0,0 +1,125 @@
+//! Attaching entities to the body. Anything with a [`BodyAnchor`] has its
+//! `Transform` moved onto a [`Joint`] of the [`Skeleton`], the closest blob
+//! or a fixed point in the room, every frame, and is hidden while that isn't
+//! tracked:
+//!
+//! ```ignore
+//! commands.spawn((
+//!     SpriteBundle { texture: crown, ..default() },
+//!     BodyAnchor { joint: Joint::Head.into(), offset: Vec3::new(0.0, 0.2, 0.0) },
+//! ));
+//! ```
+//!
+//! The translation is in the main camera's world space, over the body in the
+//! picture: the depth view, or the flipped video while the mirror is on. z is
+//! left alone for layering. Anchored entities are top level, or children of
+//! something at the origin.
+
+use bevy::prelude::*;
+
+use crate::coords::{self, DisplayRect, COLOR_INTRINSICS, DEPTH_INTRINSICS, DEPTH_WIDTH};
+use crate::mirror::MirrorSettings;
+use crate::skeleton::{self, Joint, Skeleton};
+use crate::touchless;
+use crate::{BlobMotion, CloseBlob, CurrentDepth, MainCamera, MainKinect};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AnchorPoint {
+    Joint(Joint),
+    /// the closest thing to the sensor, where the crosshair is
+    CloseBlob,
+    /// a fixed point in sensor space, e.g. one picked off the depth image
+    Point(Vec3),
+}
+
+impl From<Joint> for AnchorPoint {
+    fn from(joint: Joint) -> Self {
+        AnchorPoint::Joint(joint)
+    }
+}
+
+#[derive(Component, Clone, Copy, Debug)]
+pub struct BodyAnchor {
+    pub joint: AnchorPoint,
+    /// from the anchor point in sensor space (x right, y up, z away from the
+    /// sensor), meters
+    pub offset: Vec3,
+}
+
+/// Also sets the `Transform` scale of a [`BodyAnchor`] so one unit is a meter
+/// at the anchor's distance, e.g. a sprite's `custom_size` in meters.
+#[derive(Component)]
+pub struct ScaleWithDistance;
+
+pub struct AnchorPlugin;
+
+impl Plugin for AnchorPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(
+            follow_body_anchors
+                .after(skeleton::estimate_skeleton)
+                .after(crate::interpolate_close_blob),
+        );
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn follow_body_anchors(
+    (skeleton, motion, blob): (Res<Skeleton>, Res<BlobMotion>, Res<CloseBlob>),
+    (mirror, rect, windows): (Res<MirrorSettings>, Res<DisplayRect>, Res<Windows>),
+    depth_query: Query<&CurrentDepth, With<MainKinect>>,
+    camera_query: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
+    mut anchor_query: Query<(
+        &BodyAnchor,
+        &mut Transform,
+        &mut Visibility,
+        Option<&ScaleWithDistance>,
+    )>,
+) {
+    let (camera, camera_transform) = match camera_query.get_single() {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+    let window = windows.primary();
+    let window_size = Vec2::new(window.width(), window.height());
+    let ndc_to_world = coords::ndc_to_world(camera, camera_transform);
+    // distance to the blob, it's tracked in depth pixels only
+    let blob_meters = match (blob.0, depth_query.get_single()) {
+        (Some(center), Ok(depth)) if depth.is_medium() => {
+            touchless::hand_meters(&depth.depth_array, center)
+        }
+        _ => None,
+    };
+
+    for (anchor, mut transform, mut visibility, scale) in anchor_query.iter_mut() {
+        let base = match anchor.joint {
+            AnchorPoint::Joint(joint) => skeleton.get(joint).map(|joint| joint.position),
+            AnchorPoint::CloseBlob => motion
+                .shown
+                .zip(blob_meters)
+                .map(|(pixel, meters)| coords::depth_pixel_to_sensor(pixel, meters)),
+            AnchorPoint::Point(point) => Some(point),
+        };
+        let point = match base {
+            Some(base) if base.z + anchor.offset.z > 0.0 => base + anchor.offset,
+            _ => {
+                visibility.is_visible = false;
+                continue;
+            }
+        };
+        let (pixel, focal) = if mirror.enabled {
+            let pixel = coords::sensor_to_color_pixel(point);
+            let flipped = Vec2::new(DEPTH_WIDTH as f32 - 1.0 - pixel.x, pixel.y);
+            (flipped, COLOR_INTRINSICS.fx)
+        } else {
+            (DEPTH_INTRINSICS.project(point), DEPTH_INTRINSICS.fx)
+        };
+        let world = coords::screen_to_world(rect.image_to_screen(pixel), window_size, ndc_to_world);
+        transform.translation.x = world.x;
+        transform.translation.y = world.y;
+        if scale.is_some() {
+            transform.scale = (rect.scale() * focal / point.z).extend(1.0);
+        }
+        visibility.is_visible = true;
+    }
+}
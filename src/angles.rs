@@ -0,0 +1,222 @@
+//! Joint angles of the tracked [`Skeleton`], for scoring poses in fitness
+//! or physiotherapy style apps. Every angle is in degrees, 0 in the neutral
+//! standing pose, and carries the lowest confidence of the joints it was
+//! measured from. J shows them next to the tilt dial.
+
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::presentation::DebugUi;
+use crate::skeleton::{Joint, Skeleton};
+use crate::tilt::TiltState;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BodyAngle {
+    /// bend of the elbow, 0 straight
+    ElbowLeft,
+    ElbowRight,
+    /// arm raised away from the body, 0 hanging down, 180 straight up
+    ShoulderLeft,
+    ShoulderRight,
+    /// thigh raised forward or sideways, 0 straight down
+    HipLeft,
+    HipRight,
+    /// bend of the knee, 0 straight
+    KneeLeft,
+    KneeRight,
+    /// spine from vertical, positive leaning towards the sensor
+    TorsoLean,
+    /// spine from vertical, positive leaning to the person's right
+    TorsoSideLean,
+}
+
+impl BodyAngle {
+    pub const ALL: [BodyAngle; 10] = [
+        BodyAngle::ElbowLeft,
+        BodyAngle::ElbowRight,
+        BodyAngle::ShoulderLeft,
+        BodyAngle::ShoulderRight,
+        BodyAngle::HipLeft,
+        BodyAngle::HipRight,
+        BodyAngle::KneeLeft,
+        BodyAngle::KneeRight,
+        BodyAngle::TorsoLean,
+        BodyAngle::TorsoSideLean,
+    ];
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct JointAngle {
+    pub degrees: f32,
+    pub confidence: f32,
+}
+
+/// Angles of the current skeleton, empty while nobody is tracked.
+#[derive(Resource, Clone, Debug, Default)]
+pub struct JointAngles(pub HashMap<BodyAngle, JointAngle>);
+
+impl JointAngles {
+    pub fn get(&self, angle: BodyAngle) -> Option<JointAngle> {
+        self.0.get(&angle).copied()
+    }
+}
+
+#[derive(Component)]
+struct AngleText;
+
+pub struct AnglesPlugin;
+
+impl Plugin for AnglesPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<JointAngles>()
+            .add_startup_system(spawn_angle_text)
+            .add_system(compute_joint_angles)
+            .add_system(update_angle_text.after(compute_joint_angles));
+    }
+}
+
+pub fn compute_joint_angles(
+    skeleton: Res<Skeleton>,
+    tilt: Res<TiltState>,
+    mut angles: ResMut<JointAngles>,
+) {
+    if !skeleton.is_changed() {
+        return;
+    }
+    angles.0.clear();
+    if !skeleton.is_tracked() {
+        return;
+    }
+    let up = world_up(&tilt);
+    for angle in BodyAngle::ALL {
+        if let Some(measured) = measure(&skeleton, angle, up) {
+            angles.0.insert(angle, measured);
+        }
+    }
+}
+
+/// Straight up in sensor space, from the accelerometer's pitch and roll.
+pub fn world_up(tilt: &TiltState) -> Vec3 {
+    // the same rotation as the sensor entity, which is in bevy's -z forward
+    // frame rather than sensor space
+    let rotation = Quat::from_rotation_z(-tilt.roll.to_radians())
+        * Quat::from_rotation_x(tilt.pitch.to_radians());
+    let up = rotation.inverse() * Vec3::Y;
+    Vec3::new(up.x, up.y, -up.z)
+}
+
+fn measure(skeleton: &Skeleton, angle: BodyAngle, up: Vec3) -> Option<JointAngle> {
+    let joints = |list: &[Joint]| -> Option<(Vec<Vec3>, f32)> {
+        let tracked = list
+            .iter()
+            .map(|joint| skeleton.get(*joint))
+            .collect::<Option<Vec<_>>>()?;
+        let confidence = tracked
+            .iter()
+            .map(|joint| joint.confidence)
+            .fold(1.0, f32::min);
+        Some((
+            tracked.iter().map(|joint| joint.position).collect(),
+            confidence,
+        ))
+    };
+    // 180 minus the angle at the middle joint, so straight limbs are 0
+    let bend = |a: Joint, b: Joint, c: Joint| {
+        joints(&[a, b, c]).map(|(p, confidence)| {
+            let degrees = 180.0 - (p[0] - p[1]).angle_between(p[2] - p[1]).to_degrees();
+            JointAngle {
+                degrees,
+                confidence,
+            }
+        })
+    };
+    // limb from straight down
+    let raise = |base: Joint, end: Joint| {
+        joints(&[base, end]).map(|(p, confidence)| JointAngle {
+            degrees: (p[1] - p[0]).angle_between(-up).to_degrees(),
+            confidence,
+        })
+    };
+    let lean = |side: Vec3| {
+        joints(&[Joint::Pelvis, Joint::Neck]).map(|(p, confidence)| {
+            let spine = p[1] - p[0];
+            // horizontal direction of `side`, so pitch doesn't count as lean
+            let side = (side - up * side.dot(up)).normalize();
+            JointAngle {
+                degrees: spine.dot(side).atan2(spine.dot(up)).to_degrees(),
+                confidence,
+            }
+        })
+    };
+
+    match angle {
+        BodyAngle::ElbowLeft => bend(Joint::ShoulderLeft, Joint::ElbowLeft, Joint::HandLeft),
+        BodyAngle::ElbowRight => bend(Joint::ShoulderRight, Joint::ElbowRight, Joint::HandRight),
+        BodyAngle::ShoulderLeft => raise(Joint::ShoulderLeft, Joint::ElbowLeft),
+        BodyAngle::ShoulderRight => raise(Joint::ShoulderRight, Joint::ElbowRight),
+        BodyAngle::HipLeft => raise(Joint::HipLeft, Joint::KneeLeft),
+        BodyAngle::HipRight => raise(Joint::HipRight, Joint::KneeRight),
+        BodyAngle::KneeLeft => bend(Joint::HipLeft, Joint::KneeLeft, Joint::FootLeft),
+        BodyAngle::KneeRight => bend(Joint::HipRight, Joint::KneeRight, Joint::FootRight),
+        // towards the sensor is -z, the person's right is the sensor's left
+        BodyAngle::TorsoLean => lean(Vec3::NEG_Z),
+        BodyAngle::TorsoSideLean => lean(Vec3::NEG_X),
+    }
+}
+
+fn spawn_angle_text(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let mut text = TextBundle::from_section(
+        "",
+        TextStyle {
+            font: asset_server.load("fonts/Hack-Regular.ttf"),
+            font_size: 12.0,
+            color: Color::WHITE,
+        },
+    )
+    .with_style(Style {
+        position_type: PositionType::Absolute,
+        position: UiRect {
+            top: Val::Px(150.0),
+            right: Val::Px(8.0),
+            ..default()
+        },
+        ..default()
+    });
+    text.visibility.is_visible = false;
+    commands.spawn((text, AngleText, DebugUi));
+}
+
+fn update_angle_text(
+    keys: Res<Input<KeyCode>>,
+    angles: Res<JointAngles>,
+    mut text_query: Query<(&mut Text, &mut Visibility), With<AngleText>>,
+) {
+    let toggled = keys.just_pressed(KeyCode::J);
+    for (mut text, mut visibility) in text_query.iter_mut() {
+        if toggled {
+            visibility.is_visible = !visibility.is_visible;
+        }
+        if !visibility.is_visible || !(toggled || angles.is_changed()) {
+            continue;
+        }
+        text.sections[0].value = if angles.0.is_empty() {
+            "nobody tracked".to_string()
+        } else {
+            BodyAngle::ALL
+                .iter()
+                .filter_map(|angle| angles.get(*angle).map(|measured| (angle, measured)))
+                .map(|(angle, measured)| {
+                    format!(
+                        "{angle:?} {:+.0}° ({:.0}%)",
+                        measured.degrees,
+                        measured.confidence * 100.0
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+    }
+}
@@ -0,0 +1,98 @@
+//! Kiosk-style attract mode. When nobody has been close to the sensor for a
+//! while the app switches to an attract state, optionally showing the depth
+//! view in [`DepthStyle::Rainbow`](crate::DepthStyle), and goes back to
+//! interactive the moment someone shows up again.
+
+use bevy::prelude::*;
+
+use crate::CloseBlob;
+
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct AttractSettings {
+    /// seconds without anyone close before attract mode starts
+    pub timeout: f32,
+    /// recolor the depth view while in attract mode
+    pub screensaver: bool,
+}
+
+impl Default for AttractSettings {
+    fn default() -> Self {
+        AttractSettings {
+            timeout: 60.0,
+            screensaver: true,
+        }
+    }
+}
+
+/// Sent when attract mode starts or ends.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AttractEvent {
+    Started,
+    Ended,
+}
+
+#[derive(Resource, Default, Debug)]
+pub struct AttractMode {
+    active: bool,
+    last_presence: f64,
+    /// kept in attract mode whoever is around, see [`hold`](Self::hold)
+    held: bool,
+}
+
+impl AttractMode {
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Stays in attract mode until let go, e.g. outside the
+    /// [operating hours](crate::hours).
+    pub fn hold(&mut self, held: bool) {
+        self.held = held;
+    }
+}
+
+pub struct AttractPlugin;
+
+impl Plugin for AttractPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<AttractSettings>()
+            .init_resource::<AttractMode>()
+            .add_event::<AttractEvent>()
+            .add_system(update_attract_mode)
+            .add_system(log_attract_events);
+    }
+}
+
+fn update_attract_mode(
+    blob: Res<CloseBlob>,
+    settings: Res<AttractSettings>,
+    time: Res<Time>,
+    mut attract: ResMut<AttractMode>,
+    mut events: EventWriter<AttractEvent>,
+) {
+    let now = time.elapsed_seconds_f64();
+    if attract.held {
+        if !attract.active {
+            attract.active = true;
+            events.send(AttractEvent::Started);
+        }
+    } else if blob.0.is_some() {
+        attract.last_presence = now;
+        if attract.active {
+            attract.active = false;
+            events.send(AttractEvent::Ended);
+        }
+    } else if !attract.active && now - attract.last_presence > settings.timeout as f64 {
+        attract.active = true;
+        events.send(AttractEvent::Started);
+    }
+}
+
+fn log_attract_events(mut events: EventReader<AttractEvent>) {
+    for event in events.iter() {
+        match event {
+            AttractEvent::Started => info!("Nobody around, starting attract mode"),
+            AttractEvent::Ended => info!("Someone is here, back to interactive"),
+        }
+    }
+}
@@ -0,0 +1,277 @@
+//! The Kinect's four microphones. With `--mic` the audio subdevice is opened
+//! in a context of its own, like the [`Motor`](crate::motor::Motor), and a
+//! thread keeps libfreenect's audio transfers going; every sample lands in
+//! the [`MicAudio`] ring buffer, and [`MicLevel`] has each microphone's
+//! loudness for the frame, for installations that react to sound picked up
+//! by the same device.
+//!
+//! Audio on a Kinect needs its firmware uploaded first, which libfreenect
+//! does at open from an `audios.bin` in its search path (see libfreenect's
+//! `fwfetcher.py`). Without it opening fails and audio stays off.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use bevy::prelude::*;
+
+/// Samples per second, per microphone.
+pub const SAMPLE_RATE: usize = 16_000;
+/// Seconds of audio [`MicAudio`] keeps.
+const KEPT_SECONDS: usize = 2;
+/// Seconds [`MicLevel`] is taken over.
+const LEVEL_WINDOW: f32 = 0.05;
+
+/// One sample of each of the four microphones.
+pub type MicSample = [i32; 4];
+
+/// The last few seconds of samples, oldest first.
+#[derive(Debug, Default)]
+pub struct MicRing {
+    samples: VecDeque<MicSample>,
+    /// samples pushed since the start, including the ones dropped since
+    received: u64,
+}
+
+impl MicRing {
+    pub fn push(&mut self, samples: impl IntoIterator<Item = MicSample>) {
+        for sample in samples {
+            if self.samples.len() == SAMPLE_RATE * KEPT_SECONDS {
+                self.samples.pop_front();
+            }
+            self.samples.push_back(sample);
+            self.received += 1;
+        }
+    }
+
+    pub fn samples(&self) -> &VecDeque<MicSample> {
+        &self.samples
+    }
+
+    /// The samples that came in since the last call with the same `cursor`,
+    /// for readers that want every sample once. A reader that falls more
+    /// than the buffer behind misses the oldest.
+    pub fn read_new(&self, cursor: &mut u64) -> Vec<MicSample> {
+        let new = (self.received - (*cursor).min(self.received)) as usize;
+        *cursor = self.received;
+        let skip = self.samples.len() - new.min(self.samples.len());
+        self.samples.iter().skip(skip).copied().collect()
+    }
+
+    /// Root mean square of each microphone over the last `count` samples,
+    /// 0..1 of full scale.
+    pub fn rms(&self, count: usize) -> [f32; 4] {
+        let count = count.min(self.samples.len());
+        let mut sums = [0.0f64; 4];
+        for sample in self.samples.iter().rev().take(count) {
+            for (sum, value) in sums.iter_mut().zip(sample) {
+                let value = *value as f64 / i32::MAX as f64;
+                *sum += value * value;
+            }
+        }
+        sums.map(|sum| {
+            if count == 0 {
+                0.0
+            } else {
+                (sum / count as f64).sqrt() as f32
+            }
+        })
+    }
+}
+
+/// Shared with the audio thread, which fills it as transfers complete.
+#[derive(Resource, Clone, Default)]
+pub struct MicAudio(Arc<Mutex<MicRing>>);
+
+impl MicAudio {
+    pub fn lock(&self) -> MutexGuard<'_, MicRing> {
+        self.0.lock().unwrap()
+    }
+}
+
+/// Loudness of each microphone over the last 50 ms, 0..1 of full scale.
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct MicLevel(pub [f32; 4]);
+
+impl MicLevel {
+    /// The loudest microphone's level.
+    pub fn max(&self) -> f32 {
+        self.0.iter().copied().fold(0.0, f32::max)
+    }
+}
+
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct AudioSettings {
+    pub enabled: bool,
+}
+
+pub struct AudioPlugin;
+
+impl Plugin for AudioPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<AudioSettings>()
+            .init_resource::<MicAudio>()
+            .init_resource::<MicLevel>()
+            .add_system(measure_level);
+        #[cfg(feature = "freenect")]
+        app.add_startup_system(open_mic_array);
+    }
+}
+
+#[cfg(feature = "freenect")]
+fn open_mic_array(
+    mut commands: Commands,
+    settings: Res<AudioSettings>,
+    config: Res<crate::KinectConfig>,
+    audio: Res<MicAudio>,
+) {
+    if settings.enabled {
+        commands.insert_resource(mic::MicArray::open(config.device_index, audio.clone()));
+    }
+}
+
+fn measure_level(settings: Res<AudioSettings>, audio: Res<MicAudio>, mut level: ResMut<MicLevel>) {
+    if !settings.enabled {
+        return;
+    }
+    level.0 = audio
+        .lock()
+        .rms((SAMPLE_RATE as f32 * LEVEL_WINDOW) as usize);
+}
+
+#[cfg(feature = "freenect")]
+mod mic {
+    use std::os::raw::{c_int, c_void};
+    use std::ptr;
+    use std::slice;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread::{self, JoinHandle};
+
+    use bevy::prelude::*;
+
+    use super::{MicAudio, MicRing};
+    use crate::motor::ffi;
+
+    /// The audio thread, which owns the context and device. Dropping it
+    /// stops the audio and joins the thread.
+    #[derive(Resource)]
+    pub struct MicArray {
+        stop: Arc<AtomicBool>,
+        thread: Option<JoinHandle<()>>,
+    }
+
+    impl MicArray {
+        pub fn open(index: usize, audio: MicAudio) -> MicArray {
+            let stop = Arc::new(AtomicBool::new(false));
+            let thread_stop = stop.clone();
+            let thread = thread::Builder::new()
+                .name("kinect audio".into())
+                .spawn(move || {
+                    if let Err(err) = run(index, &audio.0, &thread_stop) {
+                        warn!("{err}, microphones disabled");
+                    }
+                })
+                .expect("Unable to spawn the audio thread");
+            MicArray {
+                stop,
+                thread: Some(thread),
+            }
+        }
+    }
+
+    impl Drop for MicArray {
+        fn drop(&mut self) {
+            self.stop.store(true, Ordering::Relaxed);
+            if let Some(thread) = self.thread.take() {
+                let _ = thread.join();
+            }
+        }
+    }
+
+    extern "C" fn on_audio(
+        dev: *mut ffi::freenect_device,
+        num_samples: c_int,
+        mic1: *mut i32,
+        mic2: *mut i32,
+        mic3: *mut i32,
+        mic4: *mut i32,
+        _cancelled: *mut i16,
+        _unknown: *mut c_void,
+    ) {
+        unsafe {
+            // the ring `run` set as the device's user data
+            let ring = &*(ffi::freenect_get_user(dev) as *const Mutex<MicRing>);
+            let count = num_samples.max(0) as usize;
+            let mics = [mic1, mic2, mic3, mic4].map(|mic| slice::from_raw_parts(mic, count));
+            if let Ok(mut ring) = ring.lock() {
+                ring.push((0..count).map(|i| mics.map(|mic| mic[i])));
+            }
+        }
+    }
+
+    fn run(
+        index: usize,
+        ring: &Arc<Mutex<MicRing>>,
+        stop: &AtomicBool,
+    ) -> Result<(), &'static str> {
+        unsafe {
+            let mut ctx = ptr::null_mut();
+            if ffi::freenect_init(&mut ctx, ptr::null_mut()) < 0 {
+                return Err("Unable to create freenect context for audio");
+            }
+            ffi::freenect_select_subdevices(ctx, ffi::FREENECT_DEVICE_AUDIO);
+            if ffi::freenect_num_devices(ctx) <= index as c_int {
+                ffi::freenect_shutdown(ctx);
+                return Err("Microphones not found");
+            }
+            let mut device = ptr::null_mut();
+            if ffi::freenect_open_device(ctx, &mut device, index as c_int) < 0 {
+                ffi::freenect_shutdown(ctx);
+                return Err("Unable to open the microphones");
+            }
+            // `ring` outlives the device, which is closed before returning
+            ffi::freenect_set_user(device, Arc::as_ptr(ring) as *mut c_void);
+            ffi::freenect_set_audio_in_callback(device, on_audio);
+            let result = if ffi::freenect_start_audio(device) < 0 {
+                Err("Unable to start the microphones")
+            } else {
+                while !stop.load(Ordering::Relaxed) {
+                    let mut timeout = ffi::timeval {
+                        tv_sec: 0,
+                        tv_usec: 10_000,
+                    };
+                    if ffi::freenect_process_events_timeout(ctx, &mut timeout) < 0 {
+                        break;
+                    }
+                }
+                ffi::freenect_stop_audio(device);
+                Ok(())
+            };
+            ffi::freenect_close_device(device);
+            ffi::freenect_shutdown(ctx);
+            result
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring_keeps_the_last_seconds() {
+        let mut ring = MicRing::default();
+        let mut cursor = 0;
+        ring.push(vec![[i32::MAX / 2, 0, 0, 0]; 100]);
+        assert_eq!(ring.read_new(&mut cursor).len(), 100);
+        assert!(ring.read_new(&mut cursor).is_empty());
+        let [loud, quiet, ..] = ring.rms(50);
+        assert!((loud - 0.5).abs() < 1e-3 && quiet == 0.0);
+
+        // more than it keeps
+        ring.push(vec![[0; 4]; SAMPLE_RATE * KEPT_SECONDS]);
+        assert_eq!(ring.samples().len(), SAMPLE_RATE * KEPT_SECONDS);
+        assert_eq!(ring.read_new(&mut cursor).len(), SAMPLE_RATE * KEPT_SECONDS);
+        assert_eq!(ring.rms(SAMPLE_RATE), [0.0; 4]);
+    }
+}
@@ -0,0 +1,133 @@
+//! Depth cameras behind one interface. Everything that reads frames, moves
+//! the motor or checks on the device goes through the [`DepthCamera`]
+//! resource, so none of it knows which camera is plugged in; the
+//! Kinect through libfreenect (`FreenectBackend` in `crate::freenect`) is one
+//! implementation of [`DepthCameraBackend`]. Each backend is behind a cargo
+//! feature of its own, `freenect` and `mock` are on by default.
+//!
+//! Backends deliver 640x480 depth in Kinect Bit10 units (see
+//! [`crate::coords`]), `NO_DEPTH` for holes, and video in the format of the
+//! [`VideoSettings`] they were configured with. They do their I/O on threads
+//! of their own and have to be `Send + Sync`, so the systems using the camera
+//! aren't tied to the main thread.
+
+use std::ops::{Deref, DerefMut};
+
+use bevy::prelude::Resource;
+
+use crate::consumer::FrameConsumers;
+use crate::device::{DepthFrame, KinectError, VideoFrame};
+use crate::motor::{Led, MotorState};
+use crate::video::VideoSettings;
+
+pub trait DepthCameraBackend: Send + Sync {
+    /// Opens the device and starts streaming. Does nothing if already open,
+    /// failures show up in [`DepthCameraBackend::take_exit`].
+    fn open(&mut self);
+
+    /// Stops streaming and closes the device.
+    fn close(&mut self) -> Result<(), KinectError>;
+
+    fn is_running(&self) -> bool;
+
+    /// How the device stopped if it did on its own, e.g. because it couldn't
+    /// be opened or was unplugged.
+    fn take_exit(&mut self) -> Option<Result<(), KinectError>>;
+
+    /// Sets the video mode, used from the next [`DepthCameraBackend::open`].
+    fn configure(&mut self, video: VideoSettings);
+
+    /// Whether the device streams this video mode, checked before a
+    /// [`KinectCommand::Configure`](crate::device::KinectCommand) passes it
+    /// to [`DepthCameraBackend::configure`].
+    fn supports_video(&self, video: VideoSettings) -> bool {
+        video.format.supports(video.resolution)
+    }
+
+    /// Who gets every frame as it arrives, see [`crate::consumer`]; used
+    /// from the next [`DepthCameraBackend::open`].
+    fn set_consumers(&mut self, consumers: FrameConsumers);
+
+    /// The latest depth frame, without waiting, if a new one arrived.
+    fn next_frame(&mut self) -> Option<DepthFrame>;
+
+    /// Like [`DepthCameraBackend::next_frame`], for video.
+    fn next_video_frame(&mut self) -> Option<VideoFrame>;
+
+    /// Depth frames the device delivered so far, including ones nobody
+    /// picked up in time.
+    fn depth_frames_received(&self) -> u64;
+
+    fn video_frames_received(&self) -> u64;
+
+    /// Whether there is a video stream at all. The watchdog only waits for
+    /// video frames from backends that send them.
+    fn streams_video(&self) -> bool {
+        true
+    }
+
+    /// Symptoms of a struggling link, for [`crate::health`].
+    fn health(&self) -> StreamHealth {
+        StreamHealth {
+            depth_frames: self.depth_frames_received(),
+            ..StreamHealth::default()
+        }
+    }
+
+    /// Points the sensor up or down, degrees from level.
+    fn tilt(&mut self, degrees: f64) -> Result<(), KinectError>;
+
+    /// Motor angle and accelerometer.
+    fn tilt_state(&mut self) -> Result<MotorState, KinectError>;
+
+    fn led(&mut self, led: Led) -> Result<(), KinectError>;
+
+    /// Switches the IR projector, for sensors taking turns in a room (see
+    /// [`crate::interference`]). Most can't.
+    fn emitter(&mut self, _on: bool) -> Result<(), KinectError> {
+        Err(KinectError::Unsupported("switching the IR emitter"))
+    }
+
+    fn restart(&mut self) -> Result<(), KinectError> {
+        let stopped = self.close();
+        self.open();
+        stopped
+    }
+}
+
+/// How well the depth stream is getting through, counted since the app
+/// started.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct StreamHealth {
+    pub depth_frames: u64,
+    /// frames lost on the way, incomplete ones included
+    pub missed_frames: u64,
+    /// times the stream was reopened
+    pub restarts: u64,
+    /// spread of the time between frames, milliseconds
+    pub jitter_ms: f32,
+}
+
+/// The camera in use.
+#[derive(Resource)]
+pub struct DepthCamera(Box<dyn DepthCameraBackend>);
+
+impl DepthCamera {
+    pub fn new(backend: impl DepthCameraBackend + 'static) -> DepthCamera {
+        DepthCamera(Box::new(backend))
+    }
+}
+
+impl Deref for DepthCamera {
+    type Target = dyn DepthCameraBackend;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.0
+    }
+}
+
+impl DerefMut for DepthCamera {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut *self.0
+    }
+}
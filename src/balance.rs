@@ -0,0 +1,216 @@
+//! Balance and sway, for balance training and rehab apps. The center of mass
+//! is approximated by the middle of the segmented body, every person pixel
+//! weighted by how much of the body it covers, and dropped straight down
+//! (by the accelerometer) to the floor under the [`Skeleton`]'s feet. How
+//! that point wanders over the last [`SWAY_WINDOW`] seconds is the sway, in
+//! [`Balance::sway`].
+//!
+//! Sway is measured on the floor in the person's frame, facing the sensor:
+//! x to their right, y towards the sensor, both in meters.
+
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+
+use crate::angles;
+use crate::coords::{self, DEPTH_WIDTH};
+use crate::segmentation::PersonMask;
+use crate::skeleton::{Joint, Skeleton};
+use crate::tilt::TiltState;
+use crate::{CurrentDepth, MainKinect};
+
+/// Seconds of center of mass positions the sway is measured over.
+pub const SWAY_WINDOW: f64 = 10.0;
+/// Person pixels needed before the mask counts as a body.
+const MIN_PIXELS: usize = 2000;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Sway {
+    /// root mean square distance from the mean position, side to side and
+    /// front to back
+    pub sideways_rms: f32,
+    pub forward_rms: f32,
+    /// how far the point travelled, meters
+    pub path_length: f32,
+    /// path length over the time it took, meters per second
+    pub mean_speed: f32,
+    /// time the measurement covers, up to [`SWAY_WINDOW`]
+    pub seconds: f32,
+}
+
+impl Sway {
+    /// Sway of timed floor positions, oldest first.
+    pub fn measure<'a>(samples: impl IntoIterator<Item = &'a (f64, Vec2)>) -> Sway {
+        let samples: Vec<(f64, Vec2)> = samples.into_iter().copied().collect();
+        let (first, last) = match (samples.first(), samples.last()) {
+            (Some(first), Some(last)) if samples.len() > 1 => (first.0, last.0),
+            _ => return Sway::default(),
+        };
+        let mean = samples.iter().map(|(_, point)| *point).sum::<Vec2>() / samples.len() as f32;
+        let square = samples
+            .iter()
+            .map(|(_, point)| (*point - mean) * (*point - mean))
+            .sum::<Vec2>()
+            / samples.len() as f32;
+        let path_length = samples
+            .windows(2)
+            .map(|pair| pair[0].1.distance(pair[1].1))
+            .sum::<f32>();
+        let seconds = (last - first) as f32;
+        Sway {
+            sideways_rms: square.x.sqrt(),
+            forward_rms: square.y.sqrt(),
+            path_length,
+            mean_speed: if seconds > 0.0 {
+                path_length / seconds
+            } else {
+                0.0
+            },
+            seconds,
+        }
+    }
+}
+
+#[derive(Resource, Clone, Debug, Default)]
+pub struct Balance {
+    /// sensor space, meters, `None` while nobody is segmented
+    pub center_of_mass: Option<Vec3>,
+    /// the center of mass dropped to the floor the feet stand on, sensor
+    /// space; `None` while the feet aren't tracked
+    pub on_floor: Option<Vec3>,
+    /// where that is from the middle between the feet, on the floor in the
+    /// person's frame; how far they lean over their base of support
+    pub off_center: Option<Vec2>,
+    pub sway: Sway,
+}
+
+pub struct BalancePlugin;
+
+impl Plugin for BalancePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Balance>()
+            .add_system(estimate_balance.after(crate::skeleton::estimate_skeleton));
+    }
+}
+
+fn estimate_balance(
+    mask: Res<PersonMask>,
+    skeleton: Res<Skeleton>,
+    tilt: Res<TiltState>,
+    depth_query: Query<&CurrentDepth, With<MainKinect>>,
+    mut balance: ResMut<Balance>,
+    mut history: Local<VecDeque<(f64, Vec2)>>,
+) {
+    if !mask.is_changed() {
+        return;
+    }
+    let depth = match depth_query.get_single() {
+        Ok(depth) if depth.depth_array.len() == mask.0.len() => depth,
+        _ => return,
+    };
+    let up = angles::world_up(&tilt);
+    // the person's right and towards the sensor, level with the floor
+    let level = |direction: Vec3| (direction - up * direction.dot(up)).normalize();
+    let (right, towards) = (level(Vec3::NEG_X), level(Vec3::NEG_Z));
+    let on_level = |point: Vec3| Vec2::new(point.dot(right), point.dot(towards));
+
+    let center_of_mass = center_of_mass(&mask.0, &depth.depth_array);
+    let feet = match (
+        skeleton.get(Joint::FootLeft),
+        skeleton.get(Joint::FootRight),
+    ) {
+        (Some(left), Some(right)) => Some((left.position, right.position)),
+        _ => None,
+    };
+    let on_floor = match (center_of_mass, feet) {
+        (Some(center), Some((left, right))) => {
+            let floor = left.dot(up).min(right.dot(up));
+            Some(center - up * (center.dot(up) - floor))
+        }
+        _ => None,
+    };
+
+    match on_floor {
+        Some(point) => history.push_back((depth.received_at, on_level(point))),
+        None => history.clear(),
+    }
+    while history
+        .front()
+        .is_some_and(|(at, _)| depth.received_at - at > SWAY_WINDOW)
+    {
+        history.pop_front();
+    }
+
+    *balance = Balance {
+        center_of_mass,
+        on_floor,
+        off_center: on_floor
+            .zip(feet)
+            .map(|(point, (left, right))| on_level(point) - on_level((left + right) / 2.0)),
+        sway: Sway::measure(history.iter()),
+    };
+}
+
+/// Middle of the person pixels in sensor space, each weighted by the area it
+/// covers at its distance, so near and far parts of the body count the same.
+pub fn center_of_mass(mask: &[bool], depth: &[u16]) -> Option<Vec3> {
+    let (mut sum, mut weight, mut pixels) = (Vec3::ZERO, 0.0, 0);
+    for (i, (person, raw)) in mask.iter().zip(depth).enumerate() {
+        let meters = match (*person, coords::raw_depth_to_meters(*raw)) {
+            (true, Some(meters)) => meters,
+            _ => continue,
+        };
+        let pixel = Vec2::new((i % DEPTH_WIDTH) as f32, (i / DEPTH_WIDTH) as f32);
+        let area = meters * meters;
+        sum += coords::depth_pixel_to_sensor(pixel, meters) * area;
+        weight += area;
+        pixels += 1;
+    }
+    (pixels >= MIN_PIXELS).then(|| sum / weight)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coords::DEPTH_HEIGHT;
+
+    #[test]
+    fn a_flat_body_has_its_center_in_the_middle() {
+        let raw = coords::meters_to_raw_depth(2.0);
+        let depth = vec![raw; DEPTH_WIDTH * DEPTH_HEIGHT];
+        let center = Vec2::new(320.0, 240.0);
+        let mask: Vec<bool> = (0..depth.len())
+            .map(|i| {
+                let pixel = Vec2::new((i % DEPTH_WIDTH) as f32, (i / DEPTH_WIDTH) as f32);
+                (pixel - center).abs().cmple(Vec2::new(40.0, 100.0)).all()
+            })
+            .collect();
+        let com = center_of_mass(&mask, &depth).unwrap();
+        let expected =
+            coords::depth_pixel_to_sensor(center, coords::raw_depth_to_meters(raw).unwrap());
+        assert!(com.distance(expected) < 0.01, "{com} {expected}");
+        assert_eq!(center_of_mass(&vec![false; depth.len()], &depth), None);
+    }
+
+    #[test]
+    fn sway_measures_a_side_to_side_swing() {
+        // 2 cm either way, once a second, for 4 seconds at 30 Hz
+        let samples: Vec<(f64, Vec2)> = (0..=120)
+            .map(|frame| {
+                let at = frame as f64 / 30.0;
+                let x = 0.02 * (at as f32 * std::f32::consts::TAU).sin();
+                (at, Vec2::new(x, 0.0))
+            })
+            .collect();
+        let sway = Sway::measure(samples.iter());
+        assert!(
+            (sway.sideways_rms - 0.02 / 2f32.sqrt()).abs() < 1e-3,
+            "{sway:?}"
+        );
+        assert!(sway.forward_rms < 1e-6);
+        // 8 cm a swing
+        assert!((sway.path_length - 0.32).abs() < 0.005, "{sway:?}");
+        assert!((sway.mean_speed - 0.08).abs() < 0.002, "{sway:?}");
+        assert_eq!(sway.seconds, 4.0);
+    }
+}
@@ -0,0 +1,458 @@
+//! Headless capture: takes frames off a sensor and saves or forwards them,
+//! without a window or any rendering, for small capture boxes. It opens the
+//! same backends with the same settings as the app.
+//!
+//! * `--record <dir>` depth frames as 16-bit PNGs in `<dir>/depth/`, the
+//!   layout `--dataset` writes and the replay tests read, and video as PNGs
+//!   in `<dir>/video/`
+//! * `--forward <addr:port>` listen there and send every frame to whoever
+//!   connects: one byte `D` or `V`, the payload length as a little endian
+//!   `u32`, then the payload, depth readings in the `--depth` format as
+//!   little endian `u16` or the video frame as the device delivers it
+//! * `--forward-ws <addr:port>` the same to WebSocket clients, such as the
+//!   app built for the browser, one binary message per frame
+//! * `--seconds <seconds>` / `--frames <count>` stop after that many depth
+//!   frames or seconds, closing the device properly
+//! * `--device <index>` / `--depth <bit10|bit11|mm|registered>` /
+//!   `--video <format>` / `--video-res <medium|high>` as for the app
+//! * `--serial <serial>` the Kinect with this serial number, in place of
+//!   `--device`
+//! * `--depth-only` / `--frame-queue <count>` /
+//!   `--drop-policy <keep-latest|keep-all|drop-oldest>` how the Kinect is
+//!   streamed, see [`UsbSettings`]
+//! * `--kinect2`, `--openni2`, `--simulate <scene.ron|sweep>` other
+//!   sensors, with their cargo features
+
+use std::fmt::Display;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bevy_kinect::backend::DepthCamera;
+#[cfg(feature = "freenect")]
+use bevy_kinect::consumer::FrameConsumers;
+use bevy_kinect::dataset;
+#[cfg(feature = "freenect")]
+use bevy_kinect::devices::KinectDevices;
+#[cfg(feature = "freenect")]
+use bevy_kinect::embedded::UsbSettings;
+#[cfg(feature = "freenect")]
+use bevy_kinect::freenect::FreenectBackend;
+#[cfg(feature = "freenect2")]
+use bevy_kinect::kinect2::Freenect2Backend;
+#[cfg(feature = "openni2")]
+use bevy_kinect::openni2::OpenNi2Backend;
+#[cfg(feature = "mock")]
+use bevy_kinect::simulation::{Scene, SimulatedKinect};
+use bevy_kinect::video::VideoSettings;
+use bevy_kinect::DepthFormat;
+
+/// How long to wait when neither stream had a frame.
+const IDLE: Duration = Duration::from_millis(2);
+/// A client that can't take a frame in this long is dropped.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Printed with what was wrong on the command line.
+const USAGE: &str = "usage: kinect-capture --record <dir> | --forward <addr:port> | \
+                     --forward-ws <addr:port> [options], the rest are listed in src/bin/kinect-capture.rs";
+
+#[derive(Default)]
+struct Options {
+    device: usize,
+    depth_format: DepthFormat,
+    video: VideoSettings,
+    record: Option<PathBuf>,
+    forward: Option<SocketAddr>,
+    forward_ws: Option<SocketAddr>,
+    seconds: Option<f32>,
+    frames: Option<u64>,
+    #[cfg(feature = "freenect")]
+    serial: Option<String>,
+    #[cfg(feature = "freenect")]
+    usb: UsbSettings,
+    #[cfg(feature = "freenect2")]
+    kinect2: bool,
+    #[cfg(feature = "openni2")]
+    openni2: bool,
+    #[cfg(feature = "mock")]
+    simulate: Option<PathBuf>,
+}
+
+impl Options {
+    fn from_args() -> Result<Options, String> {
+        let mut options = Options::default();
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("{arg} needs a value"));
+            match arg.as_str() {
+                "--device" => options.device = parse(&arg, &value()?)?,
+                "--depth" => options.depth_format = parse(&arg, &value()?)?,
+                "--video" => options.video.format = parse(&arg, &value()?)?,
+                "--video-res" => options.video.resolution = parse(&arg, &value()?)?,
+                "--record" => options.record = Some(value()?.into()),
+                "--forward" => options.forward = Some(parse(&arg, &value()?)?),
+                "--forward-ws" => options.forward_ws = Some(parse(&arg, &value()?)?),
+                "--seconds" => options.seconds = Some(parse(&arg, &value()?)?),
+                "--frames" => options.frames = Some(parse(&arg, &value()?)?),
+                #[cfg(feature = "freenect")]
+                "--serial" => options.serial = Some(value()?),
+                #[cfg(feature = "freenect")]
+                "--depth-only" => options.usb.depth_only = true,
+                #[cfg(feature = "freenect")]
+                "--frame-queue" => options.usb.frame_queue = parse(&arg, &value()?)?,
+                #[cfg(feature = "freenect")]
+                "--drop-policy" => options.usb.drop_policy = parse(&arg, &value()?)?,
+                #[cfg(feature = "freenect2")]
+                "--kinect2" => options.kinect2 = true,
+                #[cfg(feature = "openni2")]
+                "--openni2" => options.openni2 = true,
+                #[cfg(feature = "mock")]
+                "--simulate" => options.simulate = Some(value()?.into()),
+                _ => return Err(format!("unknown argument '{arg}'")),
+            }
+        }
+        if !options.video.format.supports(options.video.resolution) {
+            return Err(format!(
+                "{:?} video is not available in {:?} resolution",
+                options.video.format, options.video.resolution
+            ));
+        }
+        if options.record.is_none() && options.forward.is_none() && options.forward_ws.is_none() {
+            return Err("nothing to do".to_string());
+        }
+        Ok(options)
+    }
+}
+
+/// `value` parsed, with `flag` in the error.
+fn parse<T>(flag: &str, value: &str) -> Result<T, String>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    value
+        .parse()
+        .map_err(|err| format!("{flag} {value}: {err}"))
+}
+
+/// The sensor, picked the way the app picks it. Backends other than the
+/// Kinect through libfreenect wait for `open`.
+fn camera(options: &Options) -> DepthCamera {
+    #[cfg(feature = "freenect2")]
+    if options.kinect2 {
+        // recordings and forwarded frames are 640x480
+        return DepthCamera::new(Freenect2Backend::new(options.video).v1_picture(true));
+    }
+    #[cfg(feature = "openni2")]
+    if options.openni2 {
+        return DepthCamera::new(OpenNi2Backend::new(options.video));
+    }
+    #[cfg(feature = "mock")]
+    if let Some(path) = &options.simulate {
+        let scene = if path.as_os_str() == "sweep" {
+            Scene::sweep()
+        } else {
+            Scene::load(path).unwrap_or_else(|err| panic!("{err}"))
+        };
+        return DepthCamera::new(SimulatedKinect::new(scene));
+    }
+    #[cfg(feature = "freenect")]
+    let device = match &options.serial {
+        Some(serial) => {
+            let devices = KinectDevices::list().unwrap_or_else(|err| panic!("{err}"));
+            match devices.by_serial(serial) {
+                Some(device) => device.index,
+                None => panic!("no Kinect with serial {serial} connected"),
+            }
+        }
+        None => options.device,
+    };
+    #[cfg(feature = "freenect")]
+    return DepthCamera::new(FreenectBackend::spawn(
+        options.video,
+        device,
+        options.depth_format,
+        FrameConsumers::default(),
+        options.usb,
+    ));
+    #[cfg(not(feature = "freenect"))]
+    panic!(
+        "no camera for device {}: build with the freenect feature for a Kinect, or pick another sensor",
+        options.device
+    );
+}
+
+/// Frame files under `--record`.
+struct Recorder {
+    dir: PathBuf,
+    video: VideoSettings,
+    rgba: Vec<u8>,
+}
+
+impl Recorder {
+    fn new(dir: &Path, video: VideoSettings) -> io::Result<Recorder> {
+        fs::create_dir_all(dir.join("depth"))?;
+        fs::create_dir_all(dir.join("video"))?;
+        Ok(Recorder {
+            dir: dir.to_path_buf(),
+            video,
+            rgba: Vec::new(),
+        })
+    }
+
+    fn depth(&self, index: u64, depth: &[u16]) -> io::Result<()> {
+        dataset::write_depth_png(&self.dir.join(format!("depth/{index:08}.png")), depth)
+    }
+
+    fn video(&mut self, index: u64, frame: &[u8]) -> io::Result<()> {
+        self.video.frame_to_rgba(frame, &mut self.rgba);
+        let (width, height) = self.video.resolution.size();
+        let path = self.dir.join(format!("video/{index:08}.png"));
+        dataset::write_color_png(&path, width, height, &self.rgba)
+    }
+}
+
+/// Clients of `--forward` and `--forward-ws`, taken in by a thread per
+/// address.
+#[derive(Clone, Default)]
+struct Forwarder {
+    clients: Arc<Mutex<Vec<Client>>>,
+}
+
+struct Client {
+    stream: TcpStream,
+    websocket: bool,
+}
+
+impl Forwarder {
+    fn listen(&self, addr: SocketAddr, websocket: bool) -> io::Result<()> {
+        let listener = TcpListener::bind(addr)?;
+        let clients = self.clients.clone();
+        thread::Builder::new()
+            .name("forward".into())
+            .spawn(move || {
+                for mut stream in listener.incoming().flatten() {
+                    if websocket && accept_websocket(&mut stream).is_err() {
+                        continue;
+                    }
+                    if stream.set_write_timeout(Some(WRITE_TIMEOUT)).is_ok() {
+                        let _ = stream.set_nodelay(true);
+                        clients.lock().unwrap().push(Client { stream, websocket });
+                    }
+                }
+            })?;
+        Ok(())
+    }
+
+    /// Sends a frame to every client, dropping the ones that went away or
+    /// can't keep up.
+    fn send(&self, kind: u8, payload: &[u8]) {
+        let mut message = Vec::with_capacity(payload.len() + 5);
+        message.push(kind);
+        message.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        message.extend_from_slice(payload);
+        let mut clients = self.clients.lock().unwrap();
+        let framed = clients
+            .iter()
+            .any(|client| client.websocket)
+            .then(|| websocket_frame(&message));
+        clients.retain_mut(|client| match (&framed, client.websocket) {
+            (Some(framed), true) => client.stream.write_all(framed).is_ok(),
+            _ => client.stream.write_all(&message).is_ok(),
+        });
+    }
+}
+
+/// Answers a WebSocket opening handshake (RFC 6455). What the client sends
+/// afterwards is never read, the frames only go one way.
+fn accept_websocket(stream: &mut TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(WRITE_TIMEOUT))?;
+    let mut request = Vec::new();
+    let mut byte = [0];
+    while !request.ends_with(b"\r\n\r\n") && request.len() < 8192 {
+        stream.read_exact(&mut byte)?;
+        request.push(byte[0]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let key = request
+        .lines()
+        .find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.eq_ignore_ascii_case("sec-websocket-key")
+                .then(|| value.trim().to_string())
+        })
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "not a WebSocket request"))?;
+    let accept = base64(&sha1(
+        format!("{key}258EAFA5-E914-47DA-95CA-C5AB0DC85B11").as_bytes(),
+    ));
+    write!(
+        stream,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {accept}\r\n\r\n"
+    )
+}
+
+/// `message` as one unmasked binary WebSocket frame.
+fn websocket_frame(message: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(message.len() + 10);
+    frame.push(0x82);
+    match message.len() {
+        len if len < 126 => frame.push(len as u8),
+        len if len <= u16::MAX as usize => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(message);
+    frame
+}
+
+/// SHA-1, which the handshake is made of; nothing else relies on it.
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+    let mut padded = data.to_vec();
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0);
+    }
+    padded.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+
+    for block in padded.chunks(64) {
+        let mut words = [0u32; 80];
+        for (word, bytes) in words.iter_mut().zip(block.chunks(4)) {
+            *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        for i in 16..80 {
+            words[i] = (words[i - 3] ^ words[i - 8] ^ words[i - 14] ^ words[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (i, word) in words.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let next = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            (e, d, c, b, a) = (d, c, b.rotate_left(30), a, next);
+        }
+        for (value, add) in state.iter_mut().zip([a, b, c, d, e]) {
+            *value = value.wrapping_add(add);
+        }
+    }
+    let mut digest = [0; 20];
+    for (bytes, value) in digest.chunks_mut(4).zip(state) {
+        bytes.copy_from_slice(&value.to_be_bytes());
+    }
+    digest
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, byte)| {
+            bits | (*byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+fn main() {
+    let options = Options::from_args().unwrap_or_else(|err| {
+        eprintln!("{err}\n{USAGE}");
+        std::process::exit(2)
+    });
+    let mut recorder = options.record.as_ref().map(|dir| {
+        Recorder::new(dir, options.video)
+            .unwrap_or_else(|err| panic!("can't record to {}: {err}", dir.display()))
+    });
+    let forwarder = (options.forward.is_some() || options.forward_ws.is_some()).then(|| {
+        let forwarder = Forwarder::default();
+        let listening = [(options.forward, false), (options.forward_ws, true)];
+        for (addr, websocket) in listening {
+            if let Some(addr) = addr {
+                forwarder
+                    .listen(addr, websocket)
+                    .unwrap_or_else(|err| panic!("can't listen on {addr}: {err}"));
+            }
+        }
+        forwarder
+    });
+
+    let mut camera = camera(&options);
+    camera.open();
+    let started = Instant::now();
+    let (mut depth_frames, mut video_frames) = (0, 0);
+    loop {
+        if let Some(Err(err)) = camera.take_exit() {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+        let depth = camera.next_frame();
+        let video = camera.next_video_frame();
+        if let Some(frame) = &depth {
+            if let Some(recorder) = &recorder {
+                if let Err(err) = recorder.depth(depth_frames, &frame.data) {
+                    eprintln!("Unable to save depth frame: {err}");
+                }
+            }
+            if let Some(forwarder) = &forwarder {
+                let bytes: Vec<u8> = frame
+                    .data
+                    .iter()
+                    .flat_map(|raw| options.depth_format.from_bit10(*raw).to_le_bytes())
+                    .collect();
+                forwarder.send(b'D', &bytes);
+            }
+            depth_frames += 1;
+        }
+        if let Some(frame) = &video {
+            if let Some(recorder) = &mut recorder {
+                if let Err(err) = recorder.video(video_frames, &frame.data) {
+                    eprintln!("Unable to save video frame: {err}");
+                }
+            }
+            if let Some(forwarder) = &forwarder {
+                forwarder.send(b'V', &frame.data);
+            }
+            video_frames += 1;
+        }
+
+        let out_of_time = options
+            .seconds
+            .is_some_and(|seconds| started.elapsed().as_secs_f32() >= seconds);
+        if out_of_time || options.frames.is_some_and(|frames| depth_frames >= frames) {
+            break;
+        }
+        if depth.is_none() && video.is_none() {
+            thread::sleep(IDLE);
+        }
+    }
+
+    if let Err(err) = camera.close() {
+        eprintln!("{err}");
+    }
+    println!("Captured {depth_frames} depth and {video_frames} video frames");
+}
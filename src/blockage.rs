@@ -0,0 +1,111 @@
+//! Something in front of the lens. A hand over the sensor, a coat hung on it
+//! or a visitor pressed right up against it leaves most of the view without
+//! readings, or with readings closer than anything that can be tracked, and
+//! the trackers then chase noise. Once that has gone on for
+//! [`BlockageSettings::seconds`] a [`SensorBlocked`] event goes out, so a
+//! kiosk can ask people to step back, and [`SensorCleared`] once the view is
+//! back.
+
+use bevy::prelude::*;
+
+use crate::coords;
+use crate::{CurrentDepth, MainKinect};
+
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct BlockageSettings {
+    /// share of the view without a usable reading that counts as blocked
+    pub share: f32,
+    /// readings closer than this many meters aren't usable
+    pub near: f32,
+    /// how long the view has to stay blocked before it is reported
+    pub seconds: f32,
+}
+
+impl Default for BlockageSettings {
+    fn default() -> Self {
+        BlockageSettings {
+            share: 0.7,
+            near: 0.6,
+            seconds: 2.0,
+        }
+    }
+}
+
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct Blockage {
+    /// share of the latest frame without a usable reading, 0..1
+    pub share: f32,
+    pub blocked: bool,
+    /// when the share last went over the limit
+    since: Option<f64>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SensorBlocked {
+    pub share: f32,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SensorCleared;
+
+pub struct BlockagePlugin;
+
+impl Plugin for BlockagePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<BlockageSettings>()
+            .init_resource::<Blockage>()
+            .add_event::<SensorBlocked>()
+            .add_event::<SensorCleared>()
+            .add_system(detect_blockage.after(crate::KinectSet::Acquire))
+            .add_system(log_blockage.after(detect_blockage));
+    }
+}
+
+fn detect_blockage(
+    depth_query: Query<&CurrentDepth, (With<MainKinect>, Changed<CurrentDepth>)>,
+    settings: Res<BlockageSettings>,
+    mut blockage: ResMut<Blockage>,
+    mut blocked: EventWriter<SensorBlocked>,
+    mut cleared: EventWriter<SensorCleared>,
+) {
+    let depth = match depth_query.get_single() {
+        Ok(depth) if !depth.depth_array.is_empty() => depth,
+        _ => return,
+    };
+    let unusable = depth
+        .depth_array
+        .iter()
+        .filter(|raw| coords::raw_depth_to_meters(**raw).is_none_or(|m| m < settings.near))
+        .count();
+    blockage.share = unusable as f32 / depth.depth_array.len() as f32;
+
+    let now = depth.received_at;
+    if blockage.share >= settings.share {
+        let since = *blockage.since.get_or_insert(now);
+        if !blockage.blocked && now - since >= settings.seconds as f64 {
+            blockage.blocked = true;
+            blocked.send(SensorBlocked {
+                share: blockage.share,
+            });
+        }
+    } else {
+        blockage.since = None;
+        // over once well below the limit, not on the first frame that dips
+        if blockage.blocked && blockage.share < settings.share * 0.8 {
+            blockage.blocked = false;
+            cleared.send(SensorCleared);
+        }
+    }
+}
+
+fn log_blockage(mut blocked: EventReader<SensorBlocked>, mut cleared: EventReader<SensorCleared>) {
+    for event in blocked.iter() {
+        warn!(
+            "sensor blocked, {:.0}% of the view without readings",
+            event.share * 100.0
+        );
+    }
+    for _ in cleared.iter() {
+        info!("sensor view clear again");
+    }
+}
@@ -0,0 +1,255 @@
+//! Whole-body gestures from the [`Skeleton`]: jumping, crouching, leaning
+//! and stepping to either side, measured against where the person usually
+//! stands. That reference follows them slowly, so walking around doesn't
+//! count as stepping but a quick hop to the side does.
+//!
+//! Each gesture is sent once as a [`BodyGesture`] when it starts.
+//! `--bind-gesture jump=space` also presses a key for it, so games already
+//! played with the keyboard work without changes.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use bevy::input::InputSystem;
+use bevy::prelude::*;
+
+use crate::angles::{self, BodyAngle, JointAngles};
+use crate::presentation;
+use crate::skeleton::{Joint, Skeleton};
+use crate::tilt::TiltState;
+
+/// How fast the standing reference follows, per skeleton update.
+const REFERENCE_RATE: f32 = 0.05;
+/// Rise of the pelvis above the reference for a jump, in meters.
+const JUMP_METERS: f32 = 0.12;
+/// Drop of the head below the reference for a crouch.
+const CROUCH_METERS: f32 = 0.25;
+/// Sideways move away from the reference for a step.
+const STEP_METERS: f32 = 0.25;
+/// Torso side lean for a lean, in degrees.
+const LEAN_DEGREES: f32 = 15.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BodyGesture {
+    Jump,
+    Crouch,
+    /// left and right as the person sees it
+    LeanLeft,
+    LeanRight,
+    StepLeft,
+    StepRight,
+}
+
+impl BodyGesture {
+    const ALL: [BodyGesture; 6] = [
+        BodyGesture::Jump,
+        BodyGesture::Crouch,
+        BodyGesture::LeanLeft,
+        BodyGesture::LeanRight,
+        BodyGesture::StepLeft,
+        BodyGesture::StepRight,
+    ];
+}
+
+impl FromStr for BodyGesture {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "jump" => Ok(BodyGesture::Jump),
+            "crouch" => Ok(BodyGesture::Crouch),
+            "lean-left" => Ok(BodyGesture::LeanLeft),
+            "lean-right" => Ok(BodyGesture::LeanRight),
+            "step-left" => Ok(BodyGesture::StepLeft),
+            "step-right" => Ok(BodyGesture::StepRight),
+            _ => Err(format!(
+                "unknown gesture '{s}', expected jump, crouch, lean-left, lean-right, \
+                 step-left or step-right"
+            )),
+        }
+    }
+}
+
+/// Keys pressed for gestures, for one frame each.
+#[derive(Resource, Clone, Debug, Default)]
+pub struct GestureBindings(pub HashMap<BodyGesture, KeyCode>);
+
+impl GestureBindings {
+    /// Adds a `gesture=key` binding, keys are letters, digits, arrows, space,
+    /// enter or escape.
+    pub fn bind(&mut self, binding: &str) -> Result<(), String> {
+        let (gesture, key) = binding
+            .split_once('=')
+            .ok_or_else(|| format!("expected <gesture>=<key>, got '{binding}'"))?;
+        self.0.insert(gesture.parse()?, parse_key(key)?);
+        Ok(())
+    }
+}
+
+fn parse_key(name: &str) -> Result<KeyCode, String> {
+    const LETTERS: [KeyCode; 26] = [
+        KeyCode::A,
+        KeyCode::B,
+        KeyCode::C,
+        KeyCode::D,
+        KeyCode::E,
+        KeyCode::F,
+        KeyCode::G,
+        KeyCode::H,
+        KeyCode::I,
+        KeyCode::J,
+        KeyCode::K,
+        KeyCode::L,
+        KeyCode::M,
+        KeyCode::N,
+        KeyCode::O,
+        KeyCode::P,
+        KeyCode::Q,
+        KeyCode::R,
+        KeyCode::S,
+        KeyCode::T,
+        KeyCode::U,
+        KeyCode::V,
+        KeyCode::W,
+        KeyCode::X,
+        KeyCode::Y,
+        KeyCode::Z,
+    ];
+    const DIGITS: [KeyCode; 10] = [
+        KeyCode::Key0,
+        KeyCode::Key1,
+        KeyCode::Key2,
+        KeyCode::Key3,
+        KeyCode::Key4,
+        KeyCode::Key5,
+        KeyCode::Key6,
+        KeyCode::Key7,
+        KeyCode::Key8,
+        KeyCode::Key9,
+    ];
+    let name = name.to_lowercase();
+    match name.as_str() {
+        "space" => return Ok(KeyCode::Space),
+        "enter" => return Ok(KeyCode::Return),
+        "escape" => return Ok(KeyCode::Escape),
+        "up" => return Ok(KeyCode::Up),
+        "down" => return Ok(KeyCode::Down),
+        "left" => return Ok(KeyCode::Left),
+        "right" => return Ok(KeyCode::Right),
+        _ => {}
+    }
+    match name.as_bytes() {
+        [c @ b'a'..=b'z'] => Ok(LETTERS[(c - b'a') as usize]),
+        [c @ b'0'..=b'9'] => Ok(DIGITS[(c - b'0') as usize]),
+        _ => Err(format!("unknown key '{name}'")),
+    }
+}
+
+/// Where the person stands when not doing anything, in sensor space, with
+/// heights along the world's up.
+#[derive(Clone, Copy)]
+struct Reference {
+    pelvis_height: f32,
+    head_height: f32,
+    sideways: f32,
+}
+
+pub struct BodyGesturePlugin;
+
+impl Plugin for BodyGesturePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GestureBindings>()
+            .add_event::<BodyGesture>()
+            .add_system(detect_body_gestures.after(angles::compute_joint_angles))
+            // ahead of every system reading the keyboard, and after the
+            // presentation mode lock, which would release them again
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                press_gesture_keys
+                    .after(InputSystem)
+                    .after(presentation::lock_input),
+            );
+    }
+}
+
+fn detect_body_gestures(
+    skeleton: Res<Skeleton>,
+    angles: Res<JointAngles>,
+    tilt: Res<TiltState>,
+    mut reference: Local<Option<Reference>>,
+    mut active: Local<Vec<BodyGesture>>,
+    mut gestures: EventWriter<BodyGesture>,
+) {
+    if !skeleton.is_changed() {
+        return;
+    }
+    let (pelvis, head) = match (skeleton.get(Joint::Pelvis), skeleton.get(Joint::Head)) {
+        (Some(pelvis), Some(head)) => (pelvis.position, head.position),
+        _ => {
+            *reference = None;
+            active.clear();
+            return;
+        }
+    };
+    let up = angles::world_up(&tilt);
+    // sensor x made horizontal, the person's left when facing the sensor
+    let left = (Vec3::X - up * up.x).normalize();
+    let current = Reference {
+        pelvis_height: pelvis.dot(up),
+        head_height: head.dot(up),
+        sideways: pelvis.dot(left),
+    };
+    let base = *reference.get_or_insert(current);
+
+    let lean = angles
+        .get(BodyAngle::TorsoSideLean)
+        .map_or(0.0, |lean| lean.degrees);
+    let sideways = current.sideways - base.sideways;
+    let now: Vec<BodyGesture> = BodyGesture::ALL
+        .into_iter()
+        .filter(|gesture| match gesture {
+            BodyGesture::Jump => current.pelvis_height - base.pelvis_height > JUMP_METERS,
+            BodyGesture::Crouch => base.head_height - current.head_height > CROUCH_METERS,
+            BodyGesture::LeanLeft => lean < -LEAN_DEGREES,
+            BodyGesture::LeanRight => lean > LEAN_DEGREES,
+            BodyGesture::StepLeft => sideways > STEP_METERS,
+            BodyGesture::StepRight => sideways < -STEP_METERS,
+        })
+        .collect();
+    for gesture in &now {
+        if !active.contains(gesture) {
+            gestures.send(*gesture);
+        }
+    }
+
+    // hold the reference still during jumps and crouches, they are over
+    // before it would catch up anyway
+    if !now.contains(&BodyGesture::Jump) && !now.contains(&BodyGesture::Crouch) {
+        let follow = |from: f32, to: f32| from + (to - from) * REFERENCE_RATE;
+        *reference = Some(Reference {
+            pelvis_height: follow(base.pelvis_height, current.pelvis_height),
+            head_height: follow(base.head_height, current.head_height),
+            sideways: follow(base.sideways, current.sideways),
+        });
+    }
+    *active = now;
+}
+
+fn press_gesture_keys(
+    bindings: Res<GestureBindings>,
+    mut gestures: EventReader<BodyGesture>,
+    mut keys: ResMut<Input<KeyCode>>,
+    mut pressed: Local<Vec<KeyCode>>,
+) {
+    // let go of last frame's keys, so every gesture is one press
+    for key in pressed.drain(..) {
+        keys.release(key);
+    }
+    for gesture in gestures.iter() {
+        info!("Body gesture {gesture:?}");
+        if let Some(key) = bindings.0.get(gesture) {
+            keys.press(*key);
+            pressed.push(*key);
+        }
+    }
+}
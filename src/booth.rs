@@ -0,0 +1,200 @@
+//! Photo booth: the last few seconds of the picture are always kept, and
+//! asking for a clip saves them as a looping GIF right away, no need to have
+//! started recording. K asks for one, and so do holding the trigger pose
+//! (`hands_up` by default) and sending [`BoothRequest`] from other systems.
+//!
+//! Frames are kept at half the view's size and a low rate, GIFs get big
+//! quickly otherwise. Encoding runs on a thread and ends in a [`ClipSaved`].
+
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Mutex;
+use std::thread;
+
+use bevy::prelude::*;
+use gif::{Encoder, Frame, Repeat};
+
+use crate::analytics::unix_now;
+use crate::composite::{ClipSaved, Compositor, FrameSource, FRAME_HEIGHT, FRAME_WIDTH};
+use crate::poses::PoseMatched;
+
+/// GIF frame size, every other view pixel.
+const GIF_WIDTH: u16 = (FRAME_WIDTH / 2) as u16;
+const GIF_HEIGHT: u16 = (FRAME_HEIGHT / 2) as u16;
+/// NeuQuant speed, 1 is best and slowest, 30 fastest.
+const QUANTIZE_SPEED: i32 = 10;
+
+#[derive(Resource, Clone, Debug)]
+pub struct BoothSettings {
+    /// directory to save GIFs to, the booth is off without one
+    pub dir: Option<PathBuf>,
+    /// length of the saved clip
+    pub seconds: f32,
+    pub fps: f32,
+    pub source: FrameSource,
+    /// pose that saves a clip, `None` for keys and requests only
+    pub pose: Option<String>,
+}
+
+impl Default for BoothSettings {
+    fn default() -> Self {
+        BoothSettings {
+            dir: None,
+            seconds: 5.0,
+            fps: 10.0,
+            source: FrameSource::default(),
+            pose: Some("hands_up".to_string()),
+        }
+    }
+}
+
+/// Saves the last `seconds` as a GIF.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BoothRequest;
+
+#[derive(Resource, Default)]
+struct Booth {
+    /// time taken and pixels, oldest first
+    frames: VecDeque<(f64, Vec<u8>)>,
+    /// no new clip before this, so one pose doesn't save overlapping clips
+    ready_at: f64,
+    saving: Vec<Mutex<Receiver<io::Result<PathBuf>>>>,
+}
+
+pub struct BoothPlugin;
+
+impl Plugin for BoothPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<BoothSettings>()
+            .init_resource::<Booth>()
+            .add_event::<BoothRequest>()
+            .add_system(keep_recent_frames)
+            .add_system(request_clips)
+            .add_system(save_clips.after(keep_recent_frames).after(request_clips))
+            .add_system(collect_clips);
+    }
+}
+
+fn keep_recent_frames(
+    settings: Res<BoothSettings>,
+    time: Res<Time>,
+    compositor: Compositor,
+    mut booth: ResMut<Booth>,
+    mut frame: Local<Vec<u8>>,
+) {
+    if settings.dir.is_none() {
+        return;
+    }
+    let now = time.elapsed_seconds_f64();
+    let due = match booth.frames.back() {
+        Some((taken_at, _)) => now - taken_at >= 1.0 / settings.fps as f64,
+        None => true,
+    };
+    if !due || !compositor.frame(settings.source, &mut frame) {
+        return;
+    }
+    let (width, height) = (FRAME_WIDTH as usize, FRAME_HEIGHT as usize);
+    let mut small = Vec::with_capacity(GIF_WIDTH as usize * GIF_HEIGHT as usize * 4);
+    for y in (0..height).step_by(2) {
+        for x in (0..width).step_by(2) {
+            let i = (y * width + x) * 4;
+            small.extend_from_slice(&frame[i..i + 4]);
+        }
+    }
+    booth.frames.push_back((now, small));
+    while let Some((taken_at, _)) = booth.frames.front() {
+        if now - taken_at <= settings.seconds as f64 {
+            break;
+        }
+        booth.frames.pop_front();
+    }
+}
+
+fn request_clips(
+    settings: Res<BoothSettings>,
+    keys: Res<Input<KeyCode>>,
+    mut poses: EventReader<PoseMatched>,
+    mut requests: EventWriter<BoothRequest>,
+) {
+    let posed = poses
+        .iter()
+        .any(|pose| settings.pose.as_ref() == Some(&pose.name));
+    if keys.just_pressed(KeyCode::K) || posed {
+        requests.send(BoothRequest);
+    }
+}
+
+fn save_clips(
+    settings: Res<BoothSettings>,
+    time: Res<Time>,
+    mut requests: EventReader<BoothRequest>,
+    mut booth: ResMut<Booth>,
+) {
+    let dir = match &settings.dir {
+        Some(dir) => dir,
+        None => return,
+    };
+    let requested = requests.iter().count() > 0;
+    let now = time.elapsed_seconds_f64();
+    if !requested || now < booth.ready_at || booth.frames.is_empty() {
+        return;
+    }
+    booth.ready_at = now + settings.seconds as f64;
+
+    let frames: Vec<Vec<u8>> = booth
+        .frames
+        .iter()
+        .map(|(_, frame)| frame.clone())
+        .collect();
+    let path = dir.join(format!("booth-{}.gif", unix_now() as u64));
+    // GIF delays are in hundredths of a second
+    let delay = (100.0 / settings.fps).round() as u16;
+    let (report, done) = mpsc::channel();
+    let spawned = thread::Builder::new()
+        .name("booth encoder".to_string())
+        .spawn(move || {
+            let result = write_gif(&path, frames, delay).map(|_| path);
+            let _ = report.send(result);
+        });
+    match spawned {
+        Ok(_) => booth.saving.push(Mutex::new(done)),
+        Err(err) => error!("Unable to save booth clip: {err}"),
+    }
+}
+
+fn write_gif(path: &Path, frames: Vec<Vec<u8>>, delay: u16) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = Encoder::new(file, GIF_WIDTH, GIF_HEIGHT, &[]).map_err(io::Error::other)?;
+    encoder
+        .set_repeat(Repeat::Infinite)
+        .map_err(io::Error::other)?;
+    for mut rgba in frames {
+        let mut frame = Frame::from_rgba_speed(GIF_WIDTH, GIF_HEIGHT, &mut rgba, QUANTIZE_SPEED);
+        frame.delay = delay;
+        encoder.write_frame(&frame).map_err(io::Error::other)?;
+    }
+    Ok(())
+}
+
+fn collect_clips(mut booth: ResMut<Booth>, mut saved: EventWriter<ClipSaved>) {
+    booth
+        .saving
+        .retain(|done| match done.lock().unwrap().try_recv() {
+            Ok(Ok(path)) => {
+                saved.send(ClipSaved { path });
+                false
+            }
+            Ok(Err(err)) => {
+                error!("Unable to save booth clip: {err}");
+                false
+            }
+            Err(mpsc::TryRecvError::Empty) => true,
+            Err(mpsc::TryRecvError::Disconnected) => false,
+        });
+}
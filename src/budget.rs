@@ -0,0 +1,372 @@
+//! Where a frame's time goes, for finding what to turn off on a weak
+//! machine. Every stage of the [depth pipeline](crate::pipeline) is timed,
+//! as are the depth view and the upload of changed textures to the GPU, and
+//! so is the capture wait: how long each depth frame sat between the backend
+//! getting it and the app taking it. F5 (`--budget-overlay`) shows the
+//! milliseconds in the bottom left, averaged over the last second or so.
+//!
+//! Most optional features are pipeline stages, off with
+//! [`DepthPipeline::set_enabled`]; a switched off stage shows as `off`.
+//! Systems run in parallel where they can, so the rows can add up to more
+//! than the frame. An app can time systems of its own the same way:
+//!
+//! ```ignore
+//! app.add_system(budget::timed("particles", update_particles));
+//! ```
+
+use std::borrow::Cow;
+use std::sync::{Arc, Mutex};
+
+use bevy::ecs::archetype::ArchetypeComponentId;
+use bevy::ecs::component::ComponentId;
+use bevy::ecs::query::Access;
+use bevy::ecs::schedule::SystemLabelId;
+use bevy::prelude::*;
+use bevy::render::render_asset::PrepareAssetLabel;
+use bevy::render::{RenderApp, RenderStage};
+use bevy::utils::{Duration, Instant};
+
+use crate::pipeline::DepthPipeline;
+use crate::presentation::DebugUi;
+
+/// How much of the latest frame goes into the averages.
+const SMOOTHING: f32 = 0.05;
+
+/// The row of the capture wait.
+pub const CAPTURE_WAIT: &str = "capture wait";
+
+/// The row of the depth view.
+pub const DEPTH_VIEW: &str = "depth view";
+
+/// The row of the textures going to the GPU.
+pub const TEXTURE_UPLOAD: &str = "texture upload";
+
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct BudgetSettings {
+    pub overlay: bool,
+}
+
+struct Row {
+    name: Cow<'static, str>,
+    /// time so far this frame
+    frame: Option<Duration>,
+    /// milliseconds, averaged
+    average: Option<f32>,
+}
+
+/// The timed parts of a frame. Clones share the times, the render world has
+/// one.
+#[derive(Resource, Clone, Default)]
+pub struct FrameBudget {
+    rows: Arc<Mutex<Vec<Row>>>,
+}
+
+impl FrameBudget {
+    /// Adds `time` to the part called `name` for this frame.
+    pub fn record(&self, name: impl Into<Cow<'static, str>>, time: Duration) {
+        let name = name.into();
+        let mut rows = self.rows.lock().unwrap();
+        match rows.iter_mut().find(|row| row.name == name) {
+            Some(row) => *row.frame.get_or_insert(Duration::ZERO) += time,
+            None => rows.push(Row {
+                name,
+                frame: Some(time),
+                average: None,
+            }),
+        }
+    }
+
+    /// Each part's averaged milliseconds, in the order they were first timed.
+    pub fn rows(&self) -> Vec<(Cow<'static, str>, f32)> {
+        let rows = self.rows.lock().unwrap();
+        rows.iter()
+            .filter_map(|row| Some((row.name.clone(), row.average?)))
+            .collect()
+    }
+
+    /// Puts this frame's times into the averages. A part that didn't run
+    /// keeps its average.
+    fn end_frame(&self) {
+        let mut rows = self.rows.lock().unwrap();
+        for row in rows.iter_mut() {
+            if let Some(frame) = row.frame.take() {
+                let millis = frame.as_secs_f32() * 1000.0;
+                row.average = Some(match row.average {
+                    Some(average) => average + (millis - average) * SMOOTHING,
+                    None => millis,
+                });
+            }
+        }
+    }
+}
+
+/// A system that adds how long it runs to the [`FrameBudget`], see
+/// [`timed`].
+pub struct Timed<S> {
+    name: Cow<'static, str>,
+    system: S,
+    budget: Option<FrameBudget>,
+}
+
+/// `system`, timed as `name`.
+pub fn timed<Params, S: IntoSystem<(), (), Params>>(
+    name: impl Into<Cow<'static, str>>,
+    system: S,
+) -> Timed<S::System> {
+    Timed {
+        name: name.into(),
+        system: IntoSystem::into_system(system),
+        budget: None,
+    }
+}
+
+impl<S> Timed<S> {
+    fn record(&self, start: Instant) {
+        if let Some(budget) = &self.budget {
+            budget.record(self.name.clone(), start.elapsed());
+        }
+    }
+}
+
+impl<S: System<In = (), Out = ()>> System for Timed<S> {
+    type In = ();
+    type Out = ();
+
+    fn name(&self) -> Cow<'static, str> {
+        self.system.name()
+    }
+
+    fn component_access(&self) -> &Access<ComponentId> {
+        self.system.component_access()
+    }
+
+    fn archetype_component_access(&self) -> &Access<ArchetypeComponentId> {
+        self.system.archetype_component_access()
+    }
+
+    fn is_send(&self) -> bool {
+        self.system.is_send()
+    }
+
+    fn is_exclusive(&self) -> bool {
+        self.system.is_exclusive()
+    }
+
+    unsafe fn run_unsafe(&mut self, input: (), world: &World) {
+        let start = Instant::now();
+        self.system.run_unsafe(input, world);
+        self.record(start);
+    }
+
+    fn run(&mut self, input: (), world: &mut World) {
+        let start = Instant::now();
+        self.system.run(input, world);
+        self.record(start);
+    }
+
+    fn apply_buffers(&mut self, world: &mut World) {
+        self.system.apply_buffers(world);
+    }
+
+    fn initialize(&mut self, world: &mut World) {
+        self.system.initialize(world);
+        self.budget = Some(
+            world
+                .get_resource_or_insert_with(FrameBudget::default)
+                .clone(),
+        );
+    }
+
+    fn update_archetype_component_access(&mut self, world: &World) {
+        self.system.update_archetype_component_access(world);
+    }
+
+    fn check_change_tick(&mut self, change_tick: u32) {
+        self.system.check_change_tick(change_tick);
+    }
+
+    /// The wrapped system's, so `.after(system)` still finds it.
+    fn default_labels(&self) -> Vec<SystemLabelId> {
+        self.system.default_labels()
+    }
+
+    fn get_last_change_tick(&self) -> u32 {
+        self.system.get_last_change_tick()
+    }
+
+    fn set_last_change_tick(&mut self, last_change_tick: u32) {
+        self.system.set_last_change_tick(last_change_tick);
+    }
+}
+
+/// When this frame's texture upload started, in the render world.
+#[derive(Resource, Default)]
+struct UploadStart(Option<Instant>);
+
+#[derive(Component)]
+struct BudgetText;
+
+pub struct BudgetPlugin;
+
+impl Plugin for BudgetPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<BudgetSettings>()
+            .init_resource::<FrameBudget>()
+            .add_startup_system(spawn_budget)
+            .add_system(budget_keys)
+            .add_system(show_budget)
+            .add_system_to_stage(CoreStage::Last, end_frame);
+        let budget = app.world.resource::<FrameBudget>().clone();
+        if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
+            render_app
+                .insert_resource(budget)
+                .init_resource::<UploadStart>()
+                .add_system_to_stage(
+                    RenderStage::Prepare,
+                    start_upload.before(PrepareAssetLabel::PreAssetPrepare),
+                )
+                .add_system_to_stage(
+                    RenderStage::Prepare,
+                    end_upload.after(PrepareAssetLabel::PreAssetPrepare),
+                );
+        }
+    }
+}
+
+fn start_upload(mut start: ResMut<UploadStart>) {
+    start.0 = Some(Instant::now());
+}
+
+fn end_upload(mut start: ResMut<UploadStart>, budget: Res<FrameBudget>) {
+    if let Some(start) = start.0.take() {
+        budget.record(TEXTURE_UPLOAD, start.elapsed());
+    }
+}
+
+fn end_frame(budget: Res<FrameBudget>) {
+    budget.end_frame();
+}
+
+fn spawn_budget(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font: asset_server.load("fonts/Hack-Regular.ttf"),
+                font_size: 14.0,
+                color: Color::WHITE,
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                bottom: Val::Px(8.0),
+                left: Val::Px(8.0),
+                ..default()
+            },
+            ..default()
+        }),
+        BudgetText,
+        DebugUi,
+    ));
+}
+
+fn budget_keys(keys: Res<Input<KeyCode>>, mut settings: ResMut<BudgetSettings>) {
+    if keys.just_pressed(KeyCode::F5) {
+        settings.overlay = !settings.overlay;
+    }
+}
+
+/// The capture wait first, then the pipeline in the order it runs, then
+/// everything else.
+fn rank(name: &str, order: &[&str]) -> usize {
+    if name == CAPTURE_WAIT {
+        return 0;
+    }
+    match order.iter().position(|stage| *stage == name) {
+        Some(i) => i + 1,
+        None => order.len() + 1,
+    }
+}
+
+fn show_budget(
+    settings: Res<BudgetSettings>,
+    budget: Res<FrameBudget>,
+    pipeline: Option<Res<DepthPipeline>>,
+    time: Res<Time>,
+    mut frame: Local<Option<f32>>,
+    mut text: Query<(&mut Text, &mut Visibility), With<BudgetText>>,
+) {
+    let millis = time.delta_seconds() * 1000.0;
+    let average = frame.get_or_insert(millis);
+    *average += (millis - *average) * SMOOTHING;
+    let frame = *average;
+    let (mut text, mut visibility) = match text.get_single_mut() {
+        Ok(text) => text,
+        Err(_) => return,
+    };
+    visibility.is_visible = settings.overlay;
+    if !settings.overlay {
+        return;
+    }
+    let order: Vec<&str> = pipeline
+        .as_ref()
+        .and_then(|pipeline| pipeline.order())
+        .map(|order| order.iter().map(|label| label.as_str()).collect())
+        .unwrap_or_default();
+    let mut rows = budget.rows();
+    rows.sort_by_key(|(name, _)| rank(name, &order));
+
+    let mut lines = format!("{:<20}{frame:>7.2} ms\n", "frame");
+    let stages = pipeline
+        .as_deref()
+        .map(DepthPipeline::stages)
+        .unwrap_or_default();
+    for (name, millis) in rows {
+        let off = stages
+            .iter()
+            .any(|info| info.label.as_str() == name && !info.enabled);
+        if off {
+            lines.push_str(&format!("{name:<20}{:>7}\n", "off"));
+        } else {
+            lines.push_str(&format!("{name:<20}{millis:>7.2} ms\n"));
+        }
+    }
+    text.sections[0].value = lines;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stage() {
+        std::thread::sleep(Duration::from_millis(2));
+    }
+
+    #[test]
+    fn parts_are_timed_and_averaged() {
+        let budget = FrameBudget::default();
+        budget.record(CAPTURE_WAIT, Duration::from_millis(4));
+        budget.record(CAPTURE_WAIT, Duration::from_millis(6));
+        assert!(budget.rows().is_empty());
+        budget.end_frame();
+        let millis = |budget: &FrameBudget| budget.rows()[0].1;
+        assert_eq!(budget.rows()[0].0, CAPTURE_WAIT);
+        assert!((millis(&budget) - 10.0).abs() < 1e-3);
+        // a frame without one keeps the average
+        budget.end_frame();
+        assert!((millis(&budget) - 10.0).abs() < 1e-3);
+        budget.record(CAPTURE_WAIT, Duration::ZERO);
+        budget.end_frame();
+        assert!((millis(&budget) - 10.0 * (1.0 - SMOOTHING)).abs() < 1e-3);
+
+        let mut app = App::new();
+        app.add_system(timed("stage", stage));
+        app.update();
+        let budget = app.world.resource::<FrameBudget>();
+        budget.end_frame();
+        let rows = budget.rows();
+        assert_eq!(rows[0].0, "stage");
+        assert!(rows[0].1 >= 2.0);
+    }
+}
@@ -0,0 +1,460 @@
+//! Noise calibration. With the scene empty and still, F9 (or `--calibrate`
+//! at startup) records a few seconds of depth and measures how much each
+//! reading wanders, grouped by distance. The result goes into the
+//! calibration profile, a JSON file given with `--calibration <file>` that is
+//! read back on the next start:
+//!
+//! * the spread per distance fits the sensor's noise model, which the
+//!   [`crate::confidence`] stability score uses instead of its typical value
+//! * the closest background reading, less its noise, caps the close blob
+//!   threshold, so flicker on a near wall or table never counts as someone
+//!   being close
+//!
+//! Without a profile everything runs on typical values.
+//!
+//! The empty scene of the last calibration (or of the first second, until
+//! there is one) is also kept as the baseline. When furniture is moved, part
+//! of the view stops matching it for good; once that part has differed for
+//! [`CalibrationSettings::scene_change_seconds`] and covers
+//! [`SCENE_CHANGE_AREA`] of the view, [`SceneChanged`] goes out, and with
+//! [`CalibrationSettings::auto_rebaseline`] the calibration runs again on
+//! its own.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::coords;
+use crate::{CurrentDepth, MainKinect, TrackingSettings};
+
+/// Width of a distance bin, in meters.
+const BIN_METERS: f32 = 0.25;
+/// Readings in more than this share of frames count as background, the rest
+/// are holes or edges.
+const MIN_COVERAGE: f32 = 0.9;
+/// Standard deviations between the background and the blob threshold.
+const THRESHOLD_SIGMAS: f32 = 3.0;
+/// Frames the baseline is averaged over without a calibration.
+const BASELINE_FRAMES: u32 = 30;
+/// How far from the baseline a reading has to be to count as changed.
+const CHANGE_METERS: f32 = 0.1;
+/// Side of the blocks changes are counted in.
+const CHANGE_BLOCK: usize = 16;
+/// Share of a block that has to be changed for the block to be.
+const CHANGE_BLOCK_SHARE: f32 = 0.5;
+/// Share of the view that has to have changed for good.
+pub const SCENE_CHANGE_AREA: f32 = 0.05;
+
+#[derive(Resource, Clone, Debug)]
+pub struct CalibrationSettings {
+    /// profile to load and save, calibrating without one only lasts until
+    /// exit
+    pub file: Option<PathBuf>,
+    /// calibrate as soon as the depth stream is up
+    pub at_startup: bool,
+    pub seconds: f32,
+    /// how long part of the view has to differ from the baseline before the
+    /// scene counts as changed; people standing still that long count too
+    pub scene_change_seconds: f32,
+    /// calibrate again when the scene changed, rather than only saying so
+    pub auto_rebaseline: bool,
+}
+
+impl Default for CalibrationSettings {
+    fn default() -> Self {
+        CalibrationSettings {
+            file: None,
+            at_startup: false,
+            seconds: 5.0,
+            scene_change_seconds: 60.0,
+            auto_rebaseline: false,
+        }
+    }
+}
+
+/// Spread of the readings around one distance.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct NoiseBin {
+    /// middle of the bin, meters
+    pub meters: f32,
+    /// standard deviation, meters
+    pub spread: f32,
+    /// pixels that went into it
+    pub pixels: usize,
+}
+
+#[derive(Resource, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CalibrationProfile {
+    pub noise: Vec<NoiseBin>,
+    /// spread at 1 m, from fitting `spread = k * meters²` to the bins
+    pub noise_at_1m: Option<f32>,
+    /// background reading closest to the sensor less its noise, raw
+    pub closest_background: Option<u16>,
+}
+
+impl CalibrationProfile {
+    fn load(path: &Path) -> Result<CalibrationProfile, String> {
+        let json = fs::read_to_string(path)
+            .map_err(|err| format!("can't read calibration {}: {err}", path.display()))?;
+        serde_json::from_str(&json)
+            .map_err(|err| format!("bad calibration {}: {err}", path.display()))
+    }
+
+    fn save(&self, path: &Path) {
+        let written = serde_json::to_string_pretty(self)
+            .map_err(|err| err.to_string())
+            .and_then(|json| fs::write(path, json).map_err(|err| err.to_string()));
+        if let Err(err) = written {
+            error!("Unable to save calibration to {}: {err}", path.display());
+        }
+    }
+}
+
+/// Per pixel sums over the recording.
+struct Recording {
+    until: f64,
+    frames: usize,
+    count: Vec<u32>,
+    sum: Vec<f64>,
+    sum_squares: Vec<f64>,
+}
+
+impl Recording {
+    fn new(until: f64, pixels: usize) -> Recording {
+        Recording {
+            until,
+            frames: 0,
+            count: vec![0; pixels],
+            sum: vec![0.0; pixels],
+            sum_squares: vec![0.0; pixels],
+        }
+    }
+
+    /// Mean and standard deviation of each pixel seen often enough, raw.
+    fn pixels(&self) -> impl Iterator<Item = (f32, f32)> + '_ {
+        let min_count = (self.frames as f32 * MIN_COVERAGE).max(2.0) as u32;
+        (0..self.count.len()).filter_map(move |i| {
+            let n = self.count[i];
+            if n < min_count {
+                return None;
+            }
+            let mean = self.sum[i] / n as f64;
+            let variance = (self.sum_squares[i] / n as f64 - mean * mean).max(0.0);
+            Some((mean as f32, variance.sqrt() as f32))
+        })
+    }
+
+    fn profile(&self) -> CalibrationProfile {
+        let mut bins: Vec<(f64, usize)> = Vec::new();
+        let mut closest: Option<f32> = None;
+        for (mean, spread) in self.pixels() {
+            let low = mean - THRESHOLD_SIGMAS * spread - 1.0;
+            closest = Some(closest.map_or(low, |closest| closest.min(low)));
+            // spread in meters from the slope of the raw to meters curve
+            let (meters, higher) = match (
+                coords::raw_depth_to_meters(mean.round() as u16),
+                coords::raw_depth_to_meters(mean.round() as u16 + 1),
+            ) {
+                (Some(meters), Some(higher)) => (meters, higher),
+                _ => continue,
+            };
+            let spread = spread * (higher - meters);
+            let bin = (meters / BIN_METERS) as usize;
+            if bins.len() <= bin {
+                bins.resize(bin + 1, (0.0, 0));
+            }
+            bins[bin].0 += (spread * spread) as f64;
+            bins[bin].1 += 1;
+        }
+
+        let noise: Vec<NoiseBin> = bins
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, pixels))| *pixels > 0)
+            .map(|(bin, (variance, pixels))| NoiseBin {
+                meters: (bin as f32 + 0.5) * BIN_METERS,
+                spread: (variance / *pixels as f64).sqrt() as f32,
+                pixels: *pixels,
+            })
+            .collect();
+        // least squares for k, each bin weighted by its pixels
+        let (sz, zz) = noise.iter().fold((0.0, 0.0), |(sz, zz), bin| {
+            let z2 = bin.meters * bin.meters;
+            let weight = bin.pixels as f32;
+            (sz + weight * bin.spread * z2, zz + weight * z2 * z2)
+        });
+        CalibrationProfile {
+            noise,
+            noise_at_1m: (zz > 0.0).then_some(sz / zz),
+            closest_background: closest.map(|closest| closest.max(0.0) as u16),
+        }
+    }
+}
+
+/// The calibration being recorded, if any.
+#[derive(Resource, Default)]
+struct CalibrationRun(Option<Recording>);
+
+/// The empty scene, in meters per pixel, and since when each block of the
+/// view has differed from it.
+#[derive(Resource, Default)]
+struct Baseline {
+    sum: Vec<f32>,
+    count: Vec<u32>,
+    frames: u32,
+    changed_since: Vec<Option<f64>>,
+    reported: bool,
+}
+
+impl Baseline {
+    fn learning(pixels: usize) -> Baseline {
+        Baseline {
+            sum: vec![0.0; pixels],
+            count: vec![0; pixels],
+            ..default()
+        }
+    }
+
+    /// The means of a calibration recording.
+    fn recorded(recording: &Recording) -> Baseline {
+        let mut baseline = Baseline::learning(recording.count.len());
+        let min_count = (recording.frames as f32 * MIN_COVERAGE).max(2.0) as u32;
+        for i in 0..recording.count.len() {
+            if recording.count[i] < min_count {
+                continue;
+            }
+            let raw = (recording.sum[i] / recording.count[i] as f64).round() as u16;
+            if let Some(meters) = coords::raw_depth_to_meters(raw) {
+                baseline.sum[i] = meters;
+                baseline.count[i] = 1;
+            }
+        }
+        baseline.frames = BASELINE_FRAMES;
+        baseline
+    }
+
+    fn meters(&self, i: usize) -> Option<f32> {
+        (self.count[i] > 0).then(|| self.sum[i] / self.count[i] as f32)
+    }
+}
+
+/// Sent once when the scene stopped matching the baseline.
+#[derive(Clone, Copy, Debug)]
+pub struct SceneChanged {
+    /// share of the view that changed for good
+    pub area: f32,
+}
+
+pub struct CalibrationPlugin;
+
+impl Plugin for CalibrationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<CalibrationSettings>()
+            .init_resource::<CalibrationRun>()
+            .init_resource::<Baseline>()
+            .add_event::<SceneChanged>()
+            .add_system(start_calibration)
+            .add_system(record_calibration.after(start_calibration))
+            .add_system(apply_calibration.after(record_calibration))
+            .add_system(detect_scene_change.after(record_calibration));
+
+        let settings = app.world.resource::<CalibrationSettings>().clone();
+        let profile = match &settings.file {
+            Some(path) if path.exists() => {
+                CalibrationProfile::load(path).unwrap_or_else(|err| panic!("{err}"))
+            }
+            _ => CalibrationProfile::default(),
+        };
+        app.insert_resource(profile);
+    }
+}
+
+fn start_calibration(
+    settings: Res<CalibrationSettings>,
+    keys: Res<Input<KeyCode>>,
+    time: Res<Time>,
+    depth_query: Query<&CurrentDepth, With<MainKinect>>,
+    mut started: Local<bool>,
+    mut current: ResMut<CalibrationRun>,
+) {
+    let depth = match depth_query.get_single() {
+        Ok(depth) if !depth.depth_array.is_empty() => depth,
+        _ => return,
+    };
+    let startup = settings.at_startup && !*started;
+    if !(keys.just_pressed(KeyCode::F9) || startup) || current.0.is_some() {
+        return;
+    }
+    *started = true;
+    info!(
+        "Calibrating noise for {} s, keep the scene empty and still",
+        settings.seconds
+    );
+    current.0 = Some(Recording::new(
+        time.elapsed_seconds_f64() + settings.seconds as f64,
+        depth.depth_array.len(),
+    ));
+}
+
+fn record_calibration(
+    settings: Res<CalibrationSettings>,
+    time: Res<Time>,
+    depth_query: Query<&CurrentDepth, With<MainKinect>>,
+    mut run: ResMut<CalibrationRun>,
+    mut profile: ResMut<CalibrationProfile>,
+    mut baseline: ResMut<Baseline>,
+    mut last_frame: Local<f64>,
+) {
+    let recording = match &mut run.0 {
+        Some(recording) => recording,
+        None => return,
+    };
+    if let Ok(depth) = depth_query.get_single() {
+        if depth.received_at != *last_frame && depth.depth_array.len() == recording.count.len() {
+            *last_frame = depth.received_at;
+            recording.frames += 1;
+            for (i, raw) in depth.depth_array.iter().enumerate() {
+                if coords::raw_depth_to_meters(*raw).is_some() {
+                    let raw = *raw as f64;
+                    recording.count[i] += 1;
+                    recording.sum[i] += raw;
+                    recording.sum_squares[i] += raw * raw;
+                }
+            }
+        }
+    }
+    if time.elapsed_seconds_f64() < recording.until {
+        return;
+    }
+
+    *profile = recording.profile();
+    *baseline = Baseline::recorded(recording);
+    run.0 = None;
+    info!(
+        "Calibrated: noise {} at 1 m, closest background raw {}",
+        profile
+            .noise_at_1m
+            .map_or("unknown".to_string(), |noise| format!(
+                "{:.1} mm",
+                noise * 1000.0
+            )),
+        profile
+            .closest_background
+            .map_or("none".to_string(), |raw| raw.to_string()),
+    );
+    match &settings.file {
+        Some(path) => profile.save(path),
+        None => warn!("No --calibration file, the calibration is only kept until exit"),
+    }
+}
+
+fn detect_scene_change(
+    settings: Res<CalibrationSettings>,
+    depth_query: Query<&CurrentDepth, (With<MainKinect>, Changed<CurrentDepth>)>,
+    mut run: ResMut<CalibrationRun>,
+    mut baseline: ResMut<Baseline>,
+    mut changes: EventWriter<SceneChanged>,
+) {
+    let depth = match depth_query.get_single() {
+        Ok(depth) if depth.depth_array.len() == coords::DEPTH_WIDTH * coords::DEPTH_HEIGHT => depth,
+        _ => return,
+    };
+    // the calibration replaces the baseline when it's done
+    if run.0.is_some() {
+        return;
+    }
+    if baseline.sum.len() != depth.depth_array.len() {
+        *baseline = Baseline::learning(depth.depth_array.len());
+    }
+    let baseline = &mut *baseline;
+    if baseline.frames < BASELINE_FRAMES {
+        baseline.frames += 1;
+        for (i, raw) in depth.depth_array.iter().enumerate() {
+            if let Some(meters) = coords::raw_depth_to_meters(*raw) {
+                baseline.sum[i] += meters;
+                baseline.count[i] += 1;
+            }
+        }
+        return;
+    }
+
+    let (blocks_x, blocks_y) = (
+        coords::DEPTH_WIDTH / CHANGE_BLOCK,
+        coords::DEPTH_HEIGHT / CHANGE_BLOCK,
+    );
+    baseline.changed_since.resize(blocks_x * blocks_y, None);
+    let now = depth.received_at;
+    let mut lasting = 0;
+    for by in 0..blocks_y {
+        for bx in 0..blocks_x {
+            let changed = (by * CHANGE_BLOCK..(by + 1) * CHANGE_BLOCK)
+                .flat_map(|y| {
+                    (bx * CHANGE_BLOCK..(bx + 1) * CHANGE_BLOCK)
+                        .map(move |x| y * coords::DEPTH_WIDTH + x)
+                })
+                .filter(|i| {
+                    // a reading appearing or going away is a change too
+                    match (
+                        coords::raw_depth_to_meters(depth.depth_array[*i]),
+                        baseline.meters(*i),
+                    ) {
+                        (Some(meters), Some(base)) => (meters - base).abs() > CHANGE_METERS,
+                        (None, None) => false,
+                        _ => true,
+                    }
+                })
+                .count();
+            let since = &mut baseline.changed_since[by * blocks_x + bx];
+            if (changed as f32) < CHANGE_BLOCK_SHARE * (CHANGE_BLOCK * CHANGE_BLOCK) as f32 {
+                *since = None;
+            } else if now - *since.get_or_insert(now) >= settings.scene_change_seconds as f64 {
+                lasting += 1;
+            }
+        }
+    }
+
+    let area = lasting as f32 / (blocks_x * blocks_y) as f32;
+    if area < SCENE_CHANGE_AREA * 0.5 {
+        baseline.reported = false;
+    }
+    if area < SCENE_CHANGE_AREA || baseline.reported {
+        return;
+    }
+    baseline.reported = true;
+    changes.send(SceneChanged { area });
+    if settings.auto_rebaseline {
+        warn!(
+            "{:.0}% of the scene changed, calibrating again for {} s",
+            area * 100.0,
+            settings.seconds
+        );
+        run.0 = Some(Recording::new(
+            now + settings.seconds as f64,
+            depth.depth_array.len(),
+        ));
+    } else {
+        warn!(
+            "{:.0}% of the scene changed since calibrating, F9 calibrates again",
+            area * 100.0
+        );
+    }
+}
+
+/// Keeps the close blob threshold below the background's noise.
+fn apply_calibration(profile: Res<CalibrationProfile>, mut tracking: ResMut<TrackingSettings>) {
+    if !profile.is_changed() {
+        return;
+    }
+    if let Some(background) = profile.closest_background {
+        if tracking.threshold > background {
+            info!(
+                "Lowering the close threshold from {} to {background}, the background is that close",
+                tracking.threshold
+            );
+            tracking.threshold = background;
+        }
+    }
+}
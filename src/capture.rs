@@ -0,0 +1,93 @@
+use std::str::FromStr;
+
+use bevy::prelude::*;
+
+/// Depth always streams at 30 Hz, whatever the format.
+pub const DEPTH_NATIVE_FPS: f32 = 30.0;
+
+/// Rate a stream is captured at. The Kinect runs every mode at a fixed rate,
+/// so lower rates are reached by only keeping every n-th frame.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CaptureRate {
+    Native,
+    Fps(f32),
+}
+
+impl CaptureRate {
+    /// How many hardware frames make up one captured frame.
+    pub fn frame_step(self, native_fps: f32) -> u32 {
+        match self {
+            CaptureRate::Native => 1,
+            CaptureRate::Fps(fps) => (native_fps / fps).round().max(1.0) as u32,
+        }
+    }
+
+    /// The rate actually achieved on a stream running at `native_fps`.
+    pub fn effective_fps(self, native_fps: f32) -> f32 {
+        native_fps / self.frame_step(native_fps) as f32
+    }
+}
+
+/// Frames per second, above 0.
+impl FromStr for CaptureRate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<f32>() {
+            Ok(fps) if fps.is_finite() && fps > 0.0 => Ok(CaptureRate::Fps(fps)),
+            _ => Err(format!(
+                "invalid rate '{s}', expected frames per second above 0"
+            )),
+        }
+    }
+}
+
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct CaptureSettings {
+    pub depth: CaptureRate,
+    pub video: CaptureRate,
+    /// Blend between the last two depth frames when rendering faster than
+    /// the capture rate. Smooth, at the cost of one frame of latency.
+    pub interpolate: bool,
+}
+
+impl Default for CaptureSettings {
+    fn default() -> Self {
+        CaptureSettings {
+            depth: CaptureRate::Native,
+            video: CaptureRate::Native,
+            interpolate: true,
+        }
+    }
+}
+
+/// Drops the frames a stream's [`CaptureRate`] asks to skip.
+#[derive(Default)]
+pub struct FrameGate {
+    to_skip: u32,
+}
+
+impl FrameGate {
+    pub fn accept(&mut self, rate: CaptureRate, native_fps: f32) -> bool {
+        if self.to_skip > 0 {
+            self.to_skip -= 1;
+            return false;
+        }
+        self.to_skip = rate.frame_step(native_fps) - 1;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rates_are_positive_frames_per_second() {
+        assert_eq!("10".parse(), Ok(CaptureRate::Fps(10.0)));
+        assert_eq!(CaptureRate::Fps(10.0).frame_step(DEPTH_NATIVE_FPS), 3);
+        for rate in ["0", "-5", "nan", "inf", "fast"] {
+            assert!(rate.parse::<CaptureRate>().is_err(), "{rate}");
+        }
+    }
+}
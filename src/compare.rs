@@ -0,0 +1,282 @@
+//! A/B comparison of tracking settings. The live [`TrackingSettings`] and
+//! [`PointerSettings`] are A; B is the same with the changes in
+//! [`CompareSettings`]. Both follow the close blob on the same frames, each
+//! with its own crosshair, A in cyan and B in orange, and a legend in the
+//! corner lists what they are set to, so a threshold or smoothing change can
+//! be tried out against the current one before keeping it.
+//!
+//! F6 turns the comparison on and off, F7 keeps B: its changes go into the
+//! live settings, and A and B are the same until something else changes.
+
+use bevy::prelude::*;
+
+use crate::capture::CaptureSettings;
+use crate::coords::DisplayRect;
+use crate::pipeline::{self, DepthPipelineApp, DepthStage};
+use crate::pointer::{PointerFilter, PointerSettings};
+use crate::presentation::DebugUi;
+use crate::{
+    ease_blob, follow_blob, view_to_world, BlobMotion, Crosshair, CurrentDepth, KinectSet,
+    MainCamera, MainKinect, TrackingSettings,
+};
+
+pub const A_COLOR: Color = Color::CYAN;
+pub const B_COLOR: Color = Color::ORANGE;
+
+/// What B does differently from the live settings, `None` for the same.
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct CompareSettings {
+    pub enabled: bool,
+    pub threshold: Option<u16>,
+    pub extrapolate: Option<bool>,
+    /// seconds, see [`PointerSettings::smoothing`]
+    pub smoothing: Option<f32>,
+    /// depth pixels, see [`PointerSettings::dead_zone`]
+    pub dead_zone: Option<f32>,
+}
+
+impl CompareSettings {
+    /// The B side of the live settings.
+    pub fn apply(
+        &self,
+        tracking: &TrackingSettings,
+        pointer: &PointerSettings,
+    ) -> (TrackingSettings, PointerSettings) {
+        let tracking = TrackingSettings {
+            threshold: self.threshold.unwrap_or(tracking.threshold),
+            extrapolate: self.extrapolate.unwrap_or(tracking.extrapolate),
+        };
+        let pointer = PointerSettings {
+            smoothing: self.smoothing.or(pointer.smoothing),
+            dead_zone: self.dead_zone.or(pointer.dead_zone),
+            ..*pointer
+        };
+        (tracking, pointer)
+    }
+}
+
+/// The close blob as B sees it.
+#[derive(Resource, Default)]
+pub struct ComparedBlob {
+    pub blob: Option<Vec2>,
+    pub motion: BlobMotion,
+}
+
+#[derive(Component)]
+struct ComparedCrosshair;
+
+#[derive(Component)]
+struct CompareLegend;
+
+pub struct ComparePlugin;
+
+impl Plugin for ComparePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<CompareSettings>()
+            .init_resource::<ComparedBlob>()
+            .add_startup_system(spawn_comparison)
+            .add_system(compare_keys)
+            .add_depth_stage(
+                DepthStage::consumer(pipeline::TrackComparison, track_comparison)
+                    .after(pipeline::TrackCloseBlob),
+            )
+            .add_system(
+                ease_comparison
+                    .label(KinectSet::Process)
+                    .after(pipeline::TrackComparison),
+            )
+            .add_system(
+                show_comparison
+                    .label(KinectSet::Output)
+                    .after(KinectSet::Process),
+            );
+    }
+}
+
+fn spawn_comparison(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.spawn((
+        SpriteBundle {
+            texture: asset_server.load("crosshair.png"),
+            sprite: Sprite {
+                color: B_COLOR,
+                ..default()
+            },
+            transform: Transform::from_xyz(0.0, 0.0, 1.0),
+            visibility: Visibility { is_visible: false },
+            ..default()
+        },
+        ComparedCrosshair,
+    ));
+
+    let style = |color| TextStyle {
+        font: asset_server.load("fonts/Hack-Regular.ttf"),
+        font_size: 14.0,
+        color,
+    };
+    commands.spawn((
+        TextBundle::from_sections([
+            TextSection::new("", style(A_COLOR)),
+            TextSection::new("", style(B_COLOR)),
+        ])
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                top: Val::Px(8.0),
+                right: Val::Px(8.0),
+                ..default()
+            },
+            ..default()
+        }),
+        CompareLegend,
+        DebugUi,
+    ));
+}
+
+fn compare_keys(
+    keys: Res<Input<KeyCode>>,
+    mut settings: ResMut<CompareSettings>,
+    mut tracking: ResMut<TrackingSettings>,
+    mut pointer: ResMut<PointerSettings>,
+) {
+    if keys.just_pressed(KeyCode::F6) {
+        settings.enabled = !settings.enabled;
+        info!(
+            "A/B comparison {}",
+            if settings.enabled { "on" } else { "off" }
+        );
+    }
+    if keys.just_pressed(KeyCode::F7) && settings.enabled {
+        (*tracking, *pointer) = settings.apply(&tracking, &pointer);
+        *settings = CompareSettings {
+            enabled: true,
+            ..default()
+        };
+        info!("Kept B: threshold {}", tracking.threshold);
+    }
+}
+
+fn track_comparison(
+    settings: Res<CompareSettings>,
+    (tracking, pointer): (Res<TrackingSettings>, Res<PointerSettings>),
+    depth_query: Query<&CurrentDepth, (With<MainKinect>, Changed<CurrentDepth>)>,
+    mut compared: ResMut<ComparedBlob>,
+) {
+    if !settings.enabled {
+        return;
+    }
+    if let Ok(depth) = depth_query.get_single() {
+        if depth.depth_array.is_empty() {
+            return;
+        }
+        let (tracking, _) = settings.apply(&tracking, &pointer);
+        compared.blob = follow_blob(depth, tracking.threshold, &mut compared.motion);
+    }
+}
+
+fn ease_comparison(
+    settings: Res<CompareSettings>,
+    (capture, tracking, pointer): (
+        Res<CaptureSettings>,
+        Res<TrackingSettings>,
+        Res<PointerSettings>,
+    ),
+    time: Res<Time>,
+    mut compared: ResMut<ComparedBlob>,
+    mut filter: Local<PointerFilter>,
+) {
+    if !settings.enabled {
+        return;
+    }
+    let (tracking, pointer) = settings.apply(&tracking, &pointer);
+    ease_blob(
+        &mut compared.motion,
+        &capture,
+        &tracking,
+        &pointer,
+        &time,
+        &mut filter,
+    );
+}
+
+/// One side of the legend.
+fn describe(side: &str, tracking: &TrackingSettings, pointer: &PointerSettings) -> String {
+    format!(
+        "{side}  threshold {}  smoothing {:.2}s  dead zone {:.1}  extrapolate {}\n",
+        tracking.threshold,
+        pointer.smoothing(),
+        pointer.dead_zone(),
+        if tracking.extrapolate { "on" } else { "off" },
+    )
+}
+
+#[allow(clippy::type_complexity)]
+fn show_comparison(
+    settings: Res<CompareSettings>,
+    (tracking, pointer, compared): (
+        Res<TrackingSettings>,
+        Res<PointerSettings>,
+        Res<ComparedBlob>,
+    ),
+    (rect, windows): (Res<DisplayRect>, Res<Windows>),
+    q_camera: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
+    mut crosshair: Query<&mut Sprite, With<Crosshair>>,
+    mut compared_crosshair: Query<(&mut Transform, &mut Visibility), With<ComparedCrosshair>>,
+    mut legend: Query<
+        (&mut Text, &mut Visibility),
+        (With<CompareLegend>, Without<ComparedCrosshair>),
+    >,
+) {
+    for mut sprite in &mut crosshair {
+        sprite.color = if settings.enabled {
+            A_COLOR
+        } else {
+            Color::WHITE
+        };
+    }
+    if let Ok((mut transform, mut visibility)) = compared_crosshair.get_single_mut() {
+        visibility.is_visible = settings.enabled && compared.motion.pointer.is_some();
+        if let (true, Some(blob)) = (settings.enabled, compared.motion.pointer) {
+            let world_pos = view_to_world(blob, &rect, &windows, q_camera.single());
+            transform.translation.x = world_pos.x;
+            transform.translation.y = world_pos.y;
+        }
+    }
+    if let Ok((mut text, mut visibility)) = legend.get_single_mut() {
+        visibility.is_visible = settings.enabled;
+        if settings.enabled {
+            let (b_tracking, b_pointer) = settings.apply(&tracking, &pointer);
+            text.sections[0].value = describe("A", &tracking, &pointer);
+            text.sections[1].value = describe("B", &b_tracking, &b_pointer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn b_is_a_with_the_changes() {
+        let tracking = TrackingSettings {
+            threshold: 400,
+            extrapolate: true,
+        };
+        let pointer = PointerSettings {
+            smoothing: Some(0.1),
+            edge_margin: 0.2,
+            ..default()
+        };
+        let settings = CompareSettings {
+            enabled: true,
+            threshold: Some(450),
+            dead_zone: Some(3.0),
+            ..default()
+        };
+        let (b_tracking, b_pointer) = settings.apply(&tracking, &pointer);
+        assert_eq!(b_tracking.threshold, 450);
+        assert!(b_tracking.extrapolate);
+        assert_eq!(b_pointer.smoothing(), 0.1);
+        assert_eq!(b_pointer.dead_zone(), 3.0);
+        assert_eq!(b_pointer.edge_margin, 0.2);
+    }
+}
@@ -0,0 +1,151 @@
+//! The picture as the app shows it, on the CPU, for saving clips of it. The
+//! window itself is only ever on the GPU, so this redoes the basic layering:
+//! the video with the depth view over it. Sprites on top, like the crosshair
+//! or props, are left out.
+
+use std::marker::PhantomData;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+
+use crate::coords::{DEPTH_HEIGHT, DEPTH_WIDTH};
+use crate::segmentation::PersonMask;
+use crate::video::{CurrentVideo, VideoSettings};
+use crate::{CurrentDepth, MainKinect};
+
+/// Frame size, the view's.
+pub const FRAME_WIDTH: u32 = DEPTH_WIDTH as u32;
+pub const FRAME_HEIGHT: u32 = DEPTH_HEIGHT as u32;
+
+const CHROMA_GREEN: [u8; 4] = [0, 177, 64, 255];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FrameSource {
+    /// the video under the depth view, as on screen
+    #[default]
+    Composite,
+    /// the video where there are people, chroma green elsewhere
+    GreenScreen,
+}
+
+impl FromStr for FrameSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "composite" => Ok(FrameSource::Composite),
+            "greenscreen" => Ok(FrameSource::GreenScreen),
+            _ => Err(format!(
+                "unknown frame source '{s}', expected composite or greenscreen"
+            )),
+        }
+    }
+}
+
+/// Sent once a clip has been written and closed.
+#[derive(Clone, Debug)]
+pub struct ClipSaved {
+    pub path: PathBuf,
+}
+
+pub struct CompositePlugin;
+
+impl Plugin for CompositePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ClipSaved>().add_system(log_saved_clips);
+    }
+}
+
+fn log_saved_clips(mut saved: EventReader<ClipSaved>) {
+    for clip in saved.iter() {
+        info!("Saved clip {}", clip.path.display());
+    }
+}
+
+#[derive(SystemParam)]
+pub(crate) struct Compositor<'w, 's> {
+    video_settings: Res<'w, VideoSettings>,
+    mask: Res<'w, PersonMask>,
+    images: Res<'w, Assets<Image>>,
+    depth_query: Query<'w, 's, &'static CurrentDepth, With<MainKinect>>,
+    video_query: Query<'w, 's, &'static CurrentVideo>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
+}
+
+impl<'w, 's> Compositor<'w, 's> {
+    /// Draws the current frame into `out` as `FRAME_WIDTH` x `FRAME_HEIGHT`
+    /// RGBA, `false` while there is no video yet.
+    pub fn frame(&self, source: FrameSource, out: &mut Vec<u8>) -> bool {
+        let video = match self
+            .video_query
+            .get_single()
+            .ok()
+            .and_then(|video| self.images.get(&video.handle))
+        {
+            Some(video) => video,
+            None => return false,
+        };
+        let mut rgba = Vec::new();
+        self.video_settings.image_to_rgba(&video.data, &mut rgba);
+        let (width, height) = self.video_settings.resolution.size();
+        let (width, height) = (width as usize, height as usize);
+        if rgba.len() != width * height * 4 {
+            return false;
+        }
+        let view = self
+            .depth_query
+            .get_single()
+            .ok()
+            .and_then(|depth| self.images.get(&depth.handle));
+
+        // the video is scaled to the view's width and pinned to its top,
+        // the depth view may be upscaled
+        let scale = width as f32 / DEPTH_WIDTH as f32;
+        out.clear();
+        out.reserve(DEPTH_WIDTH * DEPTH_HEIGHT * 4);
+        for y in 0..DEPTH_HEIGHT {
+            let vy = ((y as f32 * scale) as usize).min(height - 1);
+            for x in 0..DEPTH_WIDTH {
+                let vx = ((x as f32 * scale) as usize).min(width - 1);
+                let from = (vy * width + vx) * 4;
+                let mut pixel = [rgba[from], rgba[from + 1], rgba[from + 2], 255];
+                match source {
+                    FrameSource::GreenScreen => {
+                        let person = self
+                            .mask
+                            .0
+                            .get(y * DEPTH_WIDTH + x)
+                            .copied()
+                            .unwrap_or(false);
+                        if !person {
+                            pixel = CHROMA_GREEN;
+                        }
+                    }
+                    FrameSource::Composite => {
+                        if let Some(over) = view.and_then(|view| view_pixel(view, x, y)) {
+                            let alpha = over[3] as u16;
+                            for (under, over) in pixel.iter_mut().zip(over).take(3) {
+                                *under = ((*under as u16 * (255 - alpha) + over as u16 * alpha)
+                                    / 255) as u8;
+                            }
+                        }
+                    }
+                }
+                out.extend_from_slice(&pixel);
+            }
+        }
+        true
+    }
+}
+
+/// Depth view pixel at view position `x`, `y`, whatever size it is drawn at.
+fn view_pixel(view: &Image, x: usize, y: usize) -> Option<[u8; 4]> {
+    let size = view.texture_descriptor.size;
+    let (width, height) = (size.width as usize, size.height as usize);
+    let i = ((y * height / DEPTH_HEIGHT) * width + x * width / DEPTH_WIDTH) * 4;
+    let pixel = view.data.get(i..i + 4)?;
+    Some([pixel[0], pixel[1], pixel[2], pixel[3]])
+}
@@ -0,0 +1,178 @@
+//! How much to trust each depth reading. Structured light fails in typical
+//! places: around holes, on the edges of things where the pattern lands half
+//! on the front and half on the back, and on surfaces that flicker from one
+//! frame to the next. Each pixel's [`ConfidenceMap`] value is the product of
+//! a score for each:
+//!
+//! * neighbors, the share of the 8 around it that have a reading
+//! * edges, falling to 0 as the biggest jump to a neighbor nears
+//!   [`EDGE_METERS`]
+//! * stability, falling as the reading's spread over recent frames grows
+//!   past what the sensor's noise explains at that distance, typical or
+//!   measured by [`crate::calibration`], so things moving score low too
+//!   until they settle
+//!
+//! Holes are 0. The values are there as `f32`s for the CPU and as a 640x480
+//! `R8Unorm` texture for shaders, like the [`crate::motion`] field. They are
+//! taken from the frame as it arrives, before any inpainting.
+
+use bevy::prelude::*;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+
+use crate::calibration::CalibrationProfile;
+use crate::coords::{self, DEPTH_HEIGHT, DEPTH_WIDTH};
+use crate::pipeline::{self, DepthPipelineApp, DepthStage};
+use crate::{CurrentDepth, MainKinect};
+
+/// Depth jump to a neighbor that makes a reading worthless, in meters.
+pub const EDGE_METERS: f32 = 0.1;
+/// Spread the sensor typically shows on a still surface 1 m away, in meters.
+/// It grows with the square of the distance.
+const NOISE_AT_1M: f32 = 0.005;
+/// Weight of a new frame in the running mean and variance.
+const HISTORY_RATE: f32 = 0.2;
+
+#[derive(Resource)]
+pub struct ConfidenceMap {
+    /// 0..1 per depth pixel, row by row, empty before the first frame
+    pub values: Vec<f32>,
+    pub image: Handle<Image>,
+}
+
+impl ConfidenceMap {
+    pub fn get(&self, x: usize, y: usize) -> Option<f32> {
+        if x >= DEPTH_WIDTH {
+            return None;
+        }
+        self.values.get(y * DEPTH_WIDTH + x).copied()
+    }
+}
+
+/// Running mean and variance of a pixel's reading, in meters.
+#[derive(Clone, Copy, Default)]
+struct PixelHistory {
+    mean: f32,
+    variance: f32,
+}
+
+pub struct ConfidencePlugin;
+
+impl Plugin for ConfidencePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system(spawn_confidence_map)
+            .add_depth_stage(
+                DepthStage::filter(pipeline::UpdateConfidence, update_confidence_map)
+                    .before(pipeline::FillHoles),
+            );
+    }
+}
+
+fn spawn_confidence_map(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
+    let image = images.add(Image::new_fill(
+        Extent3d {
+            width: DEPTH_WIDTH as u32,
+            height: DEPTH_HEIGHT as u32,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[0],
+        TextureFormat::R8Unorm,
+    ));
+    commands.insert_resource(ConfidenceMap {
+        values: Vec::new(),
+        image,
+    });
+}
+
+fn update_confidence_map(
+    profile: Res<CalibrationProfile>,
+    depth_query: Query<&CurrentDepth, With<MainKinect>>,
+    mut map: ResMut<ConfidenceMap>,
+    mut images: ResMut<Assets<Image>>,
+    mut history: Local<Vec<PixelHistory>>,
+    mut meters: Local<Vec<Option<f32>>>,
+    mut last_frame: Local<f64>,
+) {
+    let depth = match depth_query.get_single() {
+        Ok(depth) if depth.received_at != *last_frame && depth.is_medium() => depth,
+        _ => return,
+    };
+    *last_frame = depth.received_at;
+    if depth.depth_array.len() != DEPTH_WIDTH * DEPTH_HEIGHT {
+        return;
+    }
+    meters.clear();
+    meters.extend(
+        depth
+            .depth_array
+            .iter()
+            .map(|raw| coords::raw_depth_to_meters(*raw)),
+    );
+    if history.len() != meters.len() {
+        *history = vec![PixelHistory::default(); meters.len()];
+    }
+
+    let noise_at_1m = profile.noise_at_1m.unwrap_or(NOISE_AT_1M);
+    let map = &mut *map;
+    map.values.resize(meters.len(), 0.0);
+    for y in 0..DEPTH_HEIGHT {
+        for x in 0..DEPTH_WIDTH {
+            let i = y * DEPTH_WIDTH + x;
+            let here = match meters[i] {
+                Some(here) => here,
+                None => {
+                    map.values[i] = 0.0;
+                    continue;
+                }
+            };
+
+            let mut neighbors = 0;
+            let mut valid = 0;
+            let mut jump: f32 = 0.0;
+            for ny in y.saturating_sub(1)..(y + 2).min(DEPTH_HEIGHT) {
+                for nx in x.saturating_sub(1)..(x + 2).min(DEPTH_WIDTH) {
+                    if (nx, ny) == (x, y) {
+                        continue;
+                    }
+                    neighbors += 1;
+                    if let Some(there) = meters[ny * DEPTH_WIDTH + nx] {
+                        valid += 1;
+                        jump = jump.max((there - here).abs());
+                    }
+                }
+            }
+            let filled = valid as f32 / neighbors as f32;
+            let edge = 1.0 - (jump / EDGE_METERS).min(1.0);
+
+            let pixel = &mut history[i];
+            if pixel.mean == 0.0 {
+                *pixel = PixelHistory {
+                    mean: here,
+                    variance: 0.0,
+                };
+            } else {
+                let delta = here - pixel.mean;
+                pixel.mean += HISTORY_RATE * delta;
+                pixel.variance =
+                    (1.0 - HISTORY_RATE) * (pixel.variance + HISTORY_RATE * delta * delta);
+            }
+            let noise = noise_at_1m * here * here;
+            let stable = 1.0 / (1.0 + pixel.variance / (noise * noise));
+
+            map.values[i] = filled * edge * stable;
+        }
+    }
+    // holes restart their history
+    for (pixel, reading) in history.iter_mut().zip(meters.iter()) {
+        if reading.is_none() {
+            *pixel = PixelHistory::default();
+        }
+    }
+
+    if let Some(image) = images.get_mut(&map.image) {
+        image.data.clear();
+        image
+            .data
+            .extend(map.values.iter().map(|value| (value * 255.0).round() as u8));
+    }
+}
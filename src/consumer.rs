@@ -0,0 +1,77 @@
+//! Raw frames for consumers that can't wait for the next Bevy frame. A
+//! [`FrameConsumer`] is handed every depth and video frame on the backend's
+//! acquisition thread the moment it arrives, before the app's systems see
+//! it, which saves up to a whole render frame for audio synthesis or a
+//! robot's control loop:
+//!
+//! ```ignore
+//! struct Theremin(Sender<f32>);
+//!
+//! impl FrameConsumer for Theremin {
+//!     fn depth(&mut self, frame: &[u16]) {
+//!         let nearest = frame.iter().min().copied().unwrap_or(NO_DEPTH);
+//!         let _ = self.0.try_send(coords::raw_depth_to_meters(nearest).unwrap_or(5.0));
+//!     }
+//! }
+//!
+//! app.add_frame_consumer(Theremin(sender));
+//! ```
+//!
+//! Frames are as the backend delivers them, before hole filling or any
+//! other stage of the [pipeline](crate::pipeline), and at medium resolution
+//! whatever [`DepthResolution`](crate::DepthResolution) is set; a Kinect v2
+//! hands out its own 512x424 unless it is set to the v1 picture. The next
+//! frame waits for every consumer, so keep them short and hand heavy work to
+//! a thread of your own. The simulated sensor has no thread, its consumers
+//! run when the app takes each frame.
+
+use std::sync::{Arc, Mutex};
+
+use bevy::prelude::*;
+
+pub trait FrameConsumer: Send + 'static {
+    /// 640x480 Bit10 readings, row by row, or 512x424 from a Kinect v2,
+    /// see [`DepthFrame::with_size`](crate::device::DepthFrame::with_size).
+    fn depth(&mut self, _frame: &[u16]) {}
+
+    /// A video frame in the format of the video settings.
+    fn video(&mut self, _frame: &[u8]) {}
+}
+
+/// The consumers, shared with the backend's thread, so ones added while it
+/// runs get the next frame.
+#[derive(Resource, Clone, Default)]
+pub struct FrameConsumers(Arc<Mutex<Vec<Box<dyn FrameConsumer>>>>);
+
+impl FrameConsumers {
+    pub fn add(&self, consumer: impl FrameConsumer) {
+        self.0.lock().unwrap().push(Box::new(consumer));
+    }
+
+    /// Hands a depth frame to every consumer, for backends to call as each
+    /// frame comes in.
+    pub fn depth(&self, frame: &[u16]) {
+        for consumer in self.0.lock().unwrap().iter_mut() {
+            consumer.depth(frame);
+        }
+    }
+
+    pub fn video(&self, frame: &[u8]) {
+        for consumer in self.0.lock().unwrap().iter_mut() {
+            consumer.video(frame);
+        }
+    }
+}
+
+pub trait FrameConsumerApp {
+    fn add_frame_consumer(&mut self, consumer: impl FrameConsumer) -> &mut Self;
+}
+
+impl FrameConsumerApp for App {
+    fn add_frame_consumer(&mut self, consumer: impl FrameConsumer) -> &mut Self {
+        self.world
+            .get_resource_or_insert_with(FrameConsumers::default)
+            .add(consumer);
+        self
+    }
+}
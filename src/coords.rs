@@ -0,0 +1,513 @@
+//! Conversions between the coordinate systems in play:
+//!
+//! * depth / color pixel: image coordinates, origin top left, y down
+//! * sensor space: meters from the depth camera, x right, y up, z forward
+//! * view: the 640x480 frame the depth image and video are drawn into,
+//!   placed in the window by a [`DisplayRect`]
+//! * screen: window pixels as bevy reports the cursor, origin bottom left
+//! * world: bevy world space as seen through a camera
+//!
+//! Points handed out of the app, readouts and exported skeletons and point
+//! clouds, are sensor space rewritten in the [`WorldConvention`] asked for,
+//! so they drop into scenes with another up axis, unit or handedness.
+
+use std::str::FromStr;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::NO_DEPTH;
+
+pub const DEPTH_WIDTH: usize = 640;
+pub const DEPTH_HEIGHT: usize = 480;
+
+/// Size of the view in image pixels; depth fills it exactly.
+pub const VIEW_SIZE: Vec2 = Vec2::new(DEPTH_WIDTH as f32, DEPTH_HEIGHT as f32);
+
+/// Pinhole model of one of the cameras, in pixels.
+#[derive(Clone, Copy, Debug)]
+pub struct Intrinsics {
+    pub fx: f32,
+    pub fy: f32,
+    pub cx: f32,
+    pub cy: f32,
+}
+
+/// Factory-typical values, close enough without a per-device calibration.
+pub const DEPTH_INTRINSICS: Intrinsics = Intrinsics {
+    fx: 594.21,
+    fy: 591.04,
+    cx: 339.5,
+    cy: 242.7,
+};
+
+pub const COLOR_INTRINSICS: Intrinsics = Intrinsics {
+    fx: 529.22,
+    fy: 525.56,
+    cx: 328.94,
+    cy: 267.48,
+};
+
+/// Position of the color camera in sensor space; it sits 2.5 cm to the
+/// right of the depth camera (as seen from behind the sensor).
+pub const COLOR_OFFSET: Vec3 = Vec3::new(0.025, 0.0, 0.0);
+
+impl Intrinsics {
+    pub fn unproject(&self, pixel: Vec2, meters: f32) -> Vec3 {
+        Vec3::new(
+            (pixel.x - self.cx) * meters / self.fx,
+            (self.cy - pixel.y) * meters / self.fy,
+            meters,
+        )
+    }
+
+    pub fn project(&self, point: Vec3) -> Vec2 {
+        Vec2::new(
+            point.x * self.fx / point.z + self.cx,
+            self.cy - point.y * self.fy / point.z,
+        )
+    }
+}
+
+/// Bit10 values share the scale of the 11-bit disparity, just capped at
+/// 1023, which keeps about 5 m of range.
+pub fn raw_depth_to_meters(raw: u16) -> Option<f32> {
+    if raw == NO_DEPTH {
+        return None;
+    }
+    Some(1.0 / (raw as f32 * -0.003_071_1 + 3.330_949_5))
+}
+
+/// The raw reading a Kinect would give for a distance, for depth from other
+/// sensors. `NO_DEPTH` outside the range 10 bits can hold.
+pub fn meters_to_raw_depth(meters: f32) -> u16 {
+    if meters <= 0.0 {
+        return NO_DEPTH;
+    }
+    let raw = ((3.330_949_5 - 1.0 / meters) / 0.003_071_1).round();
+    if (0.0..1024.0).contains(&raw) {
+        raw as u16
+    } else {
+        NO_DEPTH
+    }
+}
+
+/// For each pixel of a `width` x `height` picture seen through `to`, the
+/// pixel of the `from_width` x `from_height` picture seen through `from`
+/// along the same ray, for resampling other sensors onto the Kinect's.
+#[cfg(any(feature = "freenect2", feature = "openni2"))]
+pub(crate) fn ray_lookup(
+    to: &Intrinsics,
+    (width, height): (usize, usize),
+    from: &Intrinsics,
+    (from_width, from_height): (usize, usize),
+    mirrored: bool,
+) -> Vec<Option<usize>> {
+    (0..width * height)
+        .map(|i| {
+            let pixel = Vec2::new((i % width) as f32, (i / width) as f32);
+            let there = from.project(to.unproject(pixel, 1.0));
+            let x = if mirrored {
+                from_width as f32 - 1.0 - there.x.round()
+            } else {
+                there.x.round()
+            };
+            let y = there.y.round();
+            ((0.0..from_width as f32).contains(&x) && (0.0..from_height as f32).contains(&y))
+                .then_some(y as usize * from_width + x as usize)
+        })
+        .collect()
+}
+
+pub fn depth_pixel_to_sensor(pixel: Vec2, meters: f32) -> Vec3 {
+    DEPTH_INTRINSICS.unproject(pixel, meters)
+}
+
+pub fn sensor_to_color_pixel(point: Vec3) -> Vec2 {
+    COLOR_INTRINSICS.project(point - COLOR_OFFSET)
+}
+
+pub fn depth_pixel_to_color_pixel(pixel: Vec2, meters: f32) -> Vec2 {
+    sensor_to_color_pixel(depth_pixel_to_sensor(pixel, meters))
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UpAxis {
+    #[default]
+    Y,
+    Z,
+}
+
+impl FromStr for UpAxis {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "y" => Ok(UpAxis::Y),
+            "z" => Ok(UpAxis::Z),
+            _ => Err(format!("unknown up axis '{s}', expected y or z")),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Units {
+    #[default]
+    Meters,
+    Millimeters,
+}
+
+impl Units {
+    pub fn per_meter(self) -> f32 {
+        match self {
+            Units::Meters => 1.0,
+            Units::Millimeters => 1000.0,
+        }
+    }
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Units::Meters => "m",
+            Units::Millimeters => "mm",
+        }
+    }
+}
+
+impl FromStr for Units {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "m" | "meters" => Ok(Units::Meters),
+            "mm" | "millimeters" => Ok(Units::Millimeters),
+            _ => Err(format!("unknown units '{s}', expected m or mm")),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Handedness {
+    /// like sensor space and Unity
+    #[default]
+    Left,
+    /// like bevy, Blender and ROS
+    Right,
+}
+
+impl FromStr for Handedness {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "left" => Ok(Handedness::Left),
+            "right" => Ok(Handedness::Right),
+            _ => Err(format!("unknown handedness '{s}', expected left or right")),
+        }
+    }
+}
+
+/// Axes and units points leave the app in. x is always to the sensor's
+/// right; the up axis is y or z, and the remaining axis is the sensor's
+/// forward or backward, whichever gives the handedness:
+///
+/// | up | left-handed | right-handed |
+/// |----|-------------|--------------|
+/// | y  | z forward   | z backward   |
+/// | z  | y backward  | y forward    |
+///
+/// The default, y up, meters, left-handed, is sensor space unchanged.
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct WorldConvention {
+    pub up: UpAxis,
+    pub units: Units,
+    pub handedness: Handedness,
+}
+
+impl WorldConvention {
+    /// A sensor space point, in meters, in this convention.
+    pub fn world_point(&self, point: Vec3) -> Vec3 {
+        let forward = match (self.up, self.handedness) {
+            (UpAxis::Y, Handedness::Left) | (UpAxis::Z, Handedness::Right) => point.z,
+            (UpAxis::Y, Handedness::Right) | (UpAxis::Z, Handedness::Left) => -point.z,
+        };
+        let axes = match self.up {
+            UpAxis::Y => Vec3::new(point.x, point.y, forward),
+            UpAxis::Z => Vec3::new(point.x, forward, point.y),
+        };
+        axes * self.units.per_meter()
+    }
+}
+
+/// How the view is fitted into a window of a different size.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FitMode {
+    /// as large as fits, keeping the aspect ratio, centered
+    #[default]
+    Letterbox,
+    /// covers the whole window, distorting if the aspect ratio differs
+    Stretch,
+}
+
+impl FromStr for FitMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "letterbox" => Ok(FitMode::Letterbox),
+            "stretch" => Ok(FitMode::Stretch),
+            _ => Err(format!(
+                "unknown fit mode '{s}', expected letterbox or stretch"
+            )),
+        }
+    }
+}
+
+/// Where the view sits in the window, in screen pixels.
+#[derive(Resource, Clone, Copy, Debug, PartialEq)]
+pub struct DisplayRect {
+    /// bottom left corner
+    pub min: Vec2,
+    pub size: Vec2,
+}
+
+impl Default for DisplayRect {
+    fn default() -> Self {
+        DisplayRect {
+            min: Vec2::ZERO,
+            size: VIEW_SIZE,
+        }
+    }
+}
+
+impl DisplayRect {
+    pub fn fit(window: Vec2, fit: FitMode) -> DisplayRect {
+        let size = match fit {
+            FitMode::Letterbox => VIEW_SIZE * (window / VIEW_SIZE).min_element(),
+            FitMode::Stretch => window,
+        };
+        DisplayRect {
+            min: (window - size) / 2.0,
+            size,
+        }
+    }
+
+    /// Screen pixels per view pixel.
+    pub fn scale(&self) -> Vec2 {
+        self.size / VIEW_SIZE
+    }
+
+    pub fn image_to_screen(&self, pixel: Vec2) -> Vec2 {
+        let scaled = pixel * self.scale();
+        Vec2::new(self.min.x + scaled.x, self.min.y + self.size.y - scaled.y)
+    }
+
+    /// Inverse of [`DisplayRect::image_to_screen`], `None` outside the view.
+    pub fn screen_to_image(&self, screen: Vec2) -> Option<Vec2> {
+        let from_top_left = Vec2::new(screen.x - self.min.x, self.min.y + self.size.y - screen.y);
+        let pixel = from_top_left / self.scale();
+        let inside = pixel.cmpge(Vec2::ZERO).all() && pixel.cmplt(VIEW_SIZE).all();
+        inside.then_some(pixel)
+    }
+
+    /// Center of the view in world space, for a 2D camera at the origin.
+    pub fn world_center(&self, window: Vec2) -> Vec2 {
+        self.min + self.size / 2.0 - window / 2.0
+    }
+}
+
+/// Matrix for undoing the projection and camera transform.
+pub fn ndc_to_world(camera: &Camera, camera_transform: &GlobalTransform) -> Mat4 {
+    camera_transform.compute_matrix() * camera.projection_matrix().inverse()
+}
+
+/// Screen position to world space, on the camera's near plane.
+pub fn screen_to_world(screen: Vec2, window_size: Vec2, ndc_to_world: Mat4) -> Vec2 {
+    // convert screen position [0..resolution] to ndc [-1..1] (gpu coordinates)
+    let ndc = (screen / window_size) * 2.0 - Vec2::ONE;
+    ndc_to_world.project_point3(ndc.extend(-1.0)).truncate()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-4, "{a} != {b}");
+    }
+
+    #[test]
+    fn principal_point_is_on_the_optical_axis() {
+        let center = Vec2::new(DEPTH_INTRINSICS.cx, DEPTH_INTRINSICS.cy);
+        assert_close(depth_pixel_to_sensor(center, 2.0), Vec3::new(0.0, 0.0, 2.0));
+    }
+
+    #[test]
+    fn depth_pixels_round_trip_through_sensor_space() {
+        for pixel in [Vec2::ZERO, Vec2::new(639.0, 479.0), Vec2::new(100.0, 400.0)] {
+            let point = depth_pixel_to_sensor(pixel, 1.5);
+            assert!((DEPTH_INTRINSICS.project(point) - pixel).length() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn sensor_space_is_y_up() {
+        let above = depth_pixel_to_sensor(Vec2::new(DEPTH_INTRINSICS.cx, 0.0), 1.0);
+        assert!(above.y > 0.0);
+        let right = depth_pixel_to_sensor(Vec2::new(639.0, DEPTH_INTRINSICS.cy), 1.0);
+        assert!(right.x > 0.0);
+    }
+
+    #[test]
+    fn color_camera_sees_points_shifted_left() {
+        let point = Vec3::new(0.0, 0.0, 1.0);
+        let pixel = sensor_to_color_pixel(point);
+        assert!(pixel.x < COLOR_INTRINSICS.cx);
+        assert!((pixel.y - COLOR_INTRINSICS.cy).abs() < 1e-3);
+    }
+
+    #[test]
+    fn raw_depth_grows_with_distance() {
+        assert_eq!(raw_depth_to_meters(NO_DEPTH), None);
+        let near = raw_depth_to_meters(400).unwrap();
+        let far = raw_depth_to_meters(900).unwrap();
+        assert!(0.4 < near && near < far && far < 5.5);
+    }
+
+    #[test]
+    fn meters_round_trip_through_raw_depth() {
+        for meters in [0.6, 1.0, 2.5, 4.0] {
+            let raw = meters_to_raw_depth(meters);
+            let back = raw_depth_to_meters(raw).unwrap();
+            // one raw step is a few cm at 4 m
+            assert!((back - meters).abs() < 0.03, "{meters} came back as {back}");
+        }
+        assert_eq!(meters_to_raw_depth(0.0), NO_DEPTH);
+        assert_eq!(meters_to_raw_depth(20.0), NO_DEPTH);
+    }
+
+    #[test]
+    fn depth_formats_share_the_bit10_scale() {
+        use crate::DepthFormat;
+
+        let mm = DepthFormat::Millimeters;
+        assert_eq!(mm.to_bit10(0), NO_DEPTH);
+        assert_eq!(mm.to_bit10(1500), meters_to_raw_depth(1.5));
+        assert_eq!(mm.from_bit10(NO_DEPTH), 0);
+        let back = mm.from_bit10(mm.to_bit10(2000));
+        assert!(back.abs_diff(2000) < 20, "2000 mm came back as {back}");
+        assert_eq!(DepthFormat::Bit11.to_bit10(2047), NO_DEPTH);
+        assert_eq!(DepthFormat::Bit11.from_bit10(NO_DEPTH), 2047);
+        assert_eq!(DepthFormat::Bit11.to_bit10(400), 400);
+
+        // frames that came in millimeters keep them
+        let readings = vec![2000; DEPTH_WIDTH * DEPTH_HEIGHT];
+        let data = readings
+            .iter()
+            .map(|reading| mm.to_bit10(*reading))
+            .collect();
+        let frame = crate::device::DepthFrame::new(data, 1).with_raw(mm, readings.clone());
+        let mut depth = crate::CurrentDepth::new(DEPTH_WIDTH, DEPTH_HEIGHT, default());
+        depth.receive(frame, DEPTH_WIDTH, DEPTH_HEIGHT, 0.0);
+        assert_eq!(depth.millimeters(), readings);
+
+        // a sensor of another size keeps it, whatever the resolution
+        let frame =
+            crate::device::DepthFrame::new(vec![NO_DEPTH; 512 * 424], 2).with_size(512, 424);
+        depth.receive(frame, DEPTH_WIDTH / 2, DEPTH_HEIGHT / 2, 0.0);
+        assert_eq!(
+            (depth.width, depth.height, depth.depth_array.len()),
+            (512, 424, 512 * 424)
+        );
+        assert!(!depth.is_medium());
+    }
+
+    #[test]
+    fn conventions_move_the_axes() {
+        let point = Vec3::new(0.1, 0.2, 2.0);
+        assert_eq!(WorldConvention::default().world_point(point), point);
+
+        let bevy = WorldConvention {
+            handedness: Handedness::Right,
+            ..default()
+        };
+        assert_eq!(bevy.world_point(point), Vec3::new(0.1, 0.2, -2.0));
+
+        let blender = WorldConvention {
+            up: UpAxis::Z,
+            units: Units::Millimeters,
+            handedness: Handedness::Right,
+        };
+        assert_close(blender.world_point(point), Vec3::new(100.0, 2000.0, 200.0));
+    }
+
+    #[test]
+    fn every_convention_keeps_its_handedness() {
+        for up in [UpAxis::Y, UpAxis::Z] {
+            for handedness in [Handedness::Left, Handedness::Right] {
+                let convention = WorldConvention {
+                    up,
+                    units: Units::Meters,
+                    handedness,
+                };
+                let [x, y, z] =
+                    [Vec3::X, Vec3::Y, Vec3::Z].map(|axis| convention.world_point(axis));
+                // mirroring the axes turns their triple product negative
+                let flipped = x.cross(y).dot(z) < 0.0;
+                assert_eq!(flipped, handedness == Handedness::Right, "{convention:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn screen_flips_y() {
+        let rect = DisplayRect::default();
+        let pixel = Vec2::new(10.0, 20.0);
+        let screen = rect.image_to_screen(pixel);
+        assert_eq!(screen, Vec2::new(10.0, 460.0));
+        assert_eq!(rect.screen_to_image(screen), Some(pixel));
+    }
+
+    #[test]
+    fn letterbox_keeps_the_aspect_ratio() {
+        let rect = DisplayRect::fit(Vec2::new(1920.0, 1080.0), FitMode::Letterbox);
+        assert_eq!(rect.size, Vec2::new(1440.0, 1080.0));
+        assert_eq!(rect.min, Vec2::new(240.0, 0.0));
+        assert_eq!(rect.scale(), Vec2::splat(2.25));
+
+        // the bars are outside the view
+        assert_eq!(rect.screen_to_image(Vec2::new(100.0, 500.0)), None);
+        assert_eq!(rect.image_to_screen(Vec2::ZERO), Vec2::new(240.0, 1080.0));
+        assert_eq!(rect.world_center(Vec2::new(1920.0, 1080.0)), Vec2::ZERO);
+    }
+
+    #[test]
+    fn stretch_fills_the_window() {
+        let window = Vec2::new(800.0, 300.0);
+        let rect = DisplayRect::fit(window, FitMode::Stretch);
+        assert_eq!(rect.min, Vec2::ZERO);
+        assert_eq!(rect.size, window);
+
+        let screen = rect.image_to_screen(Vec2::new(320.0, 240.0));
+        assert_eq!(screen, Vec2::new(400.0, 150.0));
+        let pixel = rect.screen_to_image(Vec2::new(800.0 - 1.0, 1.0)).unwrap();
+        assert!((pixel - Vec2::new(639.2, 478.4)).length() < 1e-3);
+    }
+
+    #[test]
+    fn screen_maps_onto_a_centered_2d_camera() {
+        // what `Camera2dBundle::default()` ends up with in a 640x480 window
+        let projection = Mat4::orthographic_rh(-320.0, 320.0, -240.0, 240.0, 0.0, 1000.0);
+        let transform = Mat4::from_translation(Vec3::new(0.0, 0.0, 999.9));
+        let ndc_to_world = transform * projection.inverse();
+        let window = Vec2::new(640.0, 480.0);
+
+        let world = screen_to_world(Vec2::new(320.0, 240.0), window, ndc_to_world);
+        assert!(world.length() < 1e-3);
+        let world = screen_to_world(Vec2::new(640.0, 480.0), window, ndc_to_world);
+        assert!((world - Vec2::new(320.0, 240.0)).length() < 1e-3);
+    }
+}
@@ -0,0 +1,385 @@
+//! Dataset capture for training models on Kinect data. At a fixed interval
+//! the current depth and video frames are saved as PNGs, together with what
+//! the tracker made of them: the close blob's box, its center and its mask.
+//! Annotations follow the COCO layout, so the usual tooling can read them.
+//!
+//! Each run goes to `<dir>/dataset-<start>/`, with `depth/` holding the raw
+//! 16-bit readings, `color/` the video frames and `annotations.json`. Boxes
+//! and masks are in depth pixels; the color camera sits a little to the side,
+//! see [`crate::coords`] to map between the two.
+//!
+//! Skeleton joints, and with `points` set a `points/` PLY point cloud per
+//! sample, are in the [`WorldConvention`] the app runs with, which the info
+//! block records.
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use serde::Serialize;
+
+use crate::analytics::unix_now;
+use crate::coords::{self, WorldConvention, DEPTH_HEIGHT, DEPTH_WIDTH};
+use crate::quality::QualityLevel;
+use crate::skeleton::{Joint, Skeleton};
+use crate::video::{CurrentVideo, VideoSettings};
+use crate::{close_blob_bounds, CurrentDepth, MainKinect, TrackingSettings};
+
+/// Samples between rewrites of `annotations.json`, besides the one on exit.
+const SAVE_EVERY: usize = 20;
+
+#[derive(Resource, Clone, Debug)]
+pub struct DatasetSettings {
+    /// directory to capture to, capture is off without one
+    pub dir: Option<PathBuf>,
+    /// seconds between samples
+    pub interval: f32,
+    /// also save each depth frame as a point cloud
+    pub points: bool,
+}
+
+impl Default for DatasetSettings {
+    fn default() -> Self {
+        DatasetSettings {
+            dir: None,
+            interval: 1.0,
+            points: false,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Coco {
+    info: Info,
+    images: Vec<CocoImage>,
+    annotations: Vec<CocoAnnotation>,
+    categories: Vec<Category>,
+}
+
+#[derive(Serialize)]
+struct Info {
+    description: &'static str,
+    /// unix time in seconds
+    started_at: f64,
+    /// raw depth below which pixels belong to the blob
+    threshold: u16,
+    /// axes and units of joints and point clouds
+    coordinates: WorldConvention,
+}
+
+#[derive(Serialize)]
+struct CocoImage {
+    id: usize,
+    file_name: String,
+    /// raw Bit10 readings of the same moment, 1023 where there is none
+    depth_file_name: String,
+    width: u32,
+    height: u32,
+    /// unix time in seconds
+    captured_at: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    points_file_name: Option<String>,
+    /// joints of the tracked person, empty while nobody is
+    skeleton: Vec<SampleJoint>,
+}
+
+#[derive(Serialize)]
+struct SampleJoint {
+    joint: Joint,
+    position: [f32; 3],
+    confidence: f32,
+}
+
+#[derive(Serialize)]
+struct CocoAnnotation {
+    id: usize,
+    image_id: usize,
+    category_id: usize,
+    /// x, y, width, height
+    bbox: [u16; 4],
+    area: usize,
+    /// where the crosshair goes
+    center: [f32; 2],
+    segmentation: Rle,
+    iscrowd: u8,
+}
+
+#[derive(Serialize)]
+struct Category {
+    id: usize,
+    name: &'static str,
+}
+
+/// Uncompressed COCO run-length encoding: alternating runs of outside and
+/// inside pixels, column by column, starting with outside.
+#[derive(Serialize)]
+struct Rle {
+    counts: Vec<u32>,
+    /// height, width
+    size: [usize; 2],
+}
+
+#[derive(Resource)]
+struct Dataset {
+    dir: PathBuf,
+    coco: Coco,
+    timer: Timer,
+}
+
+pub struct DatasetPlugin;
+
+impl Plugin for DatasetPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<DatasetSettings>()
+            .add_startup_system(start_dataset)
+            .add_system(capture_samples)
+            .add_system_to_stage(CoreStage::Last, save_on_exit);
+    }
+}
+
+fn start_dataset(
+    mut commands: Commands,
+    settings: Res<DatasetSettings>,
+    tracking: Res<TrackingSettings>,
+    convention: Res<WorldConvention>,
+) {
+    let dir = match &settings.dir {
+        Some(dir) => dir,
+        None => return,
+    };
+    let started_at = unix_now();
+    let dir = dir.join(format!("dataset-{started_at:.0}"));
+    let mut created =
+        fs::create_dir_all(dir.join("depth")).and(fs::create_dir_all(dir.join("color")));
+    if settings.points {
+        created = created.and(fs::create_dir_all(dir.join("points")));
+    }
+    if let Err(err) = created {
+        error!("Unable to create {}: {err}", dir.display());
+        return;
+    }
+    info!("Capturing a dataset to {}", dir.display());
+
+    commands.insert_resource(Dataset {
+        dir,
+        coco: Coco {
+            info: Info {
+                description: "bevy-kinect close blob",
+                started_at,
+                threshold: tracking.threshold,
+                coordinates: *convention,
+            },
+            images: Vec::new(),
+            annotations: Vec::new(),
+            categories: vec![Category {
+                id: 1,
+                name: "close_blob",
+            }],
+        },
+        timer: Timer::from_seconds(settings.interval, TimerMode::Repeating),
+    });
+}
+
+fn capture_samples(
+    dataset: Option<ResMut<Dataset>>,
+    time: Res<Time>,
+    (settings, tracking, convention, skeleton): (
+        Res<DatasetSettings>,
+        Res<TrackingSettings>,
+        Res<WorldConvention>,
+        Res<Skeleton>,
+    ),
+    (video_settings, quality): (Res<VideoSettings>, Res<QualityLevel>),
+    depth_query: Query<&CurrentDepth, With<MainKinect>>,
+    video_query: Query<&CurrentVideo>,
+    images: Res<Assets<Image>>,
+) {
+    let mut dataset = match dataset {
+        Some(dataset) => dataset,
+        None => return,
+    };
+    if !dataset.timer.tick(time.delta()).just_finished() {
+        return;
+    }
+    let depth = match depth_query.get_single() {
+        Ok(depth) if depth.is_medium() => &depth.depth_array,
+        _ => return,
+    };
+    let video = match video_query
+        .get_single()
+        .map(|video| images.get(&video.handle))
+    {
+        Ok(Some(video)) => video,
+        _ => return,
+    };
+
+    let id = dataset.coco.images.len() + 1;
+    let (width, height) = video_settings.resolution.size();
+    let mut rgba = Vec::new();
+    video_settings.image_to_rgba(&video.data, &mut rgba);
+    let image = CocoImage {
+        id,
+        file_name: format!("color/{id:06}.png"),
+        depth_file_name: format!("depth/{id:06}.png"),
+        width,
+        height,
+        captured_at: unix_now(),
+        points_file_name: settings.points.then(|| format!("points/{id:06}.ply")),
+        skeleton: sample_joints(&skeleton.in_world(&convention)),
+    };
+    let mut written = write_depth_png(&dataset.dir.join(&image.depth_file_name), depth)
+        .and_then(|_| write_color_png(&dataset.dir.join(&image.file_name), width, height, &rgba));
+    if let Some(points) = &image.points_file_name {
+        let path = dataset.dir.join(points);
+        written = written
+            .and_then(|_| write_points_ply(&path, depth, &convention, quality.point_stride()));
+    }
+    if let Err(err) = written {
+        error!("Unable to write dataset sample {id}: {err}");
+        return;
+    }
+
+    if let Some(bounds) = close_blob_bounds(depth, DEPTH_WIDTH, tracking.threshold) {
+        let center = bounds.center();
+        let (segmentation, area) = mask_rle(depth, tracking.threshold);
+        let annotation = CocoAnnotation {
+            id: dataset.coco.annotations.len() + 1,
+            image_id: id,
+            category_id: 1,
+            bbox: [
+                bounds.left,
+                bounds.top,
+                bounds.right - bounds.left + 1,
+                bounds.bottom - bounds.top + 1,
+            ],
+            area,
+            center: center.into(),
+            segmentation,
+            iscrowd: 0,
+        };
+        dataset.coco.annotations.push(annotation);
+    }
+    dataset.coco.images.push(image);
+
+    if dataset.coco.images.len() % SAVE_EVERY == 0 {
+        save(&dataset);
+    }
+}
+
+fn save_on_exit(exit: EventReader<AppExit>, dataset: Option<Res<Dataset>>) {
+    if let Some(dataset) = dataset {
+        if !exit.is_empty() {
+            save(&dataset);
+        }
+    }
+}
+
+fn save(dataset: &Dataset) {
+    let path = dataset.dir.join("annotations.json");
+    let written = File::create(&path)
+        .map_err(serde_json::Error::io)
+        .and_then(|file| serde_json::to_writer(BufWriter::new(file), &dataset.coco));
+    if let Err(err) = written {
+        error!("Unable to write {}: {err}", path.display());
+    }
+}
+
+fn sample_joints(skeleton: &Skeleton) -> Vec<SampleJoint> {
+    Joint::ALL
+        .iter()
+        .filter_map(|joint| {
+            skeleton.get(*joint).map(|tracked| SampleJoint {
+                joint: *joint,
+                position: tracked.position.into(),
+                confidence: tracked.confidence,
+            })
+        })
+        .collect()
+}
+
+/// Pixels closer than `threshold` as COCO RLE, and how many there are.
+fn mask_rle(depth: &[u16], threshold: u16) -> (Rle, usize) {
+    let (width, height) = (DEPTH_WIDTH, DEPTH_HEIGHT);
+    let mut counts = Vec::new();
+    let mut inside = false;
+    let mut run = 0;
+    let mut area = 0;
+    for x in 0..width {
+        for y in 0..height {
+            let close = depth[y * width + x] < threshold;
+            if close != inside {
+                counts.push(run);
+                inside = close;
+                run = 0;
+            }
+            run += 1;
+            area += close as usize;
+        }
+    }
+    counts.push(run);
+    (
+        Rle {
+            counts,
+            size: [height, width],
+        },
+        area,
+    )
+}
+
+/// A 640x480 depth frame as a 16-bit grayscale PNG, raw readings as they
+/// are, what `replay` reads back.
+pub fn write_depth_png(path: &Path, depth: &[u16]) -> io::Result<()> {
+    let bytes: Vec<u8> = depth.iter().flat_map(|raw| raw.to_be_bytes()).collect();
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(path)?),
+        DEPTH_WIDTH as u32,
+        DEPTH_HEIGHT as u32,
+    );
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Sixteen);
+    encoder.write_header()?.write_image_data(&bytes)?;
+    Ok(())
+}
+
+pub fn write_color_png(path: &Path, width: u32, height: u32, rgba: &[u8]) -> io::Result<()> {
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(rgba)?;
+    Ok(())
+}
+
+/// Every `stride`-th pixel with a reading as a point, binary little endian
+/// PLY.
+fn write_points_ply(
+    path: &Path,
+    depth: &[u16],
+    convention: &WorldConvention,
+    stride: usize,
+) -> io::Result<()> {
+    let points: Vec<Vec3> = depth
+        .iter()
+        .enumerate()
+        .step_by(stride)
+        .filter_map(|(i, raw)| {
+            let meters = coords::raw_depth_to_meters(*raw)?;
+            let pixel = Vec2::new((i % DEPTH_WIDTH) as f32, (i / DEPTH_WIDTH) as f32);
+            Some(convention.world_point(coords::depth_pixel_to_sensor(pixel, meters)))
+        })
+        .collect();
+    let mut file = BufWriter::new(File::create(path)?);
+    write!(
+        file,
+        "ply\nformat binary_little_endian 1.0\nelement vertex {}\n\
+         property float x\nproperty float y\nproperty float z\nend_header\n",
+        points.len()
+    )?;
+    for point in points {
+        for value in point.to_array() {
+            file.write_all(&value.to_le_bytes())?;
+        }
+    }
+    file.flush()
+}
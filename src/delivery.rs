@@ -0,0 +1,150 @@
+//! Hands saved clips to visitors: every [`ClipSaved`] is shared through the
+//! remote control server and its link shown as a QR code in the corner for
+//! a while, so a phone camera is all it takes to download it. Nothing leaves
+//! the local network.
+
+use bevy::prelude::*;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+use qrcode::{Color as Module, QrCode};
+
+use crate::composite::ClipSaved;
+use crate::remote::RemoteServer;
+
+/// Seconds the code stays up.
+const SHOW_SECONDS: f32 = 30.0;
+/// Pixels per QR module.
+const MODULE_PIXELS: usize = 6;
+/// Light border around the code, in modules, scanners need it.
+const QUIET_ZONE: usize = 4;
+
+#[derive(Component)]
+struct QrCard {
+    hide_at: f64,
+}
+
+#[derive(Component)]
+struct QrImage;
+
+pub struct DeliveryPlugin;
+
+impl Plugin for DeliveryPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system(spawn_qr_card)
+            .add_system(show_clip_links)
+            .add_system(hide_qr_card.after(show_clip_links));
+    }
+}
+
+fn spawn_qr_card(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: UiRect {
+                        right: Val::Px(16.0),
+                        bottom: Val::Px(16.0),
+                        ..default()
+                    },
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    padding: UiRect::all(Val::Px(8.0)),
+                    ..default()
+                },
+                background_color: Color::WHITE.into(),
+                visibility: Visibility { is_visible: false },
+                ..default()
+            },
+            QrCard { hide_at: 0.0 },
+        ))
+        .with_children(|card| {
+            card.spawn(TextBundle::from_section(
+                "Scan for your clip",
+                TextStyle {
+                    font: asset_server.load("fonts/Hack-Regular.ttf"),
+                    font_size: 16.0,
+                    color: Color::BLACK,
+                },
+            ));
+            card.spawn((ImageBundle::default(), QrImage));
+        });
+}
+
+fn show_clip_links(
+    server: Option<Res<RemoteServer>>,
+    time: Res<Time>,
+    mut saved: EventReader<ClipSaved>,
+    mut images: ResMut<Assets<Image>>,
+    mut card_query: Query<(&mut QrCard, &mut Visibility)>,
+    mut image_query: Query<(&mut UiImage, &mut Style), With<QrImage>>,
+) {
+    let server = match server {
+        Some(server) => server,
+        None => return,
+    };
+    let mut urls: Vec<String> = saved
+        .iter()
+        .filter_map(|clip| server.share(&clip.path))
+        .collect();
+    // only the latest clip gets a code, older ones are still served
+    let url = match urls.pop() {
+        Some(url) => url,
+        None => return,
+    };
+    let image = match qr_image(&url) {
+        Ok(image) => image,
+        Err(err) => {
+            error!("Unable to make a QR code for {url}: {err}");
+            return;
+        }
+    };
+    info!("Clip available at {url}");
+    let size = image.texture_descriptor.size;
+    for (mut qr, mut style) in image_query.iter_mut() {
+        // shown at its own size, scaling blurs the modules
+        style.size = Size::new(Val::Px(size.width as f32), Val::Px(size.height as f32));
+        qr.0 = images.add(image.clone());
+    }
+    for (mut card, mut visibility) in card_query.iter_mut() {
+        card.hide_at = time.elapsed_seconds_f64() + SHOW_SECONDS as f64;
+        visibility.is_visible = true;
+    }
+}
+
+fn hide_qr_card(time: Res<Time>, mut card_query: Query<(&QrCard, &mut Visibility)>) {
+    for (card, mut visibility) in card_query.iter_mut() {
+        if visibility.is_visible && time.elapsed_seconds_f64() >= card.hide_at {
+            visibility.is_visible = false;
+        }
+    }
+}
+
+fn qr_image(url: &str) -> Result<Image, qrcode::types::QrError> {
+    let code = QrCode::new(url.as_bytes())?;
+    let modules = code.to_colors();
+    let width = code.width();
+    let side = (width + QUIET_ZONE * 2) * MODULE_PIXELS;
+    let mut data = vec![255; side * side * 4];
+    for (i, module) in modules.iter().enumerate() {
+        if *module == Module::Light {
+            continue;
+        }
+        let (x, y) = (i % width + QUIET_ZONE, i / width + QUIET_ZONE);
+        for row in y * MODULE_PIXELS..(y + 1) * MODULE_PIXELS {
+            let start = (row * side + x * MODULE_PIXELS) * 4;
+            for pixel in data[start..start + MODULE_PIXELS * 4].chunks_exact_mut(4) {
+                pixel.copy_from_slice(&[0, 0, 0, 255]);
+            }
+        }
+    }
+    Ok(Image::new(
+        Extent3d {
+            width: side as u32,
+            height: side as u32,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    ))
+}
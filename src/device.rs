@@ -0,0 +1,324 @@
+//! What every backend shares: the frames and errors they hand out, and the
+//! device lifecycle around them, P and R to pause and reopen and leaving the
+//! sensor the way it was found on exit.
+//!
+//! Systems that want to move the motor, pause the sensor or switch its video
+//! mode send a [`KinectCommand`] instead of reaching for the [`DepthCamera`]. The
+//! commands are carried out in the order sent, once a frame, and failures
+//! come back as [`KinectError`] events.
+
+use std::any::Any;
+use std::fmt;
+use std::thread::JoinHandle;
+
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use bevy::utils::Instant;
+
+use crate::backend::DepthCamera;
+use crate::coords::{DEPTH_HEIGHT, DEPTH_WIDTH};
+use crate::motor::{Led, MotorError};
+use crate::tilt::{TiltState, TILT_LIMIT};
+use crate::video::VideoSettings;
+use crate::DepthFormat;
+
+pub struct DepthFrame {
+    /// Bit10 readings, row by row
+    pub data: Vec<u16>,
+    /// 640x480 unless the sensor delivers its own size, see
+    /// [`with_size`](Self::with_size)
+    pub width: usize,
+    pub height: usize,
+    /// the readings before they were put on the Bit10 scale, for devices
+    /// and formats that give more than it holds
+    pub raw: Option<RawDepth>,
+    /// counts the frames the device sent from 1, so a gap is frames dropped
+    /// on the way
+    pub sequence: u64,
+    /// the device's own clock, in its ticks, for the backends that have one
+    pub timestamp: Option<u64>,
+    /// when the backend got it off the device
+    pub captured_at: Instant,
+}
+
+impl DepthFrame {
+    /// Captured now.
+    pub fn new(data: Vec<u16>, sequence: u64) -> DepthFrame {
+        DepthFrame {
+            data,
+            width: DEPTH_WIDTH,
+            height: DEPTH_HEIGHT,
+            raw: None,
+            sequence,
+            timestamp: None,
+            captured_at: Instant::now(),
+        }
+    }
+
+    pub fn with_timestamp(mut self, timestamp: u64) -> DepthFrame {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    /// A frame at the sensor's own size instead of the Kinect's. It reaches
+    /// [`CurrentDepth`](crate::CurrentDepth) as it is, whatever the
+    /// [`DepthResolution`](crate::DepthResolution), and the features written
+    /// for 640x480 sit it out.
+    pub fn with_size(mut self, width: usize, height: usize) -> DepthFrame {
+        (self.width, self.height) = (width, height);
+        self
+    }
+
+    /// Keeps `data`, in `format`, next to the Bit10 readings; nothing
+    /// for Bit10 itself.
+    pub fn with_raw(mut self, format: DepthFormat, data: Vec<u16>) -> DepthFrame {
+        if format != DepthFormat::Bit10 {
+            self.raw = Some(RawDepth { format, data });
+        }
+        self
+    }
+}
+
+/// Depth readings as the device or source gave them, in the same layout as
+/// the Bit10 frame.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RawDepth {
+    pub format: DepthFormat,
+    pub data: Vec<u16>,
+}
+
+pub struct VideoFrame {
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, Debug)]
+pub enum KinectError {
+    /// the device could not be opened or configured
+    Open(String),
+    /// the acquisition thread or libfreenect's event thread panicked
+    ThreadPanicked(String),
+    /// the tilt motor or LED didn't respond, or there is none
+    Motor(String),
+    /// the backend can't do this
+    Unsupported(&'static str),
+    /// the device opened but sent nothing, or a stream went quiet
+    Stalled,
+}
+
+impl fmt::Display for KinectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KinectError::Open(reason) => write!(f, "Unable to open Kinect: {reason}"),
+            KinectError::ThreadPanicked(reason) => {
+                write!(f, "Kinect thread panicked: {reason}")
+            }
+            KinectError::Motor(reason) => write!(f, "Kinect motor: {reason}"),
+            KinectError::Unsupported(what) => write!(f, "The camera doesn't support {what}"),
+            KinectError::Stalled => write!(f, "Kinect stopped delivering frames"),
+        }
+    }
+}
+
+impl std::error::Error for KinectError {}
+
+impl From<MotorError> for KinectError {
+    fn from(err: MotorError) -> Self {
+        KinectError::Motor(err.to_string())
+    }
+}
+
+/// Waits for a backend's acquisition thread, a panic on it coming back as
+/// [`KinectError::ThreadPanicked`].
+pub fn join(handle: JoinHandle<Result<(), KinectError>>) -> Result<(), KinectError> {
+    handle
+        .join()
+        .unwrap_or_else(|panic| Err(KinectError::ThreadPanicked(panic_message(&*panic))))
+}
+
+pub(crate) fn panic_message(panic: &(dyn Any + Send)) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".into()
+    }
+}
+
+/// Something for the device to do.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KinectCommand {
+    /// open the device and start streaming, if it isn't
+    Open,
+    /// stop streaming and close the device
+    Close,
+    /// open the device if it is closed, close it otherwise
+    TogglePause,
+    Restart,
+    /// degrees from level, clamped to [`TILT_LIMIT`]
+    Tilt(f64),
+    /// degrees up from where the motor is now
+    TiltBy(f64),
+    Led(Led),
+    /// switch the IR emitter, for backends that can
+    Emitter(bool),
+    /// switch the video mode and reopen the device with it, if the device
+    /// streams it and it isn't the current one; the depth format and
+    /// resolution are fixed when the device is first opened
+    Configure(VideoSettings),
+}
+
+pub struct DevicePlugin;
+
+impl Plugin for DevicePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<KinectError>()
+            .add_event::<KinectCommand>()
+            .add_system(device_keys)
+            .add_system(apply_kinect_commands.after(device_keys))
+            .add_system(log_kinect_errors)
+            .add_system_to_stage(CoreStage::Last, shutdown_on_exit);
+    }
+}
+
+/// P closes the device and opens it again on the next press, R reopens it
+/// right away, up and down tilt it by 5°.
+fn device_keys(keys: Res<Input<KeyCode>>, mut commands: EventWriter<KinectCommand>) {
+    let pressed = [
+        (KeyCode::P, KinectCommand::TogglePause),
+        (KeyCode::R, KinectCommand::Restart),
+        (KeyCode::Up, KinectCommand::TiltBy(5.0)),
+        (KeyCode::Down, KinectCommand::TiltBy(-5.0)),
+    ];
+    for (key, command) in pressed {
+        if keys.just_pressed(key) {
+            commands.send(command);
+        }
+    }
+}
+
+pub(crate) fn apply_kinect_commands(
+    mut commands: EventReader<KinectCommand>,
+    mut camera: ResMut<DepthCamera>,
+    mut errors: EventWriter<KinectError>,
+    mut tilt: Option<ResMut<TiltState>>,
+    mut video: Option<ResMut<VideoSettings>>,
+) {
+    for command in commands.iter() {
+        let result = match *command {
+            KinectCommand::Open => {
+                camera.open();
+                Ok(())
+            }
+            KinectCommand::Close => camera.close(),
+            KinectCommand::TogglePause if camera.is_running() => camera.close(),
+            KinectCommand::TogglePause => {
+                camera.open();
+                Ok(())
+            }
+            KinectCommand::Restart => camera.restart(),
+            KinectCommand::Tilt(degrees) => tilt_to(&mut camera, degrees, &mut tilt),
+            KinectCommand::TiltBy(step) => camera
+                .tilt_state()
+                .and_then(|state| tilt_to(&mut camera, state.tilt_degrees + step, &mut tilt)),
+            KinectCommand::Led(led) => camera.led(led),
+            KinectCommand::Emitter(on) => camera.emitter(on),
+            KinectCommand::Configure(settings) if video.as_deref() == Some(&settings) => Ok(()),
+            KinectCommand::Configure(settings) if !camera.supports_video(settings) => {
+                Err(KinectError::Unsupported("that video mode"))
+            }
+            KinectCommand::Configure(settings) => {
+                // the video systems convert frames by the resource
+                if let Some(video) = &mut video {
+                    **video = settings;
+                }
+                camera.configure(settings);
+                camera.restart()
+            }
+        };
+        if let Err(err) = result {
+            errors.send(err);
+        }
+    }
+}
+
+/// Sets the motor going and notes where to in the [`TiltState`].
+fn tilt_to(
+    camera: &mut DepthCamera,
+    degrees: f64,
+    tilt: &mut Option<ResMut<TiltState>>,
+) -> Result<(), KinectError> {
+    let degrees = degrees.clamp(-TILT_LIMIT as f64, TILT_LIMIT as f64);
+    camera.tilt(degrees)?;
+    if let Some(tilt) = tilt {
+        tilt.target = Some(degrees as f32);
+    }
+    Ok(())
+}
+
+fn log_kinect_errors(mut errors: EventReader<KinectError>) {
+    for err in errors.iter() {
+        error!("{err}");
+    }
+}
+
+/// Leaves the sensor the way we found it: streams stopped, tilt back at 0°,
+/// LED off and both contexts closed, the acquisition thread joined before
+/// the motor is touched. Without this the process just ends with the device
+/// mid-stream, which can leave it in odd states.
+fn shutdown_on_exit(world: &mut World) {
+    if world.resource::<Events<AppExit>>().is_empty() {
+        return;
+    }
+
+    if let Some(mut camera) = world.remove_resource::<DepthCamera>() {
+        if let Err(err) = camera.close() {
+            error!("{err}");
+        }
+        // sensors without a motor have nothing to put back
+        for result in [camera.tilt(0.0), camera.led(Led::Off)] {
+            match result {
+                Ok(()) | Err(KinectError::Unsupported(_)) => {}
+                Err(err) => error!("{err}"),
+            }
+        }
+        info!("Kinect closed");
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::*;
+    use crate::simulation::{Scene, SimulatedKinect};
+    use crate::video::{VideoFormat, VideoResolution};
+
+    #[test]
+    fn unsupported_video_modes_are_refused() {
+        let mut app = App::new();
+        app.add_event::<KinectCommand>()
+            .add_event::<KinectError>()
+            .insert_resource(DepthCamera::new(SimulatedKinect::new(Scene::sweep())))
+            .insert_resource(VideoSettings::default())
+            .add_system(apply_kinect_commands);
+        let yuv_high = VideoSettings {
+            format: VideoFormat::YuvRgb,
+            resolution: VideoResolution::High,
+            ..default()
+        };
+        for settings in [yuv_high, VideoSettings::default()] {
+            app.world.send_event(KinectCommand::Configure(settings));
+        }
+        app.update();
+
+        let events = app.world.resource::<Events<KinectError>>();
+        let mut reader = events.get_reader();
+        let errors: Vec<&KinectError> = reader.iter(events).collect();
+        assert!(matches!(errors[..], [KinectError::Unsupported(_)]));
+        assert_eq!(
+            *app.world.resource::<VideoSettings>(),
+            VideoSettings::default()
+        );
+        // neither command reopened the device
+        assert!(!app.world.resource::<DepthCamera>().is_running());
+    }
+}
@@ -0,0 +1,182 @@
+//! The Kinects plugged in. libfreenect opens devices by their place in the
+//! USB enumeration, which changes with the port and the order they were
+//! plugged in, so an installation with several of them can't rely on
+//! `device_index` staying the same. At startup the connected devices are
+//! listed in [`KinectDevices`], and a [`KinectConfig::serial`] is looked up
+//! there and turned into the index everything else opens.
+//!
+//! Only the `freenect` backend lists devices; with the others the list stays
+//! empty and a serial is ignored.
+
+use bevy::prelude::*;
+
+use crate::KinectConfig;
+
+/// The parts of a device libfreenect can open.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Subdevices {
+    pub camera: bool,
+    pub motor: bool,
+    pub audio: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KinectDeviceInfo {
+    /// what `device_index` opens it with
+    pub index: usize,
+    /// the camera's serial number, printed on the bottom of the Kinect
+    pub serial: String,
+    pub subdevices: Subdevices,
+}
+
+/// In the order libfreenect opens them by index.
+#[derive(Resource, Clone, Debug, Default)]
+pub struct KinectDevices(pub Vec<KinectDeviceInfo>);
+
+impl KinectDevices {
+    pub fn by_serial(&self, serial: &str) -> Option<&KinectDeviceInfo> {
+        self.0.iter().find(|device| device.serial == serial)
+    }
+
+    /// The index to open for `config`: its serial's, or its `device_index`
+    /// without one. A serial that isn't connected gives an index past the
+    /// end, so the device fails to open instead of another one opening.
+    pub fn select(&self, config: &KinectConfig) -> usize {
+        let serial = match &config.serial {
+            Some(serial) => serial,
+            None => return config.device_index,
+        };
+        match self.by_serial(serial) {
+            Some(device) => device.index,
+            None => {
+                let connected: Vec<&str> = self.0.iter().map(|device| &device.serial[..]).collect();
+                error!(
+                    "No Kinect with serial {serial}, connected: {}",
+                    connected.join(", ")
+                );
+                self.0.len()
+            }
+        }
+    }
+
+    /// Lists the connected Kinects through libfreenect.
+    #[cfg(feature = "freenect")]
+    pub fn list() -> Result<KinectDevices, crate::device::KinectError> {
+        list::list().map(KinectDevices)
+    }
+}
+
+pub struct DevicesPlugin;
+
+impl Plugin for DevicesPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<KinectDevices>();
+        #[cfg(feature = "freenect")]
+        app.add_startup_system_to_stage(StartupStage::PreStartup, list_devices);
+    }
+}
+
+/// Ahead of the startup systems that open the device.
+#[cfg(feature = "freenect")]
+fn list_devices(mut devices: ResMut<KinectDevices>, config: Option<ResMut<KinectConfig>>) {
+    match KinectDevices::list() {
+        Ok(list) => *devices = list,
+        Err(err) => warn!("Unable to list Kinects: {err}"),
+    }
+    for device in &devices.0 {
+        info!(
+            "Kinect {}: serial {}, {:?}",
+            device.index, device.serial, device.subdevices
+        );
+    }
+    if let Some(mut config) = config {
+        if config.serial.is_some() {
+            config.device_index = devices.select(&config);
+        }
+    }
+}
+
+#[cfg(feature = "freenect")]
+mod list {
+    use std::ffi::CStr;
+    use std::ptr;
+
+    use super::{KinectDeviceInfo, Subdevices};
+    use crate::device::KinectError;
+    use crate::motor::ffi;
+
+    pub fn list() -> Result<Vec<KinectDeviceInfo>, KinectError> {
+        unsafe {
+            let mut ctx = ptr::null_mut();
+            if ffi::freenect_init(&mut ctx, ptr::null_mut()) < 0 {
+                return Err(KinectError::Open(
+                    "Unable to create freenect context".into(),
+                ));
+            }
+            let mut attributes = ptr::null_mut();
+            let count = ffi::freenect_list_device_attributes(ctx, &mut attributes);
+            if count < 0 {
+                ffi::freenect_shutdown(ctx);
+                return Err(KinectError::Open("Unable to list devices".into()));
+            }
+
+            // libfreenect doesn't say per device, only what it was built for
+            let supported = ffi::freenect_supported_subdevices();
+            let subdevices = Subdevices {
+                camera: supported & ffi::FREENECT_DEVICE_CAMERA != 0,
+                motor: supported & ffi::FREENECT_DEVICE_MOTOR != 0,
+                audio: supported & ffi::FREENECT_DEVICE_AUDIO != 0,
+            };
+            let mut devices = Vec::new();
+            let mut next = attributes;
+            while !next.is_null() {
+                let serial = match (*next).camera_serial {
+                    serial if serial.is_null() => String::new(),
+                    serial => CStr::from_ptr(serial).to_string_lossy().into_owned(),
+                };
+                devices.push(KinectDeviceInfo {
+                    index: devices.len(),
+                    serial,
+                    subdevices,
+                });
+                next = (*next).next;
+            }
+            ffi::freenect_free_device_attributes(attributes);
+            ffi::freenect_shutdown(ctx);
+            Ok(devices)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serial_picks_the_index() {
+        let device = |index, serial: &str| KinectDeviceInfo {
+            index,
+            serial: serial.into(),
+            subdevices: Subdevices::default(),
+        };
+        let devices = KinectDevices(vec![
+            device(0, "A00364A11700045A"),
+            device(1, "B00367611930037B"),
+        ]);
+
+        let mut config = KinectConfig {
+            device_index: 0,
+            serial: Some("B00367611930037B".into()),
+            ..default()
+        };
+        assert_eq!(devices.select(&config), 1);
+
+        // not plugged in, nothing else opens in its place
+        config.serial = Some("A00000000000000A".into());
+        assert_eq!(devices.select(&config), 2);
+
+        config.serial = None;
+        config.device_index = 1;
+        assert_eq!(devices.select(&config), 1);
+    }
+}
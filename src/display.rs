@@ -0,0 +1,179 @@
+//! Keeps the 640x480 view fitted into the window as it is resized. Anything
+//! drawn in view pixels either carries one of the markers below or maps its
+//! positions through the [`DisplayRect`] resource.
+//!
+//! Outputs that warp or split the picture set `offscreen`, which points the
+//! main camera at a [`Canvas`] image instead of the window.
+
+use bevy::prelude::*;
+use bevy::render::camera::RenderTarget;
+use bevy::render::render_resource::{
+    Extent3d, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
+};
+
+use crate::coords::{DisplayRect, FitMode};
+use crate::MainCamera;
+
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct DisplaySettings {
+    pub fit: FitMode,
+    pub offscreen: bool,
+}
+
+/// What the main camera rendered, kept at the primary window's size since UI
+/// layout follows that window.
+#[derive(Resource)]
+pub struct Canvas {
+    pub image: Handle<Image>,
+}
+
+/// UI image covering the whole view.
+#[derive(Component)]
+pub struct ViewImage;
+
+/// Sprite or mesh laid out for the unscaled view; `offset` is its position
+/// relative to the view center.
+#[derive(Component)]
+pub struct ViewSprite {
+    pub offset: Vec2,
+}
+
+pub struct DisplayPlugin;
+
+impl Plugin for DisplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<DisplaySettings>()
+            .init_resource::<DisplayRect>()
+            .add_startup_system_to_stage(StartupStage::PreStartup, create_canvas)
+            .add_startup_system_to_stage(StartupStage::PostStartup, render_to_canvas)
+            .add_system_to_stage(CoreStage::PreUpdate, fit_view_to_window)
+            .add_system_to_stage(CoreStage::PreUpdate, fit_canvas_to_window)
+            .add_system(layout_view_images)
+            .add_system(layout_view_sprites);
+    }
+}
+
+fn create_canvas(
+    mut commands: Commands,
+    settings: Res<DisplaySettings>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    if !settings.offscreen {
+        return;
+    }
+
+    let size = Extent3d {
+        width: 640,
+        height: 480,
+        depth_or_array_layers: 1,
+    };
+    let mut image = Image {
+        texture_descriptor: TextureDescriptor {
+            label: None,
+            size,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Bgra8UnormSrgb,
+            mip_level_count: 1,
+            sample_count: 1,
+            usage: TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_DST
+                | TextureUsages::RENDER_ATTACHMENT,
+        },
+        ..default()
+    };
+    // sized to the window once it is known
+    image.resize(size);
+    commands.insert_resource(Canvas {
+        image: images.add(image),
+    });
+}
+
+fn render_to_canvas(
+    canvas: Option<Res<Canvas>>,
+    mut camera_query: Query<&mut Camera, With<MainCamera>>,
+) {
+    if let Some(canvas) = canvas {
+        for mut camera in camera_query.iter_mut() {
+            camera.target = RenderTarget::Image(canvas.image.clone());
+        }
+    }
+}
+
+fn fit_canvas_to_window(
+    windows: Res<Windows>,
+    canvas: Option<Res<Canvas>>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    let (canvas, window) = match (canvas, windows.get_primary()) {
+        (Some(canvas), Some(window)) => (canvas, window),
+        _ => return,
+    };
+    let size = Extent3d {
+        width: window.width().max(1.0) as u32,
+        height: window.height().max(1.0) as u32,
+        depth_or_array_layers: 1,
+    };
+    // `get_mut` alone would flag the image as modified every frame
+    let resized = matches!(
+        images.get(&canvas.image),
+        Some(image) if image.texture_descriptor.size != size
+    );
+    if resized {
+        if let Some(image) = images.get_mut(&canvas.image) {
+            image.resize(size);
+        }
+    }
+}
+
+fn fit_view_to_window(
+    windows: Res<Windows>,
+    settings: Res<DisplaySettings>,
+    mut rect: ResMut<DisplayRect>,
+) {
+    if let Some(window) = windows.get_primary() {
+        let fitted = DisplayRect::fit(Vec2::new(window.width(), window.height()), settings.fit);
+        if *rect != fitted {
+            *rect = fitted;
+        }
+    }
+}
+
+fn layout_view_images(
+    rect: Res<DisplayRect>,
+    windows: Res<Windows>,
+    mut image_query: Query<&mut Style, With<ViewImage>>,
+) {
+    if !rect.is_changed() {
+        return;
+    }
+    let window_height = windows.primary().height();
+    for mut style in image_query.iter_mut() {
+        style.position_type = PositionType::Absolute;
+        // UI positions count from the top left of the window
+        style.position = UiRect {
+            left: Val::Px(rect.min.x),
+            top: Val::Px(window_height - rect.min.y - rect.size.y),
+            ..default()
+        };
+        style.size = Size::new(Val::Px(rect.size.x), Val::Px(rect.size.y));
+    }
+}
+
+fn layout_view_sprites(
+    rect: Res<DisplayRect>,
+    windows: Res<Windows>,
+    mut sprite_query: Query<(&ViewSprite, &mut Transform)>,
+) {
+    if !rect.is_changed() {
+        return;
+    }
+    let window = windows.primary();
+    let center = rect.world_center(Vec2::new(window.width(), window.height()));
+    let scale = rect.scale();
+    for (sprite, mut transform) in sprite_query.iter_mut() {
+        let position = center + sprite.offset * scale;
+        transform.translation.x = position.x;
+        transform.translation.y = position.y;
+        transform.scale = scale.extend(1.0);
+    }
+}
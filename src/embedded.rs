@@ -0,0 +1,90 @@
+//! Running on a Raspberry Pi or another aarch64 board, the usual computer
+//! behind a permanent installation. [`UsbSettings`] is how the libfreenect
+//! backend drives the device: how many frames it holds on to while the app
+//! is busy and which it drops, see [`queue`](crate::queue), and whether it streams video at all. Leaving video out halves
+//! the isochronous bandwidth the Kinect takes on a bus it often shares with
+//! the network.
+//!
+//! The number and size of libfreenect's isochronous transfers are fixed when
+//! libfreenect is built (`NUM_XFERS` and `PKTS_PER_XFER` in its
+//! `usb_libusb10.h`), as is the kernel's limit on memory for them
+//! (`usbcore.usbfs_memory_mb`), so those are set outside of the app, see the
+//! README.
+//!
+//! With [`EmbeddedSettings::low_memory`] the features that keep extra copies
+//! of frames around are turned down: one frame queued per stream, no blending
+//! between depth frames and no upscaled depth map.
+
+use bevy::prelude::*;
+
+use crate::capture::CaptureSettings;
+use crate::queue::DropPolicy;
+use crate::upsample::UpsampleSettings;
+
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct UsbSettings {
+    /// frames of each stream kept waiting for the app
+    pub frame_queue: usize,
+    /// which frames go while the queue is full
+    pub drop_policy: DropPolicy,
+    /// only start the depth stream
+    pub depth_only: bool,
+}
+
+impl Default for UsbSettings {
+    fn default() -> Self {
+        UsbSettings {
+            frame_queue: 2,
+            drop_policy: DropPolicy::default(),
+            depth_only: false,
+        }
+    }
+}
+
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct EmbeddedSettings {
+    pub low_memory: bool,
+}
+
+/// Goes ahead of the plugins whose settings the low memory mode changes.
+pub struct EmbeddedPlugin;
+
+impl Plugin for EmbeddedPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<EmbeddedSettings>()
+            .init_resource::<UsbSettings>();
+        if !app.world.resource::<EmbeddedSettings>().low_memory {
+            return;
+        }
+        let world = &mut app.world;
+        world.resource_mut::<UsbSettings>().frame_queue = 1;
+        world
+            .get_resource_or_insert_with(CaptureSettings::default)
+            .interpolate = false;
+        world
+            .get_resource_or_insert_with(UpsampleSettings::default)
+            .factor = 1;
+        info!("Low memory mode: one frame queued, no depth blending or upsampling");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn low_memory_turns_down_the_copies() {
+        let mut app = App::new();
+        app.insert_resource(UpsampleSettings { factor: 2 })
+            .insert_resource(EmbeddedSettings { low_memory: true })
+            .add_plugin(EmbeddedPlugin);
+        assert_eq!(app.world.resource::<UsbSettings>().frame_queue, 1);
+        assert!(!app.world.resource::<CaptureSettings>().interpolate);
+        assert_eq!(app.world.resource::<UpsampleSettings>().factor, 1);
+
+        let mut app = App::new();
+        app.add_plugin(EmbeddedPlugin);
+        assert_eq!(app.world.resource::<UsbSettings>().frame_queue, 2);
+        assert!(app.world.get_resource::<CaptureSettings>().is_none());
+    }
+}
@@ -0,0 +1,288 @@
+//! Structured log of what happened in the field, for working out afterwards
+//! why an install misbehaved. Device status changes, errors, stalls and
+//! unplugging, visitor sessions and calibrations go into one file as JSON
+//! lines, each with the unix time and the kind of event:
+//!
+//! ```text
+//! {"at":1700000000.5,"event":"kinect_status","status":"retrying","attempt":1,"delay":1.0}
+//! ```
+//!
+//! Off unless [`EventLogSettings::path`] is set. Once the file grows past
+//! [`EventLogSettings::max_bytes`] it is rotated: `events.jsonl` becomes
+//! `events.jsonl.1`, that one `.2` and so on, keeping
+//! [`EventLogSettings::keep`] old files. Apps add their own events with
+//! [`EventLog::record`].
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+use serde_json::{json, Value};
+
+use crate::analytics::{unix_now, Analytics};
+use crate::attract::AttractEvent;
+use crate::blockage::{SensorBlocked, SensorCleared};
+use crate::calibration::{CalibrationProfile, SceneChanged};
+use crate::device::KinectError;
+use crate::hotplug::{DeviceConnected, DeviceDisconnected};
+use crate::hours::HoursEvent;
+use crate::reconnect::KinectStatus;
+use crate::watchdog::StreamStalled;
+
+#[derive(Resource, Clone, Debug)]
+pub struct EventLogSettings {
+    /// file to write to, nothing is logged without one
+    pub path: Option<PathBuf>,
+    /// size a file grows to before it is rotated
+    pub max_bytes: u64,
+    /// rotated files kept besides the current one
+    pub keep: usize,
+}
+
+impl Default for EventLogSettings {
+    fn default() -> Self {
+        EventLogSettings {
+            path: None,
+            max_bytes: 10_000_000,
+            keep: 5,
+        }
+    }
+}
+
+/// Lines waiting to be written at the end of the frame.
+#[derive(Resource, Default)]
+pub struct EventLog {
+    pending: Vec<String>,
+    file: Option<File>,
+    written: u64,
+}
+
+impl EventLog {
+    /// Logs `event` with the fields of `fields`, which should be a JSON
+    /// object, e.g. `json!({"level": 3})`.
+    pub fn record(&mut self, event: &str, fields: Value) {
+        let mut line = json!({ "at": unix_now(), "event": event });
+        if let (Some(line), Value::Object(fields)) = (line.as_object_mut(), fields) {
+            line.extend(fields);
+        }
+        self.pending.push(line.to_string());
+    }
+
+    fn write(&mut self, settings: &EventLogSettings, path: &Path) -> io::Result<()> {
+        for line in std::mem::take(&mut self.pending) {
+            if self.file.is_some() && self.written + line.len() as u64 >= settings.max_bytes {
+                self.file = None;
+                rotate(path, settings.keep)?;
+            }
+            let file = match &mut self.file {
+                Some(file) => file,
+                None => {
+                    let file = OpenOptions::new().create(true).append(true).open(path)?;
+                    self.written = file.metadata()?.len();
+                    self.file.insert(file)
+                }
+            };
+            writeln!(file, "{line}")?;
+            self.written += line.len() as u64 + 1;
+        }
+        Ok(())
+    }
+}
+
+/// Shifts `path.1` to `path.2` and so on, dropping the oldest, and moves
+/// `path` to `path.1`.
+fn rotate(path: &Path, keep: usize) -> io::Result<()> {
+    let numbered = |n: usize| {
+        let mut name = path.as_os_str().to_owned();
+        name.push(format!(".{n}"));
+        PathBuf::from(name)
+    };
+    if keep == 0 {
+        return fs::remove_file(path);
+    }
+    // a missing file in the chain is fine, the log may be young
+    let _ = fs::remove_file(numbered(keep));
+    for n in (1..keep).rev() {
+        let _ = fs::rename(numbered(n), numbered(n + 1));
+    }
+    fs::rename(path, numbered(1))
+}
+
+pub struct EventLogPlugin;
+
+impl Plugin for EventLogPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<EventLogSettings>()
+            .init_resource::<EventLog>()
+            .add_system(log_device_events)
+            .add_system(log_sessions)
+            .add_system(log_calibrations)
+            .add_system_to_stage(CoreStage::Last, write_event_log);
+    }
+}
+
+fn log_device_events(
+    settings: Res<EventLogSettings>,
+    mut log: ResMut<EventLog>,
+    (mut status, mut errors, mut stalls): (
+        EventReader<KinectStatus>,
+        EventReader<KinectError>,
+        EventReader<StreamStalled>,
+    ),
+    (mut blocked, mut cleared, mut hours): (
+        EventReader<SensorBlocked>,
+        EventReader<SensorCleared>,
+        EventReader<HoursEvent>,
+    ),
+    (mut connected, mut disconnected): (
+        EventReader<DeviceConnected>,
+        EventReader<DeviceDisconnected>,
+    ),
+) {
+    if settings.path.is_none() {
+        return;
+    }
+    for status in status.iter() {
+        let fields = match status {
+            KinectStatus::Streaming => json!({ "status": "streaming" }),
+            KinectStatus::Stalled => json!({ "status": "stalled" }),
+            KinectStatus::Retrying { attempt, delay } => {
+                json!({ "status": "retrying", "attempt": attempt, "delay": delay })
+            }
+            KinectStatus::Reconnecting { attempt } => {
+                json!({ "status": "reconnecting", "attempt": attempt })
+            }
+        };
+        log.record("kinect_status", fields);
+    }
+    for event in connected.iter() {
+        log.record(
+            "device_connected",
+            json!({ "serial": event.serial, "index": event.index }),
+        );
+    }
+    for event in disconnected.iter() {
+        log.record("device_disconnected", json!({ "serial": event.serial }));
+    }
+    for err in errors.iter() {
+        log.record("kinect_error", json!({ "message": err.to_string() }));
+    }
+    for stall in stalls.iter() {
+        log.record(
+            "stream_stalled",
+            json!({ "stream": format!("{:?}", stall.stream).to_lowercase(), "seconds": stall.seconds }),
+        );
+    }
+    for event in blocked.iter() {
+        log.record("sensor_blocked", json!({ "share": event.share }));
+    }
+    for _ in cleared.iter() {
+        log.record("sensor_cleared", json!({}));
+    }
+    for event in hours.iter() {
+        let event = match event {
+            HoursEvent::Opened => "hours_opened",
+            HoursEvent::Closed => "hours_closed",
+        };
+        log.record(event, json!({}));
+    }
+}
+
+fn log_sessions(
+    settings: Res<EventLogSettings>,
+    analytics: Res<Analytics>,
+    mut attract: EventReader<AttractEvent>,
+    mut log: ResMut<EventLog>,
+    mut logged: Local<usize>,
+) {
+    if settings.path.is_none() {
+        return;
+    }
+    for event in attract.iter() {
+        let event = match event {
+            AttractEvent::Started => "attract_started",
+            AttractEvent::Ended => "attract_ended",
+        };
+        log.record(event, json!({}));
+    }
+    for session in &analytics.sessions[*logged..] {
+        log.record(
+            "session",
+            json!({ "started_at": session.started_at, "dwell_seconds": session.dwell_seconds }),
+        );
+    }
+    *logged = analytics.sessions.len();
+}
+
+fn log_calibrations(
+    settings: Res<EventLogSettings>,
+    profile: Res<CalibrationProfile>,
+    mut scene_changes: EventReader<SceneChanged>,
+    mut log: ResMut<EventLog>,
+) {
+    if settings.path.is_none() {
+        return;
+    }
+    // the one loaded at startup isn't news
+    if profile.is_changed() && !profile.is_added() {
+        log.record(
+            "calibrated",
+            json!({
+                "noise_at_1m": profile.noise_at_1m,
+                "closest_background": profile.closest_background,
+            }),
+        );
+    }
+    for change in scene_changes.iter() {
+        log.record("scene_changed", json!({ "area": change.area }));
+    }
+}
+
+fn write_event_log(settings: Res<EventLogSettings>, mut log: ResMut<EventLog>) {
+    let path = match &settings.path {
+        Some(path) => path,
+        None => return,
+    };
+    if log.pending.is_empty() {
+        return;
+    }
+    if let Err(err) = log.write(&settings, path) {
+        error!("Unable to write to {}: {err}", path.display());
+        // don't pile up lines that can't be written
+        log.pending.clear();
+        log.file = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_logs_are_rotated() {
+        let dir = std::env::temp_dir().join(format!("event-log-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("events.jsonl");
+        let settings = EventLogSettings {
+            path: Some(path.clone()),
+            max_bytes: 200,
+            keep: 2,
+        };
+
+        let mut log = EventLog::default();
+        for level in 0..20 {
+            log.record("test", json!({ "level": level }));
+            log.write(&settings, &path).unwrap();
+        }
+        let current = fs::read_to_string(&path).unwrap();
+        let last: Value = serde_json::from_str(current.lines().last().unwrap()).unwrap();
+        assert_eq!(last["event"], "test");
+        assert_eq!(last["level"], 19);
+        assert!(current.len() < 200);
+        assert!(dir.join("events.jsonl.2").exists());
+        assert!(!dir.join("events.jsonl.3").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,101 @@
+//! Long exposure: the closest depth each pixel has seen over the last few
+//! seconds. Drawn as [`DepthStyle::LongExposure`](crate::DepthStyle) it
+//! paints trails of everything that moved through the scene; read from
+//! [`LongExposure`] it is a rough record of which space was occupied.
+//!
+//! The window is split into slices that each keep their own minimum, the
+//! oldest one dropped as a new one starts, so the exposure slides along
+//! without storing every frame.
+
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+
+use crate::pipeline::{self, DepthPipelineApp, DepthStage};
+use crate::{CurrentDepth, MainKinect, NO_DEPTH};
+
+/// Slices the window is kept in, more follow the window's end more smoothly.
+const SLICES: usize = 10;
+
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct ExposureSettings {
+    /// seconds a reading stays in the exposure
+    pub window: f32,
+}
+
+impl Default for ExposureSettings {
+    fn default() -> Self {
+        ExposureSettings { window: 5.0 }
+    }
+}
+
+/// Closest raw depth per pixel over the window, `NO_DEPTH` where nothing was
+/// seen.
+#[derive(Resource, Default)]
+pub struct LongExposure {
+    pub depth: Vec<u16>,
+}
+
+struct Slice {
+    started_at: f64,
+    depth: Vec<u16>,
+}
+
+pub struct ExposurePlugin;
+
+impl Plugin for ExposurePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ExposureSettings>()
+            .init_resource::<LongExposure>()
+            .add_depth_stage(DepthStage::consumer(
+                pipeline::AccumulateExposure,
+                accumulate_exposure,
+            ));
+    }
+}
+
+fn accumulate_exposure(
+    settings: Res<ExposureSettings>,
+    depth_query: Query<&CurrentDepth, With<MainKinect>>,
+    mut exposure: ResMut<LongExposure>,
+    mut slices: Local<VecDeque<Slice>>,
+    mut last_frame: Local<f64>,
+) {
+    let depth = match depth_query.get_single() {
+        Ok(depth) if depth.received_at != *last_frame && !depth.depth_array.is_empty() => depth,
+        _ => return,
+    };
+    *last_frame = depth.received_at;
+
+    let slice_seconds = (settings.window / SLICES as f32) as f64;
+    let current = match slices.back() {
+        Some(slice) if depth.received_at - slice.started_at < slice_seconds => slices.len() - 1,
+        _ => {
+            slices.push_back(Slice {
+                started_at: depth.received_at,
+                depth: vec![NO_DEPTH; depth.depth_array.len()],
+            });
+            while slices.len() > SLICES {
+                slices.pop_front();
+            }
+            slices.len() - 1
+        }
+    };
+    // NO_DEPTH is the largest value, so holes never win
+    let slice = &mut slices[current];
+    if slice.depth.len() != depth.depth_array.len() {
+        slice.depth = vec![NO_DEPTH; depth.depth_array.len()];
+    }
+    for (kept, raw) in slice.depth.iter_mut().zip(&depth.depth_array) {
+        *kept = (*kept).min(*raw);
+    }
+
+    let len = depth.depth_array.len();
+    exposure.depth.clear();
+    exposure.depth.resize(len, NO_DEPTH);
+    for slice in slices.iter().filter(|slice| slice.depth.len() == len) {
+        for (closest, raw) in exposure.depth.iter_mut().zip(&slice.depth) {
+            *closest = (*closest).min(*raw);
+        }
+    }
+}
@@ -0,0 +1,347 @@
+//! Floor games, with the sensor looking down at a floor a projector lights.
+//! `--floor <file>` names the play area, JSON with where the corners of the
+//! projected picture are in the depth view and how many cells it has:
+//!
+//! ```json
+//! { "corners": [[102, 64], [548, 70], [560, 420], [90, 410]], "columns": 4, "rows": 3 }
+//! ```
+//!
+//! Corners go top left, top right, bottom right, bottom left of the picture
+//! as projected, so the play area can sit at any angle and keystone. The
+//! floor is learned from the first second of frames (F10 learns it again),
+//! so the area has to be clear at startup. After that, readings just above
+//! the floor are feet touching it:
+//!
+//! * [`FloorContacts`] has where they are, in play area coordinates, 0..1
+//!   from its top left
+//! * [`FloorStep`] goes out when a foot lands on a cell, again with
+//!   [`FloorStepKind::Stand`] once it stays there, and when the cell is left
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::coords::{self, DEPTH_HEIGHT, DEPTH_WIDTH};
+use crate::{CurrentDepth, MainKinect};
+
+/// Frames the floor is averaged over.
+const LEARN_FRAMES: u32 = 30;
+/// Heights above the floor that count as a foot on it, in meters. Below is
+/// the floor's own noise, above the rest of the leg.
+const CONTACT_BAND: std::ops::Range<f32> = 0.03..0.15;
+/// Contact pixels that make a cell stepped on, half of them keep it.
+const MIN_PIXELS: u32 = 40;
+/// Seconds on a cell before it is stood on.
+const STAND_SECONDS: f64 = 0.6;
+
+#[derive(Resource, Clone, Debug, Default)]
+pub struct FloorSettings {
+    pub area: Option<PathBuf>,
+}
+
+#[derive(Resource, Clone, Debug, Deserialize)]
+pub struct PlayArea {
+    /// the projected picture's corners in depth pixels, clockwise from top
+    /// left
+    pub corners: [Vec2; 4],
+    pub columns: u32,
+    pub rows: u32,
+}
+
+impl PlayArea {
+    pub fn load(path: &Path) -> Result<PlayArea, String> {
+        let json = fs::read_to_string(path)
+            .map_err(|err| format!("can't read play area {}: {err}", path.display()))?;
+        let area: PlayArea = serde_json::from_str(&json)
+            .map_err(|err| format!("bad play area {}: {err}", path.display()))?;
+        if area.columns == 0 || area.rows == 0 || area.to_area().is_none() {
+            return Err(format!(
+                "bad play area {}: needs cells and four corners that aren't in a line",
+                path.display()
+            ));
+        }
+        Ok(area)
+    }
+
+    /// Depth pixels to play area coordinates, `None` if the corners don't
+    /// make a quadrilateral.
+    pub fn to_area(&self) -> Option<Mat3> {
+        let unit = [Vec2::ZERO, Vec2::X, Vec2::ONE, Vec2::Y];
+        homography(self.corners, unit)
+    }
+
+    /// Column and row of a point in play area coordinates.
+    pub fn cell(&self, point: Vec2) -> Option<UVec2> {
+        if !(0.0..1.0).contains(&point.x) || !(0.0..1.0).contains(&point.y) {
+            return None;
+        }
+        Some(UVec2::new(
+            (point.x * self.columns as f32) as u32,
+            (point.y * self.rows as f32) as u32,
+        ))
+    }
+}
+
+/// The projective transform taking `from` to `to`, point for point.
+fn homography(from: [Vec2; 4], to: [Vec2; 4]) -> Option<Mat3> {
+    // u = (h0 x + h1 y + h2) / (h6 x + h7 y + 1), likewise v with h3..h5
+    let mut rows = [[0.0f64; 9]; 8];
+    for (i, (p, q)) in from.iter().zip(to.iter()).enumerate() {
+        let (x, y, u, v) = (p.x as f64, p.y as f64, q.x as f64, q.y as f64);
+        rows[i * 2] = [x, y, 1.0, 0.0, 0.0, 0.0, -x * u, -y * u, u];
+        rows[i * 2 + 1] = [0.0, 0.0, 0.0, x, y, 1.0, -x * v, -y * v, v];
+    }
+    // Gaussian elimination with partial pivoting
+    for column in 0..8 {
+        let pivot =
+            (column..8).max_by(|a, b| rows[*a][column].abs().total_cmp(&rows[*b][column].abs()))?;
+        if rows[pivot][column].abs() < 1e-9 {
+            return None;
+        }
+        rows.swap(column, pivot);
+        let pivot_row = rows[column];
+        for (index, row) in rows.iter_mut().enumerate() {
+            if index != column {
+                let factor = row[column] / pivot_row[column];
+                for (value, pivot_value) in row.iter_mut().zip(pivot_row).skip(column) {
+                    *value -= factor * pivot_value;
+                }
+            }
+        }
+    }
+    let h: Vec<f32> = (0..8).map(|i| (rows[i][8] / rows[i][i]) as f32).collect();
+    Some(Mat3::from_cols(
+        Vec3::new(h[0], h[3], h[6]),
+        Vec3::new(h[1], h[4], h[7]),
+        Vec3::new(h[2], h[5], 1.0),
+    ))
+}
+
+fn apply(transform: &Mat3, pixel: Vec2) -> Vec2 {
+    let point = *transform * pixel.extend(1.0);
+    point.truncate() / point.z
+}
+
+/// Feet on the floor, in play area coordinates.
+#[derive(Resource, Clone, Debug, Default)]
+pub struct FloorContacts(pub Vec<Vec2>);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FloorStepKind {
+    Step,
+    Stand,
+    Leave,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct FloorStep {
+    /// column and row
+    pub cell: UVec2,
+    pub kind: FloorStepKind,
+}
+
+/// The floor's distance per depth pixel, averaged while learning.
+#[derive(Default)]
+struct Floor {
+    sum: Vec<f32>,
+    count: Vec<u32>,
+    frames: u32,
+}
+
+impl Floor {
+    fn meters(&self, i: usize) -> Option<f32> {
+        (self.count[i] > 0).then(|| self.sum[i] / self.count[i] as f32)
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct CellState {
+    /// when the current step began
+    since: Option<f64>,
+    stood: bool,
+}
+
+pub struct FloorPlugin;
+
+impl Plugin for FloorPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<FloorSettings>();
+        let path = match &app.world.resource::<FloorSettings>().area {
+            Some(path) => path.clone(),
+            None => return,
+        };
+        let area = PlayArea::load(&path).unwrap_or_else(|err| panic!("{err}"));
+        app.insert_resource(area)
+            .init_resource::<FloorContacts>()
+            .add_event::<FloorStep>()
+            .add_system(track_feet.after(crate::KinectSet::Acquire));
+    }
+}
+
+fn track_feet(
+    depth_query: Query<&CurrentDepth, (With<MainKinect>, Changed<CurrentDepth>)>,
+    keys: Res<Input<KeyCode>>,
+    area: Res<PlayArea>,
+    mut contacts: ResMut<FloorContacts>,
+    mut steps: EventWriter<FloorStep>,
+    mut floor: Local<Floor>,
+    mut cells: Local<Vec<CellState>>,
+) {
+    let depth = match depth_query.get_single() {
+        Ok(depth) if depth.depth_array.len() == DEPTH_WIDTH * DEPTH_HEIGHT => depth,
+        _ => return,
+    };
+    if keys.just_pressed(KeyCode::F10) || floor.sum.len() != depth.depth_array.len() {
+        *floor = Floor {
+            sum: vec![0.0; depth.depth_array.len()],
+            count: vec![0; depth.depth_array.len()],
+            frames: 0,
+        };
+    }
+    if floor.frames < LEARN_FRAMES {
+        floor.frames += 1;
+        let floor = &mut *floor;
+        for (i, raw) in depth.depth_array.iter().enumerate() {
+            if let Some(meters) = coords::raw_depth_to_meters(*raw) {
+                floor.sum[i] += meters;
+                floor.count[i] += 1;
+            }
+        }
+        if floor.frames == LEARN_FRAMES {
+            info!("floor learned");
+        }
+        return;
+    }
+
+    let to_area = match area.to_area() {
+        Some(to_area) => to_area,
+        None => return,
+    };
+    let (columns, rows) = (area.columns as usize, area.rows as usize);
+    let mut pixels = vec![0u32; columns * rows];
+    let mut sums = vec![Vec2::ZERO; columns * rows];
+    for (i, raw) in depth.depth_array.iter().enumerate() {
+        let height = match (coords::raw_depth_to_meters(*raw), floor.meters(i)) {
+            (Some(meters), Some(floor)) => floor - meters,
+            _ => continue,
+        };
+        if !CONTACT_BAND.contains(&height) {
+            continue;
+        }
+        let pixel = Vec2::new((i % DEPTH_WIDTH) as f32, (i / DEPTH_WIDTH) as f32);
+        let point = apply(&to_area, pixel);
+        if let Some(cell) = area.cell(point) {
+            let index = cell.y as usize * columns + cell.x as usize;
+            pixels[index] += 1;
+            sums[index] += point;
+        }
+    }
+
+    cells.resize(columns * rows, CellState::default());
+    let now = depth.received_at;
+    for (index, state) in cells.iter_mut().enumerate() {
+        let cell = UVec2::new((index % columns) as u32, (index / columns) as u32);
+        let (on, held) = (pixels[index] >= MIN_PIXELS, pixels[index] >= MIN_PIXELS / 2);
+        match state.since {
+            None if on => {
+                *state = CellState {
+                    since: Some(now),
+                    stood: false,
+                };
+                steps.send(FloorStep {
+                    cell,
+                    kind: FloorStepKind::Step,
+                });
+            }
+            Some(_) if !held => {
+                *state = CellState::default();
+                steps.send(FloorStep {
+                    cell,
+                    kind: FloorStepKind::Leave,
+                });
+            }
+            Some(since) if !state.stood && now - since >= STAND_SECONDS => {
+                state.stood = true;
+                steps.send(FloorStep {
+                    cell,
+                    kind: FloorStepKind::Stand,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    contacts.0 = contact_points(&pixels, &sums, columns, |index| {
+        cells[index].since.is_some()
+    });
+}
+
+/// One point per group of touching stepped-on cells, where its contact
+/// pixels are on average.
+fn contact_points(
+    pixels: &[u32],
+    sums: &[Vec2],
+    columns: usize,
+    stepped: impl Fn(usize) -> bool,
+) -> Vec<Vec2> {
+    let mut seen = vec![false; pixels.len()];
+    let mut points = Vec::new();
+    for start in 0..pixels.len() {
+        if seen[start] || !stepped(start) {
+            continue;
+        }
+        let (mut count, mut sum) = (0, Vec2::ZERO);
+        let mut stack = vec![start];
+        seen[start] = true;
+        while let Some(index) = stack.pop() {
+            count += pixels[index];
+            sum += sums[index];
+            let (x, y) = (index % columns, index / columns);
+            let neighbours = [
+                (x > 0).then(|| index - 1),
+                (x + 1 < columns).then(|| index + 1),
+                (y > 0).then(|| index - columns),
+                (index + columns < pixels.len()).then(|| index + columns),
+            ];
+            for next in neighbours.into_iter().flatten() {
+                if !seen[next] && stepped(next) {
+                    seen[next] = true;
+                    stack.push(next);
+                }
+            }
+        }
+        if count > 0 {
+            points.push(sum / count as f32);
+        }
+    }
+    points
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_keystoned_area_maps_onto_its_cells() {
+        let area = PlayArea {
+            corners: [
+                Vec2::new(100.0, 60.0),
+                Vec2::new(540.0, 80.0),
+                Vec2::new(580.0, 420.0),
+                Vec2::new(60.0, 400.0),
+            ],
+            columns: 4,
+            rows: 3,
+        };
+        let to_area = area.to_area().unwrap();
+        let unit = [Vec2::ZERO, Vec2::X, Vec2::ONE, Vec2::Y];
+        for (corner, expected) in area.corners.iter().zip(unit) {
+            assert!(apply(&to_area, *corner).distance(expected) < 1e-3);
+        }
+        let inside = apply(&to_area, Vec2::new(530.0, 390.0));
+        assert_eq!(area.cell(inside), Some(UVec2::new(3, 2)));
+        assert_eq!(area.cell(apply(&to_area, Vec2::new(20.0, 20.0))), None);
+    }
+}
@@ -0,0 +1,431 @@
+//! The Kinect through libfreenect, with the `freenect` feature (on by
+//! default). The freenect context, device and streams all live on one thread
+//! that forwards owned frames over channels, so the device can be stopped,
+//! joined and reopened instead of being leaked for the whole lifetime of the
+//! app. [`FreenectBackend`] puts it and the [`Motor`] behind
+//! [`DepthCameraBackend`].
+
+use std::slice;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use bevy::utils::synccell::SyncCell;
+use freenectrs::freenect;
+
+use crate::backend::{DepthCameraBackend, StreamHealth};
+use crate::consumer::FrameConsumers;
+use crate::device::{self, DepthFrame, KinectError, VideoFrame};
+use crate::embedded::UsbSettings;
+use crate::motor::{Led, Motor, MotorState};
+use crate::queue::FrameQueue;
+use crate::video::VideoSettings;
+use crate::DepthFormat;
+
+/// How long the acquisition loop waits for a depth frame before checking
+/// whether it should stop.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Handle to the acquisition thread. Frames arrive on `depth` and `video`,
+/// which stay the same across restarts.
+pub struct Kinect {
+    pub depth: FrameQueue<DepthFrame>,
+    pub video: FrameQueue<VideoFrame>,
+    frames: Arc<FrameCounters>,
+    video_settings: VideoSettings,
+    /// which Kinect, counting from 0
+    device_index: usize,
+    depth_format: DepthFormat,
+    consumers: FrameConsumers,
+    usb: UsbSettings,
+    thread: Option<AcquisitionThread>,
+    starts: u64,
+}
+
+/// Frames received from the device, across restarts.
+#[derive(Default)]
+struct FrameCounters {
+    depth: AtomicU64,
+    video: AtomicU64,
+    /// depth frames the device timestamps say never arrived
+    depth_missed: AtomicU64,
+    /// spread of the time between depth frames, microseconds
+    depth_jitter_us: AtomicU64,
+}
+
+/// Weight of a new interval in the running jitter.
+const JITTER_RATE: f64 = 0.05;
+
+/// Gaps in the depth stream, from the device's own frame timestamps. Frames
+/// libfreenect threw away because USB lost part of them leave a gap.
+#[derive(Default)]
+struct StreamTiming {
+    last_timestamp: Option<u32>,
+    /// shortest step between timestamps, one frame
+    frame_ticks: Option<u32>,
+    last_arrival: Option<Instant>,
+    mean_interval: f64,
+    variance: f64,
+}
+
+impl StreamTiming {
+    fn frame(&mut self, timestamp: u32, arrival: Instant, frames: &FrameCounters) {
+        if let Some(last) = self.last_timestamp {
+            let ticks = timestamp.wrapping_sub(last);
+            if ticks > 0 {
+                let frame_ticks = self.frame_ticks.map_or(ticks, |frame| frame.min(ticks));
+                self.frame_ticks = Some(frame_ticks);
+                let missed = (ticks as f64 / frame_ticks as f64).round() as u64;
+                frames
+                    .depth_missed
+                    .fetch_add(missed.saturating_sub(1), Ordering::Relaxed);
+            }
+        }
+        self.last_timestamp = Some(timestamp);
+
+        if let Some(last) = self.last_arrival {
+            let interval = (arrival - last).as_secs_f64();
+            if self.mean_interval == 0.0 {
+                self.mean_interval = interval;
+            } else {
+                let delta = interval - self.mean_interval;
+                self.mean_interval += JITTER_RATE * delta;
+                self.variance = (1.0 - JITTER_RATE) * (self.variance + JITTER_RATE * delta * delta);
+            }
+            frames.depth_jitter_us.store(
+                (self.variance.sqrt() * 1_000_000.0) as u64,
+                Ordering::Relaxed,
+            );
+        }
+        self.last_arrival = Some(arrival);
+    }
+}
+
+struct AcquisitionThread {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<Result<(), KinectError>>,
+}
+
+impl Kinect {
+    pub fn spawn(
+        video_settings: VideoSettings,
+        device_index: usize,
+        depth_format: DepthFormat,
+        consumers: FrameConsumers,
+        usb: UsbSettings,
+    ) -> Kinect {
+        let mut kinect = Kinect {
+            depth: FrameQueue::new(usb.frame_queue, usb.drop_policy),
+            video: FrameQueue::new(usb.frame_queue, usb.drop_policy),
+            frames: Arc::default(),
+            video_settings,
+            device_index,
+            depth_format,
+            consumers,
+            usb,
+            thread: None,
+            starts: 0,
+        };
+        kinect.start();
+        kinect
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.thread.is_some()
+    }
+
+    /// Number of depth frames the device delivered so far, including the ones
+    /// dropped because nobody picked them up in time.
+    pub fn depth_frames_received(&self) -> u64 {
+        self.frames.depth.load(Ordering::Relaxed)
+    }
+
+    /// Like [`Kinect::depth_frames_received`], for the video stream.
+    pub fn video_frames_received(&self) -> u64 {
+        self.frames.video.load(Ordering::Relaxed)
+    }
+
+    /// Like [`DepthCameraBackend::health`].
+    pub fn health(&self) -> StreamHealth {
+        StreamHealth {
+            depth_frames: self.depth_frames_received(),
+            missed_frames: self.frames.depth_missed.load(Ordering::Relaxed),
+            restarts: self.starts.saturating_sub(1),
+            jitter_ms: self.frames.depth_jitter_us.load(Ordering::Relaxed) as f32 / 1000.0,
+        }
+    }
+
+    /// Opens the device and starts streaming. Does nothing if already running.
+    pub fn start(&mut self) {
+        if self.thread.is_some() {
+            return;
+        }
+        self.starts += 1;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let stop = stop.clone();
+            let (depth_queue, video_queue) = (self.depth.clone(), self.video.clone());
+            let frames = self.frames.clone();
+            let consumers = self.consumers.clone();
+            let (video_settings, usb) = (self.video_settings, self.usb);
+            let (device_index, depth_format) = (self.device_index, self.depth_format);
+            thread::Builder::new()
+                .name("kinect".into())
+                .spawn(move || {
+                    acquire(
+                        (video_settings, usb),
+                        device_index,
+                        depth_format,
+                        &stop,
+                        (&depth_queue, &video_queue),
+                        &consumers,
+                        &frames,
+                    )
+                })
+                .unwrap()
+        };
+        self.thread = Some(AcquisitionThread { stop, handle });
+    }
+
+    /// Stops streaming, closes the device and waits for the thread to end.
+    pub fn stop(&mut self) -> Result<(), KinectError> {
+        match self.thread.take() {
+            Some(thread) => {
+                thread.stop.store(true, Ordering::Relaxed);
+                device::join(thread.handle)
+            }
+            None => Ok(()),
+        }
+    }
+
+    pub fn restart(&mut self) -> Result<(), KinectError> {
+        let stopped = self.stop();
+        self.start();
+        stopped
+    }
+
+    /// Consumers for the next start.
+    pub fn set_consumers(&mut self, consumers: FrameConsumers) {
+        self.consumers = consumers;
+    }
+
+    /// Video mode for the next start.
+    pub fn set_video_settings(&mut self, video_settings: VideoSettings) {
+        self.video_settings = video_settings;
+    }
+
+    /// Returns how the thread ended if it stopped on its own, e.g. because the
+    /// device could not be opened.
+    pub fn take_exit(&mut self) -> Option<Result<(), KinectError>> {
+        if !self.thread.as_ref()?.handle.is_finished() {
+            return None;
+        }
+        self.thread.take().map(|thread| device::join(thread.handle))
+    }
+}
+
+impl Drop for Kinect {
+    fn drop(&mut self) {
+        if let Err(err) = self.stop() {
+            error!("{err}");
+        }
+    }
+}
+
+fn acquire(
+    (video, usb): (VideoSettings, UsbSettings),
+    device_index: usize,
+    depth_format: DepthFormat,
+    stop: &AtomicBool,
+    (depth_queue, video_queue): (&FrameQueue<DepthFrame>, &FrameQueue<VideoFrame>),
+    consumers: &FrameConsumers,
+    frames: &FrameCounters,
+) -> Result<(), KinectError> {
+    let open = |err: freenect::FreenectError| KinectError::Open(err.to_string());
+
+    // the motor is opened separately, see `motor.rs`
+    let ctx = freenect::FreenectContext::init_with_video().map_err(open)?;
+
+    let dev_count = ctx.num_devices().map_err(open)?;
+    if dev_count as usize <= device_index {
+        return Err(KinectError::Open(format!(
+            "No device {device_index}, {dev_count} connected"
+        )));
+    }
+    info!("Found {dev_count} devices, using {device_index}");
+
+    let device = ctx.open_device(device_index as u32).map_err(open)?;
+
+    let format = match depth_format {
+        DepthFormat::Bit10 => freenect::FreenectDepthFormat::Bit10,
+        DepthFormat::Bit11 => freenect::FreenectDepthFormat::Bit11,
+        DepthFormat::Millimeters => freenect::FreenectDepthFormat::MM,
+        DepthFormat::Registered => freenect::FreenectDepthFormat::Registered,
+    };
+    device
+        .set_depth_mode(freenect::FreenectResolution::Medium, format)
+        .map_err(open)?;
+    let dstream = device.depth_stream().map_err(open)?;
+    let vstream = if usb.depth_only {
+        None
+    } else {
+        device
+            .set_video_mode(video.resolution.to_freenect(), video.format.to_freenect())
+            .map_err(open)?;
+        Some(device.video_stream().map_err(open)?)
+    };
+
+    ctx.spawn_process_thread().map_err(open)?;
+
+    let video_len = video.format.frame_len(video.resolution);
+    let mut timing = StreamTiming::default();
+    while !stop.load(Ordering::Relaxed) {
+        match dstream.receiver.recv_timeout(POLL_INTERVAL) {
+            Ok((data, timestamp)) => {
+                let sequence = frames.depth.fetch_add(1, Ordering::Relaxed) + 1;
+                timing.frame(timestamp, Instant::now(), frames);
+                let frame = match depth_format {
+                    DepthFormat::Bit10 => DepthFrame::new(data.to_vec(), sequence),
+                    _ => DepthFrame::new(
+                        data.iter().map(|raw| depth_format.to_bit10(*raw)).collect(),
+                        sequence,
+                    )
+                    .with_raw(depth_format, data.to_vec()),
+                }
+                .with_timestamp(timestamp.into());
+                consumers.depth(&frame.data);
+                depth_queue.push(frame);
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+
+        let vstream = match &vstream {
+            Some(vstream) => vstream,
+            None => continue,
+        };
+        while let Ok((data, _ /* timestamp */)) = vstream.receiver.try_recv() {
+            frames.video.fetch_add(1, Ordering::Relaxed);
+            // freenectrs always hands out a 640x480x3 slice, but the buffer
+            // behind it is allocated by libfreenect for the mode that is set.
+            // Medium IR drops its 8 extra rows at the bottom here.
+            let data = unsafe { slice::from_raw_parts(data.as_ptr(), video_len) };
+            consumers.video(data);
+            let frame = VideoFrame {
+                data: data.to_vec(),
+            };
+            video_queue.push(frame);
+        }
+    }
+
+    // Event processing has to end before the streams are dropped (in reverse
+    // order of declaration once this returns), freenectrs' callbacks panic
+    // when a frame arrives for a stream that is gone.
+    ctx.stop_process_thread()
+        .map_err(|panic| KinectError::ThreadPanicked(device::panic_message(&*panic)))
+}
+
+/// The Kinect through libfreenect: [`Kinect`] for the streams, and the
+/// [`Motor`] for tilt and LED if it could be opened.
+pub struct FreenectBackend {
+    kinect: Kinect,
+    /// only ever used through `&mut self`, libfreenect's motor calls aren't
+    /// safe to make from two threads at once
+    motor: Option<SyncCell<Motor>>,
+}
+
+impl FreenectBackend {
+    /// Starts streaming right away, like [`Kinect::spawn`].
+    pub fn spawn(
+        video_settings: VideoSettings,
+        device_index: usize,
+        depth_format: DepthFormat,
+        consumers: FrameConsumers,
+        usb: UsbSettings,
+    ) -> FreenectBackend {
+        let motor = match Motor::open(device_index as u32) {
+            Ok(motor) => Some(SyncCell::new(motor)),
+            Err(err) => {
+                warn!("{err}, tilt and LED disabled");
+                None
+            }
+        };
+        FreenectBackend {
+            kinect: Kinect::spawn(video_settings, device_index, depth_format, consumers, usb),
+            motor,
+        }
+    }
+
+    fn motor(&mut self) -> Result<&Motor, KinectError> {
+        match &mut self.motor {
+            Some(motor) => Ok(motor.get()),
+            None => Err(KinectError::Motor("not open".into())),
+        }
+    }
+}
+
+impl DepthCameraBackend for FreenectBackend {
+    fn open(&mut self) {
+        self.kinect.start();
+    }
+
+    fn close(&mut self) -> Result<(), KinectError> {
+        self.kinect.stop()
+    }
+
+    fn is_running(&self) -> bool {
+        self.kinect.is_running()
+    }
+
+    fn take_exit(&mut self) -> Option<Result<(), KinectError>> {
+        self.kinect.take_exit()
+    }
+
+    fn configure(&mut self, video: VideoSettings) {
+        self.kinect.set_video_settings(video);
+    }
+
+    fn set_consumers(&mut self, consumers: FrameConsumers) {
+        self.kinect.set_consumers(consumers);
+    }
+
+    fn next_frame(&mut self) -> Option<DepthFrame> {
+        self.kinect.depth.pop()
+    }
+
+    fn next_video_frame(&mut self) -> Option<VideoFrame> {
+        self.kinect.video.pop()
+    }
+
+    fn depth_frames_received(&self) -> u64 {
+        self.kinect.depth_frames_received()
+    }
+
+    fn video_frames_received(&self) -> u64 {
+        self.kinect.video_frames_received()
+    }
+
+    fn streams_video(&self) -> bool {
+        !self.kinect.usb.depth_only
+    }
+
+    fn health(&self) -> StreamHealth {
+        self.kinect.health()
+    }
+
+    fn tilt(&mut self, degrees: f64) -> Result<(), KinectError> {
+        Ok(self.motor()?.set_tilt_degrees(degrees)?)
+    }
+
+    fn tilt_state(&mut self) -> Result<MotorState, KinectError> {
+        Ok(self.motor()?.state()?)
+    }
+
+    fn led(&mut self, led: Led) -> Result<(), KinectError> {
+        Ok(self.motor()?.set_led(led)?)
+    }
+}
@@ -0,0 +1,359 @@
+//! Coverage view for installers: a small 3D inset in the bottom-left corner
+//! showing every [`KinectSensor`] with the volume it sees, a frustum from
+//! the depth intrinsics between the closest and furthest distance it
+//! measures. The frustum is a child of the sensor entity, so it tilts with
+//! the real device. F shows and hides it.
+//!
+//! The cursor's ray through the view is cast against the live depth, and the
+//! first reading it runs into is the [`SceneHover`] the hover readout shows:
+//! the depth pixel and the point it sees.
+
+use bevy::core_pipeline::clear_color::ClearColorConfig;
+use bevy::prelude::*;
+use bevy::render::camera::Viewport;
+use bevy::render::mesh::Indices;
+use bevy::render::render_resource::PrimitiveTopology;
+
+use crate::coords::{self, DEPTH_HEIGHT, DEPTH_WIDTH};
+use crate::picking::{self, DepthPicked};
+use crate::presentation::{DebugUi, PresentationSettings};
+use crate::tilt::KinectSensor;
+use crate::{CurrentDepth, MainKinect};
+
+/// Closest distance the sensor reads, in meters.
+pub const MIN_RANGE: f32 = 0.5;
+/// Furthest distance it reads reliably.
+pub const MAX_RANGE: f32 = 4.0;
+
+/// Inset size as a fraction of the window width.
+const INSET_SCALE: f32 = 0.3;
+const INSET_MARGIN: f32 = 8.0;
+
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct FrustumSettings {
+    pub visible: bool,
+    /// fill the window instead of the corner inset
+    pub full_window: bool,
+}
+
+#[derive(Component)]
+pub struct SceneCamera;
+
+/// What the cursor points at in the scene view, updated in [`SceneRaycast`].
+#[derive(Resource, Clone, Debug, Default, PartialEq)]
+pub struct SceneHover {
+    /// the cursor is over the scene view, which hides the depth view below
+    pub over: bool,
+    pub hit: Option<SceneHit>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SceneHit {
+    /// The main Kinect's live depth, at the reading the ray ran into.
+    Depth(DepthPicked),
+    /// The floor of a [room plan](crate::planning), in room coordinates,
+    /// with the depth pixel each planned sensor that sees it at the plan's
+    /// height would see it at, by index in the plan.
+    Floor {
+        point: Vec3,
+        pixels: Vec<(usize, Vec2)>,
+    },
+}
+
+/// Systems that cast the cursor into the scene view, before the hover
+/// readout shows what they hit.
+#[derive(SystemLabel)]
+pub struct SceneRaycast;
+
+#[derive(Component)]
+struct SceneBackdrop;
+
+pub struct FrustumPlugin;
+
+impl Plugin for FrustumPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<FrustumSettings>()
+            .init_resource::<SceneHover>()
+            .add_startup_system(spawn_scene_view)
+            .add_system(attach_frustums)
+            .add_system(toggle_scene_view)
+            .add_system(fit_scene_view)
+            .add_system(hover_depth.label(SceneRaycast));
+    }
+}
+
+/// The ray from the scene camera through the cursor, `None` while the
+/// view is closed or the cursor is outside it.
+pub fn cursor_ray(window: &Window, camera: &Camera, transform: &GlobalTransform) -> Option<Ray> {
+    if !camera.is_active {
+        return None;
+    }
+    let cursor = window.cursor_position()?;
+    let (min, max) = camera.logical_viewport_rect()?;
+    // the viewport counts from the top of the window, the cursor from the
+    // bottom
+    let position = Vec2::new(cursor.x - min.x, cursor.y - (window.height() - max.y));
+    let size = max - min;
+    if position.x < 0.0 || position.y < 0.0 || position.x > size.x || position.y > size.y {
+        return None;
+    }
+    camera.viewport_to_world(transform, position)
+}
+
+pub(crate) fn hover_depth(
+    windows: Res<Windows>,
+    camera_query: Query<(&Camera, &GlobalTransform), With<SceneCamera>>,
+    sensor_query: Query<&GlobalTransform, With<KinectSensor>>,
+    depth_query: Query<&CurrentDepth, With<MainKinect>>,
+    mut hover: ResMut<SceneHover>,
+) {
+    let ray = camera_query
+        .get_single()
+        .ok()
+        .and_then(|(camera, transform)| cursor_ray(windows.primary(), camera, transform));
+    let hit = match (ray, sensor_query.get_single(), depth_query.get_single()) {
+        (Some(ray), Ok(sensor), Ok(depth)) => depth_hit(depth, sensor, ray),
+        _ => None,
+    };
+    let next = SceneHover {
+        over: ray.is_some(),
+        hit,
+    };
+    if *hover != next {
+        *hover = next;
+    }
+}
+
+/// The first reading of `depth` along a world space ray, for the sensor
+/// posed at `sensor`. Only medium resolution frames, like the picking it
+/// goes through.
+fn depth_hit(depth: &CurrentDepth, sensor: &GlobalTransform, ray: Ray) -> Option<SceneHit> {
+    if !depth.is_medium() {
+        return None;
+    }
+    let to_sensor = sensor.compute_matrix().inverse();
+    // the sensor entity looks along -z, sensor space along +z
+    let flip = Vec3::new(1.0, 1.0, -1.0);
+    let origin = to_sensor.transform_point3(ray.origin) * flip;
+    let direction = (to_sensor.transform_vector3(ray.direction) * flip).normalize();
+    // the camera is further out than a pick ray reaches, start it where it
+    // comes in range of the sensor and in front of it
+    let along = -origin.dot(direction);
+    let miss = (origin + direction * along).length();
+    let mut start = along - (MAX_RANGE * MAX_RANGE - miss * miss).max(0.0).sqrt();
+    if origin.z < 0.0 && direction.z > 0.0 {
+        start = start.max(-origin.z / direction.z);
+    }
+    let origin = origin + direction * start.max(0.0);
+    picking::cast(&depth.depth_array, origin, direction).map(SceneHit::Depth)
+}
+
+fn spawn_scene_view(mut commands: Commands) {
+    commands.spawn((
+        Camera3dBundle {
+            camera: Camera {
+                // above everything drawn to the window
+                priority: 10,
+                is_active: false,
+                ..default()
+            },
+            camera_3d: Camera3d {
+                // clearing would wipe the whole window, not just the inset
+                clear_color: ClearColorConfig::None,
+                ..default()
+            },
+            transform: Transform::from_xyz(4.0, 2.5, 1.5)
+                .looking_at(Vec3::new(0.0, 0.0, -MAX_RANGE / 2.0), Vec3::Y),
+            ..default()
+        },
+        UiCameraConfig { show_ui: false },
+        SceneCamera,
+    ));
+
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                ..default()
+            },
+            background_color: Color::rgba(0.0, 0.0, 0.0, 0.7).into(),
+            visibility: Visibility { is_visible: false },
+            ..default()
+        },
+        SceneBackdrop,
+        DebugUi,
+    ));
+}
+
+fn attach_frustums(
+    mut commands: Commands,
+    sensor_query: Query<Entity, Added<KinectSensor>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    for sensor in sensor_query.iter() {
+        let material = materials.add(StandardMaterial {
+            base_color: Color::rgb(0.3, 0.9, 1.0),
+            unlit: true,
+            ..default()
+        });
+        commands
+            .entity(sensor)
+            .insert(VisibilityBundle::default())
+            .with_children(|parent| spawn_frustum(parent, &mut meshes, material));
+    }
+}
+
+/// Adds the frustum and a box for the device itself below a sensor
+/// entity, which needs a [`VisibilityBundle`] for them to show up.
+pub fn spawn_frustum(
+    parent: &mut ChildBuilder,
+    meshes: &mut Assets<Mesh>,
+    material: Handle<StandardMaterial>,
+) {
+    parent.spawn(PbrBundle {
+        mesh: meshes.add(frustum_mesh()),
+        material: material.clone(),
+        ..default()
+    });
+    parent.spawn(PbrBundle {
+        mesh: meshes.add(Mesh::from(shape::Box::new(0.28, 0.07, 0.07))),
+        material,
+        ..default()
+    });
+}
+
+fn toggle_scene_view(
+    keys: Res<Input<KeyCode>>,
+    presentation: Res<PresentationSettings>,
+    mut settings: ResMut<FrustumSettings>,
+    mut camera_query: Query<&mut Camera, With<SceneCamera>>,
+    mut backdrop_query: Query<&mut Visibility, With<SceneBackdrop>>,
+) {
+    if keys.just_pressed(KeyCode::F) {
+        settings.visible = !settings.visible;
+    }
+    if !settings.is_changed() {
+        return;
+    }
+    // debug views stay off in presentation mode
+    let visible = settings.visible && !presentation.enabled;
+    for mut camera in camera_query.iter_mut() {
+        camera.is_active = visible;
+    }
+    for mut backdrop in backdrop_query.iter_mut() {
+        backdrop.is_visible = visible;
+    }
+}
+
+fn fit_scene_view(
+    windows: Res<Windows>,
+    settings: Res<FrustumSettings>,
+    mut camera_query: Query<&mut Camera, With<SceneCamera>>,
+    mut backdrop_query: Query<&mut Style, With<SceneBackdrop>>,
+) {
+    let window = windows.primary();
+    let (size, margin) = if settings.full_window {
+        (Vec2::new(window.width(), window.height()), 0.0)
+    } else {
+        let width = window.width() * INSET_SCALE;
+        (Vec2::new(width, width * 0.75), INSET_MARGIN)
+    };
+    let top = window.height() - size.y - margin;
+
+    for mut camera in camera_query.iter_mut() {
+        let scale = window.scale_factor() as f32;
+        let position = (Vec2::new(margin, top.max(0.0)) * scale).as_uvec2();
+        let physical_size = (size * scale).as_uvec2().max(UVec2::ONE);
+        let fits = matches!(&camera.viewport, Some(viewport)
+            if viewport.physical_position == position && viewport.physical_size == physical_size);
+        if !fits {
+            camera.viewport = Some(Viewport {
+                physical_position: position,
+                physical_size,
+                ..default()
+            });
+        }
+    }
+    for mut style in backdrop_query.iter_mut() {
+        let position = UiRect {
+            left: Val::Px(margin),
+            bottom: Val::Px(margin),
+            ..default()
+        };
+        let inset = Size::new(Val::Px(size.x), Val::Px(size.y));
+        if style.position != position || style.size != inset {
+            style.position = position;
+            style.size = inset;
+        }
+    }
+}
+
+/// Edges of the viewing volume in the sensor's frame, which looks along -z
+/// like Bevy cameras do: near and far rectangles, the edges between them and
+/// lines back to the lens.
+fn frustum_mesh() -> Mesh {
+    let corners = |meters: f32| {
+        [
+            (0.0, 0.0),
+            (DEPTH_WIDTH as f32, 0.0),
+            (DEPTH_WIDTH as f32, DEPTH_HEIGHT as f32),
+            (0.0, DEPTH_HEIGHT as f32),
+        ]
+        .map(|(x, y)| {
+            let point = coords::depth_pixel_to_sensor(Vec2::new(x, y), meters);
+            Vec3::new(point.x, point.y, -point.z)
+        })
+    };
+    let (near, far) = (corners(MIN_RANGE), corners(MAX_RANGE));
+
+    let mut positions = vec![Vec3::ZERO];
+    positions.extend(near);
+    positions.extend(far);
+    let mut indices = Vec::new();
+    for i in 0..4u32 {
+        let next = (i + 1) % 4;
+        indices.extend([1 + i, 1 + next]);
+        indices.extend([5 + i, 5 + next]);
+        indices.extend([1 + i, 5 + i]);
+        indices.extend([0, 1 + i]);
+    }
+
+    let mut mesh = Mesh::new(PrimitiveTopology::LineList);
+    mesh.insert_attribute(
+        Mesh::ATTRIBUTE_NORMAL,
+        vec![[0.0, 1.0, 0.0]; positions.len()],
+    );
+    mesh.insert_attribute(
+        Mesh::ATTRIBUTE_POSITION,
+        positions.iter().map(|p| p.to_array()).collect::<Vec<_>>(),
+    );
+    mesh.set_indices(Some(Indices::U32(indices)));
+    mesh
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wall(width: usize, height: usize) -> CurrentDepth {
+        let mut depth = CurrentDepth::new(width, height, Handle::default());
+        depth.depth_array = vec![coords::meters_to_raw_depth(2.0); width * height];
+        depth
+    }
+
+    #[test]
+    fn rays_hit_medium_frames_only() {
+        // from behind the sensor straight through the middle of the view
+        let ray = Ray {
+            origin: Vec3::new(0.0, 0.0, 6.0),
+            direction: -Vec3::Z,
+        };
+        let sensor = GlobalTransform::IDENTITY;
+        match depth_hit(&wall(DEPTH_WIDTH, DEPTH_HEIGHT), &sensor, ray) {
+            Some(SceneHit::Depth(picked)) => assert!((picked.point.z - 2.0).abs() < 0.05),
+            hit => panic!("expected the wall, got {hit:?}"),
+        }
+        assert_eq!(depth_hit(&wall(320, 240), &sensor, ray), None);
+    }
+}
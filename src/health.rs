@@ -0,0 +1,138 @@
+//! Depth stream health. A flaky hub or long cable rarely kills the stream
+//! outright; first frames arrive late or incomplete and get thrown away, and
+//! the device gets reopened now and then. Once a second the backend's
+//! [`StreamHealth`] goes into Bevy's [`Diagnostics`] (`--diagnostics` logs
+//! them), and a warning points at the USB link when too many frames go
+//! missing. With every new frame, the time from the backend getting it to
+//! the view showing it goes in too, as `depth_latency`.
+
+use bevy::diagnostic::{Diagnostic, DiagnosticId, Diagnostics};
+use bevy::prelude::*;
+
+use crate::backend::{DepthCamera, StreamHealth};
+use crate::{CurrentDepth, KinectSet, MainKinect};
+
+/// Share of missed frames over [`WARN_WINDOW`] that warns.
+const WARN_MISSED: f32 = 0.05;
+/// Seconds the missed share is taken over.
+const WARN_WINDOW: usize = 10;
+
+pub const DEPTH_FPS: DiagnosticId =
+    DiagnosticId::from_u128(0x7c1e_52d0_4b8a_4f5e_9a3c_11d2_6e0f_8b01);
+/// Share of the frames the device sent that were lost, in %.
+pub const DEPTH_MISSED: DiagnosticId =
+    DiagnosticId::from_u128(0x7c1e_52d0_4b8a_4f5e_9a3c_11d2_6e0f_8b02);
+pub const DEPTH_JITTER: DiagnosticId =
+    DiagnosticId::from_u128(0x7c1e_52d0_4b8a_4f5e_9a3c_11d2_6e0f_8b03);
+pub const DEPTH_RESTARTS: DiagnosticId =
+    DiagnosticId::from_u128(0x7c1e_52d0_4b8a_4f5e_9a3c_11d2_6e0f_8b04);
+/// Milliseconds from the backend getting a frame to the view showing it.
+pub const DEPTH_LATENCY: DiagnosticId =
+    DiagnosticId::from_u128(0x7c1e_52d0_4b8a_4f5e_9a3c_11d2_6e0f_8b05);
+
+/// The latest sample, and one per second for the last [`WARN_WINDOW`].
+#[derive(Resource, Default)]
+pub struct HealthHistory {
+    pub latest: StreamHealth,
+    samples: Vec<StreamHealth>,
+    warned: bool,
+}
+
+impl HealthHistory {
+    /// Missed share of the frames over the window, 0..1.
+    pub fn missed_share(&self) -> f32 {
+        let (first, last) = match (self.samples.first(), self.samples.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return 0.0,
+        };
+        let received = last.depth_frames - first.depth_frames;
+        let missed = last.missed_frames - first.missed_frames;
+        if received + missed == 0 {
+            return 0.0;
+        }
+        missed as f32 / (received + missed) as f32
+    }
+}
+
+#[derive(Resource)]
+struct HealthTimer(Timer);
+
+pub struct HealthPlugin;
+
+impl Plugin for HealthPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<HealthHistory>()
+            .insert_resource(HealthTimer(Timer::from_seconds(1.0, TimerMode::Repeating)))
+            .add_startup_system(register_diagnostics)
+            .add_system(sample_health)
+            .add_system(sample_latency.after(KinectSet::Output));
+    }
+}
+
+fn register_diagnostics(mut diagnostics: ResMut<Diagnostics>) {
+    diagnostics.add(Diagnostic::new(DEPTH_FPS, "depth_fps", 10));
+    diagnostics.add(Diagnostic::new(DEPTH_MISSED, "depth_missed", 10).with_suffix("%"));
+    diagnostics.add(Diagnostic::new(DEPTH_JITTER, "depth_jitter", 10).with_suffix("ms"));
+    diagnostics.add(Diagnostic::new(DEPTH_RESTARTS, "depth_restarts", 1));
+    diagnostics.add(Diagnostic::new(DEPTH_LATENCY, "depth_latency", 30).with_suffix("ms"));
+}
+
+/// Once per frame from the device, after the view has it.
+fn sample_latency(
+    depth_query: Query<&CurrentDepth, (With<MainKinect>, Changed<CurrentDepth>)>,
+    mut last_sequence: Local<u64>,
+    mut diagnostics: ResMut<Diagnostics>,
+) {
+    if let Ok(depth) = depth_query.get_single() {
+        if depth.sequence == *last_sequence {
+            return;
+        }
+        *last_sequence = depth.sequence;
+        if let Some(age) = depth.age() {
+            diagnostics.add_measurement(DEPTH_LATENCY, || age.as_secs_f64() * 1000.0);
+        }
+    }
+}
+
+fn sample_health(
+    camera: Res<DepthCamera>,
+    time: Res<Time>,
+    mut timer: ResMut<HealthTimer>,
+    mut history: ResMut<HealthHistory>,
+    mut diagnostics: ResMut<Diagnostics>,
+) {
+    if !timer.0.tick(time.delta()).just_finished() {
+        return;
+    }
+    let health = camera.health();
+    let previous = std::mem::replace(&mut history.latest, health);
+    let received = health.depth_frames.saturating_sub(previous.depth_frames);
+    let missed = health.missed_frames.saturating_sub(previous.missed_frames);
+    let seconds = timer.0.duration().as_secs_f64();
+
+    diagnostics.add_measurement(DEPTH_FPS, || received as f64 / seconds);
+    diagnostics.add_measurement(DEPTH_MISSED, || {
+        if received + missed == 0 {
+            0.0
+        } else {
+            missed as f64 * 100.0 / (received + missed) as f64
+        }
+    });
+    diagnostics.add_measurement(DEPTH_JITTER, || health.jitter_ms as f64);
+    diagnostics.add_measurement(DEPTH_RESTARTS, || health.restarts as f64);
+
+    history.samples.push(health);
+    if history.samples.len() > WARN_WINDOW + 1 {
+        history.samples.remove(0);
+    }
+    let share = history.missed_share();
+    if share >= WARN_MISSED && !history.warned {
+        warn!(
+            "{:.0}% of depth frames lost over the last {WARN_WINDOW}s, check the USB cable and hub",
+            share * 100.0
+        );
+        history.warned = true;
+    } else if share < WARN_MISSED / 2.0 {
+        history.warned = false;
+    }
+}
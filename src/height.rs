@@ -0,0 +1,97 @@
+//! How tall the person in front of the sensor is, for age-gating content and
+//! putting UI at a height they can reach. The segmented body is measured
+//! along the accelerometer's up, from its lowest points, where the floor is,
+//! to the top of the head, and averaged while the same person stays.
+//!
+//! People partly out of view aren't measured: a body cut off by the top or
+//! bottom of the frame would come out short.
+
+use bevy::prelude::*;
+
+use crate::angles;
+use crate::coords::{self, DEPTH_HEIGHT, DEPTH_WIDTH};
+use crate::segmentation::PersonMask;
+use crate::tilt::TiltState;
+use crate::{CurrentDepth, MainKinect};
+
+/// Share of the body's points left out at the top and bottom, stray
+/// readings above the head and the floor right at the feet.
+const TRIM: f32 = 0.002;
+/// Person pixels needed before the mask counts as a body.
+const MIN_PIXELS: usize = 2000;
+/// Readings that go into the average, after that it follows slowly.
+const AVERAGE_FRAMES: u32 = 30;
+
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct PersonHeight {
+    /// meters, `None` while nobody is in full view
+    pub meters: Option<f32>,
+    readings: u32,
+}
+
+impl PersonHeight {
+    /// Under `meters`, e.g. 1.4 for most children.
+    pub fn is_shorter_than(&self, meters: f32) -> bool {
+        self.meters.is_some_and(|height| height < meters)
+    }
+}
+
+pub struct HeightPlugin;
+
+impl Plugin for HeightPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PersonHeight>()
+            .add_system(estimate_height);
+    }
+}
+
+fn estimate_height(
+    mask: Res<PersonMask>,
+    tilt: Res<TiltState>,
+    depth_query: Query<&CurrentDepth, With<MainKinect>>,
+    mut height: ResMut<PersonHeight>,
+) {
+    if !mask.is_changed() {
+        return;
+    }
+    let depth = match depth_query.get_single() {
+        Ok(depth) if depth.depth_array.len() == mask.0.len() => depth,
+        _ => return,
+    };
+    match measure_height(&mask.0, &depth.depth_array, angles::world_up(&tilt)) {
+        Some(meters) => {
+            let readings = (height.readings + 1).min(AVERAGE_FRAMES);
+            let average = height.meters.unwrap_or(meters);
+            *height = PersonHeight {
+                meters: Some(average + (meters - average) / readings as f32),
+                readings,
+            };
+        }
+        None => *height = PersonHeight::default(),
+    }
+}
+
+/// Top of the head above the floor under the feet, along `up`, `None` if
+/// there is no body or it runs off the top or bottom of the frame.
+pub fn measure_height(mask: &[bool], depth: &[u16], up: Vec3) -> Option<f32> {
+    let cut_off = |y: usize| mask[y * DEPTH_WIDTH..(y + 1) * DEPTH_WIDTH].contains(&true);
+    if mask.len() != DEPTH_WIDTH * DEPTH_HEIGHT || cut_off(0) || cut_off(DEPTH_HEIGHT - 1) {
+        return None;
+    }
+    let mut heights: Vec<f32> = mask
+        .iter()
+        .zip(depth)
+        .enumerate()
+        .filter_map(|(i, (person, raw))| {
+            let meters = coords::raw_depth_to_meters(*raw).filter(|_| *person)?;
+            let pixel = Vec2::new((i % DEPTH_WIDTH) as f32, (i / DEPTH_WIDTH) as f32);
+            Some(coords::depth_pixel_to_sensor(pixel, meters).dot(up))
+        })
+        .collect();
+    if heights.len() < MIN_PIXELS {
+        return None;
+    }
+    heights.sort_by(f32::total_cmp);
+    let trim = (heights.len() as f32 * TRIM) as usize;
+    Some(heights[heights.len() - 1 - trim] - heights[trim])
+}
@@ -0,0 +1,415 @@
+//! How the readings of each depth frame are spread, counted on the GPU. A
+//! compute shader bins the raw readings and the counts come back a frame or
+//! two later into [`DepthHistogram`], so the CPU never walks the frame for
+//! it, whatever the resolution. From there the depth view can be stretched
+//! over the distances actually in the scene and the close threshold can
+//! follow the nearest thing; both are off until [`HistogramSettings`] turns
+//! them on.
+//!
+//! Without a GPU that runs compute shaders the histogram stays empty.
+
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use bevy::prelude::*;
+use bevy::render::render_graph::{self, RenderGraph};
+use bevy::render::render_resource::*;
+use bevy::render::renderer::{RenderContext, RenderDevice, RenderQueue};
+use bevy::render::{Extract, RenderApp, RenderStage};
+use crossbeam_channel::{bounded, Receiver, Sender};
+
+use crate::{coords, CurrentDepth, KinectSet, MainKinect, TrackingSettings, NO_DEPTH};
+
+/// Raw readings per bin.
+pub const BIN_WIDTH: u16 = 4;
+pub const BINS: usize = (NO_DEPTH as usize + 1) / BIN_WIDTH as usize;
+/// Share of the readings left out at either end of the auto range, so a few
+/// stray pixels don't decide it.
+const RANGE_TAIL: f32 = 0.02;
+/// Meters behind the nearest readings the adaptive threshold sits.
+const ADAPTIVE_MARGIN: f32 = 0.25;
+const WORKGROUP_SIZE: u32 = 256;
+
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct HistogramSettings {
+    /// stretch the depth view's shading over [`DepthHistogram::range`]
+    pub auto_range: bool,
+    /// keep [`TrackingSettings::threshold`] just behind the nearest readings
+    pub adaptive_threshold: bool,
+}
+
+/// Readings of the latest counted frame per [`BIN_WIDTH`] raw values, holes
+/// left out.
+#[derive(Resource, Clone, Debug, Default)]
+pub struct DepthHistogram {
+    /// empty until the GPU has counted a frame
+    pub bins: Vec<u32>,
+    /// what the depth view is stretched over, with `auto_range`
+    pub display_range: Option<(u16, u16)>,
+}
+
+impl DepthHistogram {
+    pub fn readings(&self) -> u32 {
+        self.bins.iter().sum()
+    }
+
+    /// The raw value `share` of the readings are nearer than, to the bin.
+    pub fn percentile(&self, share: f32) -> Option<u16> {
+        let readings = self.readings();
+        if readings == 0 {
+            return None;
+        }
+        let wanted = (readings as f32 * share.clamp(0.0, 1.0)) as u32;
+        let mut seen = 0;
+        for (bin, count) in self.bins.iter().enumerate() {
+            seen += count;
+            if seen > wanted {
+                return Some(bin as u16 * BIN_WIDTH);
+            }
+        }
+        Some((self.bins.len() as u16 - 1) * BIN_WIDTH)
+    }
+
+    /// Nearest and furthest raw readings, less the outer few percent.
+    pub fn range(&self) -> Option<(u16, u16)> {
+        let near = self.percentile(RANGE_TAIL)?;
+        let far = self.percentile(1.0 - RANGE_TAIL)? + BIN_WIDTH - 1;
+        Some((near, far.max(near + 1)))
+    }
+}
+
+pub struct HistogramPlugin;
+
+impl Plugin for HistogramPlugin {
+    fn build(&self, app: &mut App) {
+        let (sender, receiver) = bounded(1);
+        app.init_resource::<HistogramSettings>()
+            .init_resource::<DepthHistogram>()
+            .insert_resource(HistogramReceiver(receiver))
+            .add_system(apply_histogram.before(KinectSet::Process));
+
+        let render_app = match app.get_sub_app_mut(RenderApp) {
+            Ok(render_app) => render_app,
+            Err(_) => return,
+        };
+        // WebGL and some older GPUs have nothing to count into
+        let storage_buffers = render_app
+            .world
+            .get_resource::<RenderDevice>()
+            .map_or(0, |device| {
+                device.limits().max_storage_buffers_per_shader_stage
+            });
+        if storage_buffers < 2 {
+            return;
+        }
+        render_app
+            .insert_resource(HistogramSender(sender))
+            .init_resource::<HistogramPipeline>()
+            .add_system_to_stage(RenderStage::Extract, extract_depth)
+            .add_system_to_stage(RenderStage::Prepare, prepare_histogram)
+            .add_system_to_stage(RenderStage::Cleanup, read_back_histogram);
+        let mut graph = render_app.world.resource_mut::<RenderGraph>();
+        graph.add_node("depth_histogram", HistogramNode);
+        graph
+            .add_node_edge(
+                "depth_histogram",
+                bevy::render::main_graph::node::CAMERA_DRIVER,
+            )
+            .unwrap();
+    }
+}
+
+#[derive(Resource)]
+struct HistogramReceiver(Receiver<Vec<u32>>);
+
+#[derive(Resource)]
+struct HistogramSender(Sender<Vec<u32>>);
+
+fn apply_histogram(
+    receiver: Res<HistogramReceiver>,
+    settings: Res<HistogramSettings>,
+    mut histogram: ResMut<DepthHistogram>,
+    mut tracking: ResMut<TrackingSettings>,
+) {
+    let bins = match receiver.0.try_recv() {
+        Ok(bins) => bins,
+        Err(_) => return,
+    };
+    histogram.bins = bins;
+    histogram.display_range = settings.auto_range.then(|| histogram.range()).flatten();
+    if settings.adaptive_threshold {
+        let nearest = histogram
+            .percentile(RANGE_TAIL)
+            .and_then(coords::raw_depth_to_meters);
+        if let Some(meters) = nearest {
+            let threshold = coords::meters_to_raw_depth(meters + ADAPTIVE_MARGIN);
+            if threshold != tracking.threshold {
+                tracking.threshold = threshold;
+            }
+        }
+    }
+}
+
+/// A new frame for the GPU to count.
+#[derive(Resource)]
+struct ExtractedDepth(Vec<u16>);
+
+#[allow(clippy::type_complexity)]
+fn extract_depth(
+    mut commands: Commands,
+    depth_query: Extract<Query<&CurrentDepth, (With<MainKinect>, Changed<CurrentDepth>)>>,
+) {
+    if let Ok(depth) = depth_query.get_single() {
+        if !depth.depth_array.is_empty() {
+            commands.insert_resource(ExtractedDepth(depth.depth_array.clone()));
+        }
+    }
+}
+
+#[derive(Resource)]
+struct HistogramPipeline {
+    layout: BindGroupLayout,
+    pipeline: CachedComputePipelineId,
+}
+
+impl FromWorld for HistogramPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let storage = |binding, read_only| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let layout =
+            world
+                .resource::<RenderDevice>()
+                .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                    label: Some("depth_histogram_layout"),
+                    entries: &[storage(0, true), storage(1, false)],
+                });
+        let shader = world
+            .resource::<AssetServer>()
+            .load("shaders/depth_histogram.wgsl");
+        let pipeline = world
+            .resource_mut::<PipelineCache>()
+            .queue_compute_pipeline(ComputePipelineDescriptor {
+                label: Some("depth_histogram".into()),
+                layout: Some(vec![layout.clone()]),
+                shader,
+                shader_defs: vec![],
+                entry_point: Cow::from("count_depth"),
+            });
+        HistogramPipeline { layout, pipeline }
+    }
+}
+
+/// The GPU side. A frame is counted into `bins` and copied to `readback`,
+/// which is read once mapped; no new frame goes up until then.
+#[derive(Resource)]
+struct HistogramBuffers {
+    depth: Buffer,
+    words: u32,
+    bins: Buffer,
+    readback: Buffer,
+    bind_group: BindGroup,
+    /// counting this frame
+    dispatch: bool,
+    /// `readback` has a count on the way
+    in_flight: bool,
+    mapped: Arc<AtomicBool>,
+}
+
+fn prepare_histogram(
+    mut commands: Commands,
+    (device, queue): (Res<RenderDevice>, Res<RenderQueue>),
+    (pipeline, pipeline_cache): (Res<HistogramPipeline>, Res<PipelineCache>),
+    extracted: Option<Res<ExtractedDepth>>,
+    mut buffers: Option<ResMut<HistogramBuffers>>,
+    sender: Res<HistogramSender>,
+) {
+    if let Some(buffers) = &mut buffers {
+        buffers.dispatch = false;
+        if buffers.in_flight && buffers.mapped.swap(false, Ordering::Acquire) {
+            let bins = bytes_to_words(&buffers.readback.slice(..).get_mapped_range());
+            buffers.readback.unmap();
+            buffers.in_flight = false;
+            // dropped if the app hasn't taken the last one yet
+            let _ = sender.0.try_send(bins);
+        }
+    }
+    let depth = match extracted {
+        Some(depth) => depth,
+        None => return,
+    };
+    commands.remove_resource::<ExtractedDepth>();
+    if buffers.as_ref().is_some_and(|buffers| buffers.in_flight)
+        || pipeline_cache
+            .get_compute_pipeline(pipeline.pipeline)
+            .is_none()
+    {
+        return;
+    }
+
+    // two readings to a word, the last one padded with a hole
+    let mut bytes = Vec::with_capacity(depth.0.len() * 2 + 2);
+    for raw in &depth.0 {
+        bytes.extend_from_slice(&raw.to_le_bytes());
+    }
+    if depth.0.len() % 2 == 1 {
+        bytes.extend_from_slice(&NO_DEPTH.to_le_bytes());
+    }
+    let words = (bytes.len() / 4) as u32;
+    let mut buffers = match buffers {
+        Some(buffers) if buffers.words == words => buffers,
+        _ => {
+            commands.insert_resource(create_buffers(&device, &pipeline, words));
+            return;
+        }
+    };
+    queue.write_buffer(&buffers.depth, 0, &bytes);
+    queue.write_buffer(&buffers.bins, 0, &[0; BINS * 4]);
+    buffers.dispatch = true;
+}
+
+fn create_buffers(
+    device: &RenderDevice,
+    pipeline: &HistogramPipeline,
+    words: u32,
+) -> HistogramBuffers {
+    let buffer = |label, size, usage| {
+        device.create_buffer(&BufferDescriptor {
+            label: Some(label),
+            size,
+            usage,
+            mapped_at_creation: false,
+        })
+    };
+    let depth = buffer(
+        "depth_histogram_depth",
+        words as u64 * 4,
+        BufferUsages::STORAGE | BufferUsages::COPY_DST,
+    );
+    let bins = buffer(
+        "depth_histogram_bins",
+        (BINS * 4) as u64,
+        BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+    );
+    let readback = buffer(
+        "depth_histogram_readback",
+        (BINS * 4) as u64,
+        BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+    );
+    let bind_group = device.create_bind_group(&BindGroupDescriptor {
+        label: Some("depth_histogram"),
+        layout: &pipeline.layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: depth.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: bins.as_entire_binding(),
+            },
+        ],
+    });
+    HistogramBuffers {
+        depth,
+        words,
+        bins,
+        readback,
+        bind_group,
+        dispatch: false,
+        in_flight: false,
+        mapped: Arc::default(),
+    }
+}
+
+fn bytes_to_words(bytes: &[u8]) -> Vec<u32> {
+    bytes
+        .chunks_exact(4)
+        .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+        .collect()
+}
+
+fn read_back_histogram(buffers: Option<ResMut<HistogramBuffers>>) {
+    if let Some(mut buffers) = buffers {
+        if buffers.dispatch {
+            let mapped = buffers.mapped.clone();
+            buffers
+                .readback
+                .slice(..)
+                .map_async(MapMode::Read, move |result| {
+                    mapped.store(result.is_ok(), Ordering::Release)
+                });
+            buffers.in_flight = true;
+        }
+    }
+}
+
+struct HistogramNode;
+
+impl render_graph::Node for HistogramNode {
+    fn run(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), render_graph::NodeRunError> {
+        let buffers = match world.get_resource::<HistogramBuffers>() {
+            Some(buffers) if buffers.dispatch => buffers,
+            _ => return Ok(()),
+        };
+        let pipeline = world.resource::<HistogramPipeline>();
+        let compute = match world
+            .resource::<PipelineCache>()
+            .get_compute_pipeline(pipeline.pipeline)
+        {
+            Some(compute) => compute,
+            None => return Ok(()),
+        };
+        {
+            let mut pass = render_context
+                .command_encoder
+                .begin_compute_pass(&ComputePassDescriptor::default());
+            pass.set_bind_group(0, &buffers.bind_group, &[]);
+            pass.set_pipeline(compute);
+            pass.dispatch_workgroups(buffers.words.div_ceil(WORKGROUP_SIZE), 1, 1);
+        }
+        render_context.command_encoder.copy_buffer_to_buffer(
+            &buffers.bins,
+            0,
+            &buffers.readback,
+            0,
+            (BINS * 4) as u64,
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn range_leaves_out_the_tails() {
+        let mut histogram = DepthHistogram::default();
+        assert_eq!(histogram.range(), None);
+
+        // a stray reading either side of 1000 between raw 400 and 600
+        histogram.bins = vec![0; BINS];
+        histogram.bins[10] = 1;
+        for bin in 100..150 {
+            histogram.bins[bin] = 20;
+        }
+        histogram.bins[250] = 1;
+        assert_eq!(histogram.percentile(0.0), Some(40));
+        assert_eq!(histogram.percentile(0.5), Some(500));
+        assert_eq!(histogram.range(), Some((400, 599)));
+    }
+}
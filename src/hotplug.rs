@@ -0,0 +1,270 @@
+//! Unplugging and plugging back in. With the plugin's own Kinect (the
+//! `freenect` backend, no [`DepthCamera`] brought by the app) a thread lists
+//! the connected Kinects every second. When the main one drops off the list
+//! its streams are torn down and a [`DeviceDisconnected`] goes out; when it
+//! comes back, under whatever index it has now, it is opened afresh, motor
+//! included, and a [`DeviceConnected`] goes out. Which one is the main
+//! Kinect goes by serial number, [`KinectConfig::serial`] or the serial of
+//! the device opened at startup.
+//!
+//! While the device is unplugged the [reconnect](crate::reconnect)
+//! supervisor holds off instead of retrying into nothing. Other backends
+//! are left to the supervisor alone. The microphones aren't reopened.
+
+use bevy::prelude::*;
+
+#[cfg(feature = "freenect")]
+use crate::devices::KinectDeviceInfo;
+#[cfg(feature = "freenect")]
+use crate::KinectConfig;
+
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct HotplugSettings {
+    pub enabled: bool,
+    /// seconds between looks at the USB bus
+    pub poll_interval: f32,
+}
+
+impl Default for HotplugSettings {
+    fn default() -> Self {
+        HotplugSettings {
+            enabled: true,
+            poll_interval: 1.0,
+        }
+    }
+}
+
+/// The main Kinect was plugged back in and is being opened.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeviceConnected {
+    pub serial: String,
+    /// what it was opened with, see [`KinectDevices`](crate::devices::KinectDevices)
+    pub index: usize,
+}
+
+/// The main Kinect went off the USB bus.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeviceDisconnected {
+    pub serial: String,
+}
+
+/// Marks the main Kinect as the plugin's own, the one hotplug may reopen.
+#[cfg(feature = "freenect")]
+#[derive(Resource)]
+pub(crate) struct PluginKinect;
+
+/// What changed about the watched device between two lists.
+#[cfg(feature = "freenect")]
+#[derive(Debug, PartialEq, Eq)]
+enum Change {
+    Disconnected,
+    Connected(usize),
+}
+
+/// Whether the watched device is on the list, by serial.
+#[cfg(feature = "freenect")]
+#[derive(Debug, Default)]
+struct Presence {
+    serial: Option<String>,
+    present: bool,
+}
+
+#[cfg(feature = "freenect")]
+impl Presence {
+    /// `index` is the one opened at startup, for finding the serial when
+    /// none was asked for.
+    fn update(&mut self, devices: &[KinectDeviceInfo], index: usize) -> Option<Change> {
+        if self.serial.is_none() {
+            let device = devices.iter().find(|device| device.index == index)?;
+            self.serial = Some(device.serial.clone());
+            self.present = true;
+            return None;
+        }
+        let serial = self.serial.as_deref()?;
+        let device = devices.iter().find(|device| device.serial == serial);
+        match (self.present, device) {
+            (true, None) => {
+                self.present = false;
+                Some(Change::Disconnected)
+            }
+            (false, Some(device)) => {
+                self.present = true;
+                Some(Change::Connected(device.index))
+            }
+            _ => None,
+        }
+    }
+}
+
+pub struct HotplugPlugin;
+
+impl Plugin for HotplugPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<HotplugSettings>()
+            .add_event::<DeviceConnected>()
+            .add_event::<DeviceDisconnected>();
+        #[cfg(feature = "freenect")]
+        app.add_startup_system_to_stage(StartupStage::PostStartup, watch::start_watcher)
+            .add_system(
+                watch::follow_devices
+                    .before(crate::reconnect::supervise_connection)
+                    .before(crate::KinectSet::Acquire),
+            );
+    }
+}
+
+#[cfg(feature = "freenect")]
+mod watch {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread::{self, JoinHandle};
+    use std::time::Duration;
+
+    use bevy::prelude::*;
+    use crossbeam_channel::{bounded, Receiver};
+
+    use super::{
+        Change, DeviceConnected, DeviceDisconnected, HotplugSettings, KinectConfig, PluginKinect,
+        Presence,
+    };
+    use crate::backend::DepthCamera;
+    use crate::consumer::FrameConsumers;
+    use crate::devices::{KinectDeviceInfo, KinectDevices};
+    use crate::embedded::UsbSettings;
+    use crate::freenect::FreenectBackend;
+    use crate::reconnect::Connection;
+    use crate::video::VideoSettings;
+
+    /// The listing thread, stopped and joined when dropped.
+    #[derive(Resource)]
+    pub struct Watcher {
+        lists: Receiver<Vec<KinectDeviceInfo>>,
+        stop: Arc<AtomicBool>,
+        thread: Option<JoinHandle<()>>,
+        presence: Presence,
+    }
+
+    impl Drop for Watcher {
+        fn drop(&mut self) {
+            self.stop.store(true, Ordering::Relaxed);
+            if let Some(thread) = self.thread.take() {
+                let _ = thread.join();
+            }
+        }
+    }
+
+    pub fn start_watcher(
+        mut commands: Commands,
+        settings: Res<HotplugSettings>,
+        owned: Option<Res<PluginKinect>>,
+        config: Res<KinectConfig>,
+    ) {
+        if !settings.enabled || owned.is_none() {
+            return;
+        }
+        let (sender, lists) = bounded(1);
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let interval = Duration::from_secs_f32(settings.poll_interval.max(0.1));
+        let thread = thread::Builder::new()
+            .name("kinect hotplug".into())
+            .spawn(move || {
+                while !thread_stop.load(Ordering::Relaxed) {
+                    if let Ok(devices) = KinectDevices::list() {
+                        // the app takes one list at a time, a newer one follows
+                        let _ = sender.try_send(devices.0);
+                    }
+                    thread::sleep(interval);
+                }
+            })
+            .expect("Unable to spawn the hotplug thread");
+        commands.insert_resource(Watcher {
+            lists,
+            stop,
+            thread: Some(thread),
+            presence: Presence {
+                serial: config.serial.clone(),
+                present: true,
+            },
+        });
+    }
+
+    pub fn follow_devices(
+        mut commands: Commands,
+        watcher: Option<ResMut<Watcher>>,
+        (mut config, mut devices): (ResMut<KinectConfig>, ResMut<KinectDevices>),
+        (video, usb, consumers): (Res<VideoSettings>, Res<UsbSettings>, Res<FrameConsumers>),
+        (mut camera, mut connection, time): (ResMut<DepthCamera>, ResMut<Connection>, Res<Time>),
+        mut connected: EventWriter<DeviceConnected>,
+        mut disconnected: EventWriter<DeviceDisconnected>,
+    ) {
+        let mut watcher = match watcher {
+            Some(watcher) => watcher,
+            None => return,
+        };
+        let list = match watcher.lists.try_recv() {
+            Ok(list) => list,
+            Err(_) => return,
+        };
+        let change = watcher.presence.update(&list, config.device_index);
+        devices.0 = list;
+        let serial = watcher.presence.serial.clone().unwrap_or_default();
+        match change {
+            Some(Change::Disconnected) => {
+                warn!("Kinect {serial} unplugged");
+                connection.unplugged();
+                if let Err(err) = camera.close() {
+                    warn!("{err}");
+                }
+                disconnected.send(DeviceDisconnected { serial });
+            }
+            Some(Change::Connected(index)) => {
+                info!("Kinect {serial} plugged in as device {index}, opening it");
+                config.device_index = index;
+                // a new backend, the old one's streams and motor are gone
+                // with the device
+                commands.insert_resource(DepthCamera::new(FreenectBackend::spawn(
+                    *video,
+                    index,
+                    config.depth_format,
+                    consumers.clone(),
+                    *usb,
+                )));
+                connection.reopened(time.elapsed_seconds_f64());
+                connected.send(DeviceConnected { serial, index });
+            }
+            None => {}
+        }
+    }
+}
+
+#[cfg(all(test, feature = "freenect"))]
+mod tests {
+    use super::*;
+    use crate::devices::Subdevices;
+
+    #[test]
+    fn the_main_kinect_comes_and_goes() {
+        let device = |index, serial: &str| KinectDeviceInfo {
+            index,
+            serial: serial.into(),
+            subdevices: Subdevices::default(),
+        };
+        let mut presence = Presence::default();
+        let both = [device(0, "A00364A11700045A"), device(1, "B00367611930037B")];
+        // device 1 was opened at startup
+        assert_eq!(presence.update(&both, 1), None);
+        assert_eq!(presence.update(&both, 1), None);
+
+        assert_eq!(
+            presence.update(&[device(0, "A00364A11700045A")], 1),
+            Some(Change::Disconnected)
+        );
+        assert_eq!(presence.update(&[], 1), None);
+        // back on another port, ahead of the other one now
+        assert_eq!(
+            presence.update(&[device(0, "B00367611930037B")], 1),
+            Some(Change::Connected(0))
+        );
+    }
+}
@@ -0,0 +1,222 @@
+//! Operating hours for permanent installs. Outside them the streams are
+//! stopped and the device closed with its LED off, which is most of the
+//! sensor's wear, and it is opened again when the hours start. While closed
+//! the app either holds [attract mode](crate::attract), for a screen that
+//! keeps looping its own content, or shows that it is closed and when it
+//! opens again.
+//!
+//! There is no time zone database: the clock is UTC shifted by
+//! [`HoursSettings::utc_offset`], which has to be changed by hand for
+//! daylight saving time.
+
+use std::fmt;
+use std::str::FromStr;
+
+use bevy::prelude::*;
+
+use crate::analytics::unix_now;
+use crate::attract::AttractMode;
+use crate::device::KinectCommand;
+use crate::motor::Led;
+use crate::reconnect::Connection;
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+/// Minutes since midnight.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TimeOfDay(pub u32);
+
+impl TimeOfDay {
+    /// Now, on the clock `utc_offset` hours from UTC.
+    pub fn now(utc_offset: f32) -> TimeOfDay {
+        let seconds = unix_now() as i64;
+        let minutes = seconds / 60 + (utc_offset * 60.0).round() as i64;
+        TimeOfDay(minutes.rem_euclid(MINUTES_PER_DAY as i64) as u32)
+    }
+}
+
+impl FromStr for TimeOfDay {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parsed = s.split_once(':').and_then(|(hours, minutes)| {
+            Some((hours.parse::<u32>().ok()?, minutes.parse::<u32>().ok()?))
+        });
+        match parsed {
+            Some((hours, minutes)) if hours < 24 && minutes < 60 => {
+                Ok(TimeOfDay(hours * 60 + minutes))
+            }
+            _ => Err(format!("bad time of day '{s}', expected hh:mm")),
+        }
+    }
+}
+
+impl fmt::Display for TimeOfDay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.0 / 60, self.0 % 60)
+    }
+}
+
+/// When the install is open, `opens` up to but not including `closes`.
+/// Hours that end before they start run past midnight.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OperatingHours {
+    pub opens: TimeOfDay,
+    pub closes: TimeOfDay,
+}
+
+impl OperatingHours {
+    pub fn is_open(&self, time: TimeOfDay) -> bool {
+        if self.opens <= self.closes {
+            self.opens <= time && time < self.closes
+        } else {
+            time >= self.opens || time < self.closes
+        }
+    }
+}
+
+impl FromStr for OperatingHours {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (opens, closes) = s
+            .split_once('-')
+            .ok_or_else(|| format!("bad operating hours '{s}', expected hh:mm-hh:mm"))?;
+        let hours = OperatingHours {
+            opens: opens.trim().parse()?,
+            closes: closes.trim().parse()?,
+        };
+        if hours.opens == hours.closes {
+            return Err(format!("operating hours '{s}' never open"));
+        }
+        Ok(hours)
+    }
+}
+
+/// What the app does outside the hours, the device is closed either way.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ClosedMode {
+    /// stay in attract mode
+    #[default]
+    Attract,
+    /// show that it is closed and when it opens
+    Off,
+}
+
+impl FromStr for ClosedMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "attract" => Ok(ClosedMode::Attract),
+            "off" => Ok(ClosedMode::Off),
+            _ => Err(format!(
+                "unknown closed mode '{s}', expected attract or off"
+            )),
+        }
+    }
+}
+
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct HoursSettings {
+    /// `None` runs around the clock
+    pub hours: Option<OperatingHours>,
+    pub closed: ClosedMode,
+    /// hours the local clock is ahead of UTC
+    pub utc_offset: f32,
+}
+
+/// Sent when the hours start or end.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HoursEvent {
+    Opened,
+    Closed,
+}
+
+#[derive(Resource, Debug)]
+pub struct Operating {
+    open: bool,
+}
+
+impl Default for Operating {
+    fn default() -> Self {
+        // the device is opened at startup
+        Operating { open: true }
+    }
+}
+
+impl Operating {
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+}
+
+pub struct HoursPlugin;
+
+impl Plugin for HoursPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<HoursSettings>()
+            .init_resource::<Operating>()
+            .add_event::<HoursEvent>()
+            .add_system(follow_hours.before(crate::device::apply_kinect_commands))
+            .add_system(log_hours_events);
+    }
+}
+
+fn follow_hours(
+    settings: Res<HoursSettings>,
+    mut operating: ResMut<Operating>,
+    mut attract: ResMut<AttractMode>,
+    mut connection: ResMut<Connection>,
+    mut commands: EventWriter<KinectCommand>,
+    mut events: EventWriter<HoursEvent>,
+) {
+    let open = settings
+        .hours
+        .is_none_or(|hours| hours.is_open(TimeOfDay::now(settings.utc_offset)));
+    if open == operating.open {
+        return;
+    }
+    operating.open = open;
+    attract.hold(!open && settings.closed == ClosedMode::Attract);
+    if open {
+        commands.send(KinectCommand::Open);
+        events.send(HoursEvent::Opened);
+    } else {
+        // a pending retry would open it again
+        connection.cancel_retry();
+        commands.send(KinectCommand::Close);
+        commands.send(KinectCommand::Led(Led::Off));
+        events.send(HoursEvent::Closed);
+    }
+}
+
+fn log_hours_events(settings: Res<HoursSettings>, mut events: EventReader<HoursEvent>) {
+    for event in events.iter() {
+        match (event, settings.hours) {
+            (HoursEvent::Closed, Some(hours)) => {
+                info!("Closed, Kinect off until {}", hours.opens)
+            }
+            _ => info!("Operating hours started, opening the Kinect"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hours_can_run_past_midnight() {
+        let day: OperatingHours = "08:00-22:30".parse().unwrap();
+        let night: OperatingHours = "20:00-02:00".parse().unwrap();
+        let at = |time: &str| time.parse::<TimeOfDay>().unwrap();
+        assert!(day.is_open(at("08:00")) && day.is_open(at("22:29")));
+        assert!(!day.is_open(at("22:30")) && !day.is_open(at("07:59")));
+        assert!(night.is_open(at("23:00")) && night.is_open(at("01:59")));
+        assert!(!night.is_open(at("02:00")) && !night.is_open(at("12:00")));
+        assert!("8:00-8:00".parse::<OperatingHours>().is_err());
+        assert!("24:00-08:00".parse::<OperatingHours>().is_err());
+        assert_eq!(at("07:05").to_string(), "07:05");
+    }
+}
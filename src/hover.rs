@@ -0,0 +1,200 @@
+//! Tooltip with the depth pixel under the mouse, the point it sees in the
+//! [`WorldConvention`] (sensor space, x right, y up, z forward, in meters
+//! unless set otherwise) and where that point lands in the color image, plus
+//! how far away the closest thing in view is and how much to trust the
+//! reading, from the [`ConfidenceMap`].
+//!
+//! Over the 3D scene view it reads out what the cursor's ray hits there, the
+//! [`SceneHover`]: on the live depth the same as above for the pixel it ran
+//! into, on a room plan's floor the point and each sensor's pixel for it.
+
+use bevy::prelude::*;
+
+use crate::confidence::ConfidenceMap;
+use crate::coords::{self, DisplayRect, Units, WorldConvention, DEPTH_HEIGHT, DEPTH_WIDTH};
+use crate::frustum::{SceneHit, SceneHover, SceneRaycast};
+use crate::inference::{self, FrameModel, FrameModelAppExt, Tensor};
+use crate::presentation::DebugUi;
+use crate::{CurrentDepth, MainKinect};
+
+#[derive(Component)]
+struct HoverText;
+
+/// Closest valid depth reading, if there is one.
+#[derive(Resource, Clone, Copy, Debug)]
+struct Nearest(Option<(f32, Vec2)>);
+
+pub struct HoverPlugin;
+
+impl Plugin for HoverPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_frame_model(nearest_model())
+            .add_startup_system(spawn_hover_text)
+            .add_system(update_hover_text.after(SceneRaycast));
+    }
+}
+
+/// Small enough to run inline, but going through the model hook keeps it
+/// off the main thread and shows how a real one is wired up.
+fn nearest_model() -> FrameModel<Nearest> {
+    FrameModel {
+        every: 3,
+        preprocess: Box::new(inference::depth_tensor),
+        model: Box::new(|input: Tensor| {
+            let width = input.shape[2];
+            let nearest = input
+                .data
+                .iter()
+                .enumerate()
+                .filter(|(_, meters)| **meters > 0.0)
+                .min_by(|(_, a), (_, b)| a.total_cmp(b));
+            // meters, x, y; empty without any reading
+            let data = match nearest {
+                Some((i, meters)) => vec![*meters, (i % width) as f32, (i / width) as f32],
+                None => vec![],
+            };
+            Ok(Tensor::new(vec![data.len()], data))
+        }),
+        postprocess: Box::new(|output| match output.data[..] {
+            [meters, x, y] => Nearest(Some((meters, Vec2::new(x, y)))),
+            _ => Nearest(None),
+        }),
+    }
+}
+
+fn spawn_hover_text(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn(
+            TextBundle::from_section(
+                "",
+                TextStyle {
+                    font: asset_server.load("fonts/Hack-Regular.ttf"),
+                    font_size: 14.0,
+                    color: Color::WHITE,
+                },
+            )
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                ..default()
+            }),
+        )
+        .insert((HoverText, DebugUi));
+}
+
+fn update_hover_text(
+    windows: Res<Windows>,
+    (rect, scene): (Res<DisplayRect>, Res<SceneHover>),
+    (convention, confidence): (Res<WorldConvention>, Res<ConfidenceMap>),
+    depth_query: Query<&CurrentDepth, With<MainKinect>>,
+    nearest: Option<Res<Nearest>>,
+    mut text_query: Query<(&mut Text, &mut Style, &mut Visibility), With<HoverText>>,
+) {
+    if let Ok((mut text, mut style, mut visibility)) = text_query.get_single_mut() {
+        let window = windows.primary();
+        // the scene view covers the depth view below it
+        let pixel = |cursor| match &scene.hit {
+            Some(SceneHit::Depth(picked)) => Some(picked.pixel),
+            _ if scene.over => None,
+            _ => rect.screen_to_image(cursor),
+        };
+        let readout = match (
+            window.cursor_position(),
+            &scene.hit,
+            depth_query.get_single(),
+        ) {
+            (Some(cursor), Some(SceneHit::Floor { point, pixels }), _) => {
+                Some((cursor, floor_readout(*point, pixels)))
+            }
+            (Some(cursor), _, Ok(depth)) => pixel(cursor)
+                .and_then(|pixel| {
+                    depth_readout(
+                        &depth.depth_array,
+                        pixel.x as usize,
+                        pixel.y as usize,
+                        &convention,
+                    )
+                    .map(|readout| {
+                        match confidence.get(pixel.x as usize, pixel.y as usize) {
+                            Some(trust) => format!("{readout}\nconfidence {trust:.2}"),
+                            None => readout,
+                        }
+                    })
+                })
+                .map(|readout| (cursor, readout)),
+            _ => None,
+        };
+
+        match readout {
+            Some((cursor, readout)) => {
+                text.sections[0].value = match nearest.as_deref() {
+                    Some(Nearest(Some((meters, pixel)))) => format!(
+                        "{readout}\nnearest {meters:.2} m at ({:.0}, {:.0})",
+                        pixel.x, pixel.y
+                    ),
+                    _ => readout,
+                };
+                style.position = UiRect {
+                    left: Val::Px(cursor.x + 12.0),
+                    top: Val::Px(window.height() - cursor.y + 12.0),
+                    ..default()
+                };
+                visibility.is_visible = true;
+            }
+            None => visibility.is_visible = false,
+        }
+    }
+}
+
+fn depth_readout(
+    depth: &[u16],
+    x: usize,
+    y: usize,
+    convention: &WorldConvention,
+) -> Option<String> {
+    if x >= DEPTH_WIDTH || y >= DEPTH_HEIGHT || depth.len() != DEPTH_WIDTH * DEPTH_HEIGHT {
+        return None;
+    }
+
+    let raw = depth[y * DEPTH_WIDTH + x];
+    let point = match coords::raw_depth_to_meters(raw) {
+        Some(meters) => {
+            let pixel = Vec2::new(x as f32, y as f32);
+            let p = convention.world_point(coords::depth_pixel_to_sensor(pixel, meters));
+            let color = coords::depth_pixel_to_color_pixel(pixel, meters);
+            // two decimals of a meter, whole millimeters
+            let digits = if convention.units == Units::Meters {
+                2
+            } else {
+                0
+            };
+            format!(
+                "{:+.digits$} {:+.digits$} {:+.digits$} {}\ncolor ({:.0}, {:.0})",
+                p.x,
+                p.y,
+                p.z,
+                convention.units.symbol(),
+                color.x,
+                color.y
+            )
+        }
+        None => "no depth".to_string(),
+    };
+    Some(format!("({x}, {y}) raw {raw}\n{point}"))
+}
+
+/// A room plan's floor under the cursor, in room coordinates.
+fn floor_readout(point: Vec3, pixels: &[(usize, Vec2)]) -> String {
+    let mut readout = format!("floor {:.2} {:.2} m", point.x, point.z);
+    if pixels.is_empty() {
+        readout.push_str("\nno sensor sees it");
+    }
+    for (i, pixel) in pixels {
+        readout.push_str(&format!(
+            "\nsensor {} ({:.0}, {:.0})",
+            i + 1,
+            pixel.x,
+            pixel.y
+        ));
+    }
+    readout
+}
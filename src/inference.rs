@@ -0,0 +1,241 @@
+//! Hook for running custom models on Kinect frames. A [`FrameModel`] is a
+//! pre-processing closure that turns a [`Frame`] into a [`Tensor`] on the main
+//! thread, a [`Model`] that runs on a worker thread of its own and a
+//! post-processing closure that turns its output into a resource. Whatever the
+//! post-processing returns is inserted into the world when it is done, so
+//! systems only see typed results.
+//!
+//! [`Model`] is what an ONNX session (or any other runtime) implements, the
+//! `onnx` feature has one for `.onnx` files in [`onnx`](crate::onnx); plain
+//! closures work too. Frames arriving while the model is still busy are
+//! skipped before pre-processing rather than queued, so slow models lag
+//! instead of piling up.
+
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use bevy::prelude::*;
+
+use crate::coords::{self, DEPTH_HEIGHT, DEPTH_WIDTH};
+use crate::video::{CurrentVideo, VideoSettings};
+use crate::{CurrentDepth, MainKinect};
+
+/// Flat `f32` data with a shape, row-major.
+#[derive(Clone, Debug, Default)]
+pub struct Tensor {
+    pub shape: Vec<usize>,
+    pub data: Vec<f32>,
+}
+
+impl Tensor {
+    pub fn new(shape: Vec<usize>, data: Vec<f32>) -> Tensor {
+        assert_eq!(
+            shape.iter().product::<usize>(),
+            data.len(),
+            "tensor data doesn't match its shape"
+        );
+        Tensor { shape, data }
+    }
+}
+
+pub trait Model: Send + 'static {
+    fn run(&mut self, input: Tensor) -> Result<Tensor, String>;
+}
+
+impl<F> Model for F
+where
+    F: FnMut(Tensor) -> Result<Tensor, String> + Send + 'static,
+{
+    fn run(&mut self, input: Tensor) -> Result<Tensor, String> {
+        self(input)
+    }
+}
+
+/// What pre-processing gets to look at.
+pub struct Frame<'a> {
+    /// raw Bit10 depth, 640x480
+    pub depth: &'a [u16],
+    /// latest video frame as RGBA, in the color camera's view
+    pub video: &'a [u8],
+    pub video_size: (u32, u32),
+}
+
+pub struct FrameModel<T> {
+    /// run on every n-th depth frame
+    pub every: u32,
+    pub preprocess: Box<dyn Fn(&Frame) -> Tensor + Send + Sync>,
+    pub model: Box<dyn Model>,
+    /// runs on the worker thread, after the model
+    pub postprocess: Box<dyn Fn(Tensor) -> T + Send + Sync>,
+}
+
+/// Whether the model producing `T` gets frames, for models only some views
+/// need. On from the start.
+#[derive(Resource)]
+pub struct ModelSwitch<T> {
+    pub on: bool,
+    output: PhantomData<fn() -> T>,
+}
+
+impl<T> ModelSwitch<T> {
+    pub fn new(on: bool) -> ModelSwitch<T> {
+        ModelSwitch {
+            on,
+            output: PhantomData,
+        }
+    }
+}
+
+#[derive(Resource)]
+struct ModelWorker<T> {
+    every: u32,
+    frames: u32,
+    preprocess: Box<dyn Fn(&Frame) -> Tensor + Send + Sync>,
+    /// set while the thread has a frame
+    busy: Arc<AtomicBool>,
+    input: SyncSender<Tensor>,
+    output: Mutex<Receiver<Result<T, String>>>,
+}
+
+pub trait FrameModelAppExt {
+    /// Starts the model's worker thread and keeps the latest result in the
+    /// `T` resource. Without threads, as in the browser, the model is left
+    /// out and `T` never shows up.
+    fn add_frame_model<T: Resource>(&mut self, model: FrameModel<T>) -> &mut Self;
+}
+
+impl FrameModelAppExt for App {
+    fn add_frame_model<T: Resource>(&mut self, model: FrameModel<T>) -> &mut Self {
+        let FrameModel {
+            every,
+            preprocess,
+            mut model,
+            postprocess,
+        } = model;
+        // one frame in flight, nothing is sent while `busy`
+        let busy = Arc::new(AtomicBool::new(false));
+        let (input, inputs) = sync_channel::<Tensor>(1);
+        let (outputs, output) = sync_channel(1);
+        let done = busy.clone();
+        let name = std::any::type_name::<T>();
+        let spawned = thread::Builder::new()
+            .name(format!("model {name}"))
+            .spawn(move || {
+                for tensor in inputs {
+                    let result = model.run(tensor).map(&postprocess);
+                    if outputs.send(result).is_err() {
+                        break;
+                    }
+                    done.store(false, Ordering::Release);
+                }
+            });
+        // in the browser there are no threads
+        if let Err(err) = spawned {
+            warn!("Unable to start a thread for {name}, leaving it out: {err}");
+            return self;
+        }
+
+        if !self.world.contains_resource::<ModelSwitch<T>>() {
+            self.insert_resource(ModelSwitch::<T>::new(true));
+        }
+        self.insert_resource(ModelWorker {
+            every: every.max(1),
+            frames: 0,
+            preprocess,
+            busy,
+            input,
+            output: Mutex::new(output),
+        })
+        .add_system(feed_frame_model::<T>)
+        .add_system(publish_frame_model::<T>)
+    }
+}
+
+fn feed_frame_model<T: Resource>(
+    (worker, switch): (Option<ResMut<ModelWorker<T>>>, Res<ModelSwitch<T>>),
+    settings: Res<VideoSettings>,
+    depth_query: Query<&CurrentDepth, (With<MainKinect>, Changed<CurrentDepth>)>,
+    video_query: Query<&CurrentVideo>,
+    images: Res<Assets<Image>>,
+    mut video: Local<Vec<u8>>,
+) {
+    let mut worker = match worker {
+        Some(worker) if switch.on => worker,
+        _ => return,
+    };
+    let depth = match depth_query.get_single() {
+        Ok(depth) if depth.is_medium() => depth,
+        _ => return,
+    };
+    worker.frames += 1;
+    // still busy with an earlier frame, skip this one before making a tensor
+    // of it
+    if worker.frames % worker.every != 0 || worker.busy.load(Ordering::Acquire) {
+        return;
+    }
+
+    video.clear();
+    if let Some(image) = video_query
+        .get_single()
+        .ok()
+        .and_then(|current| images.get(&current.handle))
+    {
+        settings.image_to_rgba(&image.data, &mut video);
+    }
+    let frame = Frame {
+        depth: &depth.depth_array,
+        video: &video,
+        video_size: settings.resolution.size(),
+    };
+    let tensor = (worker.preprocess)(&frame);
+    worker.busy.store(true, Ordering::Release);
+    // only fails once the thread is gone, which publishing reports
+    let _ = worker.input.try_send(tensor);
+}
+
+fn publish_frame_model<T: Resource>(mut commands: Commands, worker: Option<Res<ModelWorker<T>>>) {
+    let worker = match worker {
+        Some(worker) => worker,
+        None => return,
+    };
+    let name = std::any::type_name::<T>();
+    let received = worker.output.lock().unwrap().try_recv();
+    match received {
+        Ok(Ok(output)) => commands.insert_resource(output),
+        Ok(Err(err)) => error!("{name} failed: {err}"),
+        Err(TryRecvError::Empty) => {}
+        Err(TryRecvError::Disconnected) => {
+            error!("Model thread for {name} panicked, stopping it");
+            commands.remove_resource::<ModelWorker<T>>();
+        }
+    }
+}
+
+/// Depth in meters, 0 where there is no reading, shaped `[1, 480, 640]`.
+pub fn depth_tensor(frame: &Frame) -> Tensor {
+    let data = frame
+        .depth
+        .iter()
+        .map(|raw| coords::raw_depth_to_meters(*raw).unwrap_or(0.0))
+        .collect();
+    Tensor::new(vec![1, DEPTH_HEIGHT, DEPTH_WIDTH], data)
+}
+
+/// RGB in 0..1, planar, shaped `[1, 3, height, width]` like most image models
+/// take it. Empty until the first video frame is in.
+pub fn video_tensor(frame: &Frame) -> Tensor {
+    let (width, height) = (frame.video_size.0 as usize, frame.video_size.1 as usize);
+    if frame.video.len() != width * height * 4 {
+        return Tensor::new(vec![1, 3, 0, 0], vec![]);
+    }
+    let mut data = vec![0.0; 3 * width * height];
+    for (i, pixel) in frame.video.chunks_exact(4).enumerate() {
+        for channel in 0..3 {
+            data[channel * width * height + i] = pixel[channel] as f32 / 255.0;
+        }
+    }
+    Tensor::new(vec![1, 3, height, width], data)
+}
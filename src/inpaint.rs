@@ -0,0 +1,144 @@
+//! Fills the holes in the depth map, guided by the color image. Holes grow
+//! inwards from their edges a ring at a time, every pixel taking the mean of
+//! its filled neighbours weighted by how alike their colors are, so depth
+//! spreads along surfaces and stops at color edges.
+//!
+//! The frame is filled in place, everything after [`fill_depth_holes`] sees
+//! the complete map.
+
+use bevy::prelude::*;
+
+use crate::coords::{self, DEPTH_HEIGHT, DEPTH_WIDTH};
+use crate::quality::QualityLevel;
+use crate::video::{CurrentVideo, VideoSettings};
+use crate::{CurrentDepth, MainKinect, NO_DEPTH};
+
+/// Rings filled per frame at full quality, holes wider than twice this keep
+/// their middle.
+const MAX_ROUNDS: usize = 32;
+/// Color distance (0..1 per channel) at which neighbours count much less.
+const COLOR_SIGMA: f32 = 0.1;
+/// Distance used to look up the color of a hole, roughly where people stand.
+const HOLE_METERS: f32 = 1.5;
+
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct InpaintSettings {
+    pub enabled: bool,
+}
+
+pub fn fill_depth_holes(
+    (settings, quality): (Res<InpaintSettings>, Res<QualityLevel>),
+    video_settings: Res<VideoSettings>,
+    mut depth_query: Query<&mut CurrentDepth, With<MainKinect>>,
+    video_query: Query<&CurrentVideo>,
+    images: Res<Assets<Image>>,
+    mut filled_at: Local<f64>,
+    mut video: Local<Vec<u8>>,
+) {
+    if !settings.enabled {
+        return;
+    }
+    let mut depth = match depth_query.get_single_mut() {
+        Ok(depth) => depth,
+        Err(_) => return,
+    };
+    // only once per frame, and without touching change detection otherwise
+    if depth.received_at == *filled_at || depth.depth_array.len() != DEPTH_WIDTH * DEPTH_HEIGHT {
+        return;
+    }
+    *filled_at = depth.received_at;
+
+    let image = match video_query
+        .get_single()
+        .ok()
+        .and_then(|current| images.get(&current.handle))
+    {
+        Some(image) => image,
+        None => return,
+    };
+    video_settings.image_to_rgba(&image.data, &mut video);
+    let guide = guide_colors(&depth.depth_array, &video, video_settings.resolution.size());
+    fill_holes(
+        &mut depth.depth_array,
+        &guide,
+        quality.filter_rounds(MAX_ROUNDS),
+    );
+}
+
+/// Color seen at every depth pixel. Pixels without depth are looked up as
+/// if they were [`HOLE_METERS`] away.
+fn guide_colors(depth: &[u16], video: &[u8], (width, height): (u32, u32)) -> Vec<Vec3> {
+    let (width, height) = (width as usize, height as usize);
+    if video.len() != width * height * 4 {
+        return vec![Vec3::ZERO; depth.len()];
+    }
+    let scale = width as f32 / DEPTH_WIDTH as f32;
+
+    depth
+        .iter()
+        .enumerate()
+        .map(|(i, raw)| {
+            let pixel = Vec2::new((i % DEPTH_WIDTH) as f32, (i / DEPTH_WIDTH) as f32);
+            let meters = coords::raw_depth_to_meters(*raw).unwrap_or(HOLE_METERS);
+            let color = coords::depth_pixel_to_color_pixel(pixel, meters) * scale;
+            let (x, y) = (color.x as isize, color.y as isize);
+            if x < 0 || y < 0 || x as usize >= width || y as usize >= height {
+                return Vec3::ZERO;
+            }
+            let at = (y as usize * width + x as usize) * 4;
+            Vec3::new(video[at] as f32, video[at + 1] as f32, video[at + 2] as f32) / 255.0
+        })
+        .collect()
+}
+
+fn fill_holes(depth: &mut [u16], guide: &[Vec3], rounds: usize) {
+    let mut holes: Vec<usize> = (0..depth.len()).filter(|i| depth[*i] == NO_DEPTH).collect();
+
+    for _ in 0..rounds {
+        // fill a whole ring from what was known before it, so the result
+        // doesn't depend on scan order
+        let mut filled = Vec::new();
+        holes.retain(|&i| {
+            let (x, y) = ((i % DEPTH_WIDTH) as isize, (i / DEPTH_WIDTH) as isize);
+            let (mut weights, mut sum) = (0.0, 0.0);
+            for (dx, dy) in NEIGHBOURS {
+                let (nx, ny) = (x + dx, y + dy);
+                if nx < 0 || ny < 0 || nx >= DEPTH_WIDTH as isize || ny >= DEPTH_HEIGHT as isize {
+                    continue;
+                }
+                let j = ny as usize * DEPTH_WIDTH + nx as usize;
+                if depth[j] == NO_DEPTH {
+                    continue;
+                }
+                let distance = (guide[i] - guide[j]).length_squared();
+                let weight = (-distance / (2.0 * COLOR_SIGMA * COLOR_SIGMA))
+                    .exp()
+                    .max(1e-6);
+                weights += weight;
+                sum += weight * depth[j] as f32;
+            }
+            if weights > 0.0 {
+                filled.push((i, (sum / weights).round() as u16));
+            }
+            weights == 0.0
+        });
+
+        if filled.is_empty() {
+            break;
+        }
+        for (i, raw) in filled {
+            depth[i] = raw;
+        }
+    }
+}
+
+const NEIGHBOURS: [(isize, isize); 8] = [
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+    (-1, 0),
+    (1, 0),
+    (-1, 1),
+    (0, 1),
+    (1, 1),
+];
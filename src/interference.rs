@@ -0,0 +1,256 @@
+//! Interference between Kinects. Two structured-light projectors lighting the
+//! same surface confuse both sensors: every pixel where the dot patterns
+//! overlap drops in and out from frame to frame in a fine speckle, unlike the
+//! steady holes of shadows and glass. Pixels that keep flipping between a
+//! reading and a hole, in blocks too dense to be the edge of something, are
+//! counted as interference; [`IrInterference`] goes out, with the share of
+//! the view and where, when that grows past
+//! [`InterferenceSettings::warn_area`].
+//!
+//! Sensors that can switch their emitter can share a room by taking turns:
+//! with `--emitter-slot 1/2` on one machine and `--emitter-slot 2/2` on the
+//! other, each lights the scene in its own
+//! [`InterferenceSettings::slot_seconds`] slice of the wall clock, so the
+//! clocks have to be in sync. The Kinect through libfreenect can't switch
+//! its emitter, see [`crate::backend::DepthCameraBackend::emitter`].
+
+use std::str::FromStr;
+
+use bevy::prelude::*;
+
+use crate::analytics::unix_now;
+use crate::backend::DepthCamera;
+use crate::coords::{self, DEPTH_HEIGHT, DEPTH_WIDTH};
+use crate::pipeline::{self, DepthPipelineApp, DepthStage};
+use crate::{CurrentDepth, MainKinect};
+
+/// Weight of a new frame in each pixel's flip rate.
+const FLICKER_RATE: f32 = 0.1;
+/// Flip rate from which a pixel counts as flickering.
+const FLICKERING: f32 = 0.3;
+/// Side of the blocks flickering pixels are counted in.
+const BLOCK: usize = 8;
+/// Share of a block that has to flicker. Edges and shadow borders flicker
+/// too, but they only cross a block in a thin line.
+const BLOCK_SHARE: f32 = 0.25;
+/// Frames after the emitter comes on that aren't looked at, the pattern
+/// takes a moment to settle.
+const SETTLE_FRAMES: u32 = 5;
+
+#[derive(Resource, Clone, Debug)]
+pub struct InterferenceSettings {
+    /// share of the view that has to be affected for a warning
+    pub warn_area: f32,
+    /// this sensor's turn, `None` keeps the emitter on
+    pub slot: Option<EmitterSlot>,
+    pub slot_seconds: f32,
+}
+
+impl Default for InterferenceSettings {
+    fn default() -> Self {
+        InterferenceSettings {
+            warn_area: 0.02,
+            slot: None,
+            slot_seconds: 0.5,
+        }
+    }
+}
+
+/// One of `count` sensors taking turns, the `index`th from 0.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EmitterSlot {
+    pub index: u32,
+    pub count: u32,
+}
+
+impl EmitterSlot {
+    /// Whether it's this slot's turn at a unix time.
+    pub fn lit_at(&self, unix_seconds: f64, slot_seconds: f32) -> bool {
+        let slot = (unix_seconds / slot_seconds as f64) as u64 % self.count as u64;
+        slot == self.index as u64
+    }
+}
+
+impl FromStr for EmitterSlot {
+    type Err = String;
+
+    /// `<slot>/<count>`, slots counted from 1.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid emitter slot '{s}', expected e.g. 1/2");
+        let (slot, count) = s.split_once('/').ok_or_else(invalid)?;
+        let slot: u32 = slot.trim().parse().map_err(|_| invalid())?;
+        let count: u32 = count.trim().parse().map_err(|_| invalid())?;
+        if slot == 0 || slot > count {
+            return Err(invalid());
+        }
+        Ok(EmitterSlot {
+            index: slot - 1,
+            count,
+        })
+    }
+}
+
+/// Interference crossed the warning level.
+#[derive(Clone, Copy, Debug)]
+pub struct IrInterference {
+    /// share of the view affected, 0..1
+    pub area: f32,
+    /// around the affected blocks, depth pixels
+    pub bounds: Rect,
+}
+
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct Interference {
+    /// share of the view affected in the latest frame, 0..1
+    pub area: f32,
+    /// whether the emitter is lit, turns off in other sensors' slots
+    pub emitter_on: bool,
+}
+
+#[derive(Default)]
+struct PixelFlicker {
+    rate: Vec<f32>,
+    had_reading: Vec<bool>,
+    settling: u32,
+    warned: bool,
+}
+
+pub struct InterferencePlugin;
+
+impl Plugin for InterferencePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<InterferenceSettings>()
+            .insert_resource(Interference {
+                area: 0.0,
+                emitter_on: true,
+            })
+            .add_event::<IrInterference>()
+            .add_system(multiplex_emitter)
+            .add_depth_stage(
+                DepthStage::filter(pipeline::DetectInterference, detect_interference)
+                    .after(multiplex_emitter)
+                    .before(pipeline::FillHoles),
+            )
+            .add_system(warn_interference.after(detect_interference));
+    }
+}
+
+fn multiplex_emitter(
+    settings: Res<InterferenceSettings>,
+    mut camera: ResMut<DepthCamera>,
+    mut interference: ResMut<Interference>,
+    mut unsupported: Local<bool>,
+) {
+    let slot = match settings.slot {
+        Some(slot) if !*unsupported => slot,
+        _ => return,
+    };
+    let lit = slot.lit_at(unix_now(), settings.slot_seconds);
+    if lit == interference.emitter_on {
+        return;
+    }
+    match camera.emitter(lit) {
+        Ok(()) => interference.emitter_on = lit,
+        Err(err) => {
+            warn!("{err}, the emitter stays on");
+            *unsupported = true;
+        }
+    }
+}
+
+fn detect_interference(
+    depth_query: Query<&CurrentDepth, With<MainKinect>>,
+    mut interference: ResMut<Interference>,
+    mut events: EventWriter<IrInterference>,
+    settings: Res<InterferenceSettings>,
+    mut flicker: Local<PixelFlicker>,
+    mut last_frame: Local<f64>,
+) {
+    let depth = match depth_query.get_single() {
+        Ok(depth) if depth.received_at != *last_frame && depth.is_medium() => depth,
+        _ => return,
+    };
+    *last_frame = depth.received_at;
+    if depth.depth_array.len() != DEPTH_WIDTH * DEPTH_HEIGHT {
+        return;
+    }
+    // frames in the dark say nothing, and the history ends with them
+    if !interference.emitter_on {
+        flicker.settling = SETTLE_FRAMES;
+        return;
+    }
+    let flicker = &mut *flicker;
+    if flicker.settling > 0 || flicker.rate.len() != depth.depth_array.len() {
+        flicker.settling = flicker.settling.saturating_sub(1);
+        flicker.rate = vec![0.0; depth.depth_array.len()];
+        flicker.had_reading = depth
+            .depth_array
+            .iter()
+            .map(|raw| coords::raw_depth_to_meters(*raw).is_some())
+            .collect();
+        return;
+    }
+
+    for (i, raw) in depth.depth_array.iter().enumerate() {
+        let reading = coords::raw_depth_to_meters(*raw).is_some();
+        let flipped = if reading != flicker.had_reading[i] {
+            1.0
+        } else {
+            0.0
+        };
+        flicker.rate[i] += FLICKER_RATE * (flipped - flicker.rate[i]);
+        flicker.had_reading[i] = reading;
+    }
+
+    let (blocks_x, blocks_y) = (DEPTH_WIDTH / BLOCK, DEPTH_HEIGHT / BLOCK);
+    let mut affected = 0;
+    let mut bounds: Option<Rect> = None;
+    for by in 0..blocks_y {
+        for bx in 0..blocks_x {
+            let flickering = (by * BLOCK..(by + 1) * BLOCK)
+                .flat_map(|y| (bx * BLOCK..(bx + 1) * BLOCK).map(move |x| y * DEPTH_WIDTH + x))
+                .filter(|i| flicker.rate[*i] >= FLICKERING)
+                .count();
+            if (flickering as f32) < BLOCK_SHARE * (BLOCK * BLOCK) as f32 {
+                continue;
+            }
+            affected += 1;
+            let block = Rect::new(
+                (bx * BLOCK) as f32,
+                (by * BLOCK) as f32,
+                ((bx + 1) * BLOCK) as f32,
+                ((by + 1) * BLOCK) as f32,
+            );
+            bounds = Some(bounds.map_or(block, |bounds| bounds.union(block)));
+        }
+    }
+    interference.area = affected as f32 / (blocks_x * blocks_y) as f32;
+
+    // warn once per episode, it's over when it drops to half
+    if interference.area < settings.warn_area * 0.5 {
+        flicker.warned = false;
+    }
+    if let (Some(bounds), false) = (bounds, flicker.warned) {
+        if interference.area >= settings.warn_area {
+            flicker.warned = true;
+            events.send(IrInterference {
+                area: interference.area,
+                bounds,
+            });
+        }
+    }
+}
+
+fn warn_interference(mut events: EventReader<IrInterference>) {
+    for event in events.iter() {
+        warn!(
+            "IR interference on {:.0}% of the view, around ({:.0}, {:.0})-({:.0}, {:.0}): \
+             another sensor's projector is lighting the same surfaces",
+            event.area * 100.0,
+            event.bounds.min.x,
+            event.bounds.min.y,
+            event.bounds.max.x,
+            event.bounds.max.y,
+        );
+    }
+}
@@ -0,0 +1,318 @@
+//! Kinect v2 (Xbox One) through libfreenect2, with the `freenect2` feature and
+//! `--kinect2`. libfreenect2 only has a C++ API, `kinect2/shim.cpp` wraps the
+//! few calls needed.
+//!
+//! The time-of-flight depth comes as it is: 512x424 frames, the millimeters
+//! in [`CurrentDepth::raw`](crate::CurrentDepth::raw) and on the Bit10 scale
+//! next to them. The features that work pixel by pixel are written for the
+//! Kinect v1's 640x480 picture and sit these out; with
+//! [`v1_picture`](Freenect2Backend::v1_picture) depth is resampled onto that
+//! picture instead, looked up for each 640x480 pixel along the same ray, and
+//! the v2 sees wider than the v1, so the edges of its picture are cropped.
+//! The 1920x1080 color is always resampled into 640x480 RGB like that. It
+//! has no tilt motor and no LED to set.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use bevy::log::error;
+
+use crate::backend::DepthCameraBackend;
+use crate::consumer::FrameConsumers;
+use crate::coords::{
+    self, Intrinsics, COLOR_INTRINSICS, DEPTH_HEIGHT, DEPTH_INTRINSICS, DEPTH_WIDTH,
+};
+use crate::device::{self, DepthFrame, KinectError, VideoFrame};
+use crate::motor::{Led, MotorState};
+use crate::queue::{DropPolicy, FrameQueue};
+use crate::video::{VideoFormat, VideoResolution, VideoSettings};
+use crate::DepthFormat;
+
+const V2_DEPTH_WIDTH: usize = 512;
+const V2_DEPTH_HEIGHT: usize = 424;
+const V2_COLOR_WIDTH: usize = 1920;
+const V2_COLOR_HEIGHT: usize = 1080;
+/// How long the acquisition loop waits for frames before checking whether it
+/// should stop, in milliseconds.
+const POLL_MS: i32 = 10;
+
+#[allow(non_camel_case_types)]
+mod ffi {
+    use std::os::raw::c_int;
+
+    pub enum k2_device {}
+
+    #[repr(C)]
+    #[derive(Clone, Copy, Default)]
+    pub struct k2_intrinsics {
+        pub fx: f32,
+        pub fy: f32,
+        pub cx: f32,
+        pub cy: f32,
+    }
+
+    // linked by build.rs
+    extern "C" {
+        pub fn k2_open(depth: *mut k2_intrinsics, color: *mut k2_intrinsics) -> *mut k2_device;
+        pub fn k2_wait_frames(
+            dev: *mut k2_device,
+            timeout_ms: c_int,
+            depth: *mut f32,
+            color: *mut u8,
+        ) -> c_int;
+        pub fn k2_close(dev: *mut k2_device);
+    }
+}
+
+impl From<ffi::k2_intrinsics> for Intrinsics {
+    fn from(k: ffi::k2_intrinsics) -> Self {
+        Intrinsics {
+            fx: k.fx,
+            fy: k.fy,
+            cx: k.cx,
+            cy: k.cy,
+        }
+    }
+}
+
+#[derive(Default)]
+struct FrameCounters {
+    depth: AtomicU64,
+    video: AtomicU64,
+}
+
+struct AcquisitionThread {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<Result<(), KinectError>>,
+}
+
+/// A Kinect v2 behind [`DepthCameraBackend`]. Nothing is opened until
+/// [`DepthCameraBackend::open`].
+pub struct Freenect2Backend {
+    depth: FrameQueue<DepthFrame>,
+    video: FrameQueue<VideoFrame>,
+    frames: Arc<FrameCounters>,
+    video_settings: VideoSettings,
+    v1_picture: bool,
+    consumers: FrameConsumers,
+    thread: Option<AcquisitionThread>,
+}
+
+impl Freenect2Backend {
+    pub fn new(video_settings: VideoSettings) -> Freenect2Backend {
+        Freenect2Backend {
+            depth: FrameQueue::new(2, DropPolicy::default()),
+            video: FrameQueue::new(2, DropPolicy::default()),
+            frames: Arc::default(),
+            video_settings,
+            v1_picture: false,
+            consumers: FrameConsumers::default(),
+            thread: None,
+        }
+    }
+
+    /// Depth resampled onto the Kinect v1's 640x480 picture instead of the
+    /// v2's own 512x424, off by default.
+    pub fn v1_picture(mut self, v1_picture: bool) -> Freenect2Backend {
+        self.v1_picture = v1_picture;
+        self
+    }
+
+    /// Frames of each stream kept waiting for the app and which go when it
+    /// doesn't keep up, two with [`DropPolicy::KeepLatest`] by default.
+    pub fn queue(mut self, len: usize, policy: DropPolicy) -> Freenect2Backend {
+        self.depth = FrameQueue::new(len, policy);
+        self.video = FrameQueue::new(len, policy);
+        self
+    }
+}
+
+impl DepthCameraBackend for Freenect2Backend {
+    fn open(&mut self) {
+        if self.thread.is_some() {
+            return;
+        }
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let stop = stop.clone();
+            let (depth_queue, video_queue) = (self.depth.clone(), self.video.clone());
+            let frames = self.frames.clone();
+            let consumers = self.consumers.clone();
+            let video_settings = self.video_settings;
+            let v1_picture = self.v1_picture;
+            thread::Builder::new()
+                .name("kinect2".into())
+                .spawn(move || {
+                    acquire(
+                        (video_settings, v1_picture),
+                        &stop,
+                        (&depth_queue, &video_queue),
+                        &consumers,
+                        &frames,
+                    )
+                })
+                .unwrap()
+        };
+        self.thread = Some(AcquisitionThread { stop, handle });
+    }
+
+    fn close(&mut self) -> Result<(), KinectError> {
+        match self.thread.take() {
+            Some(thread) => {
+                thread.stop.store(true, Ordering::Relaxed);
+                device::join(thread.handle)
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn is_running(&self) -> bool {
+        self.thread.is_some()
+    }
+
+    fn take_exit(&mut self) -> Option<Result<(), KinectError>> {
+        if !self.thread.as_ref()?.handle.is_finished() {
+            return None;
+        }
+        self.thread.take().map(|thread| device::join(thread.handle))
+    }
+
+    fn configure(&mut self, video: VideoSettings) {
+        self.video_settings = video;
+    }
+
+    fn supports_video(&self, video: VideoSettings) -> bool {
+        video.format == VideoFormat::Rgb && video.resolution == VideoResolution::Medium
+    }
+
+    fn set_consumers(&mut self, consumers: FrameConsumers) {
+        self.consumers = consumers;
+    }
+
+    fn next_frame(&mut self) -> Option<DepthFrame> {
+        self.depth.pop()
+    }
+
+    fn next_video_frame(&mut self) -> Option<VideoFrame> {
+        self.video.pop()
+    }
+
+    fn depth_frames_received(&self) -> u64 {
+        self.frames.depth.load(Ordering::Relaxed)
+    }
+
+    fn video_frames_received(&self) -> u64 {
+        self.frames.video.load(Ordering::Relaxed)
+    }
+
+    fn tilt(&mut self, _degrees: f64) -> Result<(), KinectError> {
+        Err(KinectError::Unsupported("tilt on a Kinect v2"))
+    }
+
+    fn tilt_state(&mut self) -> Result<MotorState, KinectError> {
+        Err(KinectError::Unsupported("tilt on a Kinect v2"))
+    }
+
+    fn led(&mut self, _led: Led) -> Result<(), KinectError> {
+        Err(KinectError::Unsupported("the LED on a Kinect v2"))
+    }
+}
+
+impl Drop for Freenect2Backend {
+    fn drop(&mut self) {
+        if let Err(err) = self.close() {
+            error!("{err}");
+        }
+    }
+}
+
+fn acquire(
+    (video, v1_picture): (VideoSettings, bool),
+    stop: &AtomicBool,
+    (depth_queue, video_queue): (&FrameQueue<DepthFrame>, &FrameQueue<VideoFrame>),
+    consumers: &FrameConsumers,
+    frames: &FrameCounters,
+) -> Result<(), KinectError> {
+    if video.format != VideoFormat::Rgb || video.resolution != VideoResolution::Medium {
+        return Err(KinectError::Open(
+            "a Kinect v2 only streams rgb video at medium resolution".into(),
+        ));
+    }
+
+    let mut depth_k = ffi::k2_intrinsics::default();
+    let mut color_k = ffi::k2_intrinsics::default();
+    let dev = unsafe { ffi::k2_open(&mut depth_k, &mut color_k) };
+    if dev.is_null() {
+        return Err(KinectError::Open("No Kinect v2 connected".into()));
+    }
+
+    // libfreenect2's pictures are mirrored
+    let depth_lookup = coords::ray_lookup(
+        &DEPTH_INTRINSICS,
+        (DEPTH_WIDTH, DEPTH_HEIGHT),
+        &depth_k.into(),
+        (V2_DEPTH_WIDTH, V2_DEPTH_HEIGHT),
+        true,
+    );
+    let (color_width, color_height) = video.resolution.size();
+    let color_lookup = coords::ray_lookup(
+        &COLOR_INTRINSICS,
+        (color_width as usize, color_height as usize),
+        &color_k.into(),
+        (V2_COLOR_WIDTH, V2_COLOR_HEIGHT),
+        true,
+    );
+    let mut depth_mm = vec![0.0f32; V2_DEPTH_WIDTH * V2_DEPTH_HEIGHT];
+    let mut bgrx = vec![0u8; V2_COLOR_WIDTH * V2_COLOR_HEIGHT * 4];
+
+    while !stop.load(Ordering::Relaxed) {
+        let got =
+            unsafe { ffi::k2_wait_frames(dev, POLL_MS, depth_mm.as_mut_ptr(), bgrx.as_mut_ptr()) };
+        if got <= 0 {
+            continue;
+        }
+
+        let sequence = frames.depth.fetch_add(1, Ordering::Relaxed) + 1;
+        let mm: Vec<u16> = if v1_picture {
+            depth_lookup
+                .iter()
+                .map(|from| from.map_or(0, |from| depth_mm[from].round() as u16))
+                .collect()
+        } else {
+            // mirrored back
+            depth_mm
+                .chunks_exact(V2_DEPTH_WIDTH)
+                .flat_map(|row| row.iter().rev().map(|mm| mm.round() as u16))
+                .collect()
+        };
+        let data = mm
+            .iter()
+            .map(|mm| DepthFormat::Millimeters.to_bit10(*mm))
+            .collect::<Vec<_>>();
+        consumers.depth(&data);
+        let frame = DepthFrame::new(data, sequence).with_raw(DepthFormat::Millimeters, mm);
+        depth_queue.push(if v1_picture {
+            frame
+        } else {
+            frame.with_size(V2_DEPTH_WIDTH, V2_DEPTH_HEIGHT)
+        });
+
+        frames.video.fetch_add(1, Ordering::Relaxed);
+        let mut data = Vec::with_capacity(color_lookup.len() * 3);
+        for from in &color_lookup {
+            match from {
+                Some(from) => {
+                    let bgr = &bgrx[from * 4..from * 4 + 3];
+                    data.extend_from_slice(&[bgr[2], bgr[1], bgr[0]]);
+                }
+                None => data.extend_from_slice(&[0, 0, 0]),
+            }
+        }
+        consumers.video(&data);
+        video_queue.push(VideoFrame { data });
+    }
+
+    unsafe { ffi::k2_close(dev) };
+    Ok(())
+}
@@ -0,0 +1,79 @@
+//! The LED on the front of the Kinect as a resource. Setting [`KinectLed`]
+//! sends the colour to the device once, and again whenever the device is
+//! reopened, so an app can keep it in step with its own state the way it
+//! would any other resource:
+//!
+//! ```ignore
+//! fn signal(blob: Res<CloseBlob>, mut led: ResMut<KinectLed>) {
+//!     let wanted = if blob.0.is_some() { Led::Green } else { Led::BlinkRedYellow };
+//!     if led.0 != Some(wanted) {
+//!         led.0 = Some(wanted);
+//!     }
+//! }
+//! ```
+//!
+//! That one is built in as `--led-tracking`. A [`KinectCommand::Led`] sends
+//! a colour right away instead, and the resource doesn't know about it.
+
+use bevy::prelude::*;
+
+use crate::backend::DepthCamera;
+use crate::device::KinectCommand;
+use crate::motor::Led;
+use crate::CloseBlob;
+
+/// What the LED shows, `None` leaves it to [`KinectCommand::Led`].
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct KinectLed(pub Option<Led>);
+
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct LedSettings {
+    /// green while something is close, blinking while nothing is
+    pub tracking: bool,
+}
+
+pub struct LedPlugin;
+
+impl Plugin for LedPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<KinectLed>()
+            .init_resource::<LedSettings>()
+            .add_system(show_tracking.after(crate::pipeline::TrackCloseBlob))
+            .add_system(
+                send_led
+                    .after(show_tracking)
+                    .before(crate::device::apply_kinect_commands),
+            );
+    }
+}
+
+fn show_tracking(settings: Res<LedSettings>, blob: Res<CloseBlob>, mut led: ResMut<KinectLed>) {
+    if !settings.tracking {
+        return;
+    }
+    let wanted = match blob.0 {
+        Some(_) => Led::Green,
+        // the closest the Kinect has to blinking red
+        None => Led::BlinkRedYellow,
+    };
+    // only touch it on a change, every write would go out to the device
+    if led.0 != Some(wanted) {
+        led.0 = Some(wanted);
+    }
+}
+
+fn send_led(
+    led: Res<KinectLed>,
+    camera: Res<DepthCamera>,
+    mut commands: EventWriter<KinectCommand>,
+    mut was_running: Local<bool>,
+) {
+    // a reopened device starts with its LED in its own state
+    let reopened = camera.is_running() && !*was_running;
+    *was_running = camera.is_running();
+    if let Some(color) = led.0 {
+        if led.is_changed() || reopened {
+            commands.send(KinectCommand::Led(color));
+        }
+    }
+}
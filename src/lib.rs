@@ -0,0 +1,1367 @@
+//! Kinect depth and video in Bevy. [`KinectPlugin`] adds everything: the
+//! device, the depth and video streams, close blob and skeleton tracking and
+//! the features built on them, each its own plugin in its own module.
+//! Settings resources inserted before it are kept, anything left out starts
+//! at its defaults:
+//!
+//! ```ignore
+//! App::new()
+//!     .insert_resource(TrackingSettings { threshold: 450, ..default() })
+//!     .add_plugins(DefaultPlugins)
+//!     .add_plugins(KinectPlugin::default())
+//!     .run();
+//! ```
+//!
+//! [`KinectPlugin::builder`] sets the usual ones, and which Kinect to open,
+//! in one go with a [`KinectConfig`].
+//!
+//! `src/main.rs` is the app around it, with every setting on the command
+//! line.
+//!
+//! For the browser, build for `wasm32-unknown-unknown` without the sensor
+//! backends, `--no-default-features --features network`: frames then come
+//! from a capture box over the [`network`].
+
+use std::str::FromStr;
+
+use array2d::Array2D;
+use bevy::app::PluginGroupBuilder;
+use bevy::prelude::*;
+use bevy::render::render_resource::{Extent3d, TextureFormat};
+use bevy::utils::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+
+#[cfg(all(
+    target_arch = "wasm32",
+    any(feature = "freenect", feature = "freenect2", feature = "openni2")
+))]
+compile_error!(
+    "sensors can't be opened from the browser, build for wasm32 with --no-default-features --features network"
+);
+
+pub mod analytics;
+pub mod anchor;
+pub mod angles;
+pub mod attract;
+pub mod audio;
+pub mod backend;
+pub mod balance;
+pub mod blockage;
+pub mod body_gestures;
+pub mod booth;
+pub mod budget;
+pub mod calibration;
+pub mod capture;
+pub mod compare;
+pub mod composite;
+pub mod confidence;
+pub mod consumer;
+pub mod coords;
+pub mod dataset;
+#[cfg(feature = "remote")]
+pub mod delivery;
+pub mod device;
+pub mod devices;
+pub mod display;
+pub mod embedded;
+pub mod event_log;
+pub mod exposure;
+pub mod floor;
+#[cfg(feature = "freenect")]
+pub mod freenect;
+pub mod frustum;
+pub mod health;
+pub mod height;
+pub mod histogram;
+pub mod hotplug;
+pub mod hours;
+pub mod hover;
+pub mod inference;
+pub mod inpaint;
+pub mod interference;
+#[cfg(feature = "freenect2")]
+pub mod kinect2;
+pub mod led;
+pub mod mirror;
+pub mod motion;
+pub mod motor;
+pub mod multi;
+#[cfg(feature = "network")]
+pub mod network;
+pub mod numpy;
+#[cfg(feature = "onnx")]
+pub mod onnx;
+#[cfg(feature = "openni2")]
+pub mod openni2;
+pub mod overlay;
+pub mod picking;
+pub mod pipeline;
+pub mod planning;
+#[cfg(feature = "playback")]
+pub mod playback;
+pub mod players;
+pub mod pointer;
+pub mod poses;
+pub mod presentation;
+pub mod presets;
+pub mod proximity;
+pub mod quality;
+pub mod queue;
+pub mod reconnect;
+#[cfg(feature = "record")]
+pub mod recording;
+pub mod region;
+#[cfg(feature = "remote")]
+pub mod remote;
+#[cfg(feature = "mock")]
+pub mod replay;
+pub mod rgbd;
+pub mod segmentation;
+#[cfg(feature = "mock")]
+pub mod simulation;
+pub mod skeleton;
+pub mod span;
+pub mod tilt;
+pub mod touchless;
+pub mod upsample;
+pub mod video;
+pub mod watchdog;
+pub mod zones;
+
+use analytics::AnalyticsPlugin;
+use anchor::AnchorPlugin;
+use angles::AnglesPlugin;
+use attract::{AttractMode, AttractPlugin, AttractSettings};
+use audio::AudioPlugin;
+use backend::DepthCamera;
+use balance::BalancePlugin;
+use blockage::BlockagePlugin;
+use body_gestures::BodyGesturePlugin;
+use booth::BoothPlugin;
+use budget::{BudgetPlugin, FrameBudget};
+use calibration::CalibrationPlugin;
+use capture::{CaptureSettings, FrameGate, DEPTH_NATIVE_FPS};
+use compare::ComparePlugin;
+use composite::CompositePlugin;
+use confidence::ConfidencePlugin;
+use consumer::FrameConsumers;
+use coords::{DisplayRect, WorldConvention, DEPTH_HEIGHT, DEPTH_WIDTH, VIEW_SIZE};
+use dataset::DatasetPlugin;
+#[cfg(feature = "remote")]
+use delivery::DeliveryPlugin;
+use device::{DepthFrame, DevicePlugin, RawDepth};
+use devices::DevicesPlugin;
+use display::{DisplayPlugin, ViewImage};
+use embedded::EmbeddedPlugin;
+use event_log::EventLogPlugin;
+use exposure::{ExposurePlugin, LongExposure};
+use floor::FloorPlugin;
+#[cfg(feature = "freenect")]
+use freenect::FreenectBackend;
+use frustum::FrustumPlugin;
+use health::HealthPlugin;
+use height::HeightPlugin;
+use histogram::{DepthHistogram, HistogramPlugin};
+use hotplug::HotplugPlugin;
+use hours::HoursPlugin;
+use hover::HoverPlugin;
+use inpaint::InpaintSettings;
+use interference::InterferencePlugin;
+use led::LedPlugin;
+use mirror::{MirrorPlugin, MirrorSettings};
+use motion::MotionPlugin;
+use multi::{KinectId, MultiKinectPlugin};
+use numpy::NumpyPlugin;
+use overlay::StatusOverlayPlugin;
+use picking::PickingPlugin;
+use pipeline::{DepthPipelineApp, DepthStage};
+use planning::PlanningPlugin;
+use players::PlayerPlugin;
+use pointer::{PointerFilter, PointerSettings};
+use poses::PosePlugin;
+use presentation::PresentationPlugin;
+use presets::PresetPlugin;
+use proximity::ProximityPlugin;
+use quality::{QualityLevel, QualityPlugin};
+use reconnect::ReconnectPlugin;
+#[cfg(feature = "record")]
+use recording::RecordingPlugin;
+#[cfg(feature = "remote")]
+use remote::RemotePlugin;
+use rgbd::RgbdPlugin;
+use segmentation::{PersonMask, SegmentationPlugin};
+use skeleton::SkeletonPlugin;
+use span::SpanPlugin;
+use tilt::TiltPlugin;
+use touchless::TouchlessPlugin;
+use upsample::{UpsamplePlugin, UpsampledDepth};
+use video::{VideoFormat, VideoPlugin, VideoResolution, VideoSettings};
+use watchdog::WatchdogPlugin;
+use zones::ZonesPlugin;
+
+/// Bit10 value for pixels without a depth reading.
+pub const NO_DEPTH: u16 = 1023;
+
+/// How the Kinect delivers depth, and the units readings are given in, a
+/// threshold for one. Inside, frames are Bit10 readings whatever the
+/// format, converted with [`to_bit10`](Self::to_bit10) on the acquisition
+/// thread, so everything downstream shares one scale; the readings as they
+/// came stay next to them in [`CurrentDepth::raw`], for consumers that want
+/// what precision the format has past the Bit10 scale.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DepthFormat {
+    /// 10-bit disparity, about 5 m of range
+    #[default]
+    Bit10,
+    /// 11-bit disparity, the same scale with 2047 for no reading; what is
+    /// past the Bit10 range is too far to measure reliably and counts as
+    /// no reading
+    Bit11,
+    /// millimeters as converted by libfreenect, 0 for no reading
+    Millimeters,
+    /// millimeters shifted by libfreenect onto the color camera's view, so
+    /// each depth pixel lines up with the video pixel at the same place, see
+    /// [`rgbd`]; only at medium video resolution
+    Registered,
+}
+
+impl DepthFormat {
+    /// A reading in this format on the Bit10 scale.
+    pub fn to_bit10(self, reading: u16) -> u16 {
+        match self {
+            DepthFormat::Bit10 | DepthFormat::Bit11 => reading.min(NO_DEPTH),
+            DepthFormat::Millimeters | DepthFormat::Registered => match reading {
+                0 => NO_DEPTH,
+                mm => coords::meters_to_raw_depth(mm as f32 / 1000.0),
+            },
+        }
+    }
+
+    /// A Bit10 reading in this format.
+    pub fn from_bit10(self, raw: u16) -> u16 {
+        match (self, coords::raw_depth_to_meters(raw)) {
+            (DepthFormat::Bit10, _) => raw,
+            (DepthFormat::Bit11, Some(_)) => raw,
+            (DepthFormat::Bit11, None) => 2047,
+            (DepthFormat::Millimeters | DepthFormat::Registered, meters) => {
+                meters.map_or(0, |meters| (meters * 1000.0).round() as u16)
+            }
+        }
+    }
+}
+
+impl FromStr for DepthFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bit10" => Ok(DepthFormat::Bit10),
+            "bit11" => Ok(DepthFormat::Bit11),
+            "mm" => Ok(DepthFormat::Millimeters),
+            "registered" => Ok(DepthFormat::Registered),
+            _ => Err(format!(
+                "unknown depth format '{s}', expected bit10, bit11, mm or registered"
+            )),
+        }
+    }
+}
+
+/// Size of the depth frames the app works with. The Kinect's depth camera
+/// only has medium resolution: `Low` keeps the closest reading of each 2x2
+/// block, for a quarter of the work per frame, and `High` repeats each
+/// reading, to draw depth on the same grid as high resolution video.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DepthResolution {
+    Low,
+    #[default]
+    Medium,
+    High,
+}
+
+impl DepthResolution {
+    pub fn size(self) -> (usize, usize) {
+        match self {
+            DepthResolution::Low => (DEPTH_WIDTH / 2, DEPTH_HEIGHT / 2),
+            DepthResolution::Medium => (DEPTH_WIDTH, DEPTH_HEIGHT),
+            DepthResolution::High => (DEPTH_WIDTH * 2, DEPTH_HEIGHT * 2),
+        }
+    }
+}
+
+impl FromStr for DepthResolution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "low" => Ok(DepthResolution::Low),
+            "medium" => Ok(DepthResolution::Medium),
+            "high" => Ok(DepthResolution::High),
+            _ => Err(format!(
+                "unknown depth resolution '{s}', expected low, medium or high"
+            )),
+        }
+    }
+}
+
+/// The latest depth frame, raw Bit10 readings row by row, and its view. One
+/// per Kinect, the features all follow the [`MainKinect`]'s (see
+/// [`multi`]).
+#[derive(Component)]
+pub struct CurrentDepth {
+    pub depth_array: Vec<u16>,
+    /// size of `depth_array`, see [`DepthResolution`]
+    pub width: usize,
+    pub height: usize,
+    pub handle: Handle<Image>,
+    /// copy of the frame before `depth_array`, for interpolating the display
+    pub previous: Vec<u16>,
+    /// `Time::elapsed_seconds_f64` the frame arrived at
+    pub received_at: f64,
+    /// the frame's [`DepthFrame::sequence`], 0 before the first
+    pub sequence: u64,
+    /// frames the device sent between the one before and this one that
+    /// never got here: dropped on the way, or left out for a lower
+    /// `--depth-fps`
+    pub skipped: u64,
+    /// the device's clock at the frame, see [`DepthFrame::timestamp`]
+    pub timestamp: Option<u64>,
+    /// when the backend got the frame, for the latency to the screen
+    pub captured_at: Option<Instant>,
+    /// the frame's readings in the format they came in, when that isn't
+    /// Bit10, at the size they came in whatever the [`DepthResolution`]
+    pub raw: Option<RawDepth>,
+}
+
+impl CurrentDepth {
+    /// Before any frame.
+    pub fn new(width: usize, height: usize, handle: Handle<Image>) -> CurrentDepth {
+        CurrentDepth {
+            depth_array: vec![],
+            width,
+            height,
+            handle,
+            previous: vec![],
+            received_at: 0.0,
+            sequence: 0,
+            skipped: 0,
+            timestamp: None,
+            captured_at: None,
+            raw: None,
+        }
+    }
+
+    /// Takes `frame` as the latest, scaled to `width` x `height`.
+    /// Frames at another sensor's own size keep it.
+    pub(crate) fn receive(&mut self, frame: DepthFrame, width: usize, height: usize, now: f64) {
+        let (data, width, height) = if (frame.width, frame.height) == (DEPTH_WIDTH, DEPTH_HEIGHT) {
+            (resize_depth(frame.data, width, height), width, height)
+        } else {
+            (frame.data, frame.width, frame.height)
+        };
+        if data.len() == width * height {
+            (self.width, self.height) = (width, height);
+        }
+        self.previous = std::mem::replace(&mut self.depth_array, data);
+        self.received_at = now;
+        // a reopened device counts from the start again
+        self.skipped = match frame.sequence.checked_sub(self.sequence) {
+            Some(step) if self.sequence > 0 => step.saturating_sub(1),
+            _ => 0,
+        };
+        self.sequence = frame.sequence;
+        self.timestamp = frame.timestamp;
+        self.captured_at = Some(frame.captured_at);
+        self.raw = frame.raw;
+    }
+
+    /// How long ago the backend got the frame.
+    pub fn age(&self) -> Option<Duration> {
+        Some(self.captured_at?.elapsed())
+    }
+
+    /// Whether this is a whole medium resolution frame. The features that
+    /// look at single pixels are written for that layout and sit out at
+    /// other resolutions.
+    pub fn is_medium(&self) -> bool {
+        (self.width, self.height) == (DEPTH_WIDTH, DEPTH_HEIGHT)
+            && self.depth_array.len() == DEPTH_WIDTH * DEPTH_HEIGHT
+    }
+
+    /// A pixel of this frame in medium resolution pixels, where
+    /// [`CloseBlob`] and the view's coordinates are.
+    pub fn to_medium(&self, pixel: Vec2) -> Vec2 {
+        pixel * VIEW_SIZE / Vec2::new(self.width as f32, self.height as f32)
+    }
+
+    /// The frame in millimeters, 0 where there is no reading. Frames that
+    /// came in millimeters are given as they came, others are converted
+    /// from Bit10.
+    pub fn millimeters(&self) -> Vec<u16> {
+        match &self.raw {
+            Some(raw)
+                if matches!(
+                    raw.format,
+                    DepthFormat::Millimeters | DepthFormat::Registered
+                ) && raw.data.len() == self.depth_array.len() =>
+            {
+                return raw.data.clone()
+            }
+            _ => {}
+        }
+        self.depth_array
+            .iter()
+            .map(|raw| DepthFormat::Millimeters.from_bit10(*raw))
+            .collect()
+    }
+}
+
+/// How the depth view is drawn.
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DepthStyle {
+    /// darker the further away, over the video
+    #[default]
+    Shadow,
+    /// opaque, hue by distance, slowly cycling
+    Rainbow,
+    /// video where there are people, chroma green everywhere else
+    GreenScreen,
+    /// closest depth over the last few seconds, in rainbow colors
+    #[serde(rename = "exposure")]
+    LongExposure,
+}
+
+impl FromStr for DepthStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "shadow" => Ok(DepthStyle::Shadow),
+            "rainbow" => Ok(DepthStyle::Rainbow),
+            "greenscreen" => Ok(DepthStyle::GreenScreen),
+            "exposure" => Ok(DepthStyle::LongExposure),
+            _ => Err(format!(
+                "unknown depth view '{s}', expected shadow, rainbow, greenscreen or exposure"
+            )),
+        }
+    }
+}
+
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct TrackingSettings {
+    /// raw depth below which something counts as close
+    pub threshold: u16,
+    /// Move the crosshair ahead along the blob's last step between frames,
+    /// instead of easing it towards the latest position a frame behind.
+    pub extrapolate: bool,
+}
+
+impl Default for TrackingSettings {
+    fn default() -> Self {
+        TrackingSettings {
+            threshold: 400,
+            extrapolate: false,
+        }
+    }
+}
+
+/// Inclusive pixel bounds of the close blob.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct BlobBounds {
+    left: u16,
+    right: u16,
+    top: u16,
+    bottom: u16,
+}
+
+impl BlobBounds {
+    fn center(&self) -> Vec2 {
+        Vec2::new(
+            ((self.left + self.right) / 2).into(),
+            ((self.top + self.bottom) / 2).into(),
+        )
+    }
+}
+
+#[derive(Component)]
+pub(crate) struct Crosshair;
+
+/// The 2D camera the view and sprites over it are drawn with.
+#[derive(Component)]
+pub struct MainCamera;
+
+/// On the [`CurrentDepth`] of the [`DepthCamera`] resource's sensor, the one
+/// tracking and every other feature runs on.
+#[derive(Component)]
+pub struct MainKinect;
+
+/// A sensor the plugin opens itself, unless the app brought a [`DepthCamera`]
+/// of its own.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SensorBackend {
+    /// a Kinect through libfreenect, with the `freenect` feature
+    #[default]
+    Freenect,
+    /// a Kinect v2 through libfreenect2, with the `freenect2` feature
+    Freenect2,
+    /// the first OpenNI2 sensor, with the `openni2` feature
+    OpenNi2,
+}
+
+impl FromStr for SensorBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "freenect" => Ok(SensorBackend::Freenect),
+            "freenect2" | "kinect2" => Ok(SensorBackend::Freenect2),
+            "openni2" => Ok(SensorBackend::OpenNi2),
+            _ => Err(format!(
+                "unknown backend '{s}', expected freenect, freenect2 or openni2"
+            )),
+        }
+    }
+}
+
+/// Opens the [`KinectConfig::backend`] sensor, unless the app brought a
+/// [`DepthCamera`] of its own.
+fn setup_kinect(world: &mut World) {
+    let video = *world.resource::<VideoSettings>();
+    let config = world.resource::<KinectConfig>().clone();
+    let consumers = world
+        .get_resource_or_insert_with(FrameConsumers::default)
+        .clone();
+    match world.get_resource_mut::<DepthCamera>() {
+        Some(mut camera) => {
+            warn_unregistered(&config);
+            camera.configure(video);
+            camera.set_consumers(consumers);
+            camera.open();
+        }
+        #[cfg(any(feature = "freenect", feature = "freenect2", feature = "openni2"))]
+        None => {
+            let usb = *world.resource::<embedded::UsbSettings>();
+            let camera = match config.backend {
+                #[cfg(feature = "freenect")]
+                SensorBackend::Freenect => {
+                    world.insert_resource(hotplug::PluginKinect);
+                    DepthCamera::new(FreenectBackend::spawn(
+                        video,
+                        config.device_index,
+                        config.depth_format,
+                        consumers,
+                        usb,
+                    ))
+                }
+                #[cfg(feature = "freenect2")]
+                SensorBackend::Freenect2 => opened(
+                    DepthCamera::new(
+                        kinect2::Freenect2Backend::new(video)
+                            .queue(usb.frame_queue, usb.drop_policy)
+                            .v1_picture(config.v1_picture),
+                    ),
+                    &config,
+                    consumers,
+                ),
+                #[cfg(feature = "openni2")]
+                SensorBackend::OpenNi2 => opened(
+                    DepthCamera::new(
+                        openni2::OpenNi2Backend::new(video).queue(usb.frame_queue, usb.drop_policy),
+                    ),
+                    &config,
+                    consumers,
+                ),
+                #[allow(unreachable_patterns)]
+                backend => panic!(
+                    "no camera: insert a DepthCamera, or build with the feature for {backend:?}"
+                ),
+            };
+            world.insert_resource(camera);
+        }
+        #[cfg(not(any(feature = "freenect", feature = "freenect2", feature = "openni2")))]
+        None => panic!("no camera: insert a DepthCamera, or build with a sensor feature"),
+    }
+}
+
+/// A backend that waits for `open`, started.
+#[cfg(any(feature = "freenect2", feature = "openni2"))]
+fn opened(
+    mut camera: DepthCamera,
+    config: &KinectConfig,
+    consumers: FrameConsumers,
+) -> DepthCamera {
+    // these settings pick and drive a Kinect through libfreenect
+    if config.device_index != 0 || config.serial.is_some() {
+        warn!(
+            "{:?} opens the first sensor it finds, the device index and serial are ignored",
+            config.backend
+        );
+    }
+    match config.depth_format {
+        DepthFormat::Bit10 => {}
+        DepthFormat::Registered => warn_unregistered(config),
+        format => warn!(
+            "{:?} streams depth in millimeters, {format:?} only sets the threshold's units",
+            config.backend
+        ),
+    }
+    camera.set_consumers(consumers);
+    camera.open();
+    camera
+}
+
+fn warn_unregistered(config: &KinectConfig) {
+    if config.depth_format == DepthFormat::Registered {
+        warn!("Only the Kinect through libfreenect registers depth, it won't line up with video");
+    }
+}
+
+fn spawn_depth(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    asset_server: Res<AssetServer>,
+    config: Res<KinectConfig>,
+) {
+    commands
+        .spawn(SpriteBundle {
+            texture: asset_server.load("crosshair.png"),
+            // keep it above the video sprite
+            transform: Transform::from_xyz(0.0, 0.0, 1.0),
+            ..default()
+        })
+        .insert(Crosshair);
+
+    commands.spawn(Camera2dBundle::default()).insert(MainCamera);
+
+    let (width, height) = config.depth_resolution.size();
+    let image_handle = images.add(Image::new_fill(
+        Extent3d {
+            width: width as u32,
+            height: height as u32,
+            depth_or_array_layers: 1,
+        },
+        bevy::render::render_resource::TextureDimension::D2,
+        &[0, 0, 0, 255],
+        TextureFormat::Rgba8Unorm,
+    ));
+
+    commands
+        .spawn(CurrentDepth::new(width, height, image_handle.clone()))
+        .insert((MainKinect, KinectId(0)));
+
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                justify_content: JustifyContent::SpaceBetween,
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|parent| {
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                        position_type: PositionType::Absolute,
+                        justify_content: JustifyContent::Center,
+                        align_items: AlignItems::FlexStart,
+                        ..default()
+                    },
+                    ..default()
+                })
+                .with_children(|parent| {
+                    // the view's size on screen, any depth resolution is
+                    // stretched over it
+                    parent
+                        .spawn(ImageBundle {
+                            style: Style {
+                                size: Size::new(Val::Px(VIEW_SIZE.x), Val::Px(VIEW_SIZE.y)),
+                                ..default()
+                            },
+                            image: UiImage(image_handle),
+                            ..default()
+                        })
+                        .insert(ViewImage);
+                });
+        });
+}
+
+fn read_depth_data(
+    mut camera: ResMut<DepthCamera>,
+    config: Res<KinectConfig>,
+    capture: Res<CaptureSettings>,
+    (time, budget): (Res<Time>, Res<FrameBudget>),
+    mut gate: Local<FrameGate>,
+    mut depth_query: Query<&mut CurrentDepth, With<MainKinect>>,
+) {
+    if let Ok(mut depth) = depth_query.get_single_mut() {
+        if let Some(frame) = camera.next_frame() {
+            if !gate.accept(capture.depth, DEPTH_NATIVE_FPS) {
+                return;
+            }
+            budget.record(budget::CAPTURE_WAIT, frame.captured_at.elapsed());
+            let (width, height) = config.depth_resolution.size();
+            depth.receive(frame, width, height, time.elapsed_seconds_f64());
+        }
+    }
+}
+
+/// A medium resolution frame at `width` x `height`, anything else as it is.
+pub(crate) fn resize_depth(data: Vec<u16>, width: usize, height: usize) -> Vec<u16> {
+    if data.len() != DEPTH_WIDTH * DEPTH_HEIGHT || width == DEPTH_WIDTH {
+        return data;
+    }
+    let step = (DEPTH_WIDTH / width).max(1);
+    (0..width * height)
+        .map(|i| {
+            let x = i % width * DEPTH_WIDTH / width;
+            let y = i / width * DEPTH_HEIGHT / height;
+            // holes have the largest value, so any reading wins over them
+            (0..step * step)
+                .map(|j| data[(y + j / step) * DEPTH_WIDTH + x + j % step])
+                .min()
+                .unwrap_or(NO_DEPTH)
+        })
+        .collect()
+}
+
+fn update_image_from_depth_data(
+    depth_query: Query<&CurrentDepth, With<MainKinect>>,
+    capture: Res<CaptureSettings>,
+    (style, mask, histogram): (
+        Res<DepthStyle>,
+        Res<PersonMask>,
+        Option<Res<DepthHistogram>>,
+    ),
+    (attract, attract_settings): (Res<AttractMode>, Res<AttractSettings>),
+    (upsampled, exposure): (Option<Res<UpsampledDepth>>, Res<LongExposure>),
+    (time, quality): (Res<Time>, Res<QualityLevel>),
+    mut images: ResMut<Assets<Image>>,
+) {
+    if let Ok(depth) = depth_query.get_single() {
+        if depth.depth_array.is_empty() {
+            return;
+        }
+        if let Some(handle) = images.get_mut(&depth.handle) {
+            let mut new_pixels: Vec<u8> = vec![];
+
+            // Show the previous frame when a new one arrives and fade towards
+            // it until the next is due, so the render rate doesn't step at
+            // the capture rate.
+            let interval = 1.0 / capture.depth.effective_fps(DEPTH_NATIVE_FPS) as f64;
+            let t = ((time.elapsed_seconds_f64() - depth.received_at) / interval).clamp(0.0, 1.0);
+            let long_exposure = *style == DepthStyle::LongExposure
+                && exposure.depth.len() == depth.depth_array.len();
+            // an upscaled map, once there is one, is shown at its own size
+            let (source, factor) = match &upsampled {
+                _ if long_exposure => (&exposure.depth[..], 1),
+                Some(upsampled) if depth.is_medium() && quality.upsample() => {
+                    (&upsampled.data[..], upsampled.factor)
+                }
+                _ => (&depth.depth_array[..], 1),
+            };
+            let (width, height) = (depth.width * factor, depth.height * factor);
+            let blend = factor == 1
+                && !long_exposure
+                && capture.interpolate
+                && depth.previous.len() == depth.depth_array.len();
+            let rainbow = *style == DepthStyle::Rainbow
+                || long_exposure
+                || (attract.is_active() && attract_settings.screensaver);
+            let palette = rainbow.then(|| rainbow_palette(time.elapsed_seconds()));
+            let green_screen = !rainbow && *style == DepthStyle::GreenScreen;
+            let range = histogram.and_then(|histogram| histogram.display_range);
+            // under load only every n-th pixel of every n-th row is shown
+            let step = quality.decimation();
+            let shown = (0..height)
+                .step_by(step)
+                .flat_map(|y| (0..width).step_by(step).map(move |x| y * width + x));
+
+            for i in shown {
+                let mut measurement = source[i];
+                if blend {
+                    let previous = depth.previous[i];
+                    // keep holes sharp, blending into them makes up distances
+                    if previous != NO_DEPTH && measurement != NO_DEPTH {
+                        measurement =
+                            (previous as f64 + (measurement as f64 - previous as f64) * t) as u16;
+                    }
+                }
+                if let Some((near, far)) = range {
+                    // shades stretched over what is in the scene
+                    if measurement != NO_DEPTH {
+                        let offset = measurement.clamp(near, far) - near;
+                        measurement =
+                            (offset as u32 * (NO_DEPTH as u32 - 1) / (far - near) as u32) as u16;
+                    }
+                }
+
+                if green_screen {
+                    // the mask is at medium resolution
+                    let pixel = (i / width * DEPTH_HEIGHT / height) * DEPTH_WIDTH
+                        + (i % width) * DEPTH_WIDTH / width;
+                    let person = mask.0.get(pixel).copied().unwrap_or(false);
+                    new_pixels.extend_from_slice(if person {
+                        &[0, 0, 0, 0]
+                    } else {
+                        &[0, 177, 64, 255]
+                    });
+                    continue;
+                }
+
+                match &palette {
+                    Some(palette) if measurement != NO_DEPTH => {
+                        new_pixels.extend_from_slice(&palette[measurement as usize & 1023]);
+                    }
+                    _ => {
+                        new_pixels.push(0);
+                        new_pixels.push(0);
+                        new_pixels.push(0);
+                        // the Bit10 scale onto up to half opaque
+                        new_pixels.push((measurement as u32 * 128 / (NO_DEPTH as u32 + 1)) as u8);
+                    }
+                }
+            }
+
+            let size = Extent3d {
+                width: width.div_ceil(step) as u32,
+                height: height.div_ceil(step) as u32,
+                depth_or_array_layers: 1,
+            };
+            if handle.texture_descriptor.size != size {
+                handle.resize(size);
+            }
+            handle.data = new_pixels;
+        }
+    }
+}
+
+/// RGBA per raw depth value, hues drift with `seconds`.
+fn rainbow_palette(seconds: f32) -> Vec<[u8; 4]> {
+    (0..1024)
+        .map(|raw| {
+            let hue = (raw as f32 / 1023.0 * 720.0 + seconds * 20.0) % 360.0;
+            let [r, g, b, _] = Color::hsl(hue, 0.8, 0.5).as_rgba_f32();
+            [(r * 255.0) as u8, (g * 255.0) as u8, (b * 255.0) as u8, 255]
+        })
+        .collect()
+}
+
+/// Center of the closest blob in medium resolution depth pixels, whatever
+/// the [`DepthResolution`], `None` while nothing is near.
+#[derive(Resource, Default)]
+pub struct CloseBlob(pub Option<Vec2>);
+
+/// The close blob's last two positions from the sensor, and where it is shown
+/// in between, so the crosshair moves at render rate rather than in 30 Hz
+/// steps.
+#[derive(Resource, Default)]
+pub struct BlobMotion {
+    pub previous: Option<Vec2>,
+    pub current: Option<Vec2>,
+    pub received_at: f64,
+    pub shown: Option<Vec2>,
+    /// `shown` through the interaction box, where the crosshair goes
+    pub pointer: Option<Vec2>,
+}
+
+fn track_close_blob(
+    depth_query: Query<&CurrentDepth, (With<MainKinect>, Changed<CurrentDepth>)>,
+    tracking: Res<TrackingSettings>,
+    mut blob: ResMut<CloseBlob>,
+    mut motion: ResMut<BlobMotion>,
+) {
+    if let Ok(depth) = depth_query.get_single() {
+        if depth.depth_array.is_empty() {
+            return;
+        }
+        blob.0 = follow_blob(depth, tracking.threshold, &mut motion);
+    }
+}
+
+/// Finds the close blob in a frame and steps `motion` on to it.
+pub(crate) fn follow_blob(
+    depth: &CurrentDepth,
+    threshold: u16,
+    motion: &mut BlobMotion,
+) -> Option<Vec2> {
+    let center = close_blob_bounds(&depth.depth_array, depth.width, threshold)
+        .map(|bounds| depth.to_medium(bounds.center()));
+    if depth.received_at != motion.received_at {
+        motion.previous = motion.current;
+        motion.current = center;
+        motion.received_at = depth.received_at;
+    }
+    center
+}
+
+fn interpolate_close_blob(
+    capture: Res<CaptureSettings>,
+    tracking: Res<TrackingSettings>,
+    pointer: Res<PointerSettings>,
+    time: Res<Time>,
+    mut motion: ResMut<BlobMotion>,
+    mut filter: Local<PointerFilter>,
+) {
+    ease_blob(
+        &mut motion,
+        &capture,
+        &tracking,
+        &pointer,
+        &time,
+        &mut filter,
+    );
+}
+
+/// Where the blob is shown between frames, and where its crosshair goes.
+pub(crate) fn ease_blob(
+    motion: &mut BlobMotion,
+    capture: &CaptureSettings,
+    tracking: &TrackingSettings,
+    pointer: &PointerSettings,
+    time: &Time,
+    filter: &mut PointerFilter,
+) {
+    // same timing as the depth view, so both stay in step
+    let interval = 1.0 / capture.depth.effective_fps(DEPTH_NATIVE_FPS) as f64;
+    let t = ((time.elapsed_seconds_f64() - motion.received_at) / interval).clamp(0.0, 1.0) as f32;
+    let shown = match (motion.previous, motion.current) {
+        (Some(previous), Some(current)) if capture.interpolate => Some(if tracking.extrapolate {
+            current + (current - previous) * t
+        } else {
+            previous.lerp(current, t)
+        }),
+        (_, current) => current,
+    };
+    motion.shown = filter.update(shown, time.delta_seconds(), pointer);
+    motion.pointer = motion.shown.map(|shown| pointer.map_to_view(shown));
+}
+
+fn move_crosshair_to_pos(
+    motion: Res<BlobMotion>,
+    rect: Res<DisplayRect>,
+    windows: Res<Windows>,
+    mut transform_query: Query<&mut Transform, With<Crosshair>>,
+    q_camera: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
+) {
+    if let Some(blob) = motion.pointer {
+        let world_pos = view_to_world(blob, &rect, &windows, q_camera.single());
+        let mut crosshair_t = transform_query.single_mut();
+        crosshair_t.translation.x = world_pos.x;
+        crosshair_t.translation.y = world_pos.y;
+    }
+}
+
+/// A point of the depth view where it is in the 2D world.
+pub(crate) fn view_to_world(
+    view_pos: Vec2,
+    rect: &DisplayRect,
+    windows: &Windows,
+    (camera, camera_transform): (&Camera, &GlobalTransform),
+) -> Vec2 {
+    let window = windows.primary();
+    let window_size = Vec2::new(window.width(), window.height());
+    let screen_pos = rect.image_to_screen(view_pos);
+    coords::screen_to_world(
+        screen_pos,
+        window_size,
+        coords::ndc_to_world(camera, camera_transform),
+    )
+}
+
+/// Box around everything closer than `threshold` in a frame `width` pixels
+/// wide, in its pixels, or `None` when nothing is.
+fn close_blob_bounds(data: &[u16], width: usize, threshold: u16) -> Option<BlobBounds> {
+    let mut break_outer = false;
+
+    let mut left_most: u16 = 0;
+    let mut right_most: u16 = 0;
+    let mut top_most: u16 = 0;
+    let mut bottom_most: u16 = 0;
+    let height = data.len() / width;
+
+    let arr_2d = Array2D::from_iter_row_major(data.iter(), height, width);
+    for i in 0..width {
+        for k in arr_2d.column_iter(i) {
+            if **k < threshold {
+                break_outer = true;
+                left_most = i as u16;
+                break;
+            }
+        }
+        if break_outer {
+            break;
+        }
+    }
+
+    break_outer = false;
+
+    let arr_2d = Array2D::from_iter_row_major(data.iter(), height, width);
+    for i in (0..width).rev() {
+        for k in arr_2d.column_iter(i) {
+            if **k < threshold {
+                break_outer = true;
+                right_most = i as u16;
+                break;
+            }
+        }
+        if break_outer {
+            break;
+        }
+    }
+
+    break_outer = false;
+
+    let arr_2d = Array2D::from_iter_row_major(data.iter(), height, width);
+    for i in 0..height {
+        for k in arr_2d.row_iter(i) {
+            if **k < threshold {
+                break_outer = true;
+                top_most = i as u16;
+                break;
+            }
+        }
+        if break_outer {
+            break;
+        }
+    }
+
+    break_outer = false;
+
+    let arr_2d = Array2D::from_iter_row_major(data.iter(), height, width);
+    for i in (0..height).rev() {
+        for k in arr_2d.row_iter(i) {
+            if **k < threshold {
+                break_outer = true;
+                bottom_most = i as u16;
+                break;
+            }
+        }
+        if break_outer {
+            break;
+        }
+    }
+
+    let bounds = BlobBounds {
+        left: left_most,
+        right: right_most,
+        top: top_most,
+        bottom: bottom_most,
+    };
+    (bounds.center().x >= 0.1).then_some(bounds)
+}
+
+fn keyboard_input(keys: Res<Input<KeyCode>>, mut style: ResMut<DepthStyle>) {
+    if keys.just_pressed(KeyCode::V) {
+        *style = match *style {
+            DepthStyle::Shadow => DepthStyle::Rainbow,
+            DepthStyle::Rainbow => DepthStyle::GreenScreen,
+            DepthStyle::GreenScreen => DepthStyle::LongExposure,
+            DepthStyle::LongExposure => DepthStyle::Shadow,
+        };
+    }
+}
+
+/// Where the depth stream's systems run in a frame, for ordering your own
+/// around them:
+///
+/// * `Acquire` takes the new depth and video frames off the device, with
+///   the source of the [depth pipeline](pipeline)
+/// * `Process` runs the pipeline's filters and consumers, filling holes in
+///   the depth and tracking the close blob, and moves the pointer; after it,
+///   [`CurrentDepth`] and [`BlobMotion`] are this frame's
+/// * `Output` uploads the depth view's texture and places the crosshair
+///
+/// So a system that changes the depth before it is shown goes
+/// `.after(KinectSet::Process).before(KinectSet::Output)`, one whose
+/// changes the trackers should see is a pipeline filter.
+#[derive(SystemLabel, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum KinectSet {
+    Acquire,
+    Process,
+    Output,
+}
+
+/// The depth stream itself: opening the device, the depth frame and its
+/// view, close blob tracking and the V key for the view style.
+pub struct DepthPlugin;
+
+impl Plugin for DepthPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<CaptureSettings>()
+            .init_resource::<InpaintSettings>()
+            .init_resource::<DepthStyle>()
+            .init_resource::<TrackingSettings>()
+            .init_resource::<WorldConvention>()
+            .init_resource::<CloseBlob>()
+            .init_resource::<BlobMotion>()
+            .init_resource::<PointerSettings>()
+            .init_resource::<KinectConfig>()
+            .init_resource::<FrameConsumers>()
+            .init_resource::<QualityLevel>()
+            .init_resource::<FrameBudget>()
+            .add_startup_system(setup_kinect)
+            .add_startup_system(spawn_depth)
+            .add_startup_system(pipeline::log_pipeline)
+            .add_depth_stage(DepthStage::source(pipeline::ReadDepth, read_depth_data))
+            .add_depth_stage(DepthStage::filter(
+                pipeline::FillHoles,
+                inpaint::fill_depth_holes,
+            ))
+            .add_depth_stage(DepthStage::consumer(
+                pipeline::TrackCloseBlob,
+                track_close_blob,
+            ))
+            .add_system(keyboard_input)
+            .add_system(
+                budget::timed(budget::DEPTH_VIEW, update_image_from_depth_data)
+                    .label(KinectSet::Output)
+                    .after(KinectSet::Process),
+            )
+            .add_system(pointer::cycle_profile)
+            .add_system(pointer::apply_profile.after(pointer::cycle_profile))
+            .add_system(
+                interpolate_close_blob
+                    .label(KinectSet::Process)
+                    .after(pipeline::TrackCloseBlob)
+                    .after(pointer::apply_profile),
+            )
+            .add_system(pointer::calibrate_reach.after(interpolate_close_blob))
+            .add_system(
+                move_crosshair_to_pos
+                    .label(KinectSet::Output)
+                    .after(KinectSet::Process),
+            );
+    }
+}
+
+/// Which Kinect to open and the settings most apps change, as set up by
+/// [`KinectPlugin::builder`]. Adding the plugin writes them into their
+/// settings resources, which is what the systems read, replacing the same
+/// fields of any inserted before. Only `backend`, `device_index`, `serial`,
+/// `v1_picture` and the depth format and resolution are read from here;
+/// `device_index`, `serial` and the depth format's streaming mode are for the
+/// Kinect through libfreenect, other backends open the first sensor they find
+/// and stream millimeters, with a warning when these are set.
+#[derive(Resource, Clone, Debug)]
+pub struct KinectConfig {
+    /// the sensor to open when the app inserts no [`DepthCamera`]
+    pub backend: SensorBackend,
+    /// which Kinect, counting from 0, when several are plugged in
+    pub device_index: usize,
+    /// which Kinect by its serial number, see [`devices`]; replaces
+    /// `device_index` with the index it is listed at
+    pub serial: Option<String>,
+    /// resample a Kinect v2's depth onto the v1's 640x480 picture, for the
+    /// features written for it, instead of delivering its own 512x424
+    pub v1_picture: bool,
+    pub depth_format: DepthFormat,
+    pub depth_resolution: DepthResolution,
+    pub video_format: VideoFormat,
+    pub video_resolution: VideoResolution,
+    /// [`TrackingSettings::threshold`] in the units of `depth_format`,
+    /// `None` leaves it as it is
+    pub threshold: Option<u16>,
+    /// start in the magic mirror
+    pub mirror: bool,
+}
+
+impl Default for KinectConfig {
+    fn default() -> Self {
+        let video = VideoSettings::default();
+        KinectConfig {
+            backend: SensorBackend::default(),
+            device_index: 0,
+            serial: None,
+            v1_picture: false,
+            depth_format: DepthFormat::default(),
+            depth_resolution: DepthResolution::default(),
+            video_format: video.format,
+            video_resolution: video.resolution,
+            threshold: None,
+            mirror: false,
+        }
+    }
+}
+
+/// Puts a [`KinectConfig`] into the settings, ahead of the plugins reading
+/// them.
+struct ConfigPlugin(KinectConfig);
+
+impl Plugin for ConfigPlugin {
+    fn build(&self, app: &mut App) {
+        let config = self.0.clone();
+        let world = &mut app.world;
+        let mut video = world.get_resource_or_insert_with(VideoSettings::default);
+        video.format = config.video_format;
+        video.resolution = config.video_resolution;
+        if let Some(threshold) = config.threshold {
+            world
+                .get_resource_or_insert_with(TrackingSettings::default)
+                .threshold = config.depth_format.to_bit10(threshold);
+        }
+        world
+            .get_resource_or_insert_with(MirrorSettings::default)
+            .enabled = config.mirror;
+        world.insert_resource(config);
+    }
+}
+
+/// [`DepthPlugin`] and every feature on top of it, the `remote` and `record`
+/// ones with their cargo features. Goes after `DefaultPlugins`.
+#[derive(Default)]
+pub struct KinectPlugin {
+    config: Option<KinectConfig>,
+}
+
+impl KinectPlugin {
+    /// Sets up the plugin with a [`KinectConfig`]:
+    ///
+    /// ```ignore
+    /// app.add_plugins(KinectPlugin::builder().device_index(1).threshold(450).build());
+    /// ```
+    pub fn builder() -> KinectPluginBuilder {
+        KinectPluginBuilder::default()
+    }
+}
+
+#[derive(Default)]
+pub struct KinectPluginBuilder {
+    config: KinectConfig,
+}
+
+impl KinectPluginBuilder {
+    /// The sensor to open, [`SensorBackend::Freenect`] by default.
+    pub fn backend(mut self, backend: SensorBackend) -> Self {
+        self.config.backend = backend;
+        self
+    }
+
+    pub fn device_index(mut self, index: usize) -> Self {
+        self.config.device_index = index;
+        self
+    }
+
+    /// Opens the Kinect with this serial number, wherever it is plugged in.
+    pub fn serial(mut self, serial: impl Into<String>) -> Self {
+        self.config.serial = Some(serial.into());
+        self
+    }
+
+    /// A Kinect v2's depth on the v1's picture, see
+    /// [`KinectConfig::v1_picture`].
+    pub fn v1_picture(mut self, v1_picture: bool) -> Self {
+        self.config.v1_picture = v1_picture;
+        self
+    }
+
+    pub fn depth_format(mut self, format: DepthFormat) -> Self {
+        self.config.depth_format = format;
+        self
+    }
+
+    pub fn depth_resolution(mut self, resolution: DepthResolution) -> Self {
+        self.config.depth_resolution = resolution;
+        self
+    }
+
+    pub fn video_format(mut self, format: VideoFormat) -> Self {
+        self.config.video_format = format;
+        self
+    }
+
+    pub fn video_resolution(mut self, resolution: VideoResolution) -> Self {
+        self.config.video_resolution = resolution;
+        self
+    }
+
+    /// In the units of the depth format, millimeters for
+    /// [`DepthFormat::Millimeters`].
+    pub fn threshold(mut self, threshold: u16) -> Self {
+        self.config.threshold = Some(threshold);
+        self
+    }
+
+    pub fn mirror(mut self, mirror: bool) -> Self {
+        self.config.mirror = mirror;
+        self
+    }
+
+    pub fn build(self) -> KinectPlugin {
+        KinectPlugin {
+            config: Some(self.config),
+        }
+    }
+}
+
+impl PluginGroup for KinectPlugin {
+    fn build(self) -> PluginGroupBuilder {
+        let mut group = PluginGroupBuilder::start::<Self>();
+        if let Some(config) = self.config {
+            group = group.add(ConfigPlugin(config));
+        }
+        let group = group
+            .add(EmbeddedPlugin)
+            .add(DepthPlugin)
+            .add(DevicePlugin)
+            .add(DevicesPlugin)
+            .add(MultiKinectPlugin)
+            .add(DisplayPlugin)
+            .add(ReconnectPlugin)
+            .add(HotplugPlugin)
+            .add(WatchdogPlugin)
+            .add(QualityPlugin)
+            .add(StatusOverlayPlugin)
+            .add(PresentationPlugin)
+            .add(SpanPlugin)
+            .add(VideoPlugin)
+            .add(HistogramPlugin)
+            .add(RgbdPlugin)
+            .add(TiltPlugin)
+            .add(LedPlugin)
+            .add(AudioPlugin)
+            .add(HoverPlugin)
+            .add(ComparePlugin)
+            .add(AttractPlugin)
+            .add(HoursPlugin)
+            .add(ProximityPlugin)
+            .add(ZonesPlugin)
+            .add(AnalyticsPlugin)
+            .add(EventLogPlugin)
+            .add(DatasetPlugin)
+            .add(NumpyPlugin)
+            .add(SegmentationPlugin)
+            .add(UpsamplePlugin)
+            .add(FrustumPlugin)
+            .add(PlanningPlugin)
+            .add(PresetPlugin)
+            .add(TouchlessPlugin)
+            .add(PlayerPlugin)
+            .add(FloorPlugin)
+            .add(SkeletonPlugin)
+            .add(AnglesPlugin)
+            .add(BalancePlugin)
+            .add(HeightPlugin)
+            .add(PosePlugin)
+            .add(BodyGesturePlugin)
+            .add(AnchorPlugin)
+            .add(MirrorPlugin)
+            .add(MotionPlugin)
+            .add(ExposurePlugin)
+            .add(CompositePlugin)
+            .add(CalibrationPlugin)
+            .add(ConfidencePlugin)
+            .add(HealthPlugin)
+            .add(BudgetPlugin)
+            .add(InterferencePlugin)
+            .add(BlockagePlugin)
+            .add(BoothPlugin)
+            .add(PickingPlugin);
+        #[cfg(feature = "remote")]
+        let group = group.add(RemotePlugin).add(DeliveryPlugin);
+        #[cfg(feature = "record")]
+        let group = group.add(RecordingPlugin);
+        group
+    }
+}
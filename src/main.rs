@@ -1,306 +1,822 @@
-use array2d::Array2D;
-use bevy::prelude::*;
-use bevy::render::render_resource::{Extent3d, TextureFormat};
-use freenectrs::freenect::FreenectDepthStream;
-use freenectrs::freenect::{self, FreenectDevice};
-
-struct Kinect<'a> {
-    dstream: FreenectDepthStream<'a, 'a>,
-    device: &'a FreenectDevice<'a, 'a>,
-}
-
-#[derive(Component)]
-struct CurrentDepth<'a> {
-    depth_array: &'a [u16],
-    handle: Handle<Image>,
-}
-
-#[derive(Component)]
-struct Crosshair;
-
-#[derive(Component)]
-struct MainCamera;
-
-fn setup_kinect(world: &mut World) {
-    let ctx = Box::leak(Box::new(
-        freenect::FreenectContext::init_with_video_motor().unwrap(),
-    ));
-
-    let dev_count = ctx.num_devices().unwrap();
-    if dev_count == 0 {
-        eprintln!("No device connected - abort");
-    } else {
-        println!("Found {} devices, use first", dev_count);
-    }
-
-    let device = Box::leak(Box::new(ctx.open_device(0).unwrap()));
-
-    device
-        .set_depth_mode(
-            freenect::FreenectResolution::Medium,
-            freenect::FreenectDepthFormat::Bit10,
-        )
-        .unwrap();
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::str::FromStr;
 
-    let dstream = device.depth_stream().unwrap();
-
-    ctx.spawn_process_thread().unwrap();
+use bevy::diagnostic::LogDiagnosticsPlugin;
+use bevy::prelude::*;
 
-    world.insert_non_send_resource(Kinect { dstream, device });
+use bevy_kinect::analytics::AnalyticsSettings;
+use bevy_kinect::attract::AttractSettings;
+use bevy_kinect::audio::AudioSettings;
+use bevy_kinect::backend::DepthCamera;
+use bevy_kinect::body_gestures::GestureBindings;
+use bevy_kinect::booth::BoothSettings;
+use bevy_kinect::budget::BudgetSettings;
+use bevy_kinect::calibration::CalibrationSettings;
+use bevy_kinect::capture::CaptureSettings;
+use bevy_kinect::compare::CompareSettings;
+use bevy_kinect::coords::WorldConvention;
+use bevy_kinect::dataset::DatasetSettings;
+use bevy_kinect::display::DisplaySettings;
+use bevy_kinect::embedded::{EmbeddedSettings, UsbSettings};
+use bevy_kinect::event_log::EventLogSettings;
+use bevy_kinect::exposure::ExposureSettings;
+use bevy_kinect::floor::FloorSettings;
+use bevy_kinect::frustum::FrustumSettings;
+use bevy_kinect::histogram::HistogramSettings;
+use bevy_kinect::hotplug::HotplugSettings;
+use bevy_kinect::hours::HoursSettings;
+use bevy_kinect::inpaint::InpaintSettings;
+use bevy_kinect::interference::InterferenceSettings;
+use bevy_kinect::led::LedSettings;
+use bevy_kinect::mirror::MirrorSettings;
+use bevy_kinect::multi::MultiKinectSettings;
+#[cfg(feature = "network")]
+use bevy_kinect::network::NetworkBackend;
+use bevy_kinect::numpy::{NumpyBackend, NumpyExportSettings};
+use bevy_kinect::picking::PickSettings;
+use bevy_kinect::planning::PlanningSettings;
+#[cfg(feature = "playback")]
+use bevy_kinect::playback::PlaybackBackend;
+use bevy_kinect::players::PlayerSettings;
+use bevy_kinect::pointer::PointerSettings;
+use bevy_kinect::poses::PoseSettings;
+use bevy_kinect::presentation::PresentationSettings;
+use bevy_kinect::presets::PresetSettings;
+use bevy_kinect::proximity::ProximitySettings;
+use bevy_kinect::quality::QualitySettings;
+use bevy_kinect::reconnect::ReconnectSettings;
+#[cfg(feature = "record")]
+use bevy_kinect::recording::RecordingSettings;
+#[cfg(feature = "remote")]
+use bevy_kinect::remote::RemoteSettings;
+use bevy_kinect::segmentation::SegmentationSettings;
+#[cfg(feature = "mock")]
+use bevy_kinect::simulation::{Scene, SimulatedKinect};
+use bevy_kinect::span::SpanSettings;
+use bevy_kinect::upsample::UpsampleSettings;
+use bevy_kinect::video::{VideoConversion, VideoSettings};
+use bevy_kinect::watchdog::WatchdogSettings;
+use bevy_kinect::zones::ZoneSettings;
+use bevy_kinect::{
+    DepthFormat, DepthResolution, DepthStyle, KinectConfig, KinectPlugin, SensorBackend,
+    TrackingSettings,
+};
+
+#[derive(Default)]
+struct Options {
+    device: usize,
+    serial: Option<String>,
+    multi: MultiKinectSettings,
+    depth_format: DepthFormat,
+    depth_resolution: DepthResolution,
+    /// `--threshold`, in the units of `depth_format`
+    threshold: Option<u16>,
+    video: VideoSettings,
+    capture: CaptureSettings,
+    reconnect: ReconnectSettings,
+    hotplug: HotplugSettings,
+    watchdog: WatchdogSettings,
+    display: DisplaySettings,
+    presentation: PresentationSettings,
+    span: SpanSettings,
+    attract: AttractSettings,
+    hours: HoursSettings,
+    proximity: ProximitySettings,
+    histogram: HistogramSettings,
+    quality: QualitySettings,
+    embedded: EmbeddedSettings,
+    usb: UsbSettings,
+    led: LedSettings,
+    audio: AudioSettings,
+    zones: ZoneSettings,
+    analytics: AnalyticsSettings,
+    event_log: EventLogSettings,
+    dataset: DatasetSettings,
+    numpy: NumpyExportSettings,
+    world: WorldConvention,
+    style: DepthStyle,
+    tracking: TrackingSettings,
+    pointer: PointerSettings,
+    compare: CompareSettings,
+    players: PlayerSettings,
+    floor: FloorSettings,
+    calibration: CalibrationSettings,
+    interference: InterferenceSettings,
+    inpaint: InpaintSettings,
+    upsample: UpsampleSettings,
+    segmentation: SegmentationSettings,
+    frustum: FrustumSettings,
+    planning: PlanningSettings,
+    presets: PresetSettings,
+    poses: PoseSettings,
+    picking: PickSettings,
+    gestures: GestureBindings,
+    mirror: MirrorSettings,
+    exposure: ExposureSettings,
+    booth: BoothSettings,
+    log_diagnostics: bool,
+    budget: BudgetSettings,
+    #[cfg(feature = "record")]
+    recording: RecordingSettings,
+    #[cfg(feature = "remote")]
+    remote: RemoteSettings,
+    backend: SensorBackend,
+    v1_picture: bool,
+    #[cfg(feature = "mock")]
+    simulate: Option<PathBuf>,
+    #[cfg(feature = "network")]
+    connect: Option<String>,
+    #[cfg(feature = "playback")]
+    playback: Option<PathBuf>,
+    #[cfg(feature = "playback")]
+    playback_units: Option<DepthFormat>,
+    #[cfg(feature = "playback")]
+    playback_once: bool,
+    npy: Option<PathBuf>,
 }
 
-fn spawn_depth(
-    mut commands: Commands,
-    mut images: ResMut<Assets<Image>>,
-    asset_server: Res<AssetServer>,
-) {
-    commands
-        .spawn(SpriteBundle {
-            texture: asset_server.load("crosshair.png"),
-            ..default()
-        })
-        .insert(Crosshair);
-
-    commands.spawn(Camera2dBundle::default()).insert(MainCamera);
-
-    let image_handle = images.add(Image::new_fill(
-        Extent3d {
-            width: 640,
-            height: 480,
-            depth_or_array_layers: 1,
-        },
-        bevy::render::render_resource::TextureDimension::D2,
-        &[0, 0, 0, 255],
-        TextureFormat::Rgba8Unorm,
-    ));
-
-    commands.spawn_empty().insert(CurrentDepth {
-        depth_array: &[],
-        handle: image_handle.clone(),
-    });
-
-    commands
-        .spawn(NodeBundle {
-            style: Style {
-                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
-                justify_content: JustifyContent::SpaceBetween,
-                ..default()
-            },
-            ..default()
-        })
-        .with_children(|parent| {
-            parent
-                .spawn(NodeBundle {
-                    style: Style {
-                        size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
-                        position_type: PositionType::Absolute,
-                        justify_content: JustifyContent::Center,
-                        align_items: AlignItems::FlexStart,
-                        ..default()
-                    },
-                    ..default()
-                })
-                .with_children(|parent| {
-                    // bevy logo (image)
-                    parent.spawn(ImageBundle {
-                        style: Style {
-                            size: Size::new(Val::Px(640.0), Val::Px(480.0)),
-                            ..default()
-                        },
-                        image: UiImage(image_handle),
-                        ..default()
+impl Options {
+    /// * `--kinect2` use a Kinect v2 through libfreenect2, needs the
+    ///   `freenect2` feature
+    /// * `--kinect2-v1` resample its depth onto the Kinect v1's picture
+    /// * `--openni2` use the first OpenNI2 sensor (Xtion, Astra, ...), needs
+    ///   the `openni2` feature
+    /// * `--simulate <scene.ron>` render a made up scene instead of using a
+    ///   device, `--simulate sweep` a built-in one, needs the `mock` feature
+    ///   (on by default)
+    /// * `--connect <addr:port>` frames from `kinect-capture --forward` on
+    ///   another machine, in the browser `ws://<addr:port>` of a
+    ///   `--forward-ws`, needs the `network` feature
+    /// * `--playback <file>` depth from 16-bit grayscale video recorded by
+    ///   other tools, `--playback-units <mm|bit10|bit11>` what its gray
+    ///   levels are (mm by default), `--playback-once` to not loop it; needs
+    ///   the `playback` feature and ffmpeg
+    /// * `--npy <file>` depth frames from a numpy `.npy` or `.npz` in
+    ///   millimeters, looping
+    /// * `--device <index>` which Kinect to open when several are plugged in,
+    ///   counting from 0
+    /// * `--serial <serial>` which Kinect to open by its serial number,
+    ///   wherever it is plugged in
+    /// * `--extra-device <index|serial>` open another Kinect alongside, can
+    ///   be given more than once
+    /// * `--depth <bit10|bit11|mm|registered>` depth format, the unit of
+    ///   `--threshold`; registered is millimeters lined up with the video
+    ///   pixel for pixel
+    /// * `--depth-res <low|medium|high>` depth frame size; the Kinect
+    ///   measures 640x480, low halves and high doubles it, and the features
+    ///   that work per pixel only run at medium
+    /// * `--video <rgb|bayer|yuv-rgb|yuv-raw|ir>` color stream format, or
+    ///   the depth camera's infrared picture
+    /// * `--video-res <medium|high>` color stream resolution
+    /// * `--gpu` convert the raw formats in a shader instead of on the CPU
+    /// * `--demosaic <bilinear|edge-aware>` how Bayer video is converted on
+    ///   the CPU, edge-aware is sharper and slower
+    /// * `--depth-fps <fps>` / `--video-fps <fps>` capture below the native rate
+    /// * `--no-interpolate` show depth frames as they arrive
+    /// * `--view <shadow|rainbow|greenscreen|exposure>` how the depth view is
+    ///   drawn, V cycles through them
+    /// * `--exposure <seconds>` how long the exposure view keeps readings
+    /// * `--threshold <depth>` depth below which something counts as close,
+    ///   raw or in millimeters as `--depth` delivers it
+    /// * `--adaptive-threshold` keep the threshold just behind the nearest
+    ///   readings, from the GPU's depth histogram
+    /// * `--auto-range` stretch the depth view's shading over the distances
+    ///   in the scene
+    /// * `--frame-budget <ms>` lower the processing quality while frames take
+    ///   longer than this, and raise it again with headroom
+    /// * `--low-memory` keep fewer copies of frames around, for small boards
+    /// * `--depth-only` / `--frame-queue <count>` stream only depth from the
+    ///   Kinect, and how many frames of each stream wait for the app
+    /// * `--drop-policy <keep-latest|keep-all|drop-oldest>` which frames go
+    ///   when the app doesn't keep up, the newest is kept by default
+    /// * `--led-tracking` light the Kinect's LED green while something is
+    ///   close, blinking while nothing is
+    /// * `--mic` capture the Kinect's microphones, needs the audio firmware
+    /// * `--calibration <file>` noise calibration profile to load and save
+    /// * `--calibrate` measure the noise of the empty scene at startup, F9
+    ///   does any time, `--calibrate-seconds <seconds>` for how long
+    /// * `--auto-rebaseline` calibrate again when the scene changes for good,
+    ///   `--scene-change-seconds <seconds>` for how long it has to differ
+    /// * `--emitter-slot <slot>/<count>` take turns lighting the scene with
+    ///   other sensors, `--emitter-slot-seconds <seconds>` per turn
+    /// * `--interference-area <share>` share of the view with IR
+    ///   interference that warns, 0.02 by default
+    /// * `--extrapolate` predict the crosshair between frames instead of
+    ///   trailing by one
+    /// * `--pointer <precise|responsive|stage-stable>` how the crosshair
+    ///   feels, O cycles through them
+    /// * `--pointer-dead-zone <pixels>` how far the blob moves before the
+    ///   crosshair follows, instead of the profile's
+    /// * `--pointer-margin <share>` share of the frame on each side left out
+    ///   of the interaction box, so the screen corners are in easy reach
+    /// * `--compare-threshold <depth>` / `--compare-smoothing <seconds>` /
+    ///   `--compare-dead-zone <pixels>` / `--compare-extrapolate <on|off>`
+    ///   track a second time with these changed and show both crosshairs, F6
+    ///   turns the comparison on and off, F7 keeps the changes
+    /// * `--two-players` split the view down the middle and track a hand on
+    ///   each side
+    /// * `--floor <file>` play area projected on the floor, for floor games
+    /// * `--inpaint` fill holes in the depth map, guided by the video
+    /// * `--upsample <factor>` show depth upscaled, guided by the video
+    /// * `--segmentation <off|background|model.onnx>` fill the green screen's
+    ///   depth holes from the video, off by default; a network needs the
+    ///   `onnx` feature
+    /// * `--frustum` start with the coverage view open, F toggles it
+    /// * `--plan <file>` coverage of several sensors placed in a room
+    /// * `--presets <file>` named presets, 1-9 switch and Ctrl+1-9 save
+    /// * `--preset <name>` start with this preset
+    /// * `--poses <file>` pose templates to match, on top of the built-in ones
+    /// * `--pick-pose <name>` pose that picks the point the arm points at,
+    ///   clicking the view always does
+    /// * `--bind-gesture <gesture>=<key>` press a key on a body gesture, e.g.
+    ///   `jump=space`, repeatable
+    /// * `--mirror` start in the mirror view with props, M toggles it
+    /// * `--booth <dir>` save the last seconds as a GIF on K or the trigger pose
+    /// * `--booth-seconds <seconds>` / `--booth-source <composite|greenscreen>`
+    /// * `--booth-pose <name|none>` pose that saves a GIF, `hands_up` by default
+    /// * `--record <dir>` save a clip of every visit there, needs the `record`
+    ///   feature and ffmpeg
+    /// * `--record-format <mp4|webm>` / `--record-source <composite|greenscreen>`
+    /// * `--record-max <seconds>` longest clip
+    /// * `--remote <addr:port>` serve remote control over HTTP, needs the
+    ///   `remote` feature
+    /// * `--public-url <url>` base of the links in QR codes for saved clips
+    /// * `--remote-token <token>` what remote requests have to come with
+    /// * `--no-reconnect` leave the device closed after an error or stall
+    /// * `--no-hotplug` don't watch for the Kinect being unplugged and
+    ///   plugged back in
+    /// * `--incident-log <path>` append stream stalls to this file
+    /// * `--diagnostics` log stream health and frame rates every few seconds
+    /// * `--budget-overlay` show the milliseconds each part of the frame
+    ///   takes, F5 toggles it
+    /// * `--attract-after <seconds>` idle time before attract mode starts
+    /// * `--no-screensaver` keep the normal depth view in attract mode
+    /// * `--hours <hh:mm-hh:mm>` operating hours, the Kinect is off outside
+    ///   them, `--utc-offset <hours>` for the local clock
+    /// * `--closed <attract|off>` stay in attract mode outside the hours, or
+    ///   show that it's closed
+    /// * `--proximity-bands <engaged>,<near>,<mid>` where the distance bands
+    ///   of approaching visitors end, meters
+    /// * `--zone <name>:<x0>,<y0>,<z0>,<x1>,<y1>,<z1>` a box in sensor space
+    ///   between those corners, in meters, whose occupancy is published;
+    ///   repeat for more
+    /// * `--analytics <dir>` export visitor sessions and counters there
+    /// * `--analytics-format <csv|json>` / `--analytics-every <seconds>`
+    /// * `--event-log <path>` log device, session and calibration events
+    ///   there as JSON lines, rotated every 10 MB
+    /// * `--dataset <dir>` save annotated frames for training there
+    /// * `--dataset-every <seconds>` time between saved frames
+    /// * `--dataset-points` also save a point cloud per frame
+    /// * `--npy-export <file>` save depth frames in millimeters to a numpy
+    ///   `.npy`, or an `.npz` with their timestamps
+    /// * `--npy-frames <n>` how many frames to save, one by default
+    /// * `--world-up <y|z>` / `--world-units <m|mm>` /
+    ///   `--world-handedness <left|right>` axes and units of exported points
+    /// * `--fit <letterbox|stretch>` how the view fills a resized window
+    /// * `--present <monitor>` fullscreen installation output on that monitor
+    /// * `--corners <x,y,x,y,x,y,x,y>` corner-pin the presented picture
+    /// * `--span <monitor,monitor,...>` split the picture over these monitors
+    /// * `--span-offset <index>:<x>,<y>` shift one of them into line
+    fn from_args() -> Result<Options, String> {
+        let mut options = Options::default();
+        let mut args = args();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                #[cfg(feature = "freenect2")]
+                "--kinect2" => options.backend = SensorBackend::Freenect2,
+                #[cfg(feature = "freenect2")]
+                "--kinect2-v1" => options.v1_picture = true,
+                #[cfg(feature = "openni2")]
+                "--openni2" => options.backend = SensorBackend::OpenNi2,
+                #[cfg(feature = "mock")]
+                "--simulate" => {
+                    let file = value(&mut args, &arg)?;
+                    options.simulate = Some(file.into());
+                }
+                #[cfg(feature = "network")]
+                "--connect" => options.connect = Some(value(&mut args, &arg)?),
+                #[cfg(feature = "playback")]
+                "--playback" => {
+                    let file = value(&mut args, &arg)?;
+                    options.playback = Some(file.into());
+                }
+                #[cfg(feature = "playback")]
+                "--playback-units" => {
+                    let units = value(&mut args, &arg)?;
+                    options.playback_units = Some(parse(&arg, &units)?);
+                }
+                #[cfg(feature = "playback")]
+                "--playback-once" => options.playback_once = true,
+                "--npy" => {
+                    let file = value(&mut args, &arg)?;
+                    options.npy = Some(file.into());
+                }
+                "--device" => {
+                    let index = value(&mut args, &arg)?;
+                    options.device = parse(&arg, &index)?;
+                }
+                "--serial" => options.serial = Some(value(&mut args, &arg)?),
+                "--extra-device" => {
+                    let device = value(&mut args, &arg)?;
+                    options.multi.extra.push(parse(&arg, &device)?);
+                }
+                "--depth" => {
+                    let format = value(&mut args, &arg)?;
+                    options.depth_format = parse(&arg, &format)?;
+                }
+                "--depth-res" => {
+                    let resolution = value(&mut args, &arg)?;
+                    options.depth_resolution = parse(&arg, &resolution)?;
+                }
+                "--video" => {
+                    let format = value(&mut args, &arg)?;
+                    options.video.format = parse(&arg, &format)?;
+                }
+                "--video-res" => {
+                    let resolution = value(&mut args, &arg)?;
+                    options.video.resolution = parse(&arg, &resolution)?;
+                }
+                "--gpu" => options.video.conversion = VideoConversion::Gpu,
+                "--demosaic" => {
+                    let demosaic = value(&mut args, &arg)?;
+                    options.video.demosaic = parse(&arg, &demosaic)?;
+                }
+                "--depth-fps" => {
+                    let fps = value(&mut args, &arg)?;
+                    options.capture.depth = parse(&arg, &fps)?;
+                }
+                "--video-fps" => {
+                    let fps = value(&mut args, &arg)?;
+                    options.capture.video = parse(&arg, &fps)?;
+                }
+                "--no-interpolate" => options.capture.interpolate = false,
+                "--view" => {
+                    let style = value(&mut args, &arg)?;
+                    options.style = parse(&arg, &style)?;
+                }
+                "--threshold" => {
+                    let threshold = value(&mut args, &arg)?;
+                    options.threshold = Some(parse(&arg, &threshold)?);
+                }
+                "--adaptive-threshold" => options.histogram.adaptive_threshold = true,
+                "--auto-range" => options.histogram.auto_range = true,
+                "--frame-budget" => {
+                    let budget = value(&mut args, &arg)?;
+                    options.quality.budget_ms = Some(parse(&arg, &budget)?);
+                }
+                "--low-memory" => options.embedded.low_memory = true,
+                "--depth-only" => options.usb.depth_only = true,
+                "--frame-queue" => {
+                    let count = value(&mut args, &arg)?;
+                    options.usb.frame_queue = parse(&arg, &count)?;
+                }
+                "--drop-policy" => {
+                    let policy = value(&mut args, &arg)?;
+                    options.usb.drop_policy = parse(&arg, &policy)?;
+                }
+                "--led-tracking" => options.led.tracking = true,
+                "--mic" => options.audio.enabled = true,
+                "--calibration" => {
+                    let file = value(&mut args, &arg)?;
+                    options.calibration.file = Some(file.into());
+                }
+                "--calibrate" => options.calibration.at_startup = true,
+                "--auto-rebaseline" => options.calibration.auto_rebaseline = true,
+                "--scene-change-seconds" => {
+                    let seconds = value(&mut args, &arg)?;
+                    options.calibration.scene_change_seconds = parse(&arg, &seconds)?;
+                }
+                "--calibrate-seconds" => {
+                    let seconds = value(&mut args, &arg)?;
+                    options.calibration.seconds = parse(&arg, &seconds)?;
+                }
+                "--emitter-slot" => {
+                    let slot = value(&mut args, &arg)?;
+                    options.interference.slot = Some(parse(&arg, &slot)?);
+                }
+                "--emitter-slot-seconds" => {
+                    let seconds = value(&mut args, &arg)?;
+                    options.interference.slot_seconds = parse(&arg, &seconds)?;
+                }
+                "--interference-area" => {
+                    let area = value(&mut args, &arg)?;
+                    options.interference.warn_area = parse(&arg, &area)?;
+                }
+                "--exposure" => {
+                    let seconds = value(&mut args, &arg)?;
+                    options.exposure.window = parse(&arg, &seconds)?;
+                }
+                "--extrapolate" => options.tracking.extrapolate = true,
+                "--pointer" => {
+                    let profile = value(&mut args, &arg)?;
+                    options.pointer.profile = Some(parse(&arg, &profile)?);
+                }
+                "--pointer-dead-zone" => {
+                    let pixels = value(&mut args, &arg)?;
+                    options.pointer.dead_zone = Some(parse(&arg, &pixels)?);
+                }
+                "--compare-threshold" => {
+                    let threshold = value(&mut args, &arg)?;
+                    options.compare.threshold = Some(parse(&arg, &threshold)?);
+                    options.compare.enabled = true;
+                }
+                "--compare-smoothing" => {
+                    let seconds = value(&mut args, &arg)?;
+                    options.compare.smoothing = Some(parse(&arg, &seconds)?);
+                    options.compare.enabled = true;
+                }
+                "--compare-dead-zone" => {
+                    let pixels = value(&mut args, &arg)?;
+                    options.compare.dead_zone = Some(parse(&arg, &pixels)?);
+                    options.compare.enabled = true;
+                }
+                "--compare-extrapolate" => {
+                    let on = value(&mut args, &arg)?;
+                    options.compare.extrapolate = Some(match on.as_str() {
+                        "on" => true,
+                        "off" => false,
+                        _ => return Err(format!("{arg} {on}: expected on or off")),
                     });
-                });
-        });
-}
-
-fn read_depth_data(kinect: NonSend<Kinect>, mut depth_query: Query<&mut CurrentDepth<'static>>) {
-    let depth_res = depth_query.get_single_mut();
-
-    match depth_res {
-        Ok(mut depth) => {
-            if let Ok((data, _ /* timestamp */)) = kinect.dstream.receiver.try_recv() {
-                depth.depth_array = data;
-                return;
-            } else {
-                return;
-            }
-        }
-        Err(_) => {}
-    }
-}
-
-fn update_image_from_depth_data(
-    depth_query: Query<&CurrentDepth<'static>>,
-    mut images: ResMut<Assets<Image>>,
-) {
-    let depth_res = depth_query.get_single();
-
-    match depth_res {
-        Ok(depth) => {
-            if depth.depth_array.len() == 0 {
-                return;
-            }
-            if let Some(mut handle) = images.get_mut(&depth.handle) {
-                let mut new_pixels: Vec<u8> = vec![];
-
-                for measurement in depth.depth_array.iter() {
-                    new_pixels.push(0);
-                    new_pixels.push(0);
-                    new_pixels.push(0);
-                    new_pixels.push((measurement / 8) as u8);
+                    options.compare.enabled = true;
                 }
-
-                handle.data = new_pixels;
+                "--two-players" => options.players.two_players = true,
+                "--floor" => {
+                    let file = value(&mut args, &arg)?;
+                    options.floor.area = Some(file.into());
+                }
+                "--pointer-margin" => {
+                    let share = value(&mut args, &arg)?;
+                    options.pointer.edge_margin = parse(&arg, &share)?;
+                }
+                "--inpaint" => options.inpaint.enabled = true,
+                "--frustum" => options.frustum.visible = true,
+                "--presets" => {
+                    let file = value(&mut args, &arg)?;
+                    options.presets.file = Some(file.into());
+                }
+                "--preset" => options.presets.initial = Some(value(&mut args, &arg)?),
+                "--bind-gesture" => {
+                    let binding = value(&mut args, &arg)?;
+                    options.gestures.bind(&binding)?;
+                }
+                "--mirror" => options.mirror.enabled = true,
+                "--pick-pose" => options.picking.pose = args.next(),
+                "--poses" => {
+                    let file = value(&mut args, &arg)?;
+                    options.poses.file = Some(file.into());
+                }
+                "--plan" => {
+                    let file = value(&mut args, &arg)?;
+                    options.planning.plan = Some(file.into());
+                }
+                "--upsample" => {
+                    let factor = value(&mut args, &arg)?;
+                    options.upsample.factor = parse(&arg, &factor)?;
+                }
+                "--segmentation" => {
+                    let rgb = value(&mut args, &arg)?;
+                    options.segmentation.rgb = parse(&arg, &rgb)?;
+                }
+                "--booth" => {
+                    let dir = value(&mut args, &arg)?;
+                    options.booth.dir = Some(dir.into());
+                }
+                "--booth-seconds" => {
+                    let seconds = value(&mut args, &arg)?;
+                    options.booth.seconds = parse(&arg, &seconds)?;
+                }
+                "--booth-source" => {
+                    let source = value(&mut args, &arg)?;
+                    options.booth.source = parse(&arg, &source)?;
+                }
+                "--booth-pose" => {
+                    let pose = value(&mut args, &arg)?;
+                    options.booth.pose = (pose != "none").then_some(pose);
+                }
+                #[cfg(feature = "record")]
+                "--record" => {
+                    let dir = value(&mut args, &arg)?;
+                    options.recording.dir = Some(dir.into());
+                }
+                #[cfg(feature = "record")]
+                "--record-format" => {
+                    let format = value(&mut args, &arg)?;
+                    options.recording.format = parse(&arg, &format)?;
+                }
+                #[cfg(feature = "record")]
+                "--record-source" => {
+                    let source = value(&mut args, &arg)?;
+                    options.recording.source = parse(&arg, &source)?;
+                }
+                #[cfg(feature = "record")]
+                "--record-max" => {
+                    let seconds = value(&mut args, &arg)?;
+                    options.recording.max_seconds = parse(&arg, &seconds)?;
+                }
+                #[cfg(feature = "remote")]
+                "--remote" => {
+                    let addr = value(&mut args, &arg)?;
+                    options.remote.addr = Some(parse(&arg, &addr)?);
+                }
+                #[cfg(feature = "remote")]
+                "--public-url" => options.remote.public_url = args.next(),
+                #[cfg(feature = "remote")]
+                "--remote-token" => options.remote.token = args.next(),
+                "--no-reconnect" => options.reconnect.enabled = false,
+                "--no-hotplug" => options.hotplug.enabled = false,
+                "--incident-log" => {
+                    let path = value(&mut args, &arg)?;
+                    options.watchdog.log = Some(path.into());
+                }
+                "--diagnostics" => options.log_diagnostics = true,
+                "--budget-overlay" => options.budget.overlay = true,
+                "--present" => {
+                    let monitor = value(&mut args, &arg)?;
+                    options.presentation.enabled = true;
+                    options.presentation.monitor = parse(&arg, &monitor)?;
+                }
+                "--corners" => {
+                    let corners = value(&mut args, &arg)?;
+                    options.presentation.corners = parse(&arg, &corners)?;
+                }
+                "--span" => {
+                    let monitors = value(&mut args, &arg)?;
+                    options.span = parse(&arg, &monitors)?;
+                }
+                "--span-offset" => {
+                    let offset = value(&mut args, &arg)?;
+                    options.span.set_offset(&offset)?;
+                }
+                "--attract-after" => {
+                    let seconds = value(&mut args, &arg)?;
+                    options.attract.timeout = parse(&arg, &seconds)?;
+                }
+                "--no-screensaver" => options.attract.screensaver = false,
+                "--hours" => {
+                    let hours = value(&mut args, &arg)?;
+                    options.hours.hours = Some(parse(&arg, &hours)?);
+                }
+                "--utc-offset" => {
+                    let offset = value(&mut args, &arg)?;
+                    options.hours.utc_offset = parse(&arg, &offset)?;
+                }
+                "--closed" => {
+                    let mode = value(&mut args, &arg)?;
+                    options.hours.closed = parse(&arg, &mode)?;
+                }
+                "--proximity-bands" => {
+                    let bands = value(&mut args, &arg)?;
+                    options.proximity.bands = parse(&arg, &bands)?;
+                }
+                "--zone" => {
+                    let zone = value(&mut args, &arg)?;
+                    options.zones.zones.push(parse(&arg, &zone)?);
+                }
+                "--analytics" => {
+                    let dir = value(&mut args, &arg)?;
+                    options.analytics.dir = Some(dir.into());
+                }
+                "--analytics-format" => {
+                    let format = value(&mut args, &arg)?;
+                    options.analytics.format = parse(&arg, &format)?;
+                }
+                "--analytics-every" => {
+                    let seconds = value(&mut args, &arg)?;
+                    options.analytics.interval = parse(&arg, &seconds)?;
+                }
+                "--event-log" => {
+                    let path = value(&mut args, &arg)?;
+                    options.event_log.path = Some(path.into());
+                }
+                "--dataset" => {
+                    let dir = value(&mut args, &arg)?;
+                    options.dataset.dir = Some(dir.into());
+                }
+                "--dataset-every" => {
+                    let seconds = value(&mut args, &arg)?;
+                    options.dataset.interval = parse(&arg, &seconds)?;
+                }
+                "--dataset-points" => options.dataset.points = true,
+                "--npy-export" => {
+                    let file = value(&mut args, &arg)?;
+                    options.numpy.path = Some(file.into());
+                }
+                "--npy-frames" => {
+                    let frames = value(&mut args, &arg)?;
+                    options.numpy.frames = parse(&arg, &frames)?;
+                }
+                "--world-up" => {
+                    let up = value(&mut args, &arg)?;
+                    options.world.up = parse(&arg, &up)?;
+                }
+                "--world-units" => {
+                    let units = value(&mut args, &arg)?;
+                    options.world.units = parse(&arg, &units)?;
+                }
+                "--world-handedness" => {
+                    let handedness = value(&mut args, &arg)?;
+                    options.world.handedness = parse(&arg, &handedness)?;
+                }
+                "--fit" => {
+                    let fit = value(&mut args, &arg)?;
+                    options.display.fit = parse(&arg, &fit)?;
+                }
+                // the page's query string may carry more than the app's own
+                #[cfg(target_arch = "wasm32")]
+                _ => {}
+                #[cfg(not(target_arch = "wasm32"))]
+                _ => return Err(format!("unknown argument '{arg}'")),
             }
         }
-        Err(_) => {}
-    }
-}
-
-fn move_crosshair_to_pos(
-    depth_query: Query<&CurrentDepth<'static>>,
-    mut transform_query: Query<&mut Transform, With<Crosshair>>,
-    q_camera: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
-) {
-    if let Ok(depth) = depth_query.get_single() {
-        if depth.depth_array.len() == 0 {
-            return;
+        options.display.offscreen = options.presentation.enabled || options.span.is_enabled();
+        if let Some(threshold) = options.threshold {
+            options.tracking.threshold = options.depth_format.to_bit10(threshold);
         }
-        let (camera, camera_transform) = q_camera.single();
-
-        let mut screen_pos = center_of_close_blob(depth.depth_array);
-        screen_pos.y = (screen_pos.y - 480.0).abs();
-        if screen_pos.x < 0.1 {
-            return;
+        if let Some(threshold) = &mut options.compare.threshold {
+            *threshold = options.depth_format.to_bit10(*threshold);
         }
+        if !options.video.format.supports(options.video.resolution) {
+            return Err(format!(
+                "{:?} video is not available in {:?} resolution",
+                options.video.format, options.video.resolution
+            ));
+        }
+        Ok(options)
+    }
+}
 
-        let window_size = Vec2::new(640.0 as f32, 480.0 as f32);
-
-        // convert screen position [0..resolution] to ndc [-1..1] (gpu coordinates)
-        let ndc = (screen_pos / window_size) * 2.0 - Vec2::ONE;
-
-        // matrix for undoing the projection and camera transform
-        let ndc_to_world = camera_transform.compute_matrix() * camera.projection_matrix().inverse();
+/// The value after `flag`.
+fn value(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<String, String> {
+    args.next().ok_or_else(|| format!("{flag} needs a value"))
+}
 
-        // use it to convert ndc to world-space coordinates
-        let world_pos = ndc_to_world.project_point3(ndc.extend(-1.0));
+/// `value` parsed, with `flag` in the error.
+fn parse<T>(flag: &str, value: &str) -> Result<T, String>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    value
+        .parse()
+        .map_err(|err| format!("{flag} {value}: {err}"))
+}
 
-        // reduce it to a 2D value
-        let world_pos: Vec2 = world_pos.truncate();
+/// Printed with what was wrong on the command line.
+const USAGE: &str = "usage: bevy-kinect [options], the options are listed in the README";
 
-        let mut crosshair_t = transform_query.single_mut();
-        crosshair_t.translation.x = world_pos.x;
-        crosshair_t.translation.y = world_pos.y;
-    }
+#[cfg(not(target_arch = "wasm32"))]
+fn usage_error(err: &str) -> ! {
+    eprintln!("{err}\n{USAGE}");
+    std::process::exit(2)
 }
 
-fn center_of_close_blob(data: &[u16]) -> Vec2 {
-    // assumes 640 x 480
+/// There is no process to end in the browser, the panic shows in the
+/// console.
+#[cfg(target_arch = "wasm32")]
+fn usage_error(err: &str) -> ! {
+    panic!("{err}\n{USAGE}")
+}
 
-    let mut break_outer = false;
+#[cfg(not(target_arch = "wasm32"))]
+fn args() -> impl Iterator<Item = String> {
+    std::env::args().skip(1)
+}
 
-    let mut left_most: u16 = 0;
-    let mut right_most: u16 = 0;
-    let mut top_most: u16 = 0;
-    let mut bottom_most: u16 = 0;
+/// In the browser the page's query string stands in for the command line,
+/// `?connect=ws://10.0.0.5:9000&view=rainbow` for `--connect
+/// ws://10.0.0.5:9000 --view rainbow`.
+#[cfg(target_arch = "wasm32")]
+fn args() -> impl Iterator<Item = String> {
+    let query = web_sys::window()
+        .and_then(|window| window.location().search().ok())
+        .unwrap_or_default();
+    let decode = |value: &str| {
+        js_sys::decode_uri_component(value)
+            .ok()
+            .and_then(|value| value.as_string())
+            .unwrap_or_default()
+    };
+    let args: Vec<String> = query
+        .trim_start_matches('?')
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .flat_map(|pair| match pair.split_once('=') {
+            Some((key, value)) => vec![format!("--{key}"), decode(value)],
+            None => vec![format!("--{pair}")],
+        })
+        .collect();
+    args.into_iter()
+}
 
-    let arr_2d = Array2D::from_iter_row_major(data.iter(), 480, 640);
-    for i in 0..640 {
-        for k in arr_2d.column_iter(i) {
-            if k < &&400 {
-                break_outer = true;
-                left_most = i as u16;
-                break;
-            }
-        }
-        if break_outer {
-            break;
-        }
+fn main() {
+    let options = Options::from_args().unwrap_or_else(|err| usage_error(&err));
+
+    let mut window = WindowDescriptor {
+        title: "Bevy Kinect".to_string(),
+        width: 640.,
+        height: 480.,
+        ..default()
+    };
+    options.presentation.apply(&mut window);
+    if options.span.is_enabled() {
+        // one 640x480 slice per display to start with
+        window.width = 640. * options.span.displays.len() as f32;
     }
 
-    break_outer = false;
-
-    let arr_2d = Array2D::from_iter_row_major(data.iter(), 480, 640);
-    for i in (0..640).rev() {
-        for k in arr_2d.column_iter(i) {
-            if k < &&400 {
-                break_outer = true;
-                right_most = i as u16;
-                break;
-            }
-        }
-        if break_outer {
-            break;
-        }
+    let mut app = App::new();
+    // before the plugins, some read their settings while being added
+    #[cfg(feature = "remote")]
+    app.insert_resource(options.remote);
+    #[cfg(feature = "record")]
+    app.insert_resource(options.recording);
+    #[cfg(feature = "mock")]
+    if let Some(path) = &options.simulate {
+        let scene = if path.as_os_str() == "sweep" {
+            Scene::sweep()
+        } else {
+            // the error names the file already
+            Scene::load(path).unwrap_or_else(|err| usage_error(&err))
+        };
+        app.insert_resource(DepthCamera::new(SimulatedKinect::new(scene)));
     }
-
-    break_outer = false;
-
-    let arr_2d = Array2D::from_iter_row_major(data.iter(), 480, 640);
-    for i in 0..480 {
-        for k in arr_2d.row_iter(i) {
-            if k < &&400 {
-                break_outer = true;
-                top_most = i as u16;
-                break;
-            }
-        }
-        if break_outer {
-            break;
-        }
+    #[cfg(feature = "network")]
+    if let Some(address) = &options.connect {
+        let backend = NetworkBackend::new(address.as_str(), options.depth_format);
+        app.insert_resource(DepthCamera::new(backend));
     }
-
-    break_outer = false;
-
-    let arr_2d = Array2D::from_iter_row_major(data.iter(), 480, 640);
-    for i in (0..480).rev() {
-        for k in arr_2d.row_iter(i) {
-            if k < &&400 {
-                break_outer = true;
-                bottom_most = i as u16;
-                break;
-            }
-        }
-        if break_outer {
-            break;
-        }
+    #[cfg(feature = "playback")]
+    if let Some(path) = &options.playback {
+        let units = options.playback_units.unwrap_or(DepthFormat::Millimeters);
+        let backend = PlaybackBackend::new(path, units).looping(!options.playback_once);
+        app.insert_resource(DepthCamera::new(backend));
     }
-
-    Vec2::new(
-        ((left_most + right_most) / 2).into(),
-        ((top_most + bottom_most) / 2).into(),
-    )
-}
-
-fn keyboard_input(keys: Res<Input<KeyCode>>, kinect: NonSend<Kinect>) {
-    if keys.just_pressed(KeyCode::Down) {
-        let tilt_degree = kinect.device.get_tilt_degree().unwrap();
-        kinect.device.set_tilt_degree(tilt_degree - 5.0).unwrap();
+    if let Some(path) = &options.npy {
+        let backend = NumpyBackend::load(path, DepthFormat::Millimeters)
+            .unwrap_or_else(|err| usage_error(&format!("{}: {err}", path.display())));
+        app.insert_resource(DepthCamera::new(backend));
     }
-
-    if keys.just_pressed(KeyCode::Up) {
-        let tilt_degree = kinect.device.get_tilt_degree().unwrap();
-        kinect.device.set_tilt_degree(tilt_degree + 5.0).unwrap();
+    app.insert_resource(KinectConfig {
+        backend: options.backend,
+        v1_picture: options.v1_picture,
+        device_index: options.device,
+        serial: options.serial.clone(),
+        depth_format: options.depth_format,
+        depth_resolution: options.depth_resolution,
+        ..default()
+    })
+    .insert_resource(options.video)
+    .insert_resource(options.capture)
+    .insert_resource(options.reconnect)
+    .insert_resource(options.hotplug)
+    .insert_resource(options.watchdog)
+    .insert_resource(options.display)
+    .insert_resource(options.presentation)
+    .insert_resource(options.span)
+    .insert_resource(options.attract)
+    .insert_resource(options.hours)
+    .insert_resource(options.proximity)
+    .insert_resource(options.histogram)
+    .insert_resource(options.quality)
+    .insert_resource(options.embedded)
+    .insert_resource(options.usb)
+    .insert_resource(options.multi.clone())
+    .insert_resource(options.led)
+    .insert_resource(options.audio)
+    .insert_resource(options.zones)
+    .insert_resource(options.analytics)
+    .insert_resource(options.event_log)
+    .insert_resource(options.dataset)
+    .insert_resource(options.numpy)
+    .insert_resource(options.world)
+    .insert_resource(options.style)
+    .insert_resource(options.tracking)
+    .insert_resource(options.pointer)
+    .insert_resource(options.compare)
+    .insert_resource(options.budget)
+    .insert_resource(options.players)
+    .insert_resource(options.floor)
+    .insert_resource(options.calibration)
+    .insert_resource(options.interference)
+    .insert_resource(options.inpaint)
+    .insert_resource(options.upsample)
+    .insert_resource(options.segmentation)
+    .insert_resource(options.frustum)
+    .insert_resource(options.planning)
+    .insert_resource(options.presets)
+    .insert_resource(options.poses)
+    .insert_resource(options.picking)
+    .insert_resource(options.gestures)
+    .insert_resource(options.mirror)
+    .insert_resource(options.exposure)
+    .insert_resource(options.booth)
+    .add_plugins(DefaultPlugins.set(WindowPlugin {
+        window,
+        ..default()
+    }))
+    .add_plugins(KinectPlugin::default());
+    if options.log_diagnostics {
+        app.add_plugin(LogDiagnosticsPlugin::default());
     }
-}
-
-fn main() {
-    App::new()
-        .add_startup_system(setup_kinect)
-        .add_startup_system(spawn_depth)
-        .add_plugins(DefaultPlugins.set(WindowPlugin {
-            window: WindowDescriptor {
-                title: "Bevy Kinect".to_string(),
-                width: 640.,
-                height: 480.,
-                ..default()
-            },
-            ..default()
-        }))
-        .add_system(read_depth_data)
-        .add_system(keyboard_input)
-        .add_system(update_image_from_depth_data)
-        .add_system(move_crosshair_to_pos)
-        .run();
+    app.run();
 }
@@ -0,0 +1,344 @@
+//! Magic mirror: the video flipped left to right so it moves like a mirror
+//! image, with props anchored to the tracked skeleton. The person is cut out of
+//! the video with the [`PersonMask`] and drawn again on top, so wings can go
+//! behind them and a hat in front:
+//!
+//! 1. the whole video, flipped
+//! 2. props behind the person
+//! 3. only the person, flipped
+//! 4. props in front
+//!
+//! M turns it on and off, `--mirror` starts with it on. The depth view is
+//! hidden meanwhile.
+
+use bevy::prelude::*;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+
+use crate::anchor::{AnchorPoint, BodyAnchor, ScaleWithDistance};
+use crate::coords::{self, DEPTH_HEIGHT, DEPTH_WIDTH};
+use crate::display::{ViewImage, ViewSprite};
+use crate::segmentation::PersonMask;
+use crate::skeleton::Joint;
+use crate::video::{CurrentVideo, VideoSettings};
+use crate::{CurrentDepth, MainKinect, TrackingSettings, NO_DEPTH};
+
+const HAT_SIZE: u32 = 64;
+const WINGS_SIZE: (u32, u32) = (128, 64);
+const SPARKLE_SIZE: u32 = 32;
+
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct MirrorSettings {
+    pub enabled: bool,
+}
+
+/// Flipped video, and the same with everything but the person transparent.
+#[derive(Resource)]
+struct MirrorImages {
+    background: Handle<Image>,
+    person: Handle<Image>,
+}
+
+#[derive(Component)]
+struct MirrorLayer;
+
+pub struct MirrorPlugin;
+
+impl Plugin for MirrorPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MirrorSettings>()
+            .add_startup_system(spawn_mirror)
+            .add_system(toggle_mirror)
+            .add_system(update_mirror_images.after(toggle_mirror));
+    }
+}
+
+fn spawn_mirror(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
+    let mut layer = |images: &mut Assets<Image>, z: f32| {
+        let handle = images.add(Image::new_fill(
+            Extent3d {
+                width: DEPTH_WIDTH as u32,
+                height: DEPTH_HEIGHT as u32,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            &[0, 0, 0, 0],
+            TextureFormat::Rgba8UnormSrgb,
+        ));
+        commands.spawn((
+            SpriteBundle {
+                sprite: Sprite {
+                    custom_size: Some(Vec2::new(DEPTH_WIDTH as f32, DEPTH_HEIGHT as f32)),
+                    ..default()
+                },
+                texture: handle.clone(),
+                // between the video and the crosshair
+                transform: Transform::from_xyz(0.0, 0.0, z),
+                visibility: Visibility { is_visible: false },
+                ..default()
+            },
+            ViewSprite { offset: Vec2::ZERO },
+            MirrorLayer,
+        ));
+        handle
+    };
+    let background = layer(&mut images, 0.2);
+    let person = layer(&mut images, 0.6);
+    commands.insert_resource(MirrorImages { background, person });
+
+    // the props are only shown with the mirror, anchored entities show and
+    // hide themselves as the joint comes and goes
+    let mut prop = |texture: Image, z: f32, size: Vec2, anchor: BodyAnchor| {
+        (
+            SpriteBundle {
+                sprite: Sprite {
+                    custom_size: Some(size),
+                    ..default()
+                },
+                texture: images.add(texture),
+                transform: Transform::from_xyz(0.0, 0.0, z),
+                ..default()
+            },
+            anchor,
+            ScaleWithDistance,
+        )
+    };
+    let wings = prop(
+        wings_image(),
+        0.4,
+        Vec2::new(1.2, 0.6),
+        BodyAnchor {
+            joint: Joint::Neck.into(),
+            offset: Vec3::new(0.0, -0.1, 0.1),
+        },
+    );
+    let hat = prop(
+        hat_image(),
+        0.8,
+        Vec2::new(0.3, 0.3),
+        BodyAnchor {
+            joint: Joint::Head.into(),
+            offset: Vec3::new(0.0, 0.2, 0.0),
+        },
+    );
+    // on whatever is held out closest, usually a hand
+    let sparkle = prop(
+        sparkle_image(),
+        0.9,
+        Vec2::new(0.15, 0.15),
+        BodyAnchor {
+            joint: AnchorPoint::CloseBlob,
+            offset: Vec3::ZERO,
+        },
+    );
+    commands
+        .spawn((
+            SpatialBundle {
+                visibility: Visibility { is_visible: false },
+                ..default()
+            },
+            MirrorLayer,
+        ))
+        .with_children(|parent| {
+            parent.spawn(wings);
+            parent.spawn(hat);
+            parent.spawn(sparkle);
+        });
+}
+
+fn toggle_mirror(
+    keys: Res<Input<KeyCode>>,
+    mut settings: ResMut<MirrorSettings>,
+    mut layer_query: Query<&mut Visibility, (With<MirrorLayer>, Without<ViewImage>)>,
+    mut view_query: Query<&mut Visibility, (With<ViewImage>, Without<MirrorLayer>)>,
+) {
+    if keys.just_pressed(KeyCode::M) {
+        settings.enabled = !settings.enabled;
+    }
+    if !settings.is_changed() {
+        return;
+    }
+    for mut visibility in layer_query.iter_mut() {
+        visibility.is_visible = settings.enabled;
+    }
+    for mut visibility in view_query.iter_mut() {
+        visibility.is_visible = !settings.enabled;
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn update_mirror_images(
+    settings: Res<MirrorSettings>,
+    (video_settings, tracking): (Res<VideoSettings>, Res<TrackingSettings>),
+    mirror: Res<MirrorImages>,
+    mask: Res<PersonMask>,
+    depth_query: Query<&CurrentDepth, With<MainKinect>>,
+    video_query: Query<&CurrentVideo, Changed<CurrentVideo>>,
+    mut images: ResMut<Assets<Image>>,
+    mut video: Local<Vec<u8>>,
+) {
+    if !settings.enabled {
+        return;
+    }
+    let source = match video_query
+        .get_single()
+        .ok()
+        .and_then(|current| images.get(&current.handle))
+    {
+        Some(image) => image,
+        None => return,
+    };
+    video_settings.image_to_rgba(&source.data, &mut video);
+    let (video_width, video_height) = video_settings.resolution.size();
+    let (video_width, video_height) = (video_width as usize, video_height as usize);
+    if video.len() != video_width * video_height * 4 {
+        return;
+    }
+    let person = match depth_query.get_single() {
+        Ok(depth) => color_mask(&mask.0, &depth.depth_array, tracking.threshold),
+        Err(_) => vec![false; DEPTH_WIDTH * DEPTH_HEIGHT],
+    };
+
+    // the video sprite keeps the video's aspect ratio, the view is 4:3
+    let scale = video_width as f32 / DEPTH_WIDTH as f32;
+    let mut flipped = vec![0; DEPTH_WIDTH * DEPTH_HEIGHT * 4];
+    let mut cutout = vec![0; DEPTH_WIDTH * DEPTH_HEIGHT * 4];
+    for y in 0..DEPTH_HEIGHT {
+        let sy = ((y as f32 * scale) as usize).min(video_height - 1);
+        for x in 0..DEPTH_WIDTH {
+            let source_x = DEPTH_WIDTH - 1 - x;
+            let sx = ((source_x as f32 * scale) as usize).min(video_width - 1);
+            let from = (sy * video_width + sx) * 4;
+            let to = (y * DEPTH_WIDTH + x) * 4;
+            flipped[to..to + 4].copy_from_slice(&video[from..from + 4]);
+            flipped[to + 3] = 255;
+            if person[y * DEPTH_WIDTH + source_x] {
+                cutout[to..to + 4].copy_from_slice(&flipped[to..to + 4]);
+            }
+        }
+    }
+    if let Some(image) = images.get_mut(&mirror.background) {
+        image.data = flipped;
+    }
+    if let Some(image) = images.get_mut(&mirror.person) {
+        image.data = cutout;
+    }
+}
+
+/// The person mask moved from depth pixels to the color pixels they show up
+/// at, grown by a pixel to close the gaps between them.
+fn color_mask(mask: &[bool], depth: &[u16], threshold: u16) -> Vec<bool> {
+    let mut color = vec![false; DEPTH_WIDTH * DEPTH_HEIGHT];
+    if mask.len() != depth.len() {
+        return color;
+    }
+    let hole_meters = coords::raw_depth_to_meters(threshold).unwrap_or(1.0);
+    for (i, (person, raw)) in mask.iter().zip(depth).enumerate() {
+        if !person {
+            continue;
+        }
+        let pixel = Vec2::new((i % DEPTH_WIDTH) as f32, (i / DEPTH_WIDTH) as f32);
+        let meters = match *raw {
+            NO_DEPTH => hole_meters,
+            raw => coords::raw_depth_to_meters(raw).unwrap_or(hole_meters),
+        };
+        let at = coords::depth_pixel_to_color_pixel(pixel, meters);
+        for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+            let (x, y) = (at.x as isize + dx, at.y as isize + dy);
+            if x >= 0 && y >= 0 && (x as usize) < DEPTH_WIDTH && (y as usize) < DEPTH_HEIGHT {
+                color[y as usize * DEPTH_WIDTH + x as usize] = true;
+            }
+        }
+    }
+    color
+}
+
+fn hat_image() -> Image {
+    let size = HAT_SIZE as usize;
+    let mut data = vec![0; size * size * 4];
+    for y in 0..size {
+        for x in 0..size {
+            let (u, v) = (x as f32 / size as f32 - 0.5, y as f32 / size as f32);
+            // pointed crown narrowing to the top, with a wide brim
+            let crown = v < 0.85 && u.abs() < v * 0.3;
+            let brim = (0.8..0.92).contains(&v);
+            let star = (u - 0.03).abs() + (v - 0.55).abs() < 0.07;
+            let color = match (crown, brim) {
+                _ if crown && star => [255, 220, 80, 255],
+                (true, _) => [70, 40, 150, 255],
+                (_, true) => [50, 25, 110, 255],
+                _ => continue,
+            };
+            data[(y * size + x) * 4..][..4].copy_from_slice(&color);
+        }
+    }
+    Image::new(
+        Extent3d {
+            width: HAT_SIZE,
+            height: HAT_SIZE,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    )
+}
+
+fn wings_image() -> Image {
+    let (width, height) = (WINGS_SIZE.0 as usize, WINGS_SIZE.1 as usize);
+    let mut data = vec![0; width * height * 4];
+    for y in 0..height {
+        for x in 0..width {
+            let (u, v) = (x as f32 / width as f32, y as f32 / height as f32);
+            // one wing per side, fanning out upwards from the middle
+            let side = (u - 0.5).abs() * 2.0;
+            let edge = 1.0 - side * 0.8;
+            if side < 0.05 || v < (side - 0.3).max(0.0) * 0.6 || v > edge {
+                continue;
+            }
+            let feather = ((side * 12.0).fract() < 0.15) as u8 * 40;
+            let color = [255 - feather, 250 - feather, 240 - feather, 230];
+            data[(y * width + x) * 4..][..4].copy_from_slice(&color);
+        }
+    }
+    Image::new(
+        Extent3d {
+            width: WINGS_SIZE.0,
+            height: WINGS_SIZE.1,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    )
+}
+
+fn sparkle_image() -> Image {
+    let size = SPARKLE_SIZE as usize;
+    let mut data = vec![0; size * size * 4];
+    for y in 0..size {
+        for x in 0..size {
+            let (u, v) = (
+                x as f32 / size as f32 * 2.0 - 1.0,
+                y as f32 / size as f32 * 2.0 - 1.0,
+            );
+            // four pointed star, brightest in the middle
+            let star = 1.0 - (u.abs().sqrt() + v.abs().sqrt());
+            if star <= 0.0 {
+                continue;
+            }
+            let alpha = (star * 2.0).min(1.0);
+            let color = [255, 240, 160, (alpha * 255.0) as u8];
+            data[(y * size + x) * 4..][..4].copy_from_slice(&color);
+        }
+    }
+    Image::new(
+        Extent3d {
+            width: SPARKLE_SIZE,
+            height: SPARKLE_SIZE,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    )
+}
@@ -0,0 +1,158 @@
+//! How fast each part of the scene is moving, as a texture for shaders. The
+//! [`MotionField`] image is the depth view's 640x480 in `R8Unorm`, 0 for still
+//! and 1 for [`FULL_SPEED`] or faster, from the difference between
+//! consecutive depth frames. Values fade out over a fraction of a second
+//! rather than dropping to 0, so effects driven by it trail a little.
+//!
+//! Bind it like any texture, e.g. `#[texture(1)] motion: Handle<Image>` in an
+//! `AsBindGroup` material, and read `.r`. G shows it as a glow over the view.
+
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::render::render_resource::{
+    AsBindGroup, Extent3d, ShaderRef, TextureDimension, TextureFormat,
+};
+use bevy::sprite::{Material2d, Material2dPlugin, MaterialMesh2dBundle};
+
+use crate::coords::{self, DEPTH_HEIGHT, DEPTH_WIDTH, VIEW_SIZE};
+use crate::display::ViewSprite;
+use crate::pipeline::{self, DepthPipelineApp, DepthStage};
+use crate::{CurrentDepth, MainKinect};
+
+/// Meters per second that saturate the field.
+pub const FULL_SPEED: f32 = 2.0;
+/// How fast old motion fades, per second.
+const FADE_RATE: f32 = 6.0;
+
+#[derive(Resource)]
+pub struct MotionField {
+    pub image: Handle<Image>,
+}
+
+/// Tints the view where things move, `motion_glow.wgsl`.
+#[derive(AsBindGroup, TypeUuid, Debug, Clone)]
+#[uuid = "5a0c3e4f-8f0d-4f7c-9a55-2b6f1d3c8e91"]
+pub struct MotionGlowMaterial {
+    #[uniform(0)]
+    color: Color,
+    #[texture(1)]
+    #[sampler(2)]
+    motion: Handle<Image>,
+}
+
+impl Material2d for MotionGlowMaterial {
+    fn fragment_shader() -> ShaderRef {
+        "shaders/motion_glow.wgsl".into()
+    }
+}
+
+#[derive(Component)]
+struct MotionGlow(Handle<MotionGlowMaterial>);
+
+pub struct MotionPlugin;
+
+impl Plugin for MotionPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(Material2dPlugin::<MotionGlowMaterial>::default())
+            .add_startup_system(spawn_motion_field)
+            .add_depth_stage(DepthStage::consumer(
+                pipeline::UpdateMotionField,
+                update_motion_field,
+            ))
+            .add_system(toggle_motion_glow);
+    }
+}
+
+fn spawn_motion_field(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<MotionGlowMaterial>>,
+) {
+    let image = images.add(Image::new_fill(
+        Extent3d {
+            width: DEPTH_WIDTH as u32,
+            height: DEPTH_HEIGHT as u32,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[0],
+        TextureFormat::R8Unorm,
+    ));
+    let material = materials.add(MotionGlowMaterial {
+        color: Color::rgba(1.0, 0.5, 0.1, 0.8),
+        motion: image.clone(),
+    });
+    commands.spawn((
+        MaterialMesh2dBundle {
+            mesh: meshes.add(Mesh::from(shape::Quad::new(VIEW_SIZE))).into(),
+            material: material.clone(),
+            // over the video, under the crosshair
+            transform: Transform::from_xyz(0.0, 0.0, 0.5),
+            visibility: Visibility { is_visible: false },
+            ..default()
+        },
+        ViewSprite { offset: Vec2::ZERO },
+        MotionGlow(material),
+    ));
+    commands.insert_resource(MotionField { image });
+}
+
+fn update_motion_field(
+    field: Res<MotionField>,
+    depth_query: Query<&CurrentDepth, With<MainKinect>>,
+    mut images: ResMut<Assets<Image>>,
+    mut values: Local<Vec<f32>>,
+    mut last_frame: Local<f64>,
+) {
+    let depth = match depth_query.get_single() {
+        Ok(depth) if depth.received_at != *last_frame && depth.is_medium() => depth,
+        _ => return,
+    };
+    let dt = (depth.received_at - *last_frame) as f32;
+    *last_frame = depth.received_at;
+    if depth.depth_array.len() != depth.previous.len() || dt <= 0.0 {
+        return;
+    }
+    values.resize(depth.depth_array.len(), 0.0);
+
+    let fade = (-FADE_RATE * dt).exp();
+    for ((value, now), before) in values
+        .iter_mut()
+        .zip(&depth.depth_array)
+        .zip(&depth.previous)
+    {
+        // holes coming and going are noise, not motion
+        let speed = match (
+            coords::raw_depth_to_meters(*now),
+            coords::raw_depth_to_meters(*before),
+        ) {
+            (Some(now), Some(before)) => (now - before).abs() / dt,
+            _ => 0.0,
+        };
+        *value = (speed / FULL_SPEED).min(1.0).max(*value * fade);
+    }
+    if let Some(image) = images.get_mut(&field.image) {
+        image.data.clear();
+        image
+            .data
+            .extend(values.iter().map(|value| (value * 255.0) as u8));
+    }
+}
+
+fn toggle_motion_glow(
+    keys: Res<Input<KeyCode>>,
+    mut materials: ResMut<Assets<MotionGlowMaterial>>,
+    mut glow_query: Query<(&MotionGlow, &mut Visibility)>,
+) {
+    for (glow, mut visibility) in glow_query.iter_mut() {
+        if keys.just_pressed(KeyCode::G) {
+            visibility.is_visible = !visibility.is_visible;
+        }
+        // materials cache their bind group, touch it so the new field is
+        // picked up
+        if visibility.is_visible {
+            materials.get_mut(&glow.0);
+        }
+    }
+}
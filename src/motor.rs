@@ -0,0 +1,249 @@
+//! Tilt motor, accelerometer and LED. freenectrs only wraps setting the
+//! tilt, so [`Motor`] talks to libfreenect directly and opens the motor
+//! subdevice in a context of its own. Motor requests are plain USB control
+//! transfers, so unlike the camera this context doesn't need an event thread.
+//! The types describing the motor are shared with the other backends, the
+//! bindings with the [microphones](crate::audio) and the [device
+//! list](crate::devices).
+
+use std::fmt;
+#[cfg(feature = "freenect")]
+use std::ptr;
+
+use bevy::prelude::Vec3;
+
+#[cfg(feature = "freenect")]
+#[allow(non_camel_case_types)]
+pub(crate) mod ffi {
+    use std::os::raw::{c_char, c_double, c_int, c_long, c_void};
+
+    pub enum freenect_context {}
+    pub enum freenect_device {}
+
+    #[repr(C)]
+    pub struct freenect_raw_tilt_state {
+        pub accelerometer_x: i16,
+        pub accelerometer_y: i16,
+        pub accelerometer_z: i16,
+        pub tilt_angle: i8,
+        pub tilt_status: c_int,
+    }
+
+    #[repr(C)]
+    pub struct timeval {
+        pub tv_sec: c_long,
+        pub tv_usec: c_long,
+    }
+
+    #[repr(C)]
+    pub struct freenect_device_attributes {
+        pub next: *mut freenect_device_attributes,
+        pub camera_serial: *const c_char,
+    }
+
+    pub const FREENECT_DEVICE_MOTOR: c_int = 0x01;
+    pub const FREENECT_DEVICE_CAMERA: c_int = 0x02;
+    pub const FREENECT_DEVICE_AUDIO: c_int = 0x04;
+
+    pub const TILT_STATUS_LIMIT: c_int = 0x01;
+    pub const TILT_STATUS_MOVING: c_int = 0x04;
+
+    pub type freenect_audio_in_cb = extern "C" fn(
+        dev: *mut freenect_device,
+        num_samples: c_int,
+        mic1: *mut i32,
+        mic2: *mut i32,
+        mic3: *mut i32,
+        mic4: *mut i32,
+        cancelled: *mut i16,
+        unknown: *mut c_void,
+    );
+
+    #[link(name = "freenect")]
+    extern "C" {
+        pub fn freenect_init(ctx: *mut *mut freenect_context, usb_ctx: *mut c_void) -> c_int;
+        pub fn freenect_shutdown(ctx: *mut freenect_context) -> c_int;
+        pub fn freenect_select_subdevices(ctx: *mut freenect_context, subdevs: c_int);
+        pub fn freenect_num_devices(ctx: *mut freenect_context) -> c_int;
+        pub fn freenect_supported_subdevices() -> c_int;
+        pub fn freenect_list_device_attributes(
+            ctx: *mut freenect_context,
+            attribute_list: *mut *mut freenect_device_attributes,
+        ) -> c_int;
+        pub fn freenect_free_device_attributes(attribute_list: *mut freenect_device_attributes);
+        pub fn freenect_open_device(
+            ctx: *mut freenect_context,
+            dev: *mut *mut freenect_device,
+            index: c_int,
+        ) -> c_int;
+        pub fn freenect_close_device(dev: *mut freenect_device) -> c_int;
+        pub fn freenect_update_tilt_state(dev: *mut freenect_device) -> c_int;
+        pub fn freenect_get_tilt_state(dev: *mut freenect_device) -> *mut freenect_raw_tilt_state;
+        pub fn freenect_get_tilt_degs(state: *mut freenect_raw_tilt_state) -> c_double;
+        pub fn freenect_get_mks_accel(
+            state: *mut freenect_raw_tilt_state,
+            x: *mut c_double,
+            y: *mut c_double,
+            z: *mut c_double,
+        );
+        pub fn freenect_set_tilt_degs(dev: *mut freenect_device, angle: c_double) -> c_int;
+        pub fn freenect_set_led(dev: *mut freenect_device, option: c_int) -> c_int;
+        pub fn freenect_process_events_timeout(
+            ctx: *mut freenect_context,
+            timeout: *mut timeval,
+        ) -> c_int;
+        pub fn freenect_set_user(dev: *mut freenect_device, user: *mut c_void);
+        pub fn freenect_get_user(dev: *mut freenect_device) -> *mut c_void;
+        pub fn freenect_set_audio_in_callback(
+            dev: *mut freenect_device,
+            callback: freenect_audio_in_cb,
+        );
+        pub fn freenect_start_audio(dev: *mut freenect_device) -> c_int;
+        pub fn freenect_stop_audio(dev: *mut freenect_device) -> c_int;
+    }
+}
+
+#[derive(Debug)]
+pub struct MotorError(&'static str);
+
+impl fmt::Display for MotorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MotorError: {}", self.0)
+    }
+}
+
+impl std::error::Error for MotorError {}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TiltStatus {
+    #[default]
+    Stopped,
+    /// the motor hit its end stop (or something is blocking it)
+    Limit,
+    Moving,
+}
+
+/// What the LED on the front shows.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Led {
+    #[default]
+    Off,
+    Green,
+    Red,
+    Yellow,
+    BlinkGreen,
+    BlinkRedYellow,
+}
+
+#[cfg(feature = "freenect")]
+impl Led {
+    fn to_freenect(self) -> std::os::raw::c_int {
+        match self {
+            Led::Off => 0,
+            Led::Green => 1,
+            Led::Red => 2,
+            Led::Yellow => 3,
+            Led::BlinkGreen => 4,
+            Led::BlinkRedYellow => 6,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct MotorState {
+    /// angle reported by the motor, in degrees
+    pub tilt_degrees: f64,
+    pub status: TiltStatus,
+    /// accelerometer reading in m/s², in the sensor's frame
+    pub accel: Vec3,
+}
+
+#[cfg(feature = "freenect")]
+pub struct Motor {
+    ctx: *mut ffi::freenect_context,
+    device: *mut ffi::freenect_device,
+}
+
+// the context and device are only pointers to libfreenect's state, nothing
+// ties them to the thread that opened them
+#[cfg(feature = "freenect")]
+unsafe impl Send for Motor {}
+
+#[cfg(feature = "freenect")]
+impl Motor {
+    pub fn open(index: u32) -> Result<Motor, MotorError> {
+        unsafe {
+            let mut ctx = ptr::null_mut();
+            if ffi::freenect_init(&mut ctx, ptr::null_mut()) < 0 {
+                return Err(MotorError("Unable to create freenect context"));
+            }
+            ffi::freenect_select_subdevices(ctx, ffi::FREENECT_DEVICE_MOTOR);
+
+            if ffi::freenect_num_devices(ctx) <= index as i32 {
+                ffi::freenect_shutdown(ctx);
+                return Err(MotorError("Motor not found"));
+            }
+
+            let mut device = ptr::null_mut();
+            if ffi::freenect_open_device(ctx, &mut device, index as i32) < 0 {
+                ffi::freenect_shutdown(ctx);
+                return Err(MotorError("Unable to open motor"));
+            }
+            Ok(Motor { ctx, device })
+        }
+    }
+
+    /// Queries the motor and accelerometer. Each call is a USB round trip.
+    pub fn state(&self) -> Result<MotorState, MotorError> {
+        unsafe {
+            if ffi::freenect_update_tilt_state(self.device) < 0 {
+                return Err(MotorError("Unable to update tilt state"));
+            }
+            let state = ffi::freenect_get_tilt_state(self.device);
+
+            let (mut x, mut y, mut z) = (0.0, 0.0, 0.0);
+            ffi::freenect_get_mks_accel(state, &mut x, &mut y, &mut z);
+
+            let status = match (*state).tilt_status {
+                ffi::TILT_STATUS_LIMIT => TiltStatus::Limit,
+                ffi::TILT_STATUS_MOVING => TiltStatus::Moving,
+                _ => TiltStatus::Stopped,
+            };
+
+            Ok(MotorState {
+                tilt_degrees: ffi::freenect_get_tilt_degs(state),
+                status,
+                accel: Vec3::new(x as f32, y as f32, z as f32),
+            })
+        }
+    }
+
+    /// The motor only moves between -31° and +31°.
+    pub fn set_tilt_degrees(&self, degrees: f64) -> Result<(), MotorError> {
+        unsafe {
+            if ffi::freenect_set_tilt_degs(self.device, degrees.clamp(-31.0, 31.0)) < 0 {
+                return Err(MotorError("Unable to set tilt degree"));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn set_led(&self, led: Led) -> Result<(), MotorError> {
+        unsafe {
+            if ffi::freenect_set_led(self.device, led.to_freenect()) < 0 {
+                return Err(MotorError("Unable to set LED"));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "freenect")]
+impl Drop for Motor {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::freenect_close_device(self.device);
+            ffi::freenect_shutdown(self.ctx);
+        }
+    }
+}
@@ -0,0 +1,254 @@
+//! Several Kinects in one app, for installations that cover more floor than
+//! one sensor sees. The [`DepthCamera`] resource is the [`MainKinect`], the
+//! one tracking and every other feature follows. Each further sensor is an
+//! entity with a [`KinectId`] and a [`KinectCamera`]; it gets a
+//! [`CurrentDepth`] of its own with a texture of the depth, darker the
+//! further away, for the app to place or combine as it likes:
+//!
+//! ```ignore
+//! fn second(depths: Query<(&KinectId, &CurrentDepth)>) {
+//!     for (id, depth) in &depths {
+//!         // id.0 is 0 for the main Kinect, then 1, 2, ... for the others
+//!     }
+//! }
+//! ```
+//!
+//! With the `freenect` backend, [`MultiKinectSettings::extra`] opens more
+//! Kinects by index or serial at startup (`--extra-device`); apps with other
+//! sensors spawn a `(KinectId, KinectCamera)` themselves. A sensor that
+//! fails is opened again every few seconds, without the backoff and status
+//! overlay of the main one.
+
+use std::str::FromStr;
+
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+
+use crate::backend::DepthCamera;
+use crate::video::VideoSettings;
+use crate::{CurrentDepth, KinectConfig, KinectSet, MainKinect, NO_DEPTH};
+
+/// Seconds before a failed sensor is opened again.
+const RETRY_SECONDS: f64 = 5.0;
+
+/// Which Kinect an entity's [`CurrentDepth`] is from, 0 for the main one.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct KinectId(pub usize);
+
+/// A sensor besides the main one.
+#[derive(Component)]
+pub struct KinectCamera {
+    pub camera: DepthCamera,
+    retry_at: Option<f64>,
+}
+
+impl KinectCamera {
+    pub fn new(camera: DepthCamera) -> KinectCamera {
+        KinectCamera {
+            camera,
+            retry_at: None,
+        }
+    }
+}
+
+/// A Kinect by its place in the USB enumeration or its serial number.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DeviceSelector {
+    Index(usize),
+    Serial(String),
+}
+
+impl FromStr for DeviceSelector {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse() {
+            Ok(index) => Ok(DeviceSelector::Index(index)),
+            Err(_) if !s.is_empty() => Ok(DeviceSelector::Serial(s.to_string())),
+            Err(_) => Err("expected a device index or serial number".to_string()),
+        }
+    }
+}
+
+#[derive(Resource, Clone, Debug, Default)]
+pub struct MultiKinectSettings {
+    /// Kinects to open besides the main one, given [`KinectId`]s 1, 2, ...
+    /// in this order
+    pub extra: Vec<DeviceSelector>,
+}
+
+pub struct MultiKinectPlugin;
+
+impl Plugin for MultiKinectPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MultiKinectSettings>()
+            .add_system(attach_depth.before(KinectSet::Acquire))
+            .add_system(read_extra_depth.label(KinectSet::Acquire))
+            .add_system(shade_extra_depth.label(KinectSet::Output))
+            .add_system(close_on_exit);
+        #[cfg(feature = "freenect")]
+        app.add_startup_system(open_extra_kinects);
+    }
+}
+
+#[cfg(feature = "freenect")]
+fn open_extra_kinects(
+    mut commands: Commands,
+    settings: Res<MultiKinectSettings>,
+    (config, video, usb): (
+        Res<KinectConfig>,
+        Res<VideoSettings>,
+        Res<crate::embedded::UsbSettings>,
+    ),
+    devices: Res<crate::devices::KinectDevices>,
+) {
+    for (i, selector) in settings.extra.iter().enumerate() {
+        let index = match selector {
+            DeviceSelector::Index(index) => *index,
+            DeviceSelector::Serial(serial) => match devices.by_serial(serial) {
+                Some(device) => device.index,
+                None => {
+                    error!("No Kinect with serial {serial}, Kinect {} left out", i + 1);
+                    continue;
+                }
+            },
+        };
+        let backend = crate::freenect::FreenectBackend::spawn(
+            *video,
+            index,
+            config.depth_format,
+            Default::default(),
+            *usb,
+        );
+        commands.spawn((
+            KinectId(i + 1),
+            KinectCamera::new(DepthCamera::new(backend)),
+        ));
+    }
+}
+
+/// Gives new sensors their depth and opens them.
+fn attach_depth(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    (config, video): (Res<KinectConfig>, Res<VideoSettings>),
+    mut cameras: Query<(Entity, &mut KinectCamera), Without<CurrentDepth>>,
+) {
+    for (entity, mut camera) in &mut cameras {
+        let (width, height) = config.depth_resolution.size();
+        let handle = images.add(Image::new_fill(
+            Extent3d {
+                width: width as u32,
+                height: height as u32,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            &[0, 0, 0, 255],
+            TextureFormat::Rgba8Unorm,
+        ));
+        commands
+            .entity(entity)
+            .insert(CurrentDepth::new(width, height, handle));
+        camera.camera.configure(*video);
+        camera.camera.open();
+    }
+}
+
+fn read_extra_depth(
+    config: Res<KinectConfig>,
+    time: Res<Time>,
+    mut cameras: Query<(&KinectId, &mut KinectCamera, &mut CurrentDepth), Without<MainKinect>>,
+) {
+    let now = time.elapsed_seconds_f64();
+    for (id, mut camera, mut depth) in &mut cameras {
+        if let Some(retry_at) = camera.retry_at {
+            if now >= retry_at {
+                camera.retry_at = None;
+                camera.camera.open();
+            }
+            continue;
+        }
+        if let Some(exit) = camera.camera.take_exit() {
+            match exit {
+                Ok(()) => warn!("Kinect {} stopped, opening it again", id.0),
+                Err(err) => warn!("Kinect {}: {err}, opening it again", id.0),
+            }
+            camera.retry_at = Some(now + RETRY_SECONDS);
+            continue;
+        }
+
+        // only depth is kept, take video off so it doesn't queue up
+        while camera.camera.next_video_frame().is_some() {}
+        if let Some(frame) = camera.camera.next_frame() {
+            let (width, height) = config.depth_resolution.size();
+            depth.receive(frame, width, height, now);
+        }
+    }
+}
+
+/// RGBA of a depth frame, white up close, black far away and in holes.
+fn shade(depth: &[u16], out: &mut Vec<u8>) {
+    out.clear();
+    for raw in depth {
+        let value = match *raw {
+            NO_DEPTH => 0,
+            raw => 255 - (raw as u32 * 255 / NO_DEPTH as u32) as u8,
+        };
+        out.extend_from_slice(&[value, value, value, 255]);
+    }
+}
+
+fn shade_extra_depth(
+    depths: Query<&CurrentDepth, (Changed<CurrentDepth>, With<KinectCamera>)>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    for depth in &depths {
+        if depth.depth_array.is_empty() {
+            continue;
+        }
+        if let Some(image) = images.get_mut(&depth.handle) {
+            let size = Extent3d {
+                width: depth.width as u32,
+                height: depth.height as u32,
+                depth_or_array_layers: 1,
+            };
+            if image.texture_descriptor.size != size {
+                image.resize(size);
+            }
+            shade(&depth.depth_array, &mut image.data);
+        }
+    }
+}
+
+fn close_on_exit(mut exits: EventReader<AppExit>, mut cameras: Query<&mut KinectCamera>) {
+    if exits.iter().last().is_none() {
+        return;
+    }
+    for mut camera in &mut cameras {
+        if let Err(err) = camera.camera.close() {
+            error!("{err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn devices_by_index_or_serial() {
+        assert_eq!("2".parse(), Ok(DeviceSelector::Index(2)));
+        assert_eq!(
+            "A00364A11700045A".parse(),
+            Ok(DeviceSelector::Serial("A00364A11700045A".into()))
+        );
+        assert!("".parse::<DeviceSelector>().is_err());
+
+        let mut rgba = Vec::new();
+        shade(&[0, 1022, NO_DEPTH], &mut rgba);
+        assert_eq!(rgba[..4], [255, 255, 255, 255]);
+        assert!(rgba[4] <= 1);
+        assert_eq!(rgba[8..], [0, 0, 0, 255]);
+    }
+}
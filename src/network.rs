@@ -0,0 +1,387 @@
+//! Frames from a sensor on another machine, as `kinect-capture` forwards
+//! them (see `src/bin/kinect-capture.rs`). [`NetworkBackend`] connects to
+//! the sender and hands its frames on like a sensor plugged in here would,
+//! with the `network` feature. Natively it connects to `--forward` over TCP;
+//! in the browser, built for wasm32 where there are no sockets, to
+//! `--forward-ws` as a WebSocket, which is how a Kinect on a capture box
+//! drives a page.
+//!
+//! Frames come as the sender's device delivers them, so the app's `--depth`
+//! and `--video` have to match the sender's. There is no tilt or LED on the
+//! other end.
+
+use std::sync::{Arc, Mutex};
+
+use crate::backend::{DepthCameraBackend, StreamHealth};
+use crate::consumer::FrameConsumers;
+use crate::coords::{DEPTH_HEIGHT, DEPTH_WIDTH};
+use crate::device::{DepthFrame, KinectError, VideoFrame};
+use crate::motor::{Led, MotorState};
+use crate::video::VideoSettings;
+use crate::DepthFormat;
+
+/// Largest payload taken for a frame, anything longer means the other end
+/// isn't sending frames.
+const MAX_PAYLOAD: usize = 16 << 20;
+
+pub struct NetworkBackend {
+    address: String,
+    depth_format: DepthFormat,
+    video: VideoSettings,
+    consumers: FrameConsumers,
+    received: Arc<Mutex<Received>>,
+    link: Option<transport::Link>,
+    starts: u64,
+}
+
+impl NetworkBackend {
+    /// `address` is the sender's `host:port` natively, its
+    /// `ws://host:port` in the browser; `depth_format` is the sender's
+    /// `--depth`.
+    pub fn new(address: impl Into<String>, depth_format: DepthFormat) -> NetworkBackend {
+        NetworkBackend {
+            address: address.into(),
+            depth_format,
+            video: VideoSettings::default(),
+            consumers: FrameConsumers::default(),
+            received: Arc::default(),
+            link: None,
+            starts: 0,
+        }
+    }
+}
+
+/// The latest frames, filled in by the transport as they arrive.
+#[derive(Default)]
+struct Received {
+    depth: Option<DepthFrame>,
+    video: Option<Vec<u8>>,
+    depth_frames: u64,
+    video_frames: u64,
+    /// frames of the wrong size, such as video in another format
+    missed_frames: u64,
+    exit: Option<Result<(), KinectError>>,
+}
+
+/// Where the transport puts what it receives.
+#[derive(Clone)]
+struct Inbox {
+    received: Arc<Mutex<Received>>,
+    consumers: FrameConsumers,
+    depth_format: DepthFormat,
+    video_len: usize,
+}
+
+impl Inbox {
+    fn message(&self, kind: u8, payload: &[u8]) {
+        match kind {
+            b'D' if payload.len() == DEPTH_WIDTH * DEPTH_HEIGHT * 2 => {
+                let raw: Vec<u16> = payload
+                    .chunks_exact(2)
+                    .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+                    .collect();
+                let depth: Vec<u16> = raw
+                    .iter()
+                    .map(|raw| self.depth_format.to_bit10(*raw))
+                    .collect();
+                self.consumers.depth(&depth);
+                let mut received = self.received.lock().unwrap();
+                received.depth_frames += 1;
+                let frame = DepthFrame::new(depth, received.depth_frames);
+                received.depth = Some(frame.with_raw(self.depth_format, raw));
+            }
+            b'V' if payload.len() == self.video_len => {
+                self.consumers.video(payload);
+                let mut received = self.received.lock().unwrap();
+                received.video = Some(payload.to_vec());
+                received.video_frames += 1;
+            }
+            _ => self.received.lock().unwrap().missed_frames += 1,
+        }
+    }
+
+    fn closed(&self, exit: Result<(), KinectError>) {
+        self.received.lock().unwrap().exit = Some(exit);
+    }
+}
+
+/// Splits the forwarded bytes back into frames: one byte `D` or `V`, the
+/// payload length as a little endian `u32`, then the payload.
+#[derive(Default)]
+struct Decoder {
+    buffer: Vec<u8>,
+}
+
+impl Decoder {
+    /// Calls `message` with every frame `bytes` completes.
+    fn push(
+        &mut self,
+        bytes: &[u8],
+        mut message: impl FnMut(u8, &[u8]),
+    ) -> Result<(), KinectError> {
+        self.buffer.extend_from_slice(bytes);
+        let mut start = 0;
+        while self.buffer.len() - start >= 5 {
+            let header = &self.buffer[start..start + 5];
+            let len = u32::from_le_bytes([header[1], header[2], header[3], header[4]]) as usize;
+            if len > MAX_PAYLOAD {
+                return Err(KinectError::Open(
+                    "the sender isn't forwarding frames".into(),
+                ));
+            }
+            if self.buffer.len() - start - 5 < len {
+                break;
+            }
+            message(header[0], &self.buffer[start + 5..start + 5 + len]);
+            start += 5 + len;
+        }
+        self.buffer.drain(..start);
+        Ok(())
+    }
+}
+
+impl DepthCameraBackend for NetworkBackend {
+    fn open(&mut self) {
+        if self.link.is_some() {
+            return;
+        }
+        let inbox = Inbox {
+            received: self.received.clone(),
+            consumers: self.consumers.clone(),
+            depth_format: self.depth_format,
+            video_len: self.video.format.frame_len(self.video.resolution),
+        };
+        match transport::Link::connect(&self.address, inbox) {
+            Ok(link) => {
+                self.link = Some(link);
+                self.starts += 1;
+            }
+            Err(err) => self.received.lock().unwrap().exit = Some(Err(err)),
+        }
+    }
+
+    fn close(&mut self) -> Result<(), KinectError> {
+        if let Some(link) = self.link.take() {
+            link.close();
+        }
+        // the connection going away is no news once closed on purpose
+        self.received.lock().unwrap().exit = None;
+        Ok(())
+    }
+
+    fn is_running(&self) -> bool {
+        self.link.is_some()
+    }
+
+    fn take_exit(&mut self) -> Option<Result<(), KinectError>> {
+        let exit = self.received.lock().unwrap().exit.take()?;
+        if let Some(link) = self.link.take() {
+            link.close();
+        }
+        Some(exit)
+    }
+
+    fn configure(&mut self, video: VideoSettings) {
+        self.video = video;
+    }
+
+    fn set_consumers(&mut self, consumers: FrameConsumers) {
+        self.consumers = consumers;
+    }
+
+    fn next_frame(&mut self) -> Option<DepthFrame> {
+        self.received.lock().unwrap().depth.take()
+    }
+
+    fn next_video_frame(&mut self) -> Option<VideoFrame> {
+        let data = self.received.lock().unwrap().video.take()?;
+        Some(VideoFrame { data })
+    }
+
+    fn depth_frames_received(&self) -> u64 {
+        self.received.lock().unwrap().depth_frames
+    }
+
+    fn video_frames_received(&self) -> u64 {
+        self.received.lock().unwrap().video_frames
+    }
+
+    fn health(&self) -> StreamHealth {
+        let received = self.received.lock().unwrap();
+        StreamHealth {
+            depth_frames: received.depth_frames,
+            missed_frames: received.missed_frames,
+            restarts: self.starts.saturating_sub(1),
+            ..StreamHealth::default()
+        }
+    }
+
+    fn tilt(&mut self, _degrees: f64) -> Result<(), KinectError> {
+        Err(KinectError::Unsupported(
+            "tilting a sensor over the network",
+        ))
+    }
+
+    fn tilt_state(&mut self) -> Result<MotorState, KinectError> {
+        Err(KinectError::Unsupported("tilt state over the network"))
+    }
+
+    fn led(&mut self, _led: Led) -> Result<(), KinectError> {
+        Err(KinectError::Unsupported(
+            "the LED of a sensor over the network",
+        ))
+    }
+}
+
+/// TCP to `kinect-capture --forward`, read on a thread of its own.
+#[cfg(not(target_arch = "wasm32"))]
+mod transport {
+    use std::io::Read;
+    use std::net::{Shutdown, TcpStream};
+    use std::thread::{self, JoinHandle};
+
+    use super::{Decoder, Inbox};
+    use crate::device::KinectError;
+
+    pub struct Link {
+        stream: TcpStream,
+        thread: JoinHandle<()>,
+    }
+
+    impl Link {
+        pub fn connect(address: &str, inbox: Inbox) -> Result<Link, KinectError> {
+            let failed = |err| KinectError::Open(format!("can't connect to {address}: {err}"));
+            let stream = TcpStream::connect(address).map_err(failed)?;
+            let reader = stream.try_clone().map_err(failed)?;
+            let thread = thread::Builder::new()
+                .name("network".into())
+                .spawn(move || read(reader, inbox))
+                .map_err(failed)?;
+            Ok(Link { stream, thread })
+        }
+
+        pub fn close(self) {
+            // ends the read on the thread
+            let _ = self.stream.shutdown(Shutdown::Both);
+            let _ = self.thread.join();
+        }
+    }
+
+    fn read(mut stream: TcpStream, inbox: Inbox) {
+        let mut decoder = Decoder::default();
+        let mut chunk = vec![0; 64 * 1024];
+        let exit = loop {
+            let len = match stream.read(&mut chunk) {
+                Ok(0) => break Err(KinectError::Open("the sender closed the connection".into())),
+                Ok(len) => len,
+                Err(err) => break Err(KinectError::Open(format!("connection lost: {err}"))),
+            };
+            if let Err(err) =
+                decoder.push(&chunk[..len], |kind, payload| inbox.message(kind, payload))
+            {
+                break Err(err);
+            }
+        };
+        inbox.closed(exit);
+    }
+}
+
+/// A WebSocket to `kinect-capture --forward-ws`, read in the browser's
+/// event loop.
+#[cfg(target_arch = "wasm32")]
+mod transport {
+    use std::cell::RefCell;
+
+    use wasm_bindgen::closure::Closure;
+    use wasm_bindgen::JsCast;
+    use web_sys::{BinaryType, CloseEvent, MessageEvent, WebSocket};
+
+    use super::{Decoder, Inbox};
+    use crate::device::KinectError;
+
+    pub struct Link {
+        socket: WebSocket,
+        _on_message: Closure<dyn FnMut(MessageEvent)>,
+        _on_close: Closure<dyn FnMut(CloseEvent)>,
+    }
+
+    // wasm32 without threads runs everything on the page's one thread, so
+    // the socket and its callbacks never leave it
+    unsafe impl Send for Link {}
+    unsafe impl Sync for Link {}
+
+    impl Link {
+        pub fn connect(address: &str, inbox: Inbox) -> Result<Link, KinectError> {
+            let socket = WebSocket::new(address)
+                .map_err(|err| KinectError::Open(format!("can't connect to {address}: {err:?}")))?;
+            socket.set_binary_type(BinaryType::Arraybuffer);
+
+            let decoder = RefCell::new(Decoder::default());
+            let message_inbox = inbox.clone();
+            let on_message = Closure::<dyn FnMut(MessageEvent)>::new(move |event: MessageEvent| {
+                let bytes = js_sys::Uint8Array::new(&event.data()).to_vec();
+                let pushed = decoder
+                    .borrow_mut()
+                    .push(&bytes, |kind, payload| message_inbox.message(kind, payload));
+                if let Err(err) = pushed {
+                    message_inbox.closed(Err(err));
+                }
+            });
+            socket.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+
+            let on_close = Closure::<dyn FnMut(CloseEvent)>::new(move |event: CloseEvent| {
+                inbox.closed(Err(KinectError::Open(format!(
+                    "the sender closed the connection ({})",
+                    event.code()
+                ))));
+            });
+            socket.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+
+            Ok(Link {
+                socket,
+                _on_message: on_message,
+                _on_close: on_close,
+            })
+        }
+
+        pub fn close(self) {
+            self.socket.set_onmessage(None);
+            self.socket.set_onclose(None);
+            let _ = self.socket.close();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_come_apart_again() {
+        let mut stream = Vec::new();
+        for (kind, payload) in [(b'D', vec![1, 2, 3]), (b'V', vec![]), (b'V', vec![4; 300])] {
+            stream.push(kind);
+            stream.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+            stream.extend_from_slice(&payload);
+        }
+
+        // however the bytes are cut up on the way
+        let mut decoder = Decoder::default();
+        let mut frames = Vec::new();
+        for chunk in stream.chunks(7) {
+            decoder
+                .push(chunk, |kind, payload| frames.push((kind, payload.to_vec())))
+                .unwrap();
+        }
+        assert_eq!(
+            frames,
+            vec![(b'D', vec![1, 2, 3]), (b'V', vec![]), (b'V', vec![4; 300])]
+        );
+        assert!(decoder.buffer.is_empty());
+
+        // an HTTP server, say
+        assert!(Decoder::default()
+            .push(b"HTTP/1.1 200 OK", |_, _| {})
+            .is_err());
+    }
+}
@@ -0,0 +1,639 @@
+//! Depth frames as numpy arrays, for working on Kinect data in Python
+//! notebooks: `np.load("depth.npy")` on frames saved here, and frames made or
+//! cleaned up there played back as a sensor.
+//!
+//! Depth is `uint16` millimeters, 0 where there is no reading, rows of the
+//! depth image top to bottom. One frame is a `(height, width)` array; a
+//! sequence is `(frames, height, width)`. A sequence saved as `.npz` also has
+//! `timestamps`, seconds since its first frame as `float64`:
+//!
+//! ```text
+//! data = np.load("kinect.npz")
+//! data["depth"].shape       # (90, 480, 640)
+//! data["timestamps"][-1]    # 2.97
+//! ```
+//!
+//! [`ExportNumpy`] saves the next frames of the main Kinect; the file is
+//! written once they are all in. [`NumpyBackend`] plays a `.npy` or `.npz`
+//! at 30 fps, looping. It takes `.npz` files from `np.savez`, not the
+//! compressed ones from `np.savez_compressed`, and 640x480 frames.
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Instant;
+
+use bevy::prelude::*;
+
+use crate::backend::DepthCameraBackend;
+use crate::consumer::FrameConsumers;
+use crate::coords::{DEPTH_HEIGHT, DEPTH_WIDTH};
+use crate::device::{DepthFrame, KinectError, VideoFrame};
+use crate::motor::{Led, MotorState};
+use crate::video::VideoSettings;
+use crate::{CurrentDepth, DepthFormat, MainKinect};
+
+const MAGIC: &[u8] = b"\x93NUMPY";
+const FRAME_SECONDS: f64 = 1.0 / 30.0;
+
+/// The element types frames and timestamps are saved as.
+#[derive(Clone, Debug, PartialEq)]
+pub enum NpyData {
+    U16(Vec<u16>),
+    F64(Vec<f64>),
+}
+
+impl NpyData {
+    fn descr(&self) -> &'static str {
+        match self {
+            NpyData::U16(_) => "<u2",
+            NpyData::F64(_) => "<f8",
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            NpyData::U16(data) => data.len(),
+            NpyData::F64(data) => data.len(),
+        }
+    }
+}
+
+/// A C-ordered array.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NpyArray {
+    pub shape: Vec<usize>,
+    pub data: NpyData,
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// The `.npy` format, version 1.0.
+pub fn write_npy(out: &mut impl Write, array: &NpyArray) -> io::Result<()> {
+    if array.shape.iter().product::<usize>() != array.data.len() {
+        return Err(invalid("the shape doesn't fit the data"));
+    }
+    let shape = match &array.shape[..] {
+        [n] => format!("({n},)"),
+        shape => {
+            let dims: Vec<String> = shape.iter().map(|n| n.to_string()).collect();
+            format!("({})", dims.join(", "))
+        }
+    };
+    let mut header = format!(
+        "{{'descr': '{}', 'fortran_order': False, 'shape': {shape}, }}",
+        array.data.descr()
+    );
+    // the data starts 64 byte aligned, the header ends in a newline
+    let unpadded = MAGIC.len() + 4 + header.len() + 1;
+    header.push_str(&" ".repeat((64 - unpadded % 64) % 64));
+    header.push('\n');
+
+    out.write_all(MAGIC)?;
+    out.write_all(&[1, 0])?;
+    out.write_all(&(header.len() as u16).to_le_bytes())?;
+    out.write_all(header.as_bytes())?;
+    match &array.data {
+        NpyData::U16(data) => {
+            for value in data {
+                out.write_all(&value.to_le_bytes())?;
+            }
+        }
+        NpyData::F64(data) => {
+            for value in data {
+                out.write_all(&value.to_le_bytes())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The value of `key` in a header like the one [`write_npy`] writes.
+fn header_field<'a>(header: &'a str, key: &str) -> Option<&'a str> {
+    let start = header.find(&format!("'{key}':"))? + key.len() + 3;
+    let value = header[start..].trim_start();
+    let end = match value.chars().next()? {
+        '(' => value.find(')')? + 1,
+        '\'' => value[1..].find('\'')? + 2,
+        _ => value.find(',').unwrap_or(value.len()),
+    };
+    Some(&value[..end])
+}
+
+pub fn read_npy(input: &mut impl Read) -> io::Result<NpyArray> {
+    let mut preamble = [0; 8];
+    input.read_exact(&mut preamble)?;
+    if &preamble[..6] != MAGIC {
+        return Err(invalid("not a .npy file"));
+    }
+    let header_len = match preamble[6] {
+        1 => {
+            let mut len = [0; 2];
+            input.read_exact(&mut len)?;
+            u16::from_le_bytes(len) as usize
+        }
+        2 | 3 => {
+            let mut len = [0; 4];
+            input.read_exact(&mut len)?;
+            u32::from_le_bytes(len) as usize
+        }
+        version => return Err(invalid(format!(".npy version {version} is not supported"))),
+    };
+    let mut header = vec![0; header_len];
+    input.read_exact(&mut header)?;
+    let header = String::from_utf8_lossy(&header);
+
+    if header_field(&header, "fortran_order") != Some("False") {
+        return Err(invalid("only C ordered arrays are supported"));
+    }
+    let shape: Vec<usize> = header_field(&header, "shape")
+        .ok_or_else(|| invalid("no shape in the .npy header"))?
+        .trim_matches(|c| c == '(' || c == ')')
+        .split(',')
+        .map(str::trim)
+        .filter(|n| !n.is_empty())
+        .map(|n| n.parse().map_err(|_| invalid(format!("bad shape {n}"))))
+        .collect::<io::Result<_>>()?;
+    let len = shape.iter().product::<usize>();
+
+    let descr = header_field(&header, "descr").unwrap_or_default();
+    let data = match descr.trim_matches('\'') {
+        "<u2" => {
+            let mut bytes = vec![0; len * 2];
+            input.read_exact(&mut bytes)?;
+            NpyData::U16(
+                bytes
+                    .chunks_exact(2)
+                    .map(|b| u16::from_le_bytes([b[0], b[1]]))
+                    .collect(),
+            )
+        }
+        "<f8" => {
+            let mut bytes = vec![0; len * 8];
+            input.read_exact(&mut bytes)?;
+            NpyData::F64(
+                bytes
+                    .chunks_exact(8)
+                    .map(|b| f64::from_le_bytes(b.try_into().unwrap()))
+                    .collect(),
+            )
+        }
+        descr => {
+            return Err(invalid(format!(
+                "arrays of {descr} are not supported, save depth as uint16"
+            )))
+        }
+    };
+    Ok(NpyArray { shape, data })
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut table = [0u32; 256];
+    for (i, entry) in table.iter_mut().enumerate() {
+        let mut crc = i as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                0xedb8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+        }
+        *entry = crc;
+    }
+    !bytes.iter().fold(!0u32, |crc, &byte| {
+        table[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// An uncompressed zip of `<name>.npy` files, like `np.savez` writes.
+pub fn write_npz(out: &mut impl Write, arrays: &[(&str, &NpyArray)]) -> io::Result<()> {
+    let mut central = Vec::new();
+    let mut offset = 0u32;
+    let too_large = || invalid("an .npz over 4 GB is not supported");
+    for (name, array) in arrays {
+        let mut data = Vec::new();
+        write_npy(&mut data, array)?;
+        let name = format!("{name}.npy");
+        let crc = crc32(&data);
+        let size = u32::try_from(data.len()).map_err(|_| too_large())?;
+
+        // version 2.0, no flags, stored, 1980-01-01 00:00
+        let mut fields = Vec::new();
+        for value in [20u16, 0, 0, 0, 0x21] {
+            fields.extend_from_slice(&value.to_le_bytes());
+        }
+        for value in [crc, size, size] {
+            fields.extend_from_slice(&value.to_le_bytes());
+        }
+        fields.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        fields.extend_from_slice(&0u16.to_le_bytes());
+
+        out.write_all(&0x0403_4b50u32.to_le_bytes())?;
+        out.write_all(&fields)?;
+        out.write_all(name.as_bytes())?;
+        out.write_all(&data)?;
+
+        central.extend_from_slice(&0x0201_4b50u32.to_le_bytes());
+        central.extend_from_slice(&20u16.to_le_bytes());
+        central.extend_from_slice(&fields);
+        // comment, disk, attributes
+        central.extend_from_slice(&[0; 10]);
+        central.extend_from_slice(&offset.to_le_bytes());
+        central.extend_from_slice(name.as_bytes());
+
+        let entry = 30 + name.len() + data.len();
+        offset = offset
+            .checked_add(entry as u32)
+            .filter(|_| entry <= u32::MAX as usize)
+            .ok_or_else(too_large)?;
+    }
+    out.write_all(&central)?;
+    out.write_all(&0x0605_4b50u32.to_le_bytes())?;
+    let count = arrays.len() as u16;
+    for value in [0, 0, count, count] {
+        out.write_all(&value.to_le_bytes())?;
+    }
+    out.write_all(&(central.len() as u32).to_le_bytes())?;
+    out.write_all(&offset.to_le_bytes())?;
+    out.write_all(&0u16.to_le_bytes())
+}
+
+/// The arrays of an uncompressed `.npz`, by name without `.npy`.
+pub fn read_npz(input: &mut impl Read) -> io::Result<Vec<(String, NpyArray)>> {
+    let mut arrays = Vec::new();
+    loop {
+        let mut header = [0; 30];
+        input.read_exact(&mut header)?;
+        let u16_at = |at: usize| u16::from_le_bytes([header[at], header[at + 1]]);
+        let u32_at = |at: usize| u32::from_le_bytes(header[at..at + 4].try_into().unwrap());
+        // the central directory follows the last file
+        if u32_at(0) != 0x0403_4b50 {
+            return Ok(arrays);
+        }
+        if u16_at(8) != 0 {
+            return Err(invalid(
+                "compressed .npz files are not supported, save with np.savez",
+            ));
+        }
+        if u16_at(6) & 0x08 != 0 {
+            return Err(invalid(".npz entries without sizes are not supported"));
+        }
+        let mut name = vec![0; u16_at(26) as usize];
+        input.read_exact(&mut name)?;
+        let mut extra = vec![0; u16_at(28) as usize];
+        input.read_exact(&mut extra)?;
+
+        let mut size = u32_at(18) as u64;
+        // numpy writes zip64 headers, the sizes are in an extra field then
+        if size == u32::MAX as u64 {
+            let mut rest = &extra[..];
+            while rest.len() >= 4 {
+                let id = u16::from_le_bytes([rest[0], rest[1]]);
+                let len = u16::from_le_bytes([rest[2], rest[3]]) as usize;
+                if id == 0x0001 && len >= 16 && rest.len() >= 4 + len {
+                    // the uncompressed size then the compressed one
+                    size = u64::from_le_bytes(rest[12..20].try_into().unwrap());
+                    break;
+                }
+                rest = &rest[(4 + len).min(rest.len())..];
+            }
+        }
+
+        let mut data = input.by_ref().take(size);
+        let name = String::from_utf8_lossy(&name);
+        let name = name.strip_suffix(".npy").unwrap_or(&name).to_string();
+        arrays.push((name, read_npy(&mut data)?));
+        // whatever the array didn't take up
+        io::copy(&mut data, &mut io::sink())?;
+    }
+}
+
+/// The frames of a `.npy`, or of the `depth` array of an `.npz` (its first
+/// one without a `depth`), as they were saved.
+pub fn load_depth(path: &Path) -> io::Result<Vec<Vec<u16>>> {
+    let mut input = BufReader::new(File::open(path)?);
+    let array = if path.extension().is_some_and(|ext| ext == "npz") {
+        let mut arrays = read_npz(&mut input)?;
+        if arrays.is_empty() {
+            return Err(invalid("the .npz has no arrays"));
+        }
+        let at = arrays
+            .iter()
+            .position(|(name, _)| name == "depth")
+            .unwrap_or(0);
+        arrays.swap_remove(at).1
+    } else {
+        read_npy(&mut input)?
+    };
+    let data = match array.data {
+        NpyData::U16(data) => data,
+        NpyData::F64(_) => return Err(invalid("depth has to be uint16")),
+    };
+    match array.shape[..] {
+        [.., height, width] if (width, height) == (DEPTH_WIDTH, DEPTH_HEIGHT) => Ok(data
+            .chunks_exact(width * height)
+            .map(|frame| frame.to_vec())
+            .collect()),
+        _ => Err(invalid(format!(
+            "expected frames of {DEPTH_HEIGHT}x{DEPTH_WIDTH}, the array is {:?}",
+            array.shape
+        ))),
+    }
+}
+
+/// Saves the next `frames` depth frames of the main Kinect to `path`, a
+/// `.npz` with timestamps or a `.npy` of just the depth.
+#[derive(Clone, Debug)]
+pub struct ExportNumpy {
+    pub path: PathBuf,
+    pub frames: usize,
+}
+
+/// An export to send once the app is up.
+#[derive(Resource, Clone, Debug)]
+pub struct NumpyExportSettings {
+    pub path: Option<PathBuf>,
+    pub frames: usize,
+}
+
+impl Default for NumpyExportSettings {
+    fn default() -> Self {
+        NumpyExportSettings {
+            path: None,
+            frames: 1,
+        }
+    }
+}
+
+/// An export collecting frames.
+#[derive(Resource, Default)]
+struct Export {
+    path: PathBuf,
+    frames: usize,
+    shape: (usize, usize),
+    depth: Vec<u16>,
+    timestamps: Vec<f64>,
+}
+
+impl Export {
+    fn arrays(&self) -> (NpyArray, NpyArray) {
+        let (height, width) = self.shape;
+        let shape = match self.timestamps.len() {
+            1 => vec![height, width],
+            n => vec![n, height, width],
+        };
+        let first = self.timestamps.first().copied().unwrap_or_default();
+        (
+            NpyArray {
+                shape,
+                data: NpyData::U16(self.depth.clone()),
+            },
+            NpyArray {
+                shape: vec![self.timestamps.len()],
+                data: NpyData::F64(self.timestamps.iter().map(|t| t - first).collect()),
+            },
+        )
+    }
+
+    fn write(&self) -> io::Result<()> {
+        let (depth, timestamps) = self.arrays();
+        let mut out = BufWriter::new(File::create(&self.path)?);
+        if self.path.extension().is_some_and(|ext| ext == "npz") {
+            write_npz(&mut out, &[("depth", &depth), ("timestamps", &timestamps)])?;
+        } else {
+            write_npy(&mut out, &depth)?;
+        }
+        out.flush()
+    }
+}
+
+pub struct NumpyPlugin;
+
+impl Plugin for NumpyPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<NumpyExportSettings>()
+            .add_event::<ExportNumpy>()
+            .add_startup_system(send_configured_export)
+            .add_system(start_export)
+            .add_system(collect_frames.after(start_export));
+    }
+}
+
+fn send_configured_export(
+    settings: Res<NumpyExportSettings>,
+    mut exports: EventWriter<ExportNumpy>,
+) {
+    if let Some(path) = &settings.path {
+        exports.send(ExportNumpy {
+            path: path.clone(),
+            frames: settings.frames,
+        });
+    }
+}
+
+fn start_export(mut commands: Commands, mut exports: EventReader<ExportNumpy>) {
+    if let Some(export) = exports.iter().last() {
+        info!(
+            "Saving {} depth frames to {}",
+            export.frames,
+            export.path.display()
+        );
+        commands.insert_resource(Export {
+            path: export.path.clone(),
+            frames: export.frames.max(1),
+            ..default()
+        });
+    }
+}
+
+fn collect_frames(
+    mut commands: Commands,
+    export: Option<ResMut<Export>>,
+    depth: Query<&CurrentDepth, (Changed<CurrentDepth>, With<MainKinect>)>,
+) {
+    let (mut export, depth) = match (export, depth.get_single()) {
+        (Some(export), Ok(depth)) if !depth.depth_array.is_empty() => (export, depth),
+        _ => return,
+    };
+    if export.timestamps.last() == Some(&depth.received_at) {
+        return;
+    }
+    let shape = (depth.height, depth.width);
+    if export.timestamps.is_empty() {
+        export.shape = shape;
+    } else if export.shape != shape {
+        // the depth resolution changed, the frames don't stack
+        warn!("Depth resolution changed, saving the frames so far");
+        export.frames = export.timestamps.len();
+    }
+    if export.timestamps.len() < export.frames {
+        let mm = depth
+            .depth_array
+            .iter()
+            .map(|&raw| DepthFormat::Millimeters.from_bit10(raw));
+        export.depth.extend(mm);
+        export.timestamps.push(depth.received_at);
+    }
+    if export.timestamps.len() < export.frames {
+        return;
+    }
+    commands.remove_resource::<Export>();
+    let export = std::mem::take(&mut *export);
+    // off the main thread, a long sequence is a few hundred megabytes
+    thread::spawn(move || match export.write() {
+        Ok(()) => info!("Saved {}", export.path.display()),
+        Err(err) => error!("Unable to save {}: {err}", export.path.display()),
+    });
+}
+
+/// Frames from a `.npy` or `.npz`, played as a sensor.
+pub struct NumpyBackend {
+    frames: Vec<Vec<u16>>,
+    looping: bool,
+    consumers: FrameConsumers,
+    started: Option<Instant>,
+    exit: Option<Result<(), KinectError>>,
+    next: usize,
+    depth_frames: u64,
+}
+
+impl NumpyBackend {
+    /// `units` are what the array holds, usually
+    /// [`DepthFormat::Millimeters`].
+    pub fn load(path: &Path, units: DepthFormat) -> io::Result<NumpyBackend> {
+        let frames = load_depth(path)?
+            .into_iter()
+            .map(|frame| frame.into_iter().map(|d| units.to_bit10(d)).collect())
+            .collect();
+        Ok(NumpyBackend {
+            frames,
+            looping: true,
+            consumers: FrameConsumers::default(),
+            started: None,
+            exit: None,
+            next: 0,
+            depth_frames: 0,
+        })
+    }
+
+    /// Whether the frames start over after the last one, instead of ending
+    /// like an unplugged device.
+    pub fn looping(mut self, looping: bool) -> NumpyBackend {
+        self.looping = looping;
+        self
+    }
+}
+
+impl DepthCameraBackend for NumpyBackend {
+    fn open(&mut self) {
+        if self.started.is_some() {
+            return;
+        }
+        if self.frames.is_empty() {
+            self.exit = Some(Err(KinectError::Open("no frames in the array".into())));
+            return;
+        }
+        self.started = Some(Instant::now());
+        self.next = 0;
+    }
+
+    fn close(&mut self) -> Result<(), KinectError> {
+        self.started = None;
+        Ok(())
+    }
+
+    fn is_running(&self) -> bool {
+        self.started.is_some() || self.exit.is_some()
+    }
+
+    fn take_exit(&mut self) -> Option<Result<(), KinectError>> {
+        self.exit.take()
+    }
+
+    fn configure(&mut self, _video: VideoSettings) {}
+
+    fn set_consumers(&mut self, consumers: FrameConsumers) {
+        self.consumers = consumers;
+    }
+
+    fn next_frame(&mut self) -> Option<DepthFrame> {
+        let seconds = self.started?.elapsed().as_secs_f64();
+        if seconds < self.next as f64 * FRAME_SECONDS {
+            return None;
+        }
+        if self.next >= self.frames.len() && !self.looping {
+            self.started = None;
+            self.exit = Some(Ok(()));
+            return None;
+        }
+        let data = self.frames[self.next % self.frames.len()].clone();
+        self.next += 1;
+        self.depth_frames += 1;
+        self.consumers.depth(&data);
+        Some(DepthFrame::new(data, self.depth_frames))
+    }
+
+    fn next_video_frame(&mut self) -> Option<VideoFrame> {
+        None
+    }
+
+    fn depth_frames_received(&self) -> u64 {
+        self.depth_frames
+    }
+
+    fn video_frames_received(&self) -> u64 {
+        0
+    }
+
+    fn streams_video(&self) -> bool {
+        false
+    }
+
+    fn tilt(&mut self, _degrees: f64) -> Result<(), KinectError> {
+        Err(KinectError::Unsupported("tilting an array"))
+    }
+
+    fn tilt_state(&mut self) -> Result<MotorState, KinectError> {
+        Err(KinectError::Unsupported("tilt state of an array"))
+    }
+
+    fn led(&mut self, _led: Led) -> Result<(), KinectError> {
+        Err(KinectError::Unsupported("the LED of an array"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arrays_round_trip() {
+        let depth = NpyArray {
+            shape: vec![2, 3, 4],
+            data: NpyData::U16((0..24).collect()),
+        };
+        let timestamps = NpyArray {
+            shape: vec![2],
+            data: NpyData::F64(vec![0.0, 0.033]),
+        };
+        let mut npy = Vec::new();
+        write_npy(&mut npy, &depth).unwrap();
+        // a 64 byte aligned header, then the data
+        let data_at = npy.len() - 24 * 2;
+        assert_eq!(data_at % 64, 0);
+        assert!(String::from_utf8_lossy(&npy[..data_at]).contains("'shape': (2, 3, 4)"));
+        assert_eq!(read_npy(&mut &npy[..]).unwrap(), depth);
+
+        let mut npz = Vec::new();
+        write_npz(&mut npz, &[("depth", &depth), ("timestamps", &timestamps)]).unwrap();
+        let arrays = read_npz(&mut &npz[..]).unwrap();
+        assert_eq!(arrays[0], ("depth".to_string(), depth));
+        assert_eq!(arrays[1], ("timestamps".to_string(), timestamps));
+
+        // the check value of the zip CRC
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+}
@@ -0,0 +1,58 @@
+//! `.onnx` files as the [`Model`] of a [`FrameModel`](crate::inference::FrameModel),
+//! run on the CPU through tract. The input shape is fixed when loading, so
+//! tract can optimize the graph for it:
+//!
+//! ```ignore
+//! let model = OnnxModel::load("model.onnx", &[1, 3, 480, 640])?;
+//! app.add_frame_model(FrameModel {
+//!     every: 2,
+//!     preprocess: Box::new(inference::video_tensor),
+//!     model: Box::new(model),
+//!     postprocess: Box::new(|output| Scores(output.data)),
+//! });
+//! ```
+//!
+//! Only the first input and the first output are used, both `f32`.
+//! `examples/onnx.rs` is a whole app.
+
+use std::path::Path;
+
+use tract_onnx::prelude::*;
+
+use crate::inference::{Model, Tensor};
+
+pub struct OnnxModel {
+    plan: TypedSimplePlan<TypedModel>,
+}
+
+impl OnnxModel {
+    /// Loads and optimizes the model for inputs of `input_shape`.
+    pub fn load(path: impl AsRef<Path>, input_shape: &[usize]) -> Result<OnnxModel, String> {
+        let path = path.as_ref();
+        let plan = tract_onnx::onnx()
+            .model_for_path(path)
+            .and_then(|model| model.with_input_fact(0, f32::fact(input_shape).into()))
+            .and_then(|model| model.into_optimized())
+            .and_then(|model| model.into_runnable())
+            .map_err(|err| format!("Unable to load {}: {err:#}", path.display()))?;
+        Ok(OnnxModel { plan })
+    }
+}
+
+impl Model for OnnxModel {
+    fn run(&mut self, input: Tensor) -> Result<Tensor, String> {
+        let input = tract_onnx::prelude::Tensor::from_shape(&input.shape, &input.data)
+            .map_err(|err| format!("{err:#}"))?;
+        let outputs = self
+            .plan
+            .run(tvec!(input.into()))
+            .map_err(|err| format!("{err:#}"))?;
+        let output = outputs[0]
+            .to_array_view::<f32>()
+            .map_err(|err| format!("{err:#}"))?;
+        Ok(Tensor::new(
+            output.shape().to_vec(),
+            output.iter().copied().collect(),
+        ))
+    }
+}
@@ -0,0 +1,469 @@
+//! Depth sensors driven by OpenNI2, Asus Xtion and Orbbec Astra among them,
+//! with the `openni2` feature and `--openni2`. The first sensor found is
+//! opened through the OpenNI2 C API.
+//!
+//! Like the Kinect v2 they are resampled onto the Kinect v1 picture: depth
+//! comes in millimeters at 640x480 and is looked up for each pixel along the
+//! same ray, through a pinhole model made from the field of view the sensor
+//! reports, and written as the raw reading a v1 would give; the color camera
+//! the same way. Sensors without color through OpenNI2 (some Orbbecs have a
+//! separate UVC camera) stream depth only. None of them has a tilt motor or
+//! an LED to set.
+
+use std::ffi::CStr;
+use std::os::raw::{c_int, c_void};
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use bevy::log::{error, warn};
+
+use crate::backend::DepthCameraBackend;
+use crate::consumer::FrameConsumers;
+use crate::coords::{
+    self, Intrinsics, COLOR_INTRINSICS, DEPTH_HEIGHT, DEPTH_INTRINSICS, DEPTH_WIDTH,
+};
+use crate::device::{self, DepthFrame, KinectError, VideoFrame};
+use crate::motor::{Led, MotorState};
+use crate::queue::{DropPolicy, FrameQueue};
+use crate::video::{VideoFormat, VideoResolution, VideoSettings};
+use crate::DepthFormat;
+
+/// How long the acquisition loop waits for frames before checking whether it
+/// should stop, in milliseconds.
+const POLL_MS: c_int = 10;
+
+#[allow(non_camel_case_types)]
+mod ffi {
+    use std::os::raw::{c_char, c_int, c_void};
+
+    pub enum OniDevice {}
+    pub enum OniStream {}
+
+    pub type OniStatus = c_int;
+    pub const ONI_STATUS_OK: OniStatus = 0;
+    pub const ONI_STATUS_TIME_OUT: OniStatus = 102;
+
+    /// 2.2, as `ONI_API_VERSION` in the headers
+    pub const ONI_API_VERSION: c_int = 2002;
+
+    pub const ONI_SENSOR_COLOR: c_int = 2;
+    pub const ONI_SENSOR_DEPTH: c_int = 3;
+
+    pub const ONI_PIXEL_FORMAT_DEPTH_1_MM: c_int = 100;
+    pub const ONI_PIXEL_FORMAT_RGB888: c_int = 200;
+
+    pub const ONI_STREAM_PROPERTY_HORIZONTAL_FOV: c_int = 1;
+    pub const ONI_STREAM_PROPERTY_VERTICAL_FOV: c_int = 2;
+    pub const ONI_STREAM_PROPERTY_VIDEO_MODE: c_int = 3;
+    pub const ONI_STREAM_PROPERTY_MIRRORING: c_int = 7;
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    pub struct OniVideoMode {
+        pub pixel_format: c_int,
+        pub resolution_x: c_int,
+        pub resolution_y: c_int,
+        pub fps: c_int,
+    }
+
+    #[repr(C)]
+    pub struct OniFrame {
+        pub data_size: c_int,
+        pub data: *mut c_void,
+        pub sensor_type: c_int,
+        pub timestamp: u64,
+        pub frame_index: c_int,
+        pub width: c_int,
+        pub height: c_int,
+        pub video_mode: OniVideoMode,
+        pub cropping_enabled: c_int,
+        pub crop_origin_x: c_int,
+        pub crop_origin_y: c_int,
+        pub stride: c_int,
+    }
+
+    #[link(name = "OpenNI2")]
+    extern "C" {
+        pub fn oniInitialize(api_version: c_int) -> OniStatus;
+        pub fn oniShutdown();
+        pub fn oniGetExtendedError() -> *const c_char;
+        pub fn oniDeviceOpen(uri: *const c_char, device: *mut *mut OniDevice) -> OniStatus;
+        pub fn oniDeviceClose(device: *mut OniDevice) -> OniStatus;
+        pub fn oniDeviceCreateStream(
+            device: *mut OniDevice,
+            sensor_type: c_int,
+            stream: *mut *mut OniStream,
+        ) -> OniStatus;
+        pub fn oniStreamDestroy(stream: *mut OniStream);
+        pub fn oniStreamStart(stream: *mut OniStream) -> OniStatus;
+        pub fn oniStreamStop(stream: *mut OniStream);
+        pub fn oniStreamSetProperty(
+            stream: *mut OniStream,
+            property: c_int,
+            data: *const c_void,
+            size: c_int,
+        ) -> OniStatus;
+        pub fn oniStreamGetProperty(
+            stream: *mut OniStream,
+            property: c_int,
+            data: *mut c_void,
+            size: *mut c_int,
+        ) -> OniStatus;
+        pub fn oniStreamReadFrame(stream: *mut OniStream, frame: *mut *mut OniFrame) -> OniStatus;
+        pub fn oniFrameRelease(frame: *mut OniFrame);
+        pub fn oniWaitForAnyStream(
+            streams: *mut *mut OniStream,
+            count: c_int,
+            ready: *mut c_int,
+            timeout_ms: c_int,
+        ) -> OniStatus;
+    }
+}
+
+#[derive(Default)]
+struct FrameCounters {
+    depth: AtomicU64,
+    video: AtomicU64,
+}
+
+struct AcquisitionThread {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<Result<(), KinectError>>,
+}
+
+/// An OpenNI2 sensor behind [`DepthCameraBackend`]. Nothing is opened until
+/// [`DepthCameraBackend::open`].
+pub struct OpenNi2Backend {
+    depth: FrameQueue<DepthFrame>,
+    video: FrameQueue<VideoFrame>,
+    frames: Arc<FrameCounters>,
+    video_settings: VideoSettings,
+    consumers: FrameConsumers,
+    thread: Option<AcquisitionThread>,
+}
+
+impl OpenNi2Backend {
+    pub fn new(video_settings: VideoSettings) -> OpenNi2Backend {
+        OpenNi2Backend {
+            depth: FrameQueue::new(2, DropPolicy::default()),
+            video: FrameQueue::new(2, DropPolicy::default()),
+            frames: Arc::default(),
+            video_settings,
+            consumers: FrameConsumers::default(),
+            thread: None,
+        }
+    }
+
+    /// Frames of each stream kept waiting for the app and which go when it
+    /// doesn't keep up, two with [`DropPolicy::KeepLatest`] by default.
+    pub fn queue(mut self, len: usize, policy: DropPolicy) -> OpenNi2Backend {
+        self.depth = FrameQueue::new(len, policy);
+        self.video = FrameQueue::new(len, policy);
+        self
+    }
+}
+
+impl DepthCameraBackend for OpenNi2Backend {
+    fn open(&mut self) {
+        if self.thread.is_some() {
+            return;
+        }
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let stop = stop.clone();
+            let (depth_queue, video_queue) = (self.depth.clone(), self.video.clone());
+            let frames = self.frames.clone();
+            let consumers = self.consumers.clone();
+            let video_settings = self.video_settings;
+            thread::Builder::new()
+                .name("openni2".into())
+                .spawn(move || {
+                    acquire(
+                        video_settings,
+                        &stop,
+                        (&depth_queue, &video_queue),
+                        &consumers,
+                        &frames,
+                    )
+                })
+                .unwrap()
+        };
+        self.thread = Some(AcquisitionThread { stop, handle });
+    }
+
+    fn close(&mut self) -> Result<(), KinectError> {
+        match self.thread.take() {
+            Some(thread) => {
+                thread.stop.store(true, Ordering::Relaxed);
+                device::join(thread.handle)
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn is_running(&self) -> bool {
+        self.thread.is_some()
+    }
+
+    fn take_exit(&mut self) -> Option<Result<(), KinectError>> {
+        if !self.thread.as_ref()?.handle.is_finished() {
+            return None;
+        }
+        self.thread.take().map(|thread| device::join(thread.handle))
+    }
+
+    fn configure(&mut self, video: VideoSettings) {
+        self.video_settings = video;
+    }
+
+    fn set_consumers(&mut self, consumers: FrameConsumers) {
+        self.consumers = consumers;
+    }
+
+    fn next_frame(&mut self) -> Option<DepthFrame> {
+        self.depth.pop()
+    }
+
+    fn next_video_frame(&mut self) -> Option<VideoFrame> {
+        self.video.pop()
+    }
+
+    fn depth_frames_received(&self) -> u64 {
+        self.frames.depth.load(Ordering::Relaxed)
+    }
+
+    fn video_frames_received(&self) -> u64 {
+        self.frames.video.load(Ordering::Relaxed)
+    }
+
+    fn tilt(&mut self, _degrees: f64) -> Result<(), KinectError> {
+        Err(KinectError::Unsupported("tilt on an OpenNI2 sensor"))
+    }
+
+    fn tilt_state(&mut self) -> Result<MotorState, KinectError> {
+        Err(KinectError::Unsupported("tilt on an OpenNI2 sensor"))
+    }
+
+    fn led(&mut self, _led: Led) -> Result<(), KinectError> {
+        Err(KinectError::Unsupported("the LED on an OpenNI2 sensor"))
+    }
+}
+
+impl Drop for OpenNi2Backend {
+    fn drop(&mut self) {
+        if let Err(err) = self.close() {
+            error!("{err}");
+        }
+    }
+}
+
+fn check(status: ffi::OniStatus) -> Result<(), KinectError> {
+    if status == ffi::ONI_STATUS_OK {
+        return Ok(());
+    }
+    let message = unsafe { CStr::from_ptr(ffi::oniGetExtendedError()) };
+    Err(KinectError::Open(format!(
+        "OpenNI2: {}",
+        message.to_string_lossy()
+    )))
+}
+
+/// The open device and its streams, all closed again on drop.
+struct Device {
+    device: *mut ffi::OniDevice,
+    streams: Vec<*mut ffi::OniStream>,
+}
+
+impl Device {
+    fn open() -> Result<Device, KinectError> {
+        check(unsafe { ffi::oniInitialize(ffi::ONI_API_VERSION) })?;
+        let mut device = ptr::null_mut();
+        // a null uri is the first device found
+        if let Err(err) = check(unsafe { ffi::oniDeviceOpen(ptr::null(), &mut device) }) {
+            unsafe { ffi::oniShutdown() };
+            return Err(err);
+        }
+        Ok(Device {
+            device,
+            streams: Vec::new(),
+        })
+    }
+
+    /// Starts a stream at 640x480 and 30 Hz, unmirrored, and returns the
+    /// pinhole model of its field of view.
+    fn start(&mut self, sensor: c_int, pixel_format: c_int) -> Result<Intrinsics, KinectError> {
+        let mut stream = ptr::null_mut();
+        check(unsafe { ffi::oniDeviceCreateStream(self.device, sensor, &mut stream) })?;
+        self.streams.push(stream);
+        let mode = ffi::OniVideoMode {
+            pixel_format,
+            resolution_x: DEPTH_WIDTH as c_int,
+            resolution_y: DEPTH_HEIGHT as c_int,
+            fps: 30,
+        };
+        set_property(stream, ffi::ONI_STREAM_PROPERTY_VIDEO_MODE, &mode)?;
+        let mirrored: c_int = 0;
+        set_property(stream, ffi::ONI_STREAM_PROPERTY_MIRRORING, &mirrored)?;
+        let horizontal: f32 = get_property(stream, ffi::ONI_STREAM_PROPERTY_HORIZONTAL_FOV)?;
+        let vertical: f32 = get_property(stream, ffi::ONI_STREAM_PROPERTY_VERTICAL_FOV)?;
+        check(unsafe { ffi::oniStreamStart(stream) })?;
+
+        let (width, height) = (DEPTH_WIDTH as f32, DEPTH_HEIGHT as f32);
+        Ok(Intrinsics {
+            fx: width / 2.0 / (horizontal / 2.0).tan(),
+            fy: height / 2.0 / (vertical / 2.0).tan(),
+            cx: width / 2.0,
+            cy: height / 2.0,
+        })
+    }
+}
+
+impl Drop for Device {
+    fn drop(&mut self) {
+        unsafe {
+            for stream in &self.streams {
+                ffi::oniStreamStop(*stream);
+                ffi::oniStreamDestroy(*stream);
+            }
+            ffi::oniDeviceClose(self.device);
+            ffi::oniShutdown();
+        }
+    }
+}
+
+fn set_property<T>(
+    stream: *mut ffi::OniStream,
+    property: c_int,
+    value: &T,
+) -> Result<(), KinectError> {
+    check(unsafe {
+        ffi::oniStreamSetProperty(
+            stream,
+            property,
+            value as *const T as *const c_void,
+            std::mem::size_of::<T>() as c_int,
+        )
+    })
+}
+
+fn get_property<T: Default>(
+    stream: *mut ffi::OniStream,
+    property: c_int,
+) -> Result<T, KinectError> {
+    let mut value = T::default();
+    let mut size = std::mem::size_of::<T>() as c_int;
+    check(unsafe {
+        ffi::oniStreamGetProperty(
+            stream,
+            property,
+            &mut value as *mut T as *mut c_void,
+            &mut size,
+        )
+    })?;
+    Ok(value)
+}
+
+/// Bytes of pixel `index` of a frame `bytes_per_pixel` wide, its rows
+/// `stride` apart.
+fn pixel(frame: &ffi::OniFrame, index: usize, bytes_per_pixel: usize) -> &[u8] {
+    let width = frame.width as usize;
+    let offset = index / width * frame.stride as usize + index % width * bytes_per_pixel;
+    unsafe { std::slice::from_raw_parts((frame.data as *const u8).add(offset), bytes_per_pixel) }
+}
+
+fn acquire(
+    video: VideoSettings,
+    stop: &AtomicBool,
+    (depth_queue, video_queue): (&FrameQueue<DepthFrame>, &FrameQueue<VideoFrame>),
+    consumers: &FrameConsumers,
+    frames: &FrameCounters,
+) -> Result<(), KinectError> {
+    if video.format != VideoFormat::Rgb || video.resolution != VideoResolution::Medium {
+        return Err(KinectError::Open(
+            "an OpenNI2 sensor only streams rgb video at medium resolution".into(),
+        ));
+    }
+
+    let mut device = Device::open()?;
+    let size = (DEPTH_WIDTH, DEPTH_HEIGHT);
+    let depth_k = device.start(ffi::ONI_SENSOR_DEPTH, ffi::ONI_PIXEL_FORMAT_DEPTH_1_MM)?;
+    let depth_lookup = coords::ray_lookup(&DEPTH_INTRINSICS, size, &depth_k, size, false);
+    let color_lookup = match device.start(ffi::ONI_SENSOR_COLOR, ffi::ONI_PIXEL_FORMAT_RGB888) {
+        Ok(color_k) => Some(coords::ray_lookup(
+            &COLOR_INTRINSICS,
+            size,
+            &color_k,
+            size,
+            false,
+        )),
+        Err(err) => {
+            warn!("{err}, depth only");
+            None
+        }
+    };
+    let mut streams = device.streams.clone();
+
+    while !stop.load(Ordering::Relaxed) {
+        let mut ready = 0;
+        let status = unsafe {
+            ffi::oniWaitForAnyStream(
+                streams.as_mut_ptr(),
+                streams.len() as c_int,
+                &mut ready,
+                POLL_MS,
+            )
+        };
+        if status == ffi::ONI_STATUS_TIME_OUT {
+            continue;
+        }
+        check(status)?;
+
+        let mut frame = ptr::null_mut();
+        check(unsafe { ffi::oniStreamReadFrame(streams[ready as usize], &mut frame) })?;
+        let oni_frame = unsafe { &*frame };
+        if (oni_frame.width as usize, oni_frame.height as usize) == size {
+            match (ready, &color_lookup) {
+                (0, _) => {
+                    let sequence = frames.depth.fetch_add(1, Ordering::Relaxed) + 1;
+                    let mm = depth_lookup
+                        .iter()
+                        .map(|from| match from {
+                            Some(from) => {
+                                let mm = pixel(oni_frame, *from, 2);
+                                u16::from_ne_bytes([mm[0], mm[1]])
+                            }
+                            None => 0,
+                        })
+                        .collect::<Vec<_>>();
+                    let data = mm
+                        .iter()
+                        .map(|mm| DepthFormat::Millimeters.to_bit10(*mm))
+                        .collect::<Vec<_>>();
+                    consumers.depth(&data);
+                    // the timestamp is in microseconds
+                    let depth_frame = DepthFrame::new(data, sequence)
+                        .with_raw(DepthFormat::Millimeters, mm)
+                        .with_timestamp(oni_frame.timestamp);
+                    depth_queue.push(depth_frame);
+                }
+                (_, Some(color_lookup)) => {
+                    frames.video.fetch_add(1, Ordering::Relaxed);
+                    let mut data = Vec::with_capacity(color_lookup.len() * 3);
+                    for from in color_lookup {
+                        match from {
+                            Some(from) => data.extend_from_slice(pixel(oni_frame, *from, 3)),
+                            None => data.extend_from_slice(&[0, 0, 0]),
+                        }
+                    }
+                    consumers.video(&data);
+                    video_queue.push(VideoFrame { data });
+                }
+                (_, None) => {}
+            }
+        }
+        unsafe { ffi::oniFrameRelease(frame) };
+    }
+    Ok(())
+}
@@ -0,0 +1,105 @@
+//! Full-screen notice shown while the sensor isn't streaming, so a dropped
+//! Kinect doesn't just look like a frozen picture. Outside the operating
+//! hours it says when the install opens again, unless attract mode is held
+//! instead.
+
+use bevy::prelude::*;
+
+use crate::hours::{ClosedMode, HoursSettings, Operating};
+use crate::reconnect::Connection;
+
+#[derive(Component)]
+struct StatusOverlay;
+
+#[derive(Component)]
+struct StatusText;
+
+pub struct StatusOverlayPlugin;
+
+impl Plugin for StatusOverlayPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system(spawn_status_overlay)
+            .add_system(update_status_overlay);
+    }
+}
+
+fn spawn_status_overlay(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                position_type: PositionType::Absolute,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            background_color: Color::rgba(0.0, 0.0, 0.0, 0.8).into(),
+            // above the depth image and the tilt dial
+            z_index: ZIndex::Global(10),
+            ..default()
+        })
+        .insert(StatusOverlay)
+        .with_children(|parent| {
+            parent
+                .spawn(
+                    TextBundle::from_section(
+                        "",
+                        TextStyle {
+                            font: asset_server.load("fonts/Hack-Regular.ttf"),
+                            font_size: 20.0,
+                            color: Color::WHITE,
+                        },
+                    )
+                    .with_text_alignment(TextAlignment::CENTER),
+                )
+                .insert(StatusText);
+        });
+}
+
+fn update_status_overlay(
+    connection: Res<Connection>,
+    (operating, hours): (Res<Operating>, Res<HoursSettings>),
+    time: Res<Time>,
+    mut overlay_query: Query<&mut Visibility, With<StatusOverlay>>,
+    mut text_query: Query<&mut Text, With<StatusText>>,
+) {
+    let visible = if operating.is_open() {
+        !connection.is_streaming()
+    } else {
+        hours.closed == ClosedMode::Off
+    };
+    for mut visibility in overlay_query.iter_mut() {
+        if visibility.is_visible != visible {
+            visibility.is_visible = visible;
+        }
+    }
+    if !visible {
+        return;
+    }
+
+    if let (false, Some(hours)) = (operating.is_open(), hours.hours) {
+        for mut text in text_query.iter_mut() {
+            text.sections[0].value = format!("Closed\n\nopens at {}", hours.opens);
+        }
+        return;
+    }
+
+    let headline = match connection.last_error() {
+        Some(err) => format!("Kinect unavailable\n{err}"),
+        None if connection.is_connecting() => "Waiting for Kinect".to_string(),
+        None => "Kinect stopped responding".to_string(),
+    };
+    let next = match connection.retry_in(&time) {
+        Some(seconds) => format!(
+            "retrying in {:.0}s (attempt {})",
+            seconds.ceil(),
+            connection.attempt()
+        ),
+        None if connection.is_connecting() => "connecting...".to_string(),
+        None => "press R to retry".to_string(),
+    };
+
+    for mut text in text_query.iter_mut() {
+        text.sections[0].value = format!("{headline}\n\n{next}");
+    }
+}
@@ -0,0 +1,210 @@
+//! Picking points in the room off the depth image. Clicking the depth view,
+//! or holding the pick pose (`--pick-pose <name>`) and pointing, sends a
+//! [`DepthPicked`] with the depth pixel and the point it sees. Putting
+//! something on the real table is then one call:
+//!
+//! ```ignore
+//! fn place_lamps(mut commands: Commands, mut picks: EventReader<DepthPicked>) {
+//!     for pick in picks.iter() {
+//!         commands.spawn((SpriteBundle { texture: lamp, ..default() }, pick.anchor()));
+//!     }
+//! }
+//! ```
+//!
+//! or, to move one entity to wherever was picked last, give it a
+//! [`PlaceOnPick`]. Either way it stays over that point in the picture
+//! through [`crate::anchor`], with [`ScaleWithDistance`] to size it in
+//! meters. A small pin marks the last pick.
+
+use bevy::prelude::*;
+
+use crate::anchor::{AnchorPoint, BodyAnchor, ScaleWithDistance};
+use crate::coords::{self, DisplayRect, DEPTH_HEIGHT, DEPTH_INTRINSICS, DEPTH_WIDTH};
+use crate::poses::PoseMatched;
+use crate::skeleton::{Joint, Skeleton};
+use crate::touchless;
+use crate::{CurrentDepth, MainKinect};
+
+/// Distance between samples along a pointing ray, in meters.
+const RAY_STEP: f32 = 0.02;
+/// Rays start this far past the hand so they don't hit the arm.
+const RAY_START: f32 = 0.3;
+const RAY_LENGTH: f32 = 5.0;
+const PIN_SIZE: f32 = 0.04;
+
+#[derive(Resource, Clone, Debug, Default)]
+pub struct PickSettings {
+    /// pose that picks where the arm points, `None` for clicks only
+    pub pose: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DepthPicked {
+    /// depth pixel picked
+    pub pixel: Vec2,
+    /// what it sees, sensor space
+    pub point: Vec3,
+}
+
+impl DepthPicked {
+    /// Anchors an entity at the picked point.
+    pub fn anchor(&self) -> BodyAnchor {
+        BodyAnchor {
+            joint: AnchorPoint::Point(self.point),
+            offset: Vec3::ZERO,
+        }
+    }
+}
+
+/// Moved to every new pick, hidden until the first.
+#[derive(Component)]
+pub struct PlaceOnPick;
+
+pub struct PickingPlugin;
+
+impl Plugin for PickingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PickSettings>()
+            .add_event::<DepthPicked>()
+            .add_startup_system(spawn_pin)
+            .add_system(pick_with_mouse)
+            .add_system(pick_with_pose)
+            .add_system(place_on_picks.after(pick_with_mouse).after(pick_with_pose));
+    }
+}
+
+/// The point a depth pixel sees, from the closest reading right around it
+/// as single pixels are often holes.
+pub fn pick(depth: &[u16], pixel: Vec2) -> Option<DepthPicked> {
+    if depth.len() != DEPTH_WIDTH * DEPTH_HEIGHT
+        || !(0.0..DEPTH_WIDTH as f32).contains(&pixel.x)
+        || !(0.0..DEPTH_HEIGHT as f32).contains(&pixel.y)
+    {
+        return None;
+    }
+    let meters = touchless::hand_meters(depth, pixel)?;
+    Some(DepthPicked {
+        pixel,
+        point: coords::depth_pixel_to_sensor(pixel, meters),
+    })
+}
+
+/// First surface along a ray in sensor space: where it passes behind a
+/// reading. Medium resolution frames only, like [`pick`].
+pub fn cast(depth: &[u16], origin: Vec3, direction: Vec3) -> Option<DepthPicked> {
+    if depth.len() != DEPTH_WIDTH * DEPTH_HEIGHT {
+        return None;
+    }
+    let direction = direction.try_normalize()?;
+    let steps = ((RAY_LENGTH - RAY_START) / RAY_STEP) as usize;
+    (0..steps).find_map(|step| {
+        let point = origin + direction * (RAY_START + step as f32 * RAY_STEP);
+        if point.z <= 0.0 {
+            return None;
+        }
+        let pixel = DEPTH_INTRINSICS.project(point);
+        let (x, y) = (pixel.x as usize, pixel.y as usize);
+        if pixel.x < 0.0 || pixel.y < 0.0 || x >= DEPTH_WIDTH || y >= DEPTH_HEIGHT {
+            return None;
+        }
+        let raw = depth[y * DEPTH_WIDTH + x];
+        match coords::raw_depth_to_meters(raw) {
+            Some(meters) if meters <= point.z => pick(depth, pixel),
+            _ => None,
+        }
+    })
+}
+
+fn spawn_pin(mut commands: Commands) {
+    commands.spawn((
+        SpriteBundle {
+            sprite: Sprite {
+                color: Color::rgb(0.2, 0.9, 1.0),
+                custom_size: Some(Vec2::splat(PIN_SIZE)),
+                ..default()
+            },
+            transform: Transform::from_xyz(0.0, 0.0, 0.95)
+                .with_rotation(Quat::from_rotation_z(std::f32::consts::FRAC_PI_4)),
+            visibility: Visibility { is_visible: false },
+            ..default()
+        },
+        PlaceOnPick,
+        ScaleWithDistance,
+    ));
+}
+
+fn pick_with_mouse(
+    (buttons, windows, rect): (Res<Input<MouseButton>>, Res<Windows>, Res<DisplayRect>),
+    depth_query: Query<&CurrentDepth, With<MainKinect>>,
+    interaction_query: Query<&Interaction>,
+    mut picks: EventWriter<DepthPicked>,
+) {
+    if !buttons.just_pressed(MouseButton::Left) {
+        return;
+    }
+    // clicks on buttons are theirs
+    if interaction_query
+        .iter()
+        .any(|interaction| *interaction != Interaction::None)
+    {
+        return;
+    }
+    let pixel = windows
+        .primary()
+        .cursor_position()
+        .and_then(|cursor| rect.screen_to_image(cursor));
+    if let (Some(pixel), Ok(depth)) = (pixel, depth_query.get_single()) {
+        if let Some(picked) = pick(&depth.depth_array, pixel) {
+            picks.send(picked);
+        }
+    }
+}
+
+fn pick_with_pose(
+    settings: Res<PickSettings>,
+    skeleton: Res<Skeleton>,
+    depth_query: Query<&CurrentDepth, With<MainKinect>>,
+    mut poses: EventReader<PoseMatched>,
+    mut picks: EventWriter<DepthPicked>,
+) {
+    let posed = poses
+        .iter()
+        .any(|pose| settings.pose.as_ref() == Some(&pose.name));
+    let depth = match depth_query.get_single() {
+        Ok(depth) if posed => depth,
+        _ => return,
+    };
+    // the arm held out furthest points
+    let arm = [
+        (Joint::ShoulderLeft, Joint::HandLeft),
+        (Joint::ShoulderRight, Joint::HandRight),
+    ]
+    .into_iter()
+    .filter_map(|(shoulder, hand)| {
+        let shoulder = skeleton.get(shoulder)?.position;
+        let hand = skeleton.get(hand)?.position;
+        Some((hand, hand - shoulder))
+    })
+    .max_by(|(_, a), (_, b)| a.length().total_cmp(&b.length()));
+    if let Some((hand, direction)) = arm {
+        if let Some(picked) = cast(&depth.depth_array, hand, direction) {
+            picks.send(picked);
+        }
+    }
+}
+
+fn place_on_picks(
+    mut commands: Commands,
+    mut picks: EventReader<DepthPicked>,
+    place_query: Query<Entity, With<PlaceOnPick>>,
+) {
+    if let Some(picked) = picks.iter().last() {
+        info!(
+            "Picked ({:.0}, {:.0}), {:+.2} {:+.2} {:.2} m",
+            picked.pixel.x, picked.pixel.y, picked.point.x, picked.point.y, picked.point.z
+        );
+        for entity in place_query.iter() {
+            commands.entity(entity).insert(picked.anchor());
+        }
+    }
+}
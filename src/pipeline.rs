@@ -0,0 +1,310 @@
+//! The depth frame's way through a frame: a source takes it off the device,
+//! filters change it in place and consumers read the result. Each stage is
+//! a system known by a label type, and says where it goes by naming the
+//! stages it comes after or before, so an app can put its own filter or
+//! detector between the stock ones:
+//!
+//! ```ignore
+//! #[derive(SystemLabel)]
+//! struct Despeckle;
+//!
+//! app.add_depth_stage(
+//!     DepthStage::filter(Despeckle, despeckle)
+//!         .after(pipeline::DetectInterference)
+//!         .before(pipeline::FillHoles),
+//! );
+//! ```
+//!
+//! Sources always run before filters and filters before consumers, `after`
+//! and `before` order stages of the same kind. [`DepthPipeline`] holds the
+//! graph as registered, for turning stages off and for showing the order
+//! they run in. Each stage's time goes into the
+//! [`FrameBudget`](crate::budget::FrameBudget) under its label's name.
+
+use bevy::ecs::schedule::{IntoSystemDescriptor, ShouldRun, SystemDescriptor, SystemLabelId};
+use bevy::ecs::system::AsSystemLabel;
+use bevy::prelude::*;
+
+use crate::budget;
+use crate::KinectSet;
+
+/// Takes frames off the device into [`CurrentDepth`](crate::CurrentDepth).
+#[derive(SystemLabel)]
+pub struct ReadDepth;
+
+/// Measures the noise of the unfiltered readings, see
+/// [`confidence`](crate::confidence).
+#[derive(SystemLabel)]
+pub struct UpdateConfidence;
+
+/// Looks for another Kinect's pattern, see
+/// [`interference`](crate::interference).
+#[derive(SystemLabel)]
+pub struct DetectInterference;
+
+/// Fills holes in the depth, see [`inpaint`](crate::inpaint).
+#[derive(SystemLabel)]
+pub struct FillHoles;
+
+/// Finds the close blob.
+#[derive(SystemLabel)]
+pub struct TrackCloseBlob;
+
+/// Finds the close blob again with the settings compared against, see
+/// [`compare`](crate::compare).
+#[derive(SystemLabel)]
+pub struct TrackComparison;
+
+/// Follows several people at once, see [`players`](crate::players).
+#[derive(SystemLabel)]
+pub struct TrackPlayers;
+
+/// Adds the frame to the long exposure, see [`exposure`](crate::exposure).
+#[derive(SystemLabel)]
+pub struct AccumulateExposure;
+
+/// Updates the motion glow, see [`motion`](crate::motion).
+#[derive(SystemLabel)]
+pub struct UpdateMotionField;
+
+/// Reads hand gestures off the close blob, see
+/// [`touchless`](crate::touchless).
+#[derive(SystemLabel)]
+pub struct DetectHandGestures;
+
+/// What a stage does with the frame, which decides the order before any
+/// `after` and `before`.
+#[derive(SystemLabel, Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum StageKind {
+    Source,
+    Filter,
+    Consumer,
+}
+
+/// A stage to add with [`DepthPipelineApp::add_depth_stage`].
+pub struct DepthStage {
+    info: StageInfo,
+    system: SystemDescriptor,
+}
+
+impl DepthStage {
+    pub fn source<Params>(
+        label: impl SystemLabel,
+        system: impl IntoSystem<(), (), Params>,
+    ) -> DepthStage {
+        DepthStage::new(StageKind::Source, label, system)
+    }
+
+    pub fn filter<Params>(
+        label: impl SystemLabel,
+        system: impl IntoSystem<(), (), Params>,
+    ) -> DepthStage {
+        DepthStage::new(StageKind::Filter, label, system)
+    }
+
+    pub fn consumer<Params>(
+        label: impl SystemLabel,
+        system: impl IntoSystem<(), (), Params>,
+    ) -> DepthStage {
+        DepthStage::new(StageKind::Consumer, label, system)
+    }
+
+    fn new<Params>(
+        kind: StageKind,
+        label: impl SystemLabel,
+        system: impl IntoSystem<(), (), Params>,
+    ) -> DepthStage {
+        let name = label.as_str();
+        DepthStage {
+            info: StageInfo {
+                label: label.as_label(),
+                kind,
+                after: Vec::new(),
+                before: Vec::new(),
+                enabled: true,
+            },
+            system: budget::timed(name, system).into_descriptor(),
+        }
+    }
+
+    /// Runs after `stage`, when both are in the pipeline. Any other system
+    /// or label works too, for the schedule only.
+    pub fn after<Marker>(mut self, stage: impl AsSystemLabel<Marker>) -> DepthStage {
+        self.info.after.push(stage.as_system_label());
+        self
+    }
+
+    /// Runs before `stage`, when both are in the pipeline. Any other system
+    /// or label works too, for the schedule only.
+    pub fn before<Marker>(mut self, stage: impl AsSystemLabel<Marker>) -> DepthStage {
+        self.info.before.push(stage.as_system_label());
+        self
+    }
+}
+
+/// A stage as registered.
+#[derive(Clone, Debug)]
+pub struct StageInfo {
+    pub label: SystemLabelId,
+    pub kind: StageKind,
+    pub after: Vec<SystemLabelId>,
+    pub before: Vec<SystemLabelId>,
+    /// off, the stage's system is skipped and the frame passes on as it is
+    pub enabled: bool,
+}
+
+#[derive(Resource, Clone, Debug, Default)]
+pub struct DepthPipeline {
+    stages: Vec<StageInfo>,
+}
+
+impl DepthPipeline {
+    /// The stages in the order they were added.
+    pub fn stages(&self) -> &[StageInfo] {
+        &self.stages
+    }
+
+    pub fn is_enabled(&self, stage: impl SystemLabel) -> bool {
+        let label = stage.as_label();
+        self.stages
+            .iter()
+            .any(|info| info.label == label && info.enabled)
+    }
+
+    /// Turns a stage on or off, `false` if there is no such stage.
+    pub fn set_enabled(&mut self, stage: impl SystemLabel, enabled: bool) -> bool {
+        let label = stage.as_label();
+        match self.stages.iter_mut().find(|info| info.label == label) {
+            Some(info) => {
+                info.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The stages in the order they run: by kind, then as `after` and
+    /// `before` say, the order they were added where nothing does. `None`
+    /// when the stages of a kind wait on each other in a circle.
+    pub fn order(&self) -> Option<Vec<SystemLabelId>> {
+        let mut order = Vec::with_capacity(self.stages.len());
+        for kind in [StageKind::Source, StageKind::Filter, StageKind::Consumer] {
+            let stages: Vec<&StageInfo> = self
+                .stages
+                .iter()
+                .filter(|info| info.kind == kind)
+                .collect();
+            let index = |label: &SystemLabelId| stages.iter().position(|info| info.label == *label);
+            // stages each one has to wait for
+            let mut waits: Vec<Vec<usize>> = vec![Vec::new(); stages.len()];
+            for (i, info) in stages.iter().enumerate() {
+                waits[i].extend(info.after.iter().filter_map(index));
+                for later in info.before.iter().filter_map(index) {
+                    waits[later].push(i);
+                }
+            }
+            let mut done = vec![false; stages.len()];
+            for _ in 0..stages.len() {
+                let next = (0..stages.len())
+                    .find(|&i| !done[i] && waits[i].iter().all(|&j| done[j] || j == i))?;
+                done[next] = true;
+                order.push(stages[next].label);
+            }
+        }
+        Some(order)
+    }
+}
+
+pub trait DepthPipelineApp {
+    /// Adds a stage to the depth pipeline and its system to the app.
+    fn add_depth_stage(&mut self, stage: DepthStage) -> &mut Self;
+}
+
+impl DepthPipelineApp for App {
+    fn add_depth_stage(&mut self, stage: DepthStage) -> &mut Self {
+        let DepthStage { info, system } = stage;
+        let label = info.label;
+        let mut system = system.label(label).label(info.kind).with_run_criteria(
+            move |pipeline: Res<DepthPipeline>| {
+                if pipeline.is_enabled(label) {
+                    ShouldRun::Yes
+                } else {
+                    ShouldRun::No
+                }
+            },
+        );
+        system = match info.kind {
+            StageKind::Source => system.label(KinectSet::Acquire),
+            StageKind::Filter => system.label(KinectSet::Process).after(StageKind::Source),
+            StageKind::Consumer => system
+                .label(KinectSet::Process)
+                .after(StageKind::Source)
+                .after(StageKind::Filter),
+        };
+        for other in &info.after {
+            system = system.after(*other);
+        }
+        for other in &info.before {
+            system = system.before(*other);
+        }
+        self.world
+            .get_resource_or_insert_with(DepthPipeline::default)
+            .stages
+            .push(info);
+        self.add_system(system)
+    }
+}
+
+pub(crate) fn log_pipeline(pipeline: Res<DepthPipeline>) {
+    match pipeline.order() {
+        Some(order) => {
+            let names: Vec<&str> = order.iter().map(|label| label.as_str()).collect();
+            info!("Depth pipeline: {}", names.join(" -> "));
+        }
+        None => error!("The depth pipeline's stages wait on each other in a circle"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(SystemLabel)]
+    struct Despeckle;
+
+    #[derive(SystemLabel)]
+    struct Count;
+
+    fn names(app: &App) -> Vec<&'static str> {
+        let pipeline = app.world.resource::<DepthPipeline>();
+        let order = pipeline.order().unwrap();
+        order.iter().map(|label| label.as_str()).collect()
+    }
+
+    #[test]
+    fn stages_go_where_they_ask() {
+        let mut app = App::new();
+        app.add_depth_stage(DepthStage::consumer(Count, || {}).after(TrackCloseBlob))
+            .add_depth_stage(DepthStage::consumer(TrackCloseBlob, || {}))
+            .add_depth_stage(DepthStage::filter(FillHoles, || {}))
+            .add_depth_stage(DepthStage::filter(Despeckle, || {}).before(FillHoles))
+            .add_depth_stage(DepthStage::source(ReadDepth, || {}));
+        assert_eq!(
+            names(&app),
+            [
+                "ReadDepth",
+                "Despeckle",
+                "FillHoles",
+                "TrackCloseBlob",
+                "Count"
+            ]
+        );
+        // the schedule takes the same order, without a circle
+        app.update();
+
+        let mut pipeline = app.world.resource_mut::<DepthPipeline>();
+        assert!(pipeline.set_enabled(Despeckle, false));
+        assert!(!pipeline.is_enabled(Despeckle) && pipeline.is_enabled(FillHoles));
+        assert!(!pipeline.set_enabled(UpdateMotionField, false));
+    }
+}
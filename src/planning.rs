@@ -0,0 +1,363 @@
+//! Planning mode for rooms with several Kinects. A JSON plan places the
+//! sensors, each gets its frustum in the coverage view, and the floor is
+//! colored by what is seen at a chosen height:
+//!
+//! * red, blind spots no sensor sees
+//! * green, seen by one sensor
+//! * blue, seen by several from different sides, good for tracking around
+//!   people
+//! * orange, seen by several looking the same way. Their IR patterns land
+//!   on the same surfaces there and garble each other's depth.
+//!
+//! ```json
+//! {
+//!     "room": [6.0, 4.0],
+//!     "height": 1.0,
+//!     "sensors": [
+//!         { "position": [0.0, 2.0, 4.0], "yaw": -45.0, "pitch": -20.0 },
+//!         { "position": [6.0, 2.0, 4.0], "yaw": 45.0, "pitch": -20.0 }
+//!     ]
+//! }
+//! ```
+//!
+//! The room spans `0..width` along x and `0..length` along z, y is up.
+//! Sensors look along -z at zero yaw, yaw turns them counter-clockwise seen
+//! from above, pitch is positive looking up like [`crate::tilt::TiltState`].
+//!
+//! Hovering the floor reads out the point under the cursor and the depth
+//! pixel each sensor that sees it there would see it at.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+use bevy::render::mesh::Indices;
+use bevy::render::render_resource::PrimitiveTopology;
+use serde::Deserialize;
+
+use crate::coords::{DEPTH_HEIGHT, DEPTH_INTRINSICS, DEPTH_WIDTH};
+use crate::frustum::{
+    self, FrustumSettings, SceneCamera, SceneHit, SceneHover, SceneRaycast, MAX_RANGE, MIN_RANGE,
+};
+use crate::presentation::{DebugUi, PresentationSettings};
+
+/// Size of a floor cell, in meters.
+const CELL: f32 = 0.1;
+/// Sensors whose rays meet at less than this light the same side of things.
+const INTERFERENCE_ANGLE: f32 = 45.0;
+
+const BLIND: Color = Color::rgba(0.9, 0.2, 0.2, 0.6);
+const COVERED: Color = Color::rgba(0.2, 0.8, 0.3, 0.6);
+const OVERLAP: Color = Color::rgba(0.2, 0.5, 1.0, 0.6);
+const INTERFERENCE: Color = Color::rgba(1.0, 0.6, 0.1, 0.6);
+
+#[derive(Resource, Clone, Debug, Default)]
+pub struct PlanningSettings {
+    pub plan: Option<PathBuf>,
+}
+
+#[derive(Resource, Clone, Debug, Deserialize)]
+pub struct RoomPlan {
+    /// width along x and length along z, in meters
+    pub room: [f32; 2],
+    /// height the coverage is checked at, about a standing person's chest
+    #[serde(default = "default_height")]
+    pub height: f32,
+    pub sensors: Vec<PlannedPose>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub struct PlannedPose {
+    pub position: [f32; 3],
+    #[serde(default)]
+    pub yaw: f32,
+    #[serde(default)]
+    pub pitch: f32,
+}
+
+fn default_height() -> f32 {
+    1.0
+}
+
+impl RoomPlan {
+    pub fn load(path: &Path) -> Result<RoomPlan, String> {
+        let json = fs::read_to_string(path)
+            .map_err(|err| format!("can't read plan {}: {err}", path.display()))?;
+        serde_json::from_str(&json).map_err(|err| format!("bad plan {}: {err}", path.display()))
+    }
+
+    fn transforms(&self) -> Vec<Transform> {
+        self.sensors
+            .iter()
+            .map(|pose| {
+                Transform::from_translation(Vec3::from(pose.position)).with_rotation(
+                    Quat::from_rotation_y(pose.yaw.to_radians())
+                        * Quat::from_rotation_x(pose.pitch.to_radians()),
+                )
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Coverage {
+    Blind,
+    Covered,
+    Overlap,
+    Interference,
+}
+
+impl Coverage {
+    fn color(self) -> Color {
+        match self {
+            Coverage::Blind => BLIND,
+            Coverage::Covered => COVERED,
+            Coverage::Overlap => OVERLAP,
+            Coverage::Interference => INTERFERENCE,
+        }
+    }
+}
+
+/// A sensor from the plan, posed where it would be mounted.
+#[derive(Component)]
+struct PlannedSensor;
+
+#[derive(Component)]
+struct PlanSummary;
+
+pub struct PlanningPlugin;
+
+impl Plugin for PlanningPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PlanningSettings>();
+        let path = match &app.world.resource::<PlanningSettings>().plan {
+            Some(path) => path.clone(),
+            None => return,
+        };
+        let plan = RoomPlan::load(&path).unwrap_or_else(|err| panic!("{err}"));
+
+        let mut view = app.world.resource_mut::<FrustumSettings>();
+        view.visible = true;
+        view.full_window = true;
+        app.insert_resource(plan)
+            .add_startup_system(spawn_plan)
+            .add_system(frame_plan)
+            .add_system(show_plan_summary)
+            .add_system(hover_floor.label(SceneRaycast).after(frustum::hover_depth));
+    }
+}
+
+fn spawn_plan(
+    mut commands: Commands,
+    plan: Res<RoomPlan>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    asset_server: Res<AssetServer>,
+) {
+    let transforms = plan.transforms();
+    let frustum_material = materials.add(StandardMaterial {
+        base_color: Color::rgb(1.0, 1.0, 0.4),
+        unlit: true,
+        ..default()
+    });
+    for transform in &transforms {
+        commands
+            .spawn((PlannedSensor, SpatialBundle::from_transform(*transform)))
+            .with_children(|parent| {
+                frustum::spawn_frustum(parent, &mut meshes, frustum_material.clone())
+            });
+    }
+
+    let (width, length) = (plan.room[0], plan.room[1]);
+    let (columns, rows) = (
+        (width / CELL).ceil() as usize,
+        (length / CELL).ceil() as usize,
+    );
+    let mut cells = Vec::with_capacity(columns * rows);
+    for row in 0..rows {
+        for column in 0..columns {
+            let center = Vec3::new(
+                (column as f32 + 0.5) * CELL,
+                plan.height,
+                (row as f32 + 0.5) * CELL,
+            );
+            cells.push((column, row, coverage(&transforms, center)));
+        }
+    }
+
+    commands.spawn(PbrBundle {
+        mesh: meshes.add(floor_mesh(&cells, width, length)),
+        material: materials.add(StandardMaterial {
+            base_color: Color::WHITE,
+            alpha_mode: AlphaMode::Blend,
+            unlit: true,
+            double_sided: true,
+            cull_mode: None,
+            ..default()
+        }),
+        ..default()
+    });
+
+    let share = |kind: Coverage| {
+        let count = cells.iter().filter(|(_, _, c)| *c == kind).count();
+        100.0 * count as f32 / cells.len().max(1) as f32
+    };
+    let summary = format!(
+        "{} sensors, at {:.1} m height\nblind {:.0}%  single {:.0}%  overlap {:.0}%  interference {:.0}%",
+        transforms.len(),
+        plan.height,
+        share(Coverage::Blind),
+        share(Coverage::Covered),
+        share(Coverage::Overlap),
+        share(Coverage::Interference),
+    );
+    info!("Room plan: {}", summary.replace('\n', ", "));
+
+    commands.spawn((
+        TextBundle::from_section(
+            summary,
+            TextStyle {
+                font: asset_server.load("fonts/Hack-Regular.ttf"),
+                font_size: 14.0,
+                color: Color::WHITE,
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                top: Val::Px(8.0),
+                left: Val::Px(8.0),
+                ..default()
+            },
+            ..default()
+        }),
+        PlanSummary,
+        DebugUi,
+    ));
+}
+
+/// How a point is seen, from the rays of every sensor that has it in view.
+fn coverage(sensors: &[Transform], point: Vec3) -> Coverage {
+    let rays: Vec<Vec3> = sensors
+        .iter()
+        .filter(|sensor| sees(sensor, point))
+        .map(|sensor| (point - sensor.translation).normalize())
+        .collect();
+    match rays.len() {
+        0 => Coverage::Blind,
+        1 => Coverage::Covered,
+        _ => {
+            let limit = INTERFERENCE_ANGLE.to_radians();
+            let interfere = rays
+                .iter()
+                .enumerate()
+                .any(|(i, a)| rays[i + 1..].iter().any(|b| a.angle_between(*b) < limit));
+            if interfere {
+                Coverage::Interference
+            } else {
+                Coverage::Overlap
+            }
+        }
+    }
+}
+
+/// Whether a world point is inside a sensor's frustum.
+fn sees(sensor: &Transform, point: Vec3) -> bool {
+    seen_at(sensor, point).is_some()
+}
+
+/// The depth pixel a sensor sees a world point at, if it is in view.
+fn seen_at(sensor: &Transform, point: Vec3) -> Option<Vec2> {
+    let local = sensor.compute_matrix().inverse().transform_point3(point);
+    // the sensor entity looks along -z, sensor space along +z
+    let point = Vec3::new(local.x, local.y, -local.z);
+    if point.z < MIN_RANGE || point.z > MAX_RANGE {
+        return None;
+    }
+    let pixel = DEPTH_INTRINSICS.project(point);
+    let inside = pixel.x >= 0.0
+        && pixel.y >= 0.0
+        && pixel.x < DEPTH_WIDTH as f32
+        && pixel.y < DEPTH_HEIGHT as f32;
+    inside.then_some(pixel)
+}
+
+/// One colored quad per cell, on the floor.
+fn floor_mesh(cells: &[(usize, usize, Coverage)], width: f32, length: f32) -> Mesh {
+    let mut positions = Vec::with_capacity(cells.len() * 4);
+    let mut colors = Vec::with_capacity(cells.len() * 4);
+    let mut indices = Vec::with_capacity(cells.len() * 6);
+    for (column, row, coverage) in cells {
+        let (x, z) = (*column as f32 * CELL, *row as f32 * CELL);
+        let (x1, z1) = ((x + CELL).min(width), (z + CELL).min(length));
+        let first = positions.len() as u32;
+        positions.extend([[x, 0.0, z], [x1, 0.0, z], [x1, 0.0, z1], [x, 0.0, z1]]);
+        colors.extend([coverage.color().as_rgba_f32(); 4]);
+        indices.extend([first, first + 2, first + 1, first, first + 3, first + 2]);
+    }
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(
+        Mesh::ATTRIBUTE_NORMAL,
+        vec![[0.0, 1.0, 0.0]; positions.len()],
+    );
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+    mesh.set_indices(Some(Indices::U32(indices)));
+    mesh
+}
+
+/// Puts the coverage camera above a corner of the room, looking across it.
+fn frame_plan(plan: Res<RoomPlan>, mut camera_query: Query<&mut Transform, Added<SceneCamera>>) {
+    let center = Vec3::new(plan.room[0] / 2.0, 0.0, plan.room[1] / 2.0);
+    let size = plan.room[0].max(plan.room[1]);
+    for mut transform in camera_query.iter_mut() {
+        *transform = Transform::from_translation(center + Vec3::new(0.6, 1.0, 0.9) * size)
+            .looking_at(center, Vec3::Y);
+    }
+}
+
+/// Where the cursor's ray meets the floor, with the pixels of the sensors
+/// that see that spot at the plan's height. Over the floor it wins against
+/// the live depth the frustum view casts against.
+fn hover_floor(
+    windows: Res<Windows>,
+    plan: Res<RoomPlan>,
+    camera_query: Query<(&Camera, &GlobalTransform), With<SceneCamera>>,
+    mut hover: ResMut<SceneHover>,
+) {
+    let ray = match camera_query.get_single() {
+        Ok((camera, transform)) => frustum::cursor_ray(windows.primary(), camera, transform),
+        Err(_) => return,
+    };
+    let point = match ray {
+        Some(ray) if ray.origin.y > 0.0 && ray.direction.y < 0.0 => {
+            ray.origin - ray.direction * (ray.origin.y / ray.direction.y)
+        }
+        _ => return,
+    };
+    if !(0.0..=plan.room[0]).contains(&point.x) || !(0.0..=plan.room[1]).contains(&point.z) {
+        return;
+    }
+    let at_height = Vec3::new(point.x, plan.height, point.z);
+    let pixels = plan
+        .transforms()
+        .iter()
+        .enumerate()
+        .filter_map(|(i, sensor)| Some((i, seen_at(sensor, at_height)?)))
+        .collect();
+    let hit = Some(SceneHit::Floor { point, pixels });
+    if hover.hit != hit {
+        hover.hit = hit;
+    }
+}
+
+fn show_plan_summary(
+    settings: Res<FrustumSettings>,
+    presentation: Res<PresentationSettings>,
+    mut summary_query: Query<&mut Visibility, With<PlanSummary>>,
+) {
+    for mut visibility in summary_query.iter_mut() {
+        visibility.is_visible = settings.visible && !presentation.enabled;
+    }
+}
@@ -0,0 +1,269 @@
+//! Depth recorded by other tools, played back as a sensor. Depth cameras and
+//! their SDKs commonly trade depth sequences as 16-bit grayscale video, each
+//! pixel a reading, usually millimeters; [`PlaybackBackend`] decodes one
+//! through `ffmpeg` and hands its frames on like a Kinect would, so the
+//! whole pipeline runs on it. Needs the `playback` feature and `ffmpeg` on
+//! the `PATH`.
+//!
+//! Only lossless 16-bit formats keep the readings: FFV1 in MKV or AVI,
+//! 16-bit PNG or TIFF sequences, and the like. 8-bit and lossy video
+//! decodes too, but its gray levels aren't distances any more. Frames are
+//! scaled to 640x480 with the nearest pixel, so no reading is blended with
+//! its neighbours, and played at 30 fps, looping by default. There is no
+//! video stream, tilt or LED.
+
+use std::ffi::OsString;
+use std::io::Read;
+use std::path::PathBuf;
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::backend::{DepthCameraBackend, StreamHealth};
+use crate::consumer::FrameConsumers;
+use crate::coords::{DEPTH_HEIGHT, DEPTH_WIDTH};
+use crate::device::{DepthFrame, KinectError, VideoFrame};
+use crate::motor::{Led, MotorState};
+use crate::video::VideoSettings;
+use crate::DepthFormat;
+
+/// Bytes of one decoded frame, little endian `u16` readings.
+const FRAME_BYTES: usize = DEPTH_WIDTH * DEPTH_HEIGHT * 2;
+
+pub struct PlaybackBackend {
+    path: PathBuf,
+    units: DepthFormat,
+    looping: bool,
+    consumers: FrameConsumers,
+    received: Arc<Mutex<Received>>,
+    player: Option<Player>,
+    starts: u64,
+}
+
+impl PlaybackBackend {
+    /// `units` are what the file's gray levels are, usually
+    /// [`DepthFormat::Millimeters`].
+    pub fn new(path: impl Into<PathBuf>, units: DepthFormat) -> PlaybackBackend {
+        PlaybackBackend {
+            path: path.into(),
+            units,
+            looping: true,
+            consumers: FrameConsumers::default(),
+            received: Arc::default(),
+            player: None,
+            starts: 0,
+        }
+    }
+
+    /// Whether the file starts over at its end, instead of ending like an
+    /// unplugged device.
+    pub fn looping(mut self, looping: bool) -> PlaybackBackend {
+        self.looping = looping;
+        self
+    }
+
+    fn ffmpeg_args(&self) -> Vec<OsString> {
+        let mut args: Vec<OsString> = vec!["-loglevel".into(), "error".into(), "-nostdin".into()];
+        if self.looping {
+            args.extend(["-stream_loop".into(), "-1".into()]);
+        }
+        // at the file's own pace, not as fast as it decodes
+        args.extend(["-re".into(), "-i".into(), self.path.clone().into()]);
+        let scale = format!("scale={DEPTH_WIDTH}:{DEPTH_HEIGHT}:flags=neighbor");
+        for arg in ["-an", "-vf", &scale, "-r", "30"] {
+            args.push(arg.into());
+        }
+        for arg in ["-f", "rawvideo", "-pix_fmt", "gray16le", "-"] {
+            args.push(arg.into());
+        }
+        args
+    }
+}
+
+/// The latest frame, filled in by the reading thread.
+#[derive(Default)]
+struct Received {
+    depth: Option<DepthFrame>,
+    frames: u64,
+    /// the end of the file, or ffmpeg not starting
+    exit: Option<Result<(), KinectError>>,
+}
+
+struct Player {
+    ffmpeg: Child,
+    thread: JoinHandle<()>,
+}
+
+/// A decoded frame of readings in `units` as Bit10.
+fn frame_to_bit10(bytes: &[u8], units: DepthFormat) -> Vec<u16> {
+    bytes
+        .chunks_exact(2)
+        .map(|bytes| units.to_bit10(u16::from_le_bytes([bytes[0], bytes[1]])))
+        .collect()
+}
+
+fn read(
+    mut stdout: ChildStdout,
+    units: DepthFormat,
+    consumers: FrameConsumers,
+    received: Arc<Mutex<Received>>,
+) {
+    let mut frame = vec![0; FRAME_BYTES];
+    while stdout.read_exact(&mut frame).is_ok() {
+        let depth = frame_to_bit10(&frame, units);
+        consumers.depth(&depth);
+        let raw = frame
+            .chunks_exact(2)
+            .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+            .collect();
+        let mut received = received.lock().unwrap();
+        received.frames += 1;
+        let depth = DepthFrame::new(depth, received.frames).with_raw(units, raw);
+        received.depth = Some(depth);
+    }
+    received.lock().unwrap().exit = Some(Ok(()));
+}
+
+impl DepthCameraBackend for PlaybackBackend {
+    fn open(&mut self) {
+        if self.player.is_some() {
+            return;
+        }
+        let spawned = Command::new("ffmpeg")
+            .args(self.ffmpeg_args())
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn();
+        let mut ffmpeg = match spawned {
+            Ok(ffmpeg) => ffmpeg,
+            Err(err) => {
+                self.received.lock().unwrap().exit = Some(Err(KinectError::Open(format!(
+                    "can't run ffmpeg to play {}: {err}",
+                    self.path.display()
+                ))));
+                return;
+            }
+        };
+        let stdout = ffmpeg.stdout.take().expect("stdout is piped");
+        let (units, consumers) = (self.units, self.consumers.clone());
+        let received = self.received.clone();
+        let thread = thread::Builder::new()
+            .name("playback".into())
+            .spawn(move || read(stdout, units, consumers, received))
+            .expect("Unable to spawn the playback thread");
+        self.player = Some(Player { ffmpeg, thread });
+        self.starts += 1;
+    }
+
+    fn close(&mut self) -> Result<(), KinectError> {
+        if let Some(mut player) = self.player.take() {
+            // ends the read on the thread
+            let _ = player.ffmpeg.kill();
+            let _ = player.ffmpeg.wait();
+            let _ = player.thread.join();
+        }
+        // the end of the file is no news once closed on purpose
+        self.received.lock().unwrap().exit = None;
+        Ok(())
+    }
+
+    fn is_running(&self) -> bool {
+        self.player.is_some()
+    }
+
+    fn take_exit(&mut self) -> Option<Result<(), KinectError>> {
+        let exit = self.received.lock().unwrap().exit.take()?;
+        let mut player = match self.player.take() {
+            Some(player) => player,
+            None => return Some(exit),
+        };
+        let _ = player.thread.join();
+        Some(match player.ffmpeg.wait() {
+            Ok(status) if status.success() => exit,
+            Ok(status) => Err(KinectError::Open(format!(
+                "ffmpeg exited with {status} playing {}",
+                self.path.display()
+            ))),
+            Err(err) => Err(KinectError::Open(format!("ffmpeg: {err}"))),
+        })
+    }
+
+    fn configure(&mut self, _video: VideoSettings) {}
+
+    fn set_consumers(&mut self, consumers: FrameConsumers) {
+        self.consumers = consumers;
+    }
+
+    fn next_frame(&mut self) -> Option<DepthFrame> {
+        self.received.lock().unwrap().depth.take()
+    }
+
+    fn next_video_frame(&mut self) -> Option<VideoFrame> {
+        None
+    }
+
+    fn depth_frames_received(&self) -> u64 {
+        self.received.lock().unwrap().frames
+    }
+
+    fn video_frames_received(&self) -> u64 {
+        0
+    }
+
+    fn streams_video(&self) -> bool {
+        false
+    }
+
+    fn health(&self) -> StreamHealth {
+        StreamHealth {
+            depth_frames: self.depth_frames_received(),
+            restarts: self.starts.saturating_sub(1),
+            ..StreamHealth::default()
+        }
+    }
+
+    fn tilt(&mut self, _degrees: f64) -> Result<(), KinectError> {
+        Err(KinectError::Unsupported("tilting a recording"))
+    }
+
+    fn tilt_state(&mut self) -> Result<MotorState, KinectError> {
+        Err(KinectError::Unsupported("tilt state of a recording"))
+    }
+
+    fn led(&mut self, _led: Led) -> Result<(), KinectError> {
+        Err(KinectError::Unsupported("the LED of a recording"))
+    }
+}
+
+impl Drop for PlaybackBackend {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NO_DEPTH;
+
+    #[test]
+    fn gray_levels_become_readings() {
+        // no reading, 1 m, and past the end of the raw scale
+        let mut bytes = Vec::new();
+        for reading in [0u16, 1000, 2000] {
+            bytes.extend_from_slice(&reading.to_le_bytes());
+        }
+        let depth = frame_to_bit10(&bytes, DepthFormat::Millimeters);
+        assert_eq!(depth[0], NO_DEPTH);
+        assert_eq!(depth[1], DepthFormat::Millimeters.to_bit10(1000));
+        assert_eq!(frame_to_bit10(&bytes, DepthFormat::Bit10)[2], NO_DEPTH);
+
+        let backend = PlaybackBackend::new("depth.mkv", DepthFormat::Millimeters);
+        let args = backend.ffmpeg_args();
+        assert!(args.contains(&"-stream_loop".into()));
+        assert!(!backend
+            .looping(false)
+            .ffmpeg_args()
+            .contains(&"-stream_loop".into()));
+    }
+}
@@ -0,0 +1,131 @@
+//! Two players side by side, for local multiplayer. With `--two-players` the
+//! view is split down the middle and each half tracks its own close blob, so
+//! each player gets a pointer and gestures of their own:
+//!
+//! * [`Players`] has where each one's hand is, in depth pixels and within
+//!   their half
+//! * [`PlayerGesture`] is a [`HandGesture`] by one of them
+//!
+//! Players face the sensor, so [`Player::One`], on their left, is in the
+//! right half of the depth picture. The single close blob, the crosshair and
+//! touchless menus carry on as before.
+
+use bevy::prelude::*;
+
+use crate::coords::{DEPTH_HEIGHT, DEPTH_WIDTH};
+use crate::pipeline::{self, DepthPipelineApp, DepthStage};
+use crate::touchless::{GestureTracker, HandGesture};
+use crate::{CurrentDepth, MainKinect, TrackingSettings, NO_DEPTH};
+
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct PlayerSettings {
+    pub two_players: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Player {
+    One,
+    Two,
+}
+
+impl Player {
+    pub const BOTH: [Player; 2] = [Player::One, Player::Two];
+
+    /// Depth picture columns the player is tracked in.
+    fn columns(self) -> std::ops::Range<usize> {
+        match self {
+            Player::One => DEPTH_WIDTH / 2..DEPTH_WIDTH,
+            Player::Two => 0..DEPTH_WIDTH / 2,
+        }
+    }
+
+    fn index(self) -> usize {
+        match self {
+            Player::One => 0,
+            Player::Two => 1,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PlayerHand {
+    /// close blob center in depth pixels, `None` while nothing is near
+    pub pixel: Option<Vec2>,
+    /// the same within the player's half, 0..1 from its top left
+    pub in_half: Option<Vec2>,
+}
+
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct Players {
+    pub hands: [PlayerHand; 2],
+}
+
+impl Players {
+    pub fn hand(&self, player: Player) -> PlayerHand {
+        self.hands[player.index()]
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PlayerGesture {
+    pub player: Player,
+    pub gesture: HandGesture,
+}
+
+pub struct PlayerPlugin;
+
+impl Plugin for PlayerPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PlayerSettings>()
+            .init_resource::<Players>()
+            .add_event::<PlayerGesture>()
+            .add_depth_stage(DepthStage::consumer(pipeline::TrackPlayers, track_players));
+    }
+}
+
+fn track_players(
+    depth_query: Query<&CurrentDepth, (With<MainKinect>, Changed<CurrentDepth>)>,
+    settings: Res<PlayerSettings>,
+    tracking: Res<TrackingSettings>,
+    mut players: ResMut<Players>,
+    mut gestures: EventWriter<PlayerGesture>,
+    mut trackers: Local<[GestureTracker; 2]>,
+) {
+    if !settings.two_players {
+        return;
+    }
+    let depth = match depth_query.get_single() {
+        Ok(depth) if depth.depth_array.len() == DEPTH_WIDTH * DEPTH_HEIGHT => depth,
+        _ => return,
+    };
+    for player in Player::BOTH {
+        let columns = player.columns();
+        // the other half as if nothing were there
+        let half: Vec<u16> = depth
+            .depth_array
+            .iter()
+            .enumerate()
+            .map(|(i, raw)| {
+                if columns.contains(&(i % DEPTH_WIDTH)) {
+                    *raw
+                } else {
+                    NO_DEPTH
+                }
+            })
+            .collect();
+        let pixel = crate::close_blob_bounds(&half, DEPTH_WIDTH, tracking.threshold)
+            .map(|bounds| bounds.center());
+        players.hands[player.index()] = PlayerHand {
+            pixel,
+            in_half: pixel.map(|pixel| {
+                Vec2::new(
+                    (pixel.x - columns.start as f32) / (DEPTH_WIDTH / 2) as f32,
+                    pixel.y / DEPTH_HEIGHT as f32,
+                )
+            }),
+        };
+        if let Some(gesture) = trackers[player.index()].update(depth, pixel) {
+            gestures.send(PlayerGesture { player, gesture });
+        }
+    }
+}
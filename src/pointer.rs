@@ -0,0 +1,324 @@
+//! How the crosshair feels. The close blob center wobbles by a pixel or two
+//! on a still hand and lags a frame behind a moving one; smoothing, a dead
+//! zone and extrapolation trade those against each other, and a
+//! [`PointerProfile`] picks all three at once:
+//!
+//! * `precise`: some smoothing and a small dead zone, for aiming at small
+//!   targets up close
+//! * `responsive`: no smoothing, extrapolated, for games
+//! * `stage-stable`: heavy smoothing and a wide dead zone, for people a few
+//!   meters away in front of a big screen
+//!
+//! `--pointer <profile>` picks one and O cycles through them. Without one the
+//! crosshair follows the blob as [`TrackingSettings`] says.
+//!
+//! The crosshair and the touchless cursor then go through the interaction
+//! box, the frame less [`PointerSettings::edge_margin`] on each side,
+//! stretched over the whole view.
+//!
+//! F8 fits the box to whoever is in front instead: for [`REACH_SECONDS`]
+//! they move their hand around as far as is comfortable, and the range it
+//! covered becomes [`PointerSettings::reach`]. A child and a tall adult
+//! both reach the whole screen that way. It is forgotten once nobody has
+//! been seen for [`REACH_FORGET`] seconds, so the next visitor starts over.
+
+use std::str::FromStr;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::coords::VIEW_SIZE;
+use crate::{BlobMotion, TrackingSettings};
+
+/// Seconds the reach is measured over.
+pub const REACH_SECONDS: f64 = 5.0;
+/// Seconds without anyone near before the reach is forgotten.
+pub const REACH_FORGET: f64 = 3.0;
+/// Share of the hand positions left out at each end of the reach, the
+/// stretches nobody would hold for long.
+const REACH_TRIM: f32 = 0.05;
+/// Smallest reach that is kept, in depth pixels each way, anything less was
+/// a hand held still.
+const MIN_REACH: f32 = 60.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PointerProfile {
+    Precise,
+    Responsive,
+    StageStable,
+}
+
+impl PointerProfile {
+    /// smoothing time constant in seconds, dead zone in depth pixels, and
+    /// whether to extrapolate
+    pub fn feel(self) -> (f32, f32, bool) {
+        match self {
+            PointerProfile::Precise => (0.08, 2.0, false),
+            PointerProfile::Responsive => (0.0, 1.0, true),
+            PointerProfile::StageStable => (0.25, 6.0, false),
+        }
+    }
+
+    fn next(self) -> PointerProfile {
+        match self {
+            PointerProfile::Precise => PointerProfile::Responsive,
+            PointerProfile::Responsive => PointerProfile::StageStable,
+            PointerProfile::StageStable => PointerProfile::Precise,
+        }
+    }
+}
+
+impl FromStr for PointerProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "precise" => Ok(PointerProfile::Precise),
+            "responsive" => Ok(PointerProfile::Responsive),
+            "stage-stable" => Ok(PointerProfile::StageStable),
+            _ => Err(format!(
+                "unknown pointer profile '{s}', expected precise, responsive or stage-stable"
+            )),
+        }
+    }
+}
+
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct PointerSettings {
+    /// smoothing and dead zone unless set below, and sets
+    /// [`TrackingSettings::extrapolate`] when it changes
+    pub profile: Option<PointerProfile>,
+    /// time constant of the smoothing, seconds, 0 for none
+    pub smoothing: Option<f32>,
+    /// how far the blob moves before the crosshair follows, depth pixels
+    pub dead_zone: Option<f32>,
+    /// share of the frame on each side outside the interaction box, 0..0.5;
+    /// the box is stretched over the whole view, so reaching its edges is
+    /// enough to hit the corners of the screen
+    pub edge_margin: f32,
+    /// interaction box fitted to the current user, depth pixels; replaces
+    /// the edge margin while set
+    pub reach: Option<Rect>,
+}
+
+impl PointerSettings {
+    pub fn smoothing(&self) -> f32 {
+        let profile = self.profile.map(|profile| profile.feel().0);
+        self.smoothing.or(profile).unwrap_or(0.0)
+    }
+
+    pub fn dead_zone(&self) -> f32 {
+        let profile = self.profile.map(|profile| profile.feel().1);
+        self.dead_zone.or(profile).unwrap_or(0.0)
+    }
+
+    /// The interaction box in depth pixels.
+    pub fn interaction_box(&self) -> Rect {
+        match self.reach {
+            Some(reach) => reach,
+            None => {
+                let margin = VIEW_SIZE * self.edge_margin.clamp(0.0, 0.49);
+                Rect::from_corners(margin, VIEW_SIZE - margin)
+            }
+        }
+    }
+
+    /// A depth pixel in the interaction box to the view it is stretched over.
+    pub fn map_to_view(&self, pixel: Vec2) -> Vec2 {
+        let bounds = self.interaction_box();
+        ((pixel - bounds.min) / bounds.size() * VIEW_SIZE).clamp(Vec2::ZERO, VIEW_SIZE)
+    }
+}
+
+/// The box the hand positions of a calibration covered, less the trimmed
+/// ends, `None` if they barely moved.
+pub fn reach_box(points: &[Vec2]) -> Option<Rect> {
+    if points.is_empty() {
+        return None;
+    }
+    let range = |axis: fn(&Vec2) -> f32| {
+        let mut values: Vec<f32> = points.iter().map(axis).collect();
+        values.sort_by(f32::total_cmp);
+        let trim = (values.len() as f32 * REACH_TRIM) as usize;
+        (values[trim], values[values.len() - 1 - trim])
+    };
+    let ((left, right), (top, bottom)) = (range(|p| p.x), range(|p| p.y));
+    let reach = Rect::new(left, top, right, bottom);
+    (reach.width() >= MIN_REACH && reach.height() >= MIN_REACH).then_some(reach)
+}
+
+/// Hand positions of a running reach calibration.
+#[derive(Default)]
+pub(crate) struct ReachCalibration {
+    until: Option<f64>,
+    points: Vec<Vec2>,
+    last_seen: f64,
+}
+
+/// Where the crosshair was left, filtered from where the blob is shown.
+#[derive(Default)]
+pub(crate) struct PointerFilter {
+    position: Option<Vec2>,
+}
+
+impl PointerFilter {
+    pub(crate) fn update(
+        &mut self,
+        target: Option<Vec2>,
+        seconds: f32,
+        settings: &PointerSettings,
+    ) -> Option<Vec2> {
+        let target = match target {
+            Some(target) => target,
+            None => {
+                self.position = None;
+                return None;
+            }
+        };
+        let position = match self.position {
+            Some(position) => position,
+            None => {
+                self.position = Some(target);
+                return self.position;
+            }
+        };
+        // only the part of the step past the dead zone counts, so the
+        // crosshair doesn't jump when it starts moving
+        let (dead_zone, smoothing) = (settings.dead_zone(), settings.smoothing());
+        let offset = target - position;
+        let distance = offset.length();
+        if distance <= dead_zone {
+            return self.position;
+        }
+        let target = position + offset * (1.0 - dead_zone / distance);
+        let follow = if smoothing > 0.0 {
+            1.0 - (-seconds / smoothing).exp()
+        } else {
+            1.0
+        };
+        self.position = Some(position.lerp(target, follow));
+        self.position
+    }
+}
+
+pub(crate) fn cycle_profile(keys: Res<Input<KeyCode>>, mut settings: ResMut<PointerSettings>) {
+    if keys.just_pressed(KeyCode::O) {
+        let profile = settings
+            .profile
+            .map_or(PointerProfile::Precise, PointerProfile::next);
+        info!("pointer profile {profile:?}");
+        settings.profile = Some(profile);
+    }
+}
+
+pub(crate) fn calibrate_reach(
+    keys: Res<Input<KeyCode>>,
+    time: Res<Time>,
+    motion: Res<BlobMotion>,
+    mut settings: ResMut<PointerSettings>,
+    mut calibration: Local<ReachCalibration>,
+) {
+    let now = time.elapsed_seconds_f64();
+    if keys.just_pressed(KeyCode::F8) {
+        info!("reach calibration: move your hand as far around as is comfortable");
+        *calibration = ReachCalibration {
+            until: Some(now + REACH_SECONDS),
+            points: Vec::new(),
+            last_seen: now,
+        };
+    }
+    if let Some(shown) = motion.shown {
+        calibration.last_seen = now;
+        if calibration.until.is_some() {
+            calibration.points.push(shown);
+        }
+    }
+
+    match calibration.until {
+        Some(until) if now >= until => {
+            calibration.until = None;
+            match reach_box(&calibration.points) {
+                Some(reach) => {
+                    info!("reach {:.0} to {:.0}", reach.min, reach.max);
+                    settings.reach = Some(reach);
+                }
+                None => warn!("reach calibration: the hand hardly moved, keeping the old box"),
+            }
+        }
+        None if settings.reach.is_some() && now - calibration.last_seen > REACH_FORGET => {
+            info!("nobody near, reach forgotten");
+            settings.reach = None;
+        }
+        _ => {}
+    }
+}
+
+pub(crate) fn apply_profile(
+    settings: Res<PointerSettings>,
+    mut tracking: ResMut<TrackingSettings>,
+    mut applied: Local<Option<PointerProfile>>,
+) {
+    if settings.profile == *applied {
+        return;
+    }
+    *applied = settings.profile;
+    if let Some(profile) = settings.profile {
+        tracking.extrapolate = profile.feel().2;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dead_zone_holds_the_crosshair_and_smoothing_eases_it() {
+        let settings = PointerSettings {
+            smoothing: Some(0.1),
+            dead_zone: Some(2.0),
+            ..default()
+        };
+        let mut filter = PointerFilter::default();
+        let start = Vec2::new(100.0, 100.0);
+        assert_eq!(filter.update(Some(start), 0.016, &settings), Some(start));
+        let wobble = Vec2::new(101.5, 99.0);
+        assert_eq!(filter.update(Some(wobble), 0.016, &settings), Some(start));
+
+        let far = Vec2::new(120.0, 100.0);
+        let eased = filter.update(Some(far), 0.016, &settings).unwrap();
+        assert!(eased.x > start.x && eased.x < far.x - 2.0, "{eased}");
+        let mut settled = eased;
+        for _ in 0..120 {
+            settled = filter.update(Some(far), 0.016, &settings).unwrap();
+        }
+        assert!((settled.x - (far.x - 2.0)).abs() < 0.01, "{settled}");
+        assert_eq!(filter.update(None, 0.016, &settings), None);
+    }
+
+    #[test]
+    fn the_interaction_box_reaches_the_corners() {
+        let settings = PointerSettings {
+            edge_margin: 0.1,
+            ..default()
+        };
+        assert_eq!(settings.map_to_view(Vec2::new(64.0, 48.0)), Vec2::ZERO);
+        assert_eq!(settings.map_to_view(Vec2::new(600.0, 460.0)), VIEW_SIZE);
+        assert_eq!(settings.map_to_view(VIEW_SIZE / 2.0), VIEW_SIZE / 2.0);
+
+        // a short reach, mostly to the left, with a stray point far out
+        let mut points: Vec<Vec2> = (0..=100)
+            .map(|i| Vec2::new(200.0 + i as f32, 150.0 + i as f32 * 1.5))
+            .collect();
+        points.push(Vec2::new(630.0, 470.0));
+        let reach = reach_box(&points).unwrap();
+        assert!(reach.max.x < 320.0, "{reach:?}");
+        let settings = PointerSettings {
+            reach: Some(reach),
+            ..settings
+        };
+        assert_eq!(settings.map_to_view(reach.min), Vec2::ZERO);
+        assert_eq!(settings.map_to_view(reach.max), VIEW_SIZE);
+        assert_eq!(reach_box(&[Vec2::splat(300.0); 50]), None);
+    }
+}
@@ -0,0 +1,199 @@
+//! Pose matching for dance and exercise games. A [`PoseTemplate`] is a set
+//! of target [`JointAngles`], and every template gets a score from 0 to 1 for
+//! how close the tracked person is to it, each angle weighted by how sure the
+//! skeleton is about it. Holding every angle within tolerance for long
+//! enough sends a [`PoseMatched`].
+//!
+//! Templates come from [`PoseAppExt::add_pose`] or a JSON file:
+//!
+//! ```json
+//! [
+//!     {
+//!         "name": "squat",
+//!         "angles": { "knee_left": 90, "knee_right": 90, "torso_lean": 30 },
+//!         "tolerance": 25
+//!     }
+//! ]
+//! ```
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::angles::{BodyAngle, JointAngles};
+
+#[derive(Resource, Clone, Debug, Default)]
+pub struct PoseSettings {
+    pub file: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct PoseTemplate {
+    pub name: String,
+    /// target of each angle that matters, in degrees
+    pub angles: HashMap<BodyAngle, f32>,
+    /// how far off an angle can be and still match, in degrees
+    #[serde(default = "default_tolerance")]
+    pub tolerance: f32,
+    /// seconds to hold the pose before it counts
+    #[serde(default = "default_hold")]
+    pub hold: f32,
+}
+
+fn default_tolerance() -> f32 {
+    20.0
+}
+
+fn default_hold() -> f32 {
+    0.5
+}
+
+impl PoseTemplate {
+    pub fn new(name: &str, angles: &[(BodyAngle, f32)]) -> PoseTemplate {
+        PoseTemplate {
+            name: name.to_string(),
+            angles: angles.iter().copied().collect(),
+            tolerance: default_tolerance(),
+            hold: default_hold(),
+        }
+    }
+
+    /// Similarity from 0 to 1, and whether every angle is within tolerance.
+    fn score(&self, angles: &JointAngles) -> (f32, bool) {
+        let (mut sum, mut weights, mut within) = (0.0, 0.0, true);
+        for (angle, target) in &self.angles {
+            match angles.get(*angle) {
+                Some(measured) => {
+                    let error = (measured.degrees - target).abs();
+                    within &= error <= self.tolerance;
+                    // a tolerance off still scores about 0.6
+                    let similarity = (-0.5 * (error / self.tolerance).powi(2)).exp();
+                    sum += similarity * measured.confidence;
+                    weights += measured.confidence;
+                }
+                // not seen counts as not matching
+                None => {
+                    within = false;
+                    weights += 1.0;
+                }
+            }
+        }
+        if weights == 0.0 {
+            return (0.0, false);
+        }
+        (sum / weights, within)
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct Poses(pub Vec<PoseTemplate>);
+
+/// Latest score of every template by name, 0 while nobody is tracked.
+#[derive(Resource, Clone, Debug, Default)]
+pub struct PoseScores(pub HashMap<String, f32>);
+
+/// Sent once every time a template has been held, it has to be left before it
+/// matches again.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PoseMatched {
+    pub name: String,
+    pub score: f32,
+}
+
+pub trait PoseAppExt {
+    fn add_pose(&mut self, template: PoseTemplate) -> &mut Self;
+}
+
+impl PoseAppExt for App {
+    fn add_pose(&mut self, template: PoseTemplate) -> &mut Self {
+        self.world
+            .get_resource_or_insert_with(Poses::default)
+            .0
+            .push(template);
+        self
+    }
+}
+
+pub struct PosePlugin;
+
+impl Plugin for PosePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PoseSettings>()
+            .init_resource::<Poses>()
+            .init_resource::<PoseScores>()
+            .add_event::<PoseMatched>()
+            .add_pose(PoseTemplate::new(
+                "hands_up",
+                &[
+                    (BodyAngle::ShoulderLeft, 170.0),
+                    (BodyAngle::ShoulderRight, 170.0),
+                ],
+            ))
+            .add_pose(PoseTemplate::new(
+                "t_pose",
+                &[
+                    (BodyAngle::ShoulderLeft, 90.0),
+                    (BodyAngle::ShoulderRight, 90.0),
+                    (BodyAngle::ElbowLeft, 0.0),
+                    (BodyAngle::ElbowRight, 0.0),
+                ],
+            ))
+            .add_system(match_poses)
+            .add_system(log_pose_matches.after(match_poses));
+
+        if let Some(path) = app.world.resource::<PoseSettings>().file.clone() {
+            for template in load(&path).unwrap_or_else(|err| panic!("{err}")) {
+                app.add_pose(template);
+            }
+        }
+    }
+}
+
+fn load(path: &Path) -> Result<Vec<PoseTemplate>, String> {
+    let json = fs::read_to_string(path)
+        .map_err(|err| format!("can't read poses {}: {err}", path.display()))?;
+    serde_json::from_str(&json).map_err(|err| format!("bad poses {}: {err}", path.display()))
+}
+
+fn match_poses(
+    angles: Res<JointAngles>,
+    poses: Res<Poses>,
+    time: Res<Time>,
+    mut scores: ResMut<PoseScores>,
+    mut matched: EventWriter<PoseMatched>,
+    // since when each pose has been within tolerance, `None` once matched
+    mut held: Local<HashMap<String, Option<f64>>>,
+) {
+    if !angles.is_changed() {
+        return;
+    }
+    let now = time.elapsed_seconds_f64();
+    for template in &poses.0 {
+        let (score, within) = template.score(&angles);
+        scores.0.insert(template.name.clone(), score);
+
+        if !within {
+            held.remove(&template.name);
+            continue;
+        }
+        let since = held.entry(template.name.clone()).or_insert(Some(now));
+        if let Some(start) = *since {
+            if now - start >= template.hold as f64 {
+                matched.send(PoseMatched {
+                    name: template.name.clone(),
+                    score,
+                });
+                *since = None;
+            }
+        }
+    }
+}
+
+fn log_pose_matches(mut matched: EventReader<PoseMatched>) {
+    for pose in matched.iter() {
+        info!("Pose '{}' matched, score {:.2}", pose.name, pose.score);
+    }
+}
@@ -0,0 +1,271 @@
+//! Projector-ready output for installations. The window goes borderless
+//! fullscreen on the chosen monitor and the [`Canvas`] is drawn corner-pinned
+//! onto it, so the picture can be keystoned to whatever surface the
+//! projector hits. Debug UI is hidden and keyboard and mouse are ignored;
+//! Ctrl+Alt+Q still quits.
+//!
+//! Whenever there is a canvas, this also puts it back on the primary window.
+
+use std::str::FromStr;
+
+use bevy::app::AppExit;
+use bevy::input::keyboard::KeyboardInput;
+use bevy::input::{ButtonState, InputSystem};
+use bevy::prelude::*;
+use bevy::render::mesh::Indices;
+use bevy::render::render_resource::PrimitiveTopology;
+use bevy::render::view::{RenderLayers, VisibilitySystems};
+use bevy::sprite::MaterialMesh2dBundle;
+use bevy::window::{MonitorSelection, WindowMode, WindowPosition};
+
+use crate::display::Canvas;
+
+/// Cells per side of the warped output mesh. The warp is projective, so the
+/// quad is subdivided to keep the affine interpolation in each cell close.
+const WARP_GRID: u32 = 16;
+
+/// Render layer of the output quad, away from everything else.
+pub const OUTPUT_LAYER: u8 = 1;
+
+/// Where the corners of the picture land, as fractions of the screen with
+/// the origin top left: top left, top right, bottom right, bottom left.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Corners(pub [Vec2; 4]);
+
+impl Default for Corners {
+    fn default() -> Self {
+        Corners([
+            Vec2::new(0.0, 0.0),
+            Vec2::new(1.0, 0.0),
+            Vec2::new(1.0, 1.0),
+            Vec2::new(0.0, 1.0),
+        ])
+    }
+}
+
+impl FromStr for Corners {
+    type Err = String;
+
+    /// Eight comma separated numbers, `x,y` for each corner in order.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s
+            .split(',')
+            .map(|v| v.trim().parse::<f32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| format!("invalid corners '{s}': {err}"))?;
+        if values.len() != 8 {
+            return Err(format!(
+                "expected 8 values for the corners, got {}",
+                values.len()
+            ));
+        }
+        let mut corners = [Vec2::ZERO; 4];
+        for (corner, xy) in corners.iter_mut().zip(values.chunks(2)) {
+            *corner = Vec2::new(xy[0], xy[1]);
+        }
+        Ok(Corners(corners))
+    }
+}
+
+impl Corners {
+    /// Maps a point of the unit square (y down) to the pinned quad, see
+    /// Heckbert, "Fundamentals of Texture Mapping and Image Warping".
+    fn warp(&self, uv: Vec2) -> Vec2 {
+        let [p0, p1, p2, p3] = self.0;
+        let d1 = p1 - p2;
+        let d2 = p3 - p2;
+        let d3 = p0 - p1 + p2 - p3;
+        let den = d1.x * d2.y - d2.x * d1.y;
+        let (g, h) = if den.abs() < f32::EPSILON {
+            (0.0, 0.0)
+        } else {
+            (
+                (d3.x * d2.y - d2.x * d3.y) / den,
+                (d1.x * d3.y - d3.x * d1.y) / den,
+            )
+        };
+        let a = p1 - p0 + g * p1;
+        let b = p3 - p0 + h * p3;
+        (a * uv.x + b * uv.y + p0) / (g * uv.x + h * uv.y + 1.0)
+    }
+}
+
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct PresentationSettings {
+    pub enabled: bool,
+    pub monitor: usize,
+    pub corners: Corners,
+}
+
+impl PresentationSettings {
+    pub fn apply(&self, window: &mut WindowDescriptor) {
+        if !self.enabled {
+            return;
+        }
+        window.mode = WindowMode::BorderlessFullscreen;
+        window.monitor = MonitorSelection::Index(self.monitor);
+        window.position = WindowPosition::Centered;
+        window.decorations = false;
+        window.cursor_visible = false;
+    }
+}
+
+/// Development UI that should not end up on the projection.
+#[derive(Component)]
+pub struct DebugUi;
+
+#[derive(Resource)]
+struct PresentationOutput {
+    mesh: Handle<Mesh>,
+    size: Vec2,
+}
+
+pub struct PresentationPlugin;
+
+impl Plugin for PresentationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PresentationSettings>()
+            .add_startup_system_to_stage(StartupStage::PostStartup, setup_presentation)
+            .add_system_to_stage(CoreStage::PreUpdate, lock_input.after(InputSystem))
+            .add_system(fit_presentation_to_window)
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                hide_debug_ui.before(VisibilitySystems::VisibilityPropagate),
+            );
+    }
+}
+
+fn setup_presentation(
+    mut commands: Commands,
+    settings: Res<PresentationSettings>,
+    canvas: Option<Res<Canvas>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let canvas = match canvas {
+        Some(canvas) => canvas,
+        None => return,
+    };
+
+    let size = Vec2::new(640.0, 480.0);
+    let mesh = meshes.add(warped_quad(&settings.corners, size));
+    commands.spawn((
+        MaterialMesh2dBundle {
+            mesh: mesh.clone().into(),
+            material: materials.add(ColorMaterial::from(canvas.image.clone())),
+            ..default()
+        },
+        RenderLayers::layer(OUTPUT_LAYER),
+    ));
+    commands.spawn((
+        Camera2dBundle {
+            camera: Camera {
+                // after the main camera has filled the canvas
+                priority: 1,
+                ..default()
+            },
+            ..default()
+        },
+        UiCameraConfig { show_ui: false },
+        RenderLayers::layer(OUTPUT_LAYER),
+    ));
+    commands.insert_resource(PresentationOutput { mesh, size });
+}
+
+/// Keeps the output quad on its corners as the window changes size.
+fn fit_presentation_to_window(
+    windows: Res<Windows>,
+    settings: Res<PresentationSettings>,
+    output: Option<ResMut<PresentationOutput>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    let (mut output, window) = match (output, windows.get_primary()) {
+        (Some(output), Some(window)) => (output, window),
+        _ => return,
+    };
+    let size = Vec2::new(window.width(), window.height());
+    if output.size == size && !settings.is_changed() {
+        return;
+    }
+    output.size = size;
+    if let Some(mesh) = meshes.get_mut(&output.mesh) {
+        *mesh = warped_quad(&settings.corners, size);
+    }
+}
+
+fn warped_quad(corners: &Corners, screen: Vec2) -> Mesh {
+    let mut positions = Vec::new();
+    let mut uvs = Vec::new();
+    for row in 0..=WARP_GRID {
+        for column in 0..=WARP_GRID {
+            let uv = Vec2::new(column as f32, row as f32) / WARP_GRID as f32;
+            // corners count from the top left, the 2D camera from the center
+            let p = (corners.warp(uv) - Vec2::splat(0.5)) * screen;
+            positions.push([p.x, -p.y, 0.0]);
+            uvs.push([uv.x, uv.y]);
+        }
+    }
+
+    let mut indices = Vec::new();
+    let stride = WARP_GRID + 1;
+    for row in 0..WARP_GRID {
+        for column in 0..WARP_GRID {
+            let i = row * stride + column;
+            indices.extend_from_slice(&[i, i + stride, i + 1, i + 1, i + stride, i + stride + 1]);
+        }
+    }
+
+    let normals = vec![[0.0, 0.0, 1.0]; positions.len()];
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.set_indices(Some(Indices::U32(indices)));
+    mesh
+}
+
+pub fn lock_input(
+    settings: Res<PresentationSettings>,
+    mut keyboard_events: EventReader<KeyboardInput>,
+    // what is actually held, `keys` is wiped every frame
+    mut held: Local<Input<KeyCode>>,
+    mut keys: ResMut<Input<KeyCode>>,
+    mut buttons: ResMut<Input<MouseButton>>,
+    mut exit: EventWriter<AppExit>,
+) {
+    if !settings.enabled {
+        return;
+    }
+
+    held.clear();
+    for event in keyboard_events.iter() {
+        if let Some(key) = event.key_code {
+            match event.state {
+                ButtonState::Pressed => held.press(key),
+                ButtonState::Released => held.release(key),
+            }
+        }
+    }
+    let ctrl = held.any_pressed([KeyCode::LControl, KeyCode::RControl]);
+    let alt = held.any_pressed([KeyCode::LAlt, KeyCode::RAlt]);
+    if ctrl && alt && held.just_pressed(KeyCode::Q) {
+        exit.send(AppExit);
+    }
+
+    keys.reset_all();
+    buttons.reset_all();
+}
+
+fn hide_debug_ui(
+    settings: Res<PresentationSettings>,
+    mut debug_query: Query<&mut Visibility, With<DebugUi>>,
+) {
+    if !settings.enabled {
+        return;
+    }
+    for mut visibility in debug_query.iter_mut() {
+        if visibility.is_visible {
+            visibility.is_visible = false;
+        }
+    }
+}
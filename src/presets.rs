@@ -0,0 +1,246 @@
+//! Named presets of the settings that can change while running, for
+//! switching an installation between tunings like "daytime lobby" and
+//! "evening event" in one go. Presets live in a JSON file, a list in the
+//! order the number keys pick them:
+//!
+//! ```json
+//! [
+//!     { "name": "daytime lobby", "threshold": 450, "view": "shadow" },
+//!     { "name": "evening event", "threshold": 380, "view": "rainbow", "inpaint": true }
+//! ]
+//! ```
+//!
+//! Anything a preset leaves out is the default. 1 to 9 switch to a preset,
+//! Ctrl with a number saves the current settings into it and writes the
+//! file back.
+
+use std::fs;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::attract::AttractSettings;
+use crate::capture::CaptureSettings;
+use crate::coords::FitMode;
+use crate::display::DisplaySettings;
+use crate::inpaint::InpaintSettings;
+use crate::{DepthStyle, TrackingSettings};
+
+const SLOT_KEYS: [KeyCode; 9] = [
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+];
+
+#[derive(Resource, Clone, Debug, Default)]
+pub struct PresetSettings {
+    pub file: Option<PathBuf>,
+    /// preset to start with
+    pub initial: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Preset {
+    pub name: String,
+    pub threshold: u16,
+    pub extrapolate: bool,
+    pub view: DepthStyle,
+    pub inpaint: bool,
+    pub interpolate: bool,
+    pub fit: FitMode,
+    pub attract_after: f32,
+    pub screensaver: bool,
+}
+
+impl Default for Preset {
+    fn default() -> Self {
+        let (tracking, capture, attract) = (
+            TrackingSettings::default(),
+            CaptureSettings::default(),
+            AttractSettings::default(),
+        );
+        Preset {
+            name: String::new(),
+            threshold: tracking.threshold,
+            extrapolate: tracking.extrapolate,
+            view: DepthStyle::default(),
+            inpaint: InpaintSettings::default().enabled,
+            interpolate: capture.interpolate,
+            fit: FitMode::default(),
+            attract_after: attract.timeout,
+            screensaver: attract.screensaver,
+        }
+    }
+}
+
+#[derive(Resource, Debug, Default)]
+pub struct Presets {
+    file: Option<PathBuf>,
+    pub list: Vec<Preset>,
+    /// name of the preset applied last
+    pub active: Option<String>,
+}
+
+impl Presets {
+    fn load(path: &Path) -> Result<Vec<Preset>, String> {
+        let json = fs::read_to_string(path)
+            .map_err(|err| format!("can't read presets {}: {err}", path.display()))?;
+        serde_json::from_str(&json).map_err(|err| format!("bad presets {}: {err}", path.display()))
+    }
+
+    fn save(&self) {
+        let path = match &self.file {
+            Some(path) => path,
+            None => {
+                warn!("No --presets file, the preset is only kept until exit");
+                return;
+            }
+        };
+        let written = serde_json::to_string_pretty(&self.list)
+            .map_err(|err| err.to_string())
+            .and_then(|json| fs::write(path, json).map_err(|err| err.to_string()));
+        if let Err(err) = written {
+            error!("Unable to save presets to {}: {err}", path.display());
+        }
+    }
+}
+
+/// Switch to or save a preset by name, from the keyboard or remote control.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PresetRequest {
+    Apply(String),
+    Save(String),
+}
+
+/// Everything a preset covers.
+#[derive(SystemParam)]
+struct Tuning<'w, 's> {
+    tracking: ResMut<'w, TrackingSettings>,
+    style: ResMut<'w, DepthStyle>,
+    inpaint: ResMut<'w, InpaintSettings>,
+    capture: ResMut<'w, CaptureSettings>,
+    display: ResMut<'w, DisplaySettings>,
+    attract: ResMut<'w, AttractSettings>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
+}
+
+impl<'w, 's> Tuning<'w, 's> {
+    fn current(&self, name: &str) -> Preset {
+        Preset {
+            name: name.to_string(),
+            threshold: self.tracking.threshold,
+            extrapolate: self.tracking.extrapolate,
+            view: *self.style,
+            inpaint: self.inpaint.enabled,
+            interpolate: self.capture.interpolate,
+            fit: self.display.fit,
+            attract_after: self.attract.timeout,
+            screensaver: self.attract.screensaver,
+        }
+    }
+
+    fn apply(&mut self, preset: &Preset) {
+        self.tracking.threshold = preset.threshold;
+        self.tracking.extrapolate = preset.extrapolate;
+        *self.style = preset.view;
+        self.inpaint.enabled = preset.inpaint;
+        self.capture.interpolate = preset.interpolate;
+        self.display.fit = preset.fit;
+        self.attract.timeout = preset.attract_after;
+        self.attract.screensaver = preset.screensaver;
+    }
+}
+
+pub struct PresetPlugin;
+
+impl Plugin for PresetPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PresetSettings>()
+            .add_event::<PresetRequest>()
+            .add_system(preset_keys)
+            .add_system(handle_preset_requests.after(preset_keys));
+
+        let settings = app.world.resource::<PresetSettings>().clone();
+        let list = match &settings.file {
+            Some(path) if path.exists() => {
+                Presets::load(path).unwrap_or_else(|err| panic!("{err}"))
+            }
+            // saving creates it
+            _ => Vec::new(),
+        };
+        app.insert_resource(Presets {
+            file: settings.file,
+            list,
+            active: None,
+        });
+        if let Some(name) = settings.initial {
+            app.world.send_event(PresetRequest::Apply(name));
+        }
+    }
+}
+
+fn preset_keys(
+    keys: Res<Input<KeyCode>>,
+    presets: Res<Presets>,
+    mut requests: EventWriter<PresetRequest>,
+) {
+    let ctrl = keys.any_pressed([KeyCode::LControl, KeyCode::RControl]);
+    for (slot, key) in SLOT_KEYS.iter().enumerate() {
+        if !keys.just_pressed(*key) {
+            continue;
+        }
+        let name = presets.list.get(slot).map(|preset| preset.name.clone());
+        match (ctrl, name) {
+            (true, name) => requests.send(PresetRequest::Save(
+                name.unwrap_or_else(|| format!("preset {}", slot + 1)),
+            )),
+            (false, Some(name)) => requests.send(PresetRequest::Apply(name)),
+            (false, None) => info!("No preset {}", slot + 1),
+        }
+    }
+}
+
+fn handle_preset_requests(
+    mut requests: EventReader<PresetRequest>,
+    mut presets: ResMut<Presets>,
+    mut tuning: Tuning,
+) {
+    for request in requests.iter() {
+        match request {
+            PresetRequest::Apply(name) => {
+                match presets.list.iter().find(|preset| preset.name == *name) {
+                    Some(preset) => {
+                        tuning.apply(preset);
+                        info!("Switched to preset '{name}'");
+                    }
+                    None => {
+                        warn!("No preset named '{name}'");
+                        continue;
+                    }
+                }
+                presets.active = Some(name.clone());
+            }
+            PresetRequest::Save(name) => {
+                let preset = tuning.current(name);
+                match presets.list.iter_mut().find(|preset| preset.name == *name) {
+                    Some(existing) => *existing = preset,
+                    None => presets.list.push(preset),
+                }
+                presets.save();
+                presets.active = Some(name.clone());
+                info!("Saved preset '{name}'");
+            }
+        }
+    }
+}
@@ -0,0 +1,244 @@
+//! How close the nearest visitor is, for revealing content as they approach.
+//! Anything that stands out from the learned background counts as a person,
+//! however far; their distance puts them in a [`ProximityBand`], and a
+//! [`ProximityChanged`] event goes out when they move into another one. The
+//! band only changes once they are [`ProximitySettings::hysteresis`] past a
+//! limit, so someone standing right on one doesn't flicker between two.
+//!
+//! The background is the furthest the scene has been at each pixel. Things
+//! that stay put are let into it over about a minute, so a moved chair
+//! doesn't count as a visitor for long.
+
+use std::str::FromStr;
+
+use bevy::prelude::*;
+
+use crate::coords;
+use crate::{CurrentDepth, MainKinect};
+
+/// How much closer than the background a reading has to be to count.
+const FOREGROUND_MARGIN: f32 = 0.15;
+/// Foreground pixels that make a person, about a body at 4.5 m.
+const MIN_PIXELS: usize = 1500;
+/// Share of the foreground in front of the distance that is reported, so a
+/// few noisy pixels don't make someone look closer than they are.
+const NEAREST_SHARE: f32 = 0.1;
+/// How fast things staying closer than the background join it, per frame.
+const ABSORB_RATE: f32 = 1.0 / 1800.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ProximityBand {
+    /// nobody, or further than `mid`
+    Far,
+    Mid,
+    Near,
+    /// within reach of the screen
+    Engaged,
+}
+
+/// Where the bands end, meters from the sensor.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProximityBands {
+    pub engaged: f32,
+    pub near: f32,
+    pub mid: f32,
+}
+
+impl Default for ProximityBands {
+    fn default() -> Self {
+        ProximityBands {
+            engaged: 1.2,
+            near: 2.5,
+            mid: 4.0,
+        }
+    }
+}
+
+impl ProximityBands {
+    pub fn band(&self, meters: f32) -> ProximityBand {
+        if meters < self.engaged {
+            ProximityBand::Engaged
+        } else if meters < self.near {
+            ProximityBand::Near
+        } else if meters < self.mid {
+            ProximityBand::Mid
+        } else {
+            ProximityBand::Far
+        }
+    }
+
+    /// The band after `current` for someone at `meters`, which only changes
+    /// once they are `hysteresis` past the limit.
+    pub fn next_band(&self, current: ProximityBand, meters: f32, hysteresis: f32) -> ProximityBand {
+        let closer = self.band(meters + hysteresis);
+        let further = self.band(meters - hysteresis);
+        if closer > current {
+            closer
+        } else if further < current {
+            further
+        } else {
+            current
+        }
+    }
+}
+
+impl FromStr for ProximityBands {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let limits: Vec<f32> = s
+            .split(',')
+            .map(|limit| limit.trim().parse::<f32>())
+            .collect::<Result<_, _>>()
+            .map_err(|err| format!("bad proximity bands '{s}': {err}"))?;
+        match limits[..] {
+            [engaged, near, mid] if 0.0 < engaged && engaged < near && near < mid => {
+                Ok(ProximityBands { engaged, near, mid })
+            }
+            _ => Err(format!(
+                "bad proximity bands '{s}', expected <engaged>,<near>,<mid> in increasing meters"
+            )),
+        }
+    }
+}
+
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct ProximitySettings {
+    pub bands: ProximityBands,
+    /// meters past a limit before the band changes
+    pub hysteresis: f32,
+}
+
+impl Default for ProximitySettings {
+    fn default() -> Self {
+        ProximitySettings {
+            bands: ProximityBands::default(),
+            hysteresis: 0.15,
+        }
+    }
+}
+
+/// The nearest visitor.
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct Proximity {
+    pub band: ProximityBand,
+    /// meters from the sensor, `None` while nobody is there
+    pub meters: Option<f32>,
+}
+
+impl Default for Proximity {
+    fn default() -> Self {
+        Proximity {
+            band: ProximityBand::Far,
+            meters: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ProximityChanged {
+    pub band: ProximityBand,
+    pub previous: ProximityBand,
+}
+
+pub struct ProximityPlugin;
+
+impl Plugin for ProximityPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ProximitySettings>()
+            .init_resource::<Proximity>()
+            .add_event::<ProximityChanged>()
+            .add_system(track_proximity.after(crate::KinectSet::Acquire));
+    }
+}
+
+fn track_proximity(
+    depth_query: Query<&CurrentDepth, (With<MainKinect>, Changed<CurrentDepth>)>,
+    settings: Res<ProximitySettings>,
+    mut proximity: ResMut<Proximity>,
+    mut changes: EventWriter<ProximityChanged>,
+    mut background: Local<Background>,
+) {
+    let depth = match depth_query.get_single() {
+        Ok(depth) if !depth.depth_array.is_empty() => depth,
+        _ => return,
+    };
+
+    let mut foreground: Vec<f32> = background
+        .foreground(&depth.depth_array)
+        .into_iter()
+        .flatten()
+        .collect();
+    let meters = (foreground.len() >= MIN_PIXELS).then(|| {
+        let nth = (foreground.len() as f32 * NEAREST_SHARE) as usize;
+        *foreground.select_nth_unstable_by(nth, f32::total_cmp).1
+    });
+    let band = match meters {
+        Some(meters) => settings
+            .bands
+            .next_band(proximity.band, meters, settings.hysteresis),
+        None => ProximityBand::Far,
+    };
+    if band != proximity.band {
+        changes.send(ProximityChanged {
+            band,
+            previous: proximity.band,
+        });
+    }
+    *proximity = Proximity { band, meters };
+}
+
+/// The furthest the scene has been at each pixel, see the module docs.
+#[derive(Default)]
+pub(crate) struct Background(Vec<Option<f32>>);
+
+impl Background {
+    /// Learns from a frame and gives the meters of each pixel standing out
+    /// in front of the background, `None` for the rest.
+    pub(crate) fn foreground(&mut self, depth: &[u16]) -> Vec<Option<f32>> {
+        if self.0.len() != depth.len() {
+            self.0 = vec![None; depth.len()];
+        }
+        depth
+            .iter()
+            .zip(self.0.iter_mut())
+            .map(|(raw, furthest)| {
+                let meters = coords::raw_depth_to_meters(*raw)?;
+                match *furthest {
+                    Some(back) if meters < back - FOREGROUND_MARGIN => {
+                        *furthest = Some(back + (meters - back) * ABSORB_RATE);
+                        return Some(meters);
+                    }
+                    Some(back) if meters < back => {}
+                    _ => *furthest = Some(meters),
+                }
+                None
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bands_hold_until_well_past_a_limit() {
+        let bands: ProximityBands = "1.2,2.5,4".parse().unwrap();
+        assert_eq!(bands, ProximityBands::default());
+        assert!("2.5,1.2,4".parse::<ProximityBands>().is_err());
+
+        let walk = [5.0, 3.9, 3.8, 3.9, 4.1, 2.0, 1.1, 1.3, 1.4];
+        let mut band = ProximityBand::Far;
+        let seen: Vec<ProximityBand> = walk
+            .iter()
+            .map(|meters| {
+                band = bands.next_band(band, *meters, 0.15);
+                band
+            })
+            .collect();
+        use ProximityBand::*;
+        assert_eq!(seen, [Far, Far, Mid, Mid, Mid, Near, Near, Near, Near]);
+        assert_eq!(bands.next_band(Engaged, 1.0, 0.15), Engaged);
+    }
+}
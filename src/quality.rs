@@ -0,0 +1,186 @@
+//! Trading processing quality for frame rate. With a frame budget set
+//! (`--frame-budget <ms>`), the [`QualityLevel`] steps down while frames
+//! take longer than that, and back up once there is headroom again. The
+//! expensive steps read it: hole filling does fewer rounds, the depth view
+//! skips pixels and leaves out the upscaled map, and point clouds keep every
+//! n-th point.
+//!
+//! Without a budget the level stays at [`QualityLevel::Full`], and apps can
+//! set it themselves.
+
+use bevy::prelude::*;
+
+/// Seconds the frame time is averaged over.
+const SMOOTHING: f32 = 0.5;
+/// Seconds over budget before stepping down.
+const LOWER_AFTER: f32 = 1.0;
+/// Seconds with headroom before stepping back up.
+const RAISE_AFTER: f32 = 3.0;
+/// Share of the budget below which there is headroom, low enough that the
+/// step up doesn't go straight back over.
+const HEADROOM: f32 = 0.75;
+
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum QualityLevel {
+    #[default]
+    Full,
+    Reduced,
+    Low,
+    Minimal,
+}
+
+impl QualityLevel {
+    /// Depth view pixels per shown pixel, along each side.
+    pub fn decimation(self) -> usize {
+        match self {
+            QualityLevel::Full | QualityLevel::Reduced => 1,
+            QualityLevel::Low => 2,
+            QualityLevel::Minimal => 4,
+        }
+    }
+
+    /// Passes of a filter that does `full` of them at full quality, halved
+    /// at every level down.
+    pub fn filter_rounds(self, full: usize) -> usize {
+        (full >> self as usize).max(1)
+    }
+
+    /// Whether the view shows the upscaled depth map, when there is one.
+    pub fn upsample(self) -> bool {
+        self <= QualityLevel::Reduced
+    }
+
+    /// Every how many points a point cloud keeps.
+    pub fn point_stride(self) -> usize {
+        match self {
+            QualityLevel::Full => 1,
+            QualityLevel::Reduced | QualityLevel::Low => 2,
+            QualityLevel::Minimal => 4,
+        }
+    }
+
+    fn lower(self) -> Self {
+        match self {
+            QualityLevel::Full => QualityLevel::Reduced,
+            QualityLevel::Reduced => QualityLevel::Low,
+            QualityLevel::Low | QualityLevel::Minimal => QualityLevel::Minimal,
+        }
+    }
+
+    fn raise(self) -> Self {
+        match self {
+            QualityLevel::Full | QualityLevel::Reduced => QualityLevel::Full,
+            QualityLevel::Low => QualityLevel::Reduced,
+            QualityLevel::Minimal => QualityLevel::Low,
+        }
+    }
+}
+
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct QualitySettings {
+    /// milliseconds a frame may take, `None` keeps the level where it is
+    pub budget_ms: Option<f32>,
+}
+
+/// The averaged frame time and how long it has been over budget or under
+/// the headroom.
+#[derive(Default)]
+struct Governor {
+    frame_ms: Option<f32>,
+    over: f32,
+    under: f32,
+}
+
+impl Governor {
+    /// The level after a frame that took `delta` seconds.
+    fn update(&mut self, level: QualityLevel, delta: f32, budget_ms: f32) -> QualityLevel {
+        let ms = delta * 1000.0;
+        let frame_ms = match self.frame_ms {
+            Some(average) => average + (ms - average) * (delta / SMOOTHING).min(1.0),
+            None => ms,
+        };
+        self.frame_ms = Some(frame_ms);
+
+        if frame_ms > budget_ms {
+            self.over += delta;
+            self.under = 0.0;
+        } else if frame_ms < budget_ms * HEADROOM {
+            self.under += delta;
+            self.over = 0.0;
+        } else {
+            self.over = 0.0;
+            self.under = 0.0;
+        }
+        if self.over >= LOWER_AFTER {
+            self.over = 0.0;
+            return level.lower();
+        }
+        if self.under >= RAISE_AFTER {
+            self.under = 0.0;
+            return level.raise();
+        }
+        level
+    }
+}
+
+pub struct QualityPlugin;
+
+impl Plugin for QualityPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<QualitySettings>()
+            .init_resource::<QualityLevel>()
+            .add_system(govern_quality.before(crate::KinectSet::Acquire));
+    }
+}
+
+fn govern_quality(
+    settings: Res<QualitySettings>,
+    time: Res<Time>,
+    mut level: ResMut<QualityLevel>,
+    mut governor: Local<Governor>,
+) {
+    let budget_ms = match settings.budget_ms {
+        Some(budget_ms) => budget_ms,
+        None => return,
+    };
+    let delta = time.delta_seconds();
+    if delta <= 0.0 {
+        return;
+    }
+    let next = governor.update(*level, delta, budget_ms);
+    if next != *level {
+        info!("Frames against a {budget_ms} ms budget, quality {level:?} -> {next:?}");
+        *level = next;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quality_follows_the_budget() {
+        let mut governor = Governor::default();
+        let mut level = QualityLevel::Full;
+        // 50 ms frames against a 33 ms budget
+        for _ in 0..25 {
+            level = governor.update(level, 0.05, 33.0);
+        }
+        assert_eq!(level, QualityLevel::Reduced);
+        for _ in 0..100 {
+            level = governor.update(level, 0.05, 33.0);
+        }
+        assert_eq!(level, QualityLevel::Minimal);
+        assert_eq!(level.filter_rounds(32), 4);
+
+        // 10 ms frames come back a level every few seconds
+        for _ in 0..400 {
+            level = governor.update(level, 0.01, 33.0);
+        }
+        assert_eq!(level, QualityLevel::Low);
+        for _ in 0..1000 {
+            level = governor.update(level, 0.01, 33.0);
+        }
+        assert_eq!(level, QualityLevel::Full);
+    }
+}
@@ -0,0 +1,116 @@
+//! The frames between a backend's acquisition thread and the app. The
+//! thread pushes each frame as it arrives, the app takes one per Bevy frame,
+//! and the queue holds at most [`UsbSettings::frame_queue`] in between, so a
+//! slow render frame can't leave tracking further and further behind the
+//! sensor. [`DropPolicy`] is which frames go when the app doesn't keep up.
+//!
+//! [`UsbSettings::frame_queue`]: crate::embedded::UsbSettings::frame_queue
+
+use std::str::FromStr;
+
+use crossbeam_channel::{bounded, Receiver, Sender, TrySendError};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DropPolicy {
+    /// The app takes the newest frame and the others are dropped, tracking
+    /// is never more than a frame behind.
+    #[default]
+    KeepLatest,
+    /// Every frame that fits, in order; while the queue is full new frames
+    /// are dropped.
+    KeepAll,
+    /// Every frame in order, a new one pushing out the oldest while the
+    /// queue is full.
+    DropOldest,
+}
+
+impl FromStr for DropPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep-latest" => Ok(DropPolicy::KeepLatest),
+            "keep-all" => Ok(DropPolicy::KeepAll),
+            "drop-oldest" => Ok(DropPolicy::DropOldest),
+            _ => Err(format!(
+                "unknown drop policy '{s}', expected keep-latest, keep-all or drop-oldest"
+            )),
+        }
+    }
+}
+
+/// A bounded queue of frames. Clones are the same queue, one for the
+/// thread and one for the app.
+pub struct FrameQueue<T> {
+    sender: Sender<T>,
+    receiver: Receiver<T>,
+    policy: DropPolicy,
+}
+
+impl<T> Clone for FrameQueue<T> {
+    fn clone(&self) -> Self {
+        FrameQueue {
+            sender: self.sender.clone(),
+            receiver: self.receiver.clone(),
+            policy: self.policy,
+        }
+    }
+}
+
+impl<T> FrameQueue<T> {
+    /// Holds `len` frames, at least one.
+    pub fn new(len: usize, policy: DropPolicy) -> FrameQueue<T> {
+        let (sender, receiver) = bounded(len.max(1));
+        FrameQueue {
+            sender,
+            receiver,
+            policy,
+        }
+    }
+
+    /// From the acquisition thread, never waits.
+    pub fn push(&self, frame: T) {
+        let mut frame = frame;
+        loop {
+            match self.sender.try_send(frame) {
+                Ok(()) => return,
+                Err(TrySendError::Full(full)) if self.policy != DropPolicy::KeepAll => {
+                    // the app may take it first, then there is room anyway
+                    let _ = self.receiver.try_recv();
+                    frame = full;
+                }
+                Err(_) => return,
+            }
+        }
+    }
+
+    /// The app's next frame, if one came.
+    pub fn pop(&self) -> Option<T> {
+        match self.policy {
+            DropPolicy::KeepLatest => self.receiver.try_iter().last(),
+            DropPolicy::KeepAll | DropPolicy::DropOldest => self.receiver.try_recv().ok(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn taken(policy: DropPolicy) -> Vec<u32> {
+        let queue = FrameQueue::new(2, policy);
+        for frame in 1..=4 {
+            queue.push(frame);
+        }
+        std::iter::from_fn(|| queue.pop()).collect()
+    }
+
+    #[test]
+    fn full_queues_drop_by_policy() {
+        assert_eq!(taken(DropPolicy::KeepLatest), [4]);
+        assert_eq!(taken(DropPolicy::KeepAll), [1, 2]);
+        assert_eq!(taken(DropPolicy::DropOldest), [3, 4]);
+        assert_eq!("drop-oldest".parse(), Ok(DropPolicy::DropOldest));
+        assert!("newest".parse::<DropPolicy>().is_err());
+    }
+}
@@ -0,0 +1,281 @@
+//! Self-healing for unattended setups. Long USB runs drop the Kinect now and
+//! then, either with an error from the acquisition thread or by silently
+//! ceasing to deliver frames (which the [`watchdog`](crate::watchdog)
+//! notices). Both end up here: the device is closed and reopened with
+//! exponential backoff until frames flow again.
+//!
+//! [`KinectState`] follows along as an app state, so games can show a
+//! waiting screen and only run gameplay while the sensor streams, e.g.
+//! `SystemSet::on_enter(KinectState::Streaming)`.
+
+use bevy::prelude::*;
+
+use crate::backend::DepthCamera;
+use crate::device::KinectError;
+use crate::watchdog::StreamStalled;
+
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct ReconnectSettings {
+    pub enabled: bool,
+    /// seconds to wait for the first depth frame after opening the device
+    pub connect_timeout: f32,
+    /// delay before the first retry, doubled on every failed attempt
+    pub initial_backoff: f32,
+    pub max_backoff: f32,
+}
+
+impl Default for ReconnectSettings {
+    fn default() -> Self {
+        ReconnectSettings {
+            enabled: true,
+            connect_timeout: 5.0,
+            initial_backoff: 1.0,
+            max_backoff: 30.0,
+        }
+    }
+}
+
+impl ReconnectSettings {
+    /// Delay before retry number `attempt`, counting from 1.
+    pub fn backoff(&self, attempt: u32) -> f32 {
+        let doublings = attempt.saturating_sub(1).min(16) as i32;
+        (self.initial_backoff * 2f32.powi(doublings)).min(self.max_backoff)
+    }
+}
+
+/// Sent whenever the connection changes.
+#[derive(Clone, Debug, PartialEq)]
+pub enum KinectStatus {
+    /// depth frames are arriving
+    Streaming,
+    /// the device opened but sent nothing, or a stream stopped
+    Stalled,
+    /// the device is closed and will be reopened in `delay` seconds
+    Retrying { attempt: u32, delay: f32 },
+    /// reopening the device now
+    Reconnecting { attempt: u32 },
+}
+
+/// The connection as an app state, starting at `Connecting`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum KinectState {
+    /// the device was opened and no frame has arrived yet
+    Connecting,
+    /// depth frames are arriving
+    Streaming,
+    /// the device is closed, paused or gone quiet, and waits for a retry
+    Disconnected,
+}
+
+/// Where the supervisor is, for anything that wants to show it.
+#[derive(Resource, Debug)]
+pub struct Connection {
+    streaming: bool,
+    /// the device was (re)opened and no frame has arrived yet
+    connecting: bool,
+    attempt: u32,
+    retry_at: Option<f64>,
+    last_frame_count: u64,
+    last_frame_at: f64,
+    last_error: Option<KinectError>,
+}
+
+impl Default for Connection {
+    fn default() -> Self {
+        Connection {
+            streaming: false,
+            // backends open the device right away
+            connecting: true,
+            attempt: 0,
+            retry_at: None,
+            last_frame_count: 0,
+            last_frame_at: 0.0,
+            last_error: None,
+        }
+    }
+}
+
+impl Connection {
+    pub fn is_streaming(&self) -> bool {
+        self.streaming
+    }
+
+    pub fn is_connecting(&self) -> bool {
+        self.connecting
+    }
+
+    /// Failed attempts since frames last arrived.
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    /// Seconds until the next attempt, if one is scheduled.
+    pub fn retry_in(&self, time: &Time) -> Option<f32> {
+        self.retry_at
+            .map(|at| (at - time.elapsed_seconds_f64()).max(0.0) as f32)
+    }
+
+    /// Error that caused the current outage, cleared once frames arrive.
+    pub fn last_error(&self) -> Option<&KinectError> {
+        self.last_error.as_ref()
+    }
+
+    /// Drops a scheduled retry, for closing the device on purpose.
+    pub(crate) fn cancel_retry(&mut self) {
+        self.retry_at = None;
+        self.connecting = false;
+    }
+
+    /// The device went off the bus, nothing to retry until it is back.
+    #[cfg(feature = "freenect")]
+    pub(crate) fn unplugged(&mut self) {
+        self.cancel_retry();
+        self.streaming = false;
+    }
+
+    /// A new backend opened for a device plugged back in.
+    #[cfg(feature = "freenect")]
+    pub(crate) fn reopened(&mut self, now: f64) {
+        self.retry_at = None;
+        self.connecting = true;
+        self.attempt = 0;
+        self.last_frame_count = 0;
+        self.last_frame_at = now;
+    }
+
+    fn schedule_retry(&mut self, settings: &ReconnectSettings, now: f64) -> KinectStatus {
+        self.attempt += 1;
+        let delay = settings.backoff(self.attempt);
+        self.retry_at = Some(now + delay as f64);
+        KinectStatus::Retrying {
+            attempt: self.attempt,
+            delay,
+        }
+    }
+}
+
+pub struct ReconnectPlugin;
+
+impl Plugin for ReconnectPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ReconnectSettings>()
+            .init_resource::<Connection>()
+            .add_event::<KinectStatus>()
+            .add_state(KinectState::Connecting)
+            .add_system(supervise_connection)
+            .add_system(update_kinect_state.after(supervise_connection))
+            .add_system(log_kinect_status);
+    }
+}
+
+pub(crate) fn supervise_connection(
+    mut camera: ResMut<DepthCamera>,
+    settings: Res<ReconnectSettings>,
+    time: Res<Time>,
+    mut connection: ResMut<Connection>,
+    mut errors: EventWriter<KinectError>,
+    mut status: EventWriter<KinectStatus>,
+    mut stalls: EventReader<StreamStalled>,
+) {
+    let now = time.elapsed_seconds_f64();
+    // read them all, whatever happens below
+    let stream_stalled = stalls.iter().last().is_some();
+
+    if let Some(retry_at) = connection.retry_at {
+        if now >= retry_at {
+            connection.retry_at = None;
+            connection.connecting = true;
+            connection.last_frame_at = now;
+            camera.open();
+            status.send(KinectStatus::Reconnecting {
+                attempt: connection.attempt,
+            });
+        }
+        return;
+    }
+
+    // the thread ended on its own, usually because the device wouldn't open
+    if let Some(exit) = camera.take_exit() {
+        connection.streaming = false;
+        connection.connecting = false;
+        if let Err(err) = exit {
+            connection.last_error = Some(err.clone());
+            errors.send(err);
+        }
+        if settings.enabled {
+            status.send(connection.schedule_retry(&settings, now));
+        }
+        return;
+    }
+
+    // paused on purpose
+    if !camera.is_running() {
+        connection.last_frame_at = now;
+        return;
+    }
+
+    let frames = camera.depth_frames_received();
+    if frames != connection.last_frame_count {
+        connection.last_frame_count = frames;
+        connection.last_frame_at = now;
+        if !connection.streaming {
+            connection.streaming = true;
+            connection.connecting = false;
+            connection.attempt = 0;
+            connection.last_error = None;
+            status.send(KinectStatus::Streaming);
+        }
+    }
+
+    let timed_out =
+        connection.connecting && now - connection.last_frame_at > settings.connect_timeout as f64;
+    let stalled = connection.streaming && stream_stalled;
+    if timed_out || stalled {
+        connection.streaming = false;
+        connection.connecting = false;
+        connection.last_error = Some(KinectError::Stalled);
+        errors.send(KinectError::Stalled);
+        status.send(KinectStatus::Stalled);
+        if settings.enabled {
+            if let Err(err) = camera.close() {
+                connection.last_error = Some(err.clone());
+                errors.send(err);
+            }
+            status.send(connection.schedule_retry(&settings, now));
+        }
+    }
+}
+
+fn update_kinect_state(
+    camera: Res<DepthCamera>,
+    connection: Res<Connection>,
+    mut state: ResMut<State<KinectState>>,
+) {
+    let next = if connection.is_connecting() {
+        KinectState::Connecting
+    } else if connection.is_streaming() && camera.is_running() {
+        KinectState::Streaming
+    } else {
+        KinectState::Disconnected
+    };
+    // overwrite rather than queue, the supervisor has the last word
+    if *state.current() != next {
+        state.overwrite_set(next).unwrap();
+    }
+}
+
+fn log_kinect_status(mut status: EventReader<KinectStatus>) {
+    for status in status.iter() {
+        match status {
+            KinectStatus::Streaming => info!("Kinect streaming"),
+            // logged as a KinectError
+            KinectStatus::Stalled => {}
+            KinectStatus::Retrying { attempt, delay } => {
+                warn!("Reopening Kinect in {delay:.0}s (attempt {attempt})")
+            }
+            KinectStatus::Reconnecting { attempt } => {
+                info!("Reopening Kinect (attempt {attempt})")
+            }
+        }
+    }
+}
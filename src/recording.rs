@@ -0,0 +1,266 @@
+//! Take-home clips: records what the app shows to MP4 or WebM, one clip per
+//! visit. A clip starts when someone walks up (attract mode ends) and stops
+//! when they leave (attract mode starts) or after `max_seconds`; E starts or
+//! stops one by hand. Each finished clip is announced with a [`ClipSaved`].
+//!
+//! Frames come from the [`Compositor`] and are piped to `ffmpeg` as raw RGBA.
+//! Needs the `record` feature and `ffmpeg` on the `PATH`, with libx264 for
+//! MP4 or libvpx-vp9 for WebM.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Mutex;
+use std::thread;
+
+use bevy::prelude::*;
+
+use crate::analytics::unix_now;
+use crate::attract::AttractEvent;
+use crate::composite::{ClipSaved, Compositor, FrameSource, FRAME_HEIGHT, FRAME_WIDTH};
+
+/// Output frame rate, the depth camera's.
+const RECORD_FPS: f32 = 30.0;
+/// Frames waiting for the encoder before new ones get dropped.
+const QUEUE_FRAMES: usize = 8;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ClipFormat {
+    #[default]
+    Mp4,
+    WebM,
+}
+
+impl ClipFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            ClipFormat::Mp4 => "mp4",
+            ClipFormat::WebM => "webm",
+        }
+    }
+
+    fn codec_args(self) -> &'static [&'static str] {
+        match self {
+            ClipFormat::Mp4 => &["-c:v", "libx264", "-pix_fmt", "yuv420p", "-preset", "fast"],
+            ClipFormat::WebM => &["-c:v", "libvpx-vp9", "-b:v", "2M", "-deadline", "realtime"],
+        }
+    }
+}
+
+impl FromStr for ClipFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mp4" => Ok(ClipFormat::Mp4),
+            "webm" => Ok(ClipFormat::WebM),
+            _ => Err(format!("unknown clip format '{s}', expected mp4 or webm")),
+        }
+    }
+}
+
+#[derive(Resource, Clone, Debug)]
+pub struct RecordingSettings {
+    /// directory to save clips to, recording is off without one
+    pub dir: Option<PathBuf>,
+    pub format: ClipFormat,
+    pub source: FrameSource,
+    /// longest clip in seconds
+    pub max_seconds: f32,
+}
+
+impl Default for RecordingSettings {
+    fn default() -> Self {
+        RecordingSettings {
+            dir: None,
+            format: ClipFormat::default(),
+            source: FrameSource::default(),
+            max_seconds: 30.0,
+        }
+    }
+}
+
+/// The clip being recorded, if any.
+#[derive(Resource, Default)]
+pub struct Recording {
+    clip: Option<Clip>,
+    /// encoders still finishing, they report back here
+    finished: Vec<Mutex<Receiver<io::Result<PathBuf>>>>,
+}
+
+impl Recording {
+    pub fn is_recording(&self) -> bool {
+        self.clip.is_some()
+    }
+}
+
+struct Clip {
+    started_at: f64,
+    frames: usize,
+    sender: SyncSender<Vec<u8>>,
+    done: Mutex<Receiver<io::Result<PathBuf>>>,
+}
+
+pub struct RecordingPlugin;
+
+impl Plugin for RecordingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<RecordingSettings>()
+            .init_resource::<Recording>()
+            .add_system(start_stop_recording)
+            .add_system(record_frames.after(start_stop_recording))
+            .add_system(collect_clips);
+    }
+}
+
+fn start_stop_recording(
+    settings: Res<RecordingSettings>,
+    keys: Res<Input<KeyCode>>,
+    time: Res<Time>,
+    mut attract_events: EventReader<AttractEvent>,
+    mut recording: ResMut<Recording>,
+) {
+    let dir = match &settings.dir {
+        Some(dir) => dir,
+        None => return,
+    };
+    let mut start = false;
+    let mut stop = false;
+    for event in attract_events.iter() {
+        match event {
+            AttractEvent::Ended => start = true,
+            AttractEvent::Started => stop = true,
+        }
+    }
+    if keys.just_pressed(KeyCode::E) {
+        if recording.is_recording() {
+            stop = true;
+        } else {
+            start = true;
+        }
+    }
+    let now = time.elapsed_seconds_f64();
+    if let Some(clip) = &recording.clip {
+        stop |= now - clip.started_at >= settings.max_seconds as f64;
+    }
+
+    if stop {
+        if let Some(clip) = recording.clip.take() {
+            // closing the pipe lets ffmpeg finish the file
+            drop(clip.sender);
+            info!("Finishing clip after {} frames", clip.frames);
+            recording.finished.push(clip.done);
+        }
+    } else if start && !recording.is_recording() {
+        match start_clip(dir, settings.format, now) {
+            Ok(clip) => recording.clip = Some(clip),
+            Err(err) => error!("Unable to start recording: {err}"),
+        }
+    }
+}
+
+fn start_clip(dir: &Path, format: ClipFormat, now: f64) -> io::Result<Clip> {
+    fs::create_dir_all(dir)?;
+    let path = dir.join(format!("clip-{}.{}", unix_now() as u64, format.extension()));
+    let mut child = Command::new("ffmpeg")
+        .args([
+            "-loglevel",
+            "error",
+            "-y",
+            "-f",
+            "rawvideo",
+            "-pix_fmt",
+            "rgba",
+        ])
+        .args(["-s", &format!("{FRAME_WIDTH}x{FRAME_HEIGHT}")])
+        .args(["-r", &RECORD_FPS.to_string(), "-i", "-"])
+        .args(format.codec_args())
+        .arg(&path)
+        .stdin(Stdio::piped())
+        .spawn()?;
+    let stdin = child.stdin.take().expect("stdin is piped");
+    info!("Recording clip to {}", path.display());
+
+    let (sender, frames) = mpsc::sync_channel(QUEUE_FRAMES);
+    let (report, done) = mpsc::channel();
+    thread::Builder::new()
+        .name("clip encoder".to_string())
+        .spawn(move || {
+            let result = encode(child, stdin, frames).map(|_| path);
+            let _ = report.send(result);
+        })?;
+    Ok(Clip {
+        started_at: now,
+        frames: 0,
+        sender,
+        done: Mutex::new(done),
+    })
+}
+
+fn encode(mut child: Child, mut stdin: ChildStdin, frames: Receiver<Vec<u8>>) -> io::Result<()> {
+    for frame in frames {
+        stdin.write_all(&frame)?;
+    }
+    drop(stdin);
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("ffmpeg exited with {status}")));
+    }
+    Ok(())
+}
+
+fn record_frames(
+    settings: Res<RecordingSettings>,
+    time: Res<Time>,
+    compositor: Compositor,
+    mut recording: ResMut<Recording>,
+) {
+    let clip = match &mut recording.clip {
+        Some(clip) => clip,
+        None => return,
+    };
+    // one frame per tick of the output rate, however fast the app renders
+    let due = ((time.elapsed_seconds_f64() - clip.started_at) * RECORD_FPS as f64) as usize + 1;
+    if clip.frames >= due {
+        return;
+    }
+    let mut frame = Vec::new();
+    if !compositor.frame(settings.source, &mut frame) {
+        return;
+    }
+    // repeat the frame for ticks missed, so the clip keeps real time
+    while clip.frames < due {
+        match clip.sender.try_send(frame.clone()) {
+            Ok(()) => clip.frames += 1,
+            // the encoder is behind, catch up later rather than stall the app
+            Err(TrySendError::Full(_)) => break,
+            Err(TrySendError::Disconnected(_)) => {
+                error!("Clip encoder stopped, recording ended");
+                if let Some(clip) = recording.clip.take() {
+                    recording.finished.push(clip.done);
+                }
+                return;
+            }
+        }
+    }
+}
+
+fn collect_clips(mut recording: ResMut<Recording>, mut saved: EventWriter<ClipSaved>) {
+    recording
+        .finished
+        .retain(|done| match done.lock().unwrap().try_recv() {
+            Ok(Ok(path)) => {
+                saved.send(ClipSaved { path });
+                false
+            }
+            Ok(Err(err)) => {
+                error!("Unable to save clip: {err}");
+                false
+            }
+            Err(mpsc::TryRecvError::Empty) => true,
+            Err(mpsc::TryRecvError::Disconnected) => false,
+        });
+}
@@ -0,0 +1,138 @@
+//! Depth statistics over an area of the current frame, for the questions an
+//! app asks about a fixed spot of the room, like whether the chair is taken:
+//!
+//! ```ignore
+//! fn chair(depth: Query<&CurrentDepth, With<MainKinect>>) {
+//!     let chair = Rect::new(400.0, 260.0, 480.0, 380.0);
+//!     let stats = region::depth_stats_in_rect(depth.single(), chair);
+//!     let occupied = stats.min.is_some_and(|meters| meters < 1.5);
+//! }
+//! ```
+//!
+//! Areas are in medium resolution pixels, like [`CloseBlob`](crate::CloseBlob),
+//! whatever the frame's resolution; pixels count when their center is inside.
+
+use bevy::prelude::*;
+
+use crate::{coords, CurrentDepth};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DepthStats {
+    /// nearest reading in meters, `None` when no pixel has one
+    pub min: Option<f32>,
+    /// average of the readings in meters
+    pub mean: Option<f32>,
+    /// share of the pixels with a reading, 0 for an empty area
+    pub valid_ratio: f32,
+    /// pixels in the area
+    pub pixels: usize,
+}
+
+pub fn depth_stats_in_rect(depth: &CurrentDepth, rect: Rect) -> DepthStats {
+    stats(depth, rect, |point| rect.contains(point))
+}
+
+/// For a polygon given by its corners in order, either way round.
+pub fn depth_stats_in_polygon(depth: &CurrentDepth, polygon: &[Vec2]) -> DepthStats {
+    let bounds = polygon.iter().fold(
+        Rect {
+            min: Vec2::splat(f32::MAX),
+            max: Vec2::splat(f32::MIN),
+        },
+        |bounds, corner| Rect {
+            min: bounds.min.min(*corner),
+            max: bounds.max.max(*corner),
+        },
+    );
+    stats(depth, bounds, |point| inside(polygon, point))
+}
+
+/// Even-odd rule: a ray to the right crosses the outline an odd number of
+/// times from inside.
+fn inside(polygon: &[Vec2], point: Vec2) -> bool {
+    let mut inside = false;
+    for (i, a) in polygon.iter().enumerate() {
+        let b = polygon[(i + 1) % polygon.len()];
+        if (a.y > point.y) != (b.y > point.y) {
+            let x = a.x + (point.y - a.y) / (b.y - a.y) * (b.x - a.x);
+            if point.x < x {
+                inside = !inside;
+            }
+        }
+    }
+    inside
+}
+
+/// Over the pixels of `bounds` that `contains` keeps.
+fn stats(depth: &CurrentDepth, bounds: Rect, contains: impl Fn(Vec2) -> bool) -> DepthStats {
+    let mut result = DepthStats::default();
+    if depth.depth_array.len() != depth.width * depth.height || bounds.is_empty() {
+        return result;
+    }
+    // medium pixels into this frame's and back
+    let scale = Vec2::new(depth.width as f32, depth.height as f32) / crate::VIEW_SIZE;
+    let first = (bounds.min * scale).floor().max(Vec2::ZERO);
+    let last = (bounds.max * scale).ceil();
+    let (mut valid, mut sum) = (0, 0.0);
+    for y in first.y as usize..(last.y as usize).min(depth.height) {
+        for x in first.x as usize..(last.x as usize).min(depth.width) {
+            let center = depth.to_medium(Vec2::new(x as f32 + 0.5, y as f32 + 0.5));
+            if !contains(center) {
+                continue;
+            }
+            result.pixels += 1;
+            if let Some(meters) =
+                coords::raw_depth_to_meters(depth.depth_array[y * depth.width + x])
+            {
+                valid += 1;
+                sum += meters;
+                result.min = Some(result.min.map_or(meters, |min| min.min(meters)));
+            }
+        }
+    }
+    if result.pixels > 0 {
+        result.valid_ratio = valid as f32 / result.pixels as f32;
+    }
+    if valid > 0 {
+        result.mean = Some(sum / valid as f32);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DEPTH_HEIGHT, DEPTH_WIDTH, NO_DEPTH};
+
+    #[test]
+    fn stats_cover_the_area() {
+        // 600 in the left half, nothing in the right
+        let depth_array = (0..DEPTH_WIDTH * DEPTH_HEIGHT)
+            .map(|i| if i % DEPTH_WIDTH < 320 { 600 } else { NO_DEPTH })
+            .collect();
+        let depth = CurrentDepth {
+            depth_array,
+            ..CurrentDepth::new(DEPTH_WIDTH, DEPTH_HEIGHT, Handle::default())
+        };
+        let meters = coords::raw_depth_to_meters(600);
+
+        let stats = depth_stats_in_rect(&depth, Rect::new(300.0, 0.0, 340.0, 10.0));
+        assert_eq!(stats.pixels, 400);
+        assert_eq!(stats.valid_ratio, 0.5);
+        assert_eq!(stats.min, meters);
+        assert!((stats.mean.unwrap() - meters.unwrap()).abs() < 1e-4);
+
+        // all of it in the left half
+        let triangle = [
+            Vec2::new(0.0, 0.0),
+            Vec2::new(320.0, 0.0),
+            Vec2::new(0.0, 320.0),
+        ];
+        let stats = depth_stats_in_polygon(&depth, &triangle);
+        assert_eq!(stats.valid_ratio, 1.0);
+        assert!((stats.pixels as f32 - 320.0 * 320.0 / 2.0).abs() < 320.0);
+
+        let empty = depth_stats_in_rect(&depth, Rect::new(700.0, 0.0, 800.0, 10.0));
+        assert_eq!(empty, DepthStats::default());
+    }
+}
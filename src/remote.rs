@@ -0,0 +1,809 @@
+//! Remote control over HTTP, for fixing things from a phone when there is no
+//! keyboard at the installation. A small server thread answers
+//! `GET /status` with a JSON snapshot that the app refreshes every frame and
+//! forwards commands to the app over a channel:
+//!
+//! * `/pause`, `/resume` stop and start the Kinect
+//! * `/tilt?degrees=<d>` moves the motor
+//! * `/threshold?value=<raw>` sets the close-blob threshold
+//! * `/view?mode=<shadow|rainbow|greenscreen|exposure>` switches the depth view
+//! * `/preset?name=<name>` switches to a preset, `/preset/save?name=<name>`
+//!   saves the current settings as one
+//!
+//! `GET /metrics` has frame rates, frame counts and losses, how stale the
+//! latest depth frame is, visitors and the device state in the Prometheus
+//! text format, for fleet monitoring to scrape.
+//!
+//! Saved clips handed to [`RemoteServer::share`] are served from
+//! `/media/<file>`, for visitors to download from their phones.
+//!
+//! `/` is a dashboard page with both of these and a live preview of the
+//! depth and video views, `/preview/depth` and `/preview/video`, streamed
+//! MJPEG-style as `multipart/x-mixed-replace` PNGs. Every connection gets a
+//! thread of its own, previews stay open for as long as someone watches, up
+//! to [`MAX_CONNECTIONS`] at once.
+//!
+//! Commands are `POST` only, so a link or an `<img>` on another site can't
+//! send them, and a request from a page of another origin is refused. With
+//! [`RemoteSettings::token`] set everything but the dashboard page and
+//! `/media/` needs it, as `Authorization: Bearer <token>` or `?token=<token>`;
+//! the dashboard passes on the one it was opened with, `/?token=<token>`.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, BufRead, BufReader, ErrorKind, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use bevy::diagnostic::Diagnostics;
+use bevy::prelude::*;
+use serde_json::json;
+
+use crate::analytics::Analytics;
+use crate::attract::AttractMode;
+use crate::backend::DepthCamera;
+use crate::device::KinectCommand;
+use crate::health;
+use crate::presets::{PresetRequest, Presets};
+use crate::proximity::Proximity;
+use crate::reconnect::{Connection, KinectState};
+use crate::tilt::TiltState;
+use crate::video::{CurrentVideo, VideoSettings};
+use crate::{CurrentDepth, DepthStyle, MainKinect, TrackingSettings};
+
+/// How long a client gets to send its request.
+const READ_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Seconds between preview frames, PNG encoding is not cheap.
+const PREVIEW_INTERVAL: f32 = 0.2;
+
+/// Connections served at once, previews included; more get a 503.
+pub const MAX_CONNECTIONS: usize = 16;
+
+const DASHBOARD: &str = include_str!("dashboard.html");
+
+#[derive(Resource, Clone, Debug, Default)]
+pub struct RemoteSettings {
+    /// address to listen on, the server is off without one
+    pub addr: Option<SocketAddr>,
+    /// how others reach the server, e.g. `http://kiosk.local:8080`, for
+    /// links to media; guessed from `addr` without one
+    pub public_url: Option<String>,
+    /// what requests have to come with, anyone on the network can use the
+    /// server without one
+    pub token: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum RemoteCommand {
+    Pause,
+    Resume,
+    Tilt(f64),
+    Threshold(u16),
+    View(DepthStyle),
+    Preset(PresetRequest),
+}
+
+#[derive(Default)]
+struct PreviewFrame {
+    /// bumped on every update, so streams only send new frames
+    seq: u64,
+    width: u32,
+    height: u32,
+    rgba: Vec<u8>,
+}
+
+/// What the app publishes for the server thread.
+#[derive(Default)]
+struct Shared {
+    /// latest status as JSON, what `GET /status` answers with
+    status: Mutex<String>,
+    /// latest metrics, what `GET /metrics` answers with
+    metrics: Mutex<String>,
+    depth: Mutex<PreviewFrame>,
+    video: Mutex<PreviewFrame>,
+    /// files under `/media/` by name
+    media: Mutex<HashMap<String, PathBuf>>,
+    token: Option<String>,
+}
+
+/// The running server, only there when it started.
+#[derive(Resource)]
+pub struct RemoteServer {
+    commands: Mutex<Receiver<RemoteCommand>>,
+    shared: Arc<Shared>,
+    public_url: String,
+}
+
+impl RemoteServer {
+    /// Serves the file from `/media/` and returns its URL.
+    pub fn share(&self, path: &Path) -> Option<String> {
+        let name = path.file_name()?.to_str()?.to_string();
+        let url = format!("{}/media/{name}", self.public_url);
+        self.shared
+            .media
+            .lock()
+            .unwrap()
+            .insert(name, path.to_path_buf());
+        Some(url)
+    }
+}
+
+#[derive(Resource)]
+struct PreviewTimer(Timer);
+
+pub struct RemotePlugin;
+
+impl Plugin for RemotePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<RemoteSettings>()
+            .insert_resource(PreviewTimer(Timer::from_seconds(
+                PREVIEW_INTERVAL,
+                TimerMode::Repeating,
+            )))
+            .add_startup_system(start_server)
+            .add_system(apply_remote_commands)
+            .add_system(publish_status)
+            .add_system(publish_metrics)
+            .add_system(publish_previews);
+    }
+}
+
+fn start_server(mut commands: Commands, settings: Res<RemoteSettings>) {
+    let addr = match settings.addr {
+        Some(addr) => addr,
+        None => return,
+    };
+    let listener = match TcpListener::bind(addr) {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!("Unable to start remote control on {addr}: {err}");
+            return;
+        }
+    };
+    info!("Remote control listening on http://{addr}");
+    let public_url = settings
+        .public_url
+        .clone()
+        .unwrap_or_else(|| guess_public_url(addr));
+
+    let (sender, receiver) = channel();
+    let shared = Arc::new(Shared {
+        token: settings.token.clone(),
+        ..default()
+    });
+    {
+        let shared = shared.clone();
+        thread::Builder::new()
+            .name("remote".into())
+            .spawn(move || serve(listener, sender, shared))
+            .unwrap();
+    }
+    commands.insert_resource(RemoteServer {
+        commands: Mutex::new(receiver),
+        shared,
+        public_url: public_url.trim_end_matches('/').to_string(),
+    });
+}
+
+/// `http://<addr>`, with the address of the interface the default route goes
+/// out of when listening on all of them.
+fn guess_public_url(addr: SocketAddr) -> String {
+    let ip = match addr.ip() {
+        ip if !ip.is_unspecified() => ip,
+        // connecting a UDP socket sends nothing, it only picks the interface
+        _ => UdpSocket::bind("0.0.0.0:0")
+            .and_then(|socket| {
+                socket.connect("8.8.8.8:80")?;
+                socket.local_addr()
+            })
+            .map(|local| local.ip())
+            .unwrap_or(IpAddr::from([127, 0, 0, 1])),
+    };
+    format!("http://{}", SocketAddr::new(ip, addr.port()))
+}
+
+fn serve(listener: TcpListener, commands: Sender<RemoteCommand>, shared: Arc<Shared>) {
+    let open = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                warn!("Remote control connection failed: {err}");
+                continue;
+            }
+        };
+        if open.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+            open.fetch_sub(1, Ordering::SeqCst);
+            let _ = respond(
+                &mut stream,
+                "503 Service Unavailable",
+                "text/plain",
+                "too many connections\n",
+            );
+            continue;
+        }
+        let commands = commands.clone();
+        let shared = shared.clone();
+        let open = open.clone();
+        thread::spawn(move || {
+            if let Err(err) = handle(stream, &commands, &shared) {
+                warn!("Remote control request failed: {err}");
+            }
+            open.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+}
+
+/// The parts of a request that matter here.
+#[derive(Debug, Default, PartialEq)]
+struct Request {
+    method: String,
+    path: String,
+    query: String,
+    host: Option<String>,
+    origin: Option<String>,
+    bearer: Option<String>,
+}
+
+impl Request {
+    fn read(reader: &mut impl BufRead) -> io::Result<Request> {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let mut parts = line.split_whitespace();
+        let method = parts.next().unwrap_or("GET").to_string();
+        let target = parts.next().unwrap_or("/");
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let mut request = Request {
+            method,
+            path: path.to_string(),
+            query: query.to_string(),
+            ..default()
+        };
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? <= 2 {
+                return Ok(request);
+            }
+            let (name, value) = match line.split_once(':') {
+                Some((name, value)) => (name.trim().to_ascii_lowercase(), value.trim()),
+                None => continue,
+            };
+            match name.as_str() {
+                "host" => request.host = Some(value.to_string()),
+                "origin" => request.origin = Some(value.to_string()),
+                "authorization" => {
+                    request.bearer = value.strip_prefix("Bearer ").map(str::to_string)
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn param(&self, name: &str) -> Option<String> {
+        self.query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| decode(value))
+    }
+
+    /// Browsers send an `Origin` with requests a page makes; one from a page
+    /// the server didn't serve is another site's.
+    fn is_cross_origin(&self) -> bool {
+        match (&self.origin, &self.host) {
+            (Some(origin), Some(host)) => {
+                let origin = origin
+                    .split_once("://")
+                    .map_or(origin.as_str(), |(_, rest)| rest);
+                origin != host
+            }
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    }
+
+    fn has_token(&self, token: Option<&str>) -> bool {
+        let token = match token {
+            Some(token) => token,
+            None => return true,
+        };
+        let given = self.bearer.clone().or_else(|| self.param("token"));
+        given.is_some_and(|given| same(given.as_bytes(), token.as_bytes()))
+    }
+}
+
+/// Compares without stopping at the first difference, so the time taken
+/// doesn't tell how much of a guess was right.
+fn same(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn respond(stream: &mut TcpStream, code: &str, content_type: &str, body: &str) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {code}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+fn handle(
+    mut stream: TcpStream,
+    commands: &Sender<RemoteCommand>,
+    shared: &Shared,
+) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let request = Request::read(&mut BufReader::new(&stream))?;
+    let path = request.path.as_str();
+    let public = path == "/" || path.starts_with("/media/");
+    if request.is_cross_origin() {
+        return respond(
+            &mut stream,
+            "403 Forbidden",
+            "text/plain",
+            "cross-origin request\n",
+        );
+    }
+    if !public && !request.has_token(shared.token.as_deref()) {
+        return respond(
+            &mut stream,
+            "401 Unauthorized",
+            "text/plain",
+            "missing or wrong token\n",
+        );
+    }
+
+    let readable = public
+        || matches!(
+            path,
+            "/status" | "/metrics" | "/preview/depth" | "/preview/video"
+        );
+    let (code, content_type, body) = match path {
+        _ if readable && request.method != "GET" => (
+            "405 Method Not Allowed",
+            "text/plain",
+            "use GET\n".to_string(),
+        ),
+        "/" => ("200 OK", "text/html", DASHBOARD.to_string()),
+        "/status" => (
+            "200 OK",
+            "application/json",
+            shared.status.lock().unwrap().clone(),
+        ),
+        "/metrics" => (
+            "200 OK",
+            "text/plain; version=0.0.4",
+            shared.metrics.lock().unwrap().clone(),
+        ),
+        "/preview/depth" => return stream_preview(stream, &shared.depth),
+        "/preview/video" => return stream_preview(stream, &shared.video),
+        _ if path.starts_with("/media/") => {
+            let file = shared.media.lock().unwrap().get(&path[7..]).cloned();
+            return serve_media(stream, file.as_deref());
+        }
+        _ => match parse_command(&request) {
+            Ok(_) if request.method != "POST" => (
+                "405 Method Not Allowed",
+                "text/plain",
+                "commands are POST only\n".to_string(),
+            ),
+            Ok(command) => {
+                // the app only goes away on exit
+                let _ = commands.send(command);
+                ("200 OK", "text/plain", "ok\n".to_string())
+            }
+            Err((code, message)) => (code, "text/plain", message + "\n"),
+        },
+    };
+
+    respond(&mut stream, code, content_type, &body)
+}
+
+fn serve_media(mut stream: TcpStream, file: Option<&Path>) -> io::Result<()> {
+    let (file, body) = match file.map(|file| (file, fs::read(file))) {
+        Some((file, Ok(body))) => (file, body),
+        _ => {
+            return respond(&mut stream, "404 Not Found", "text/plain", "no such file\n");
+        }
+    };
+    let content_type = match file.extension().and_then(|extension| extension.to_str()) {
+        Some("gif") => "image/gif",
+        Some("mp4") => "video/mp4",
+        Some("webm") => "video/webm",
+        Some("png") => "image/png",
+        _ => "application/octet-stream",
+    };
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n",
+        body.len()
+    )?;
+    stream.write_all(&body)
+}
+
+/// Sends each new frame as a PNG part until the viewer goes away.
+fn stream_preview(mut stream: TcpStream, frame: &Mutex<PreviewFrame>) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: multipart/x-mixed-replace; boundary=frame\r\n\
+         Cache-Control: no-cache\r\nConnection: close\r\n\r\n"
+    )?;
+
+    let mut sent = None;
+    loop {
+        let latest = {
+            let frame = frame.lock().unwrap();
+            (!frame.rgba.is_empty() && sent != Some(frame.seq))
+                .then(|| (frame.seq, frame.width, frame.height, frame.rgba.clone()))
+        };
+        if let Some((seq, width, height, rgba)) = latest {
+            let png = encode_png(width, height, &rgba)?;
+            let part = write!(
+                stream,
+                "--frame\r\nContent-Type: image/png\r\nContent-Length: {}\r\n\r\n",
+                png.len()
+            )
+            .and_then(|_| stream.write_all(&png))
+            .and_then(|_| stream.write_all(b"\r\n"));
+            match part {
+                Ok(()) => sent = Some(seq),
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        ErrorKind::BrokenPipe | ErrorKind::ConnectionReset
+                    ) =>
+                {
+                    return Ok(())
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        thread::sleep(Duration::from_secs_f32(PREVIEW_INTERVAL));
+    }
+}
+
+fn encode_png(width: u32, height: u32, rgba: &[u8]) -> io::Result<Vec<u8>> {
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_compression(png::Compression::Fast);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(rgba))?;
+    Ok(png)
+}
+
+fn parse_command(request: &Request) -> Result<RemoteCommand, (&'static str, String)> {
+    let param = |name: &str| {
+        request
+            .param(name)
+            .ok_or_else(|| ("400 Bad Request", format!("missing parameter '{name}'")))
+    };
+    let invalid = |value: &str| ("400 Bad Request", format!("invalid value '{value}'"));
+
+    match request.path.as_str() {
+        "/pause" => Ok(RemoteCommand::Pause),
+        "/resume" => Ok(RemoteCommand::Resume),
+        "/tilt" => {
+            let degrees = param("degrees")?;
+            degrees
+                .parse()
+                .map(RemoteCommand::Tilt)
+                .map_err(|_| invalid(&degrees))
+        }
+        "/threshold" => {
+            let value = param("value")?;
+            value
+                .parse()
+                .map(RemoteCommand::Threshold)
+                .map_err(|_| invalid(&value))
+        }
+        "/view" => param("mode")?
+            .parse()
+            .map(RemoteCommand::View)
+            .map_err(|err| ("400 Bad Request", err)),
+        "/preset" => Ok(RemoteCommand::Preset(PresetRequest::Apply(param("name")?))),
+        "/preset/save" => Ok(RemoteCommand::Preset(PresetRequest::Save(param("name")?))),
+        path => Err(("404 Not Found", format!("no such endpoint '{path}'"))),
+    }
+}
+
+/// Undoes the URL encoding of a query value.
+fn decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+            (b'+', _) => decoded.push(b' '),
+            (byte, _) => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn apply_remote_commands(
+    server: Option<Res<RemoteServer>>,
+    mut tracking: ResMut<TrackingSettings>,
+    mut style: ResMut<DepthStyle>,
+    mut device: EventWriter<KinectCommand>,
+    mut presets: EventWriter<PresetRequest>,
+) {
+    let server = match server {
+        Some(server) => server,
+        None => return,
+    };
+    for command in server.commands.lock().unwrap().try_iter() {
+        info!("Remote control: {command:?}");
+        match command {
+            RemoteCommand::Pause => device.send(KinectCommand::Close),
+            RemoteCommand::Resume => device.send(KinectCommand::Open),
+            RemoteCommand::Tilt(degrees) => device.send(KinectCommand::Tilt(degrees)),
+            RemoteCommand::Threshold(threshold) => tracking.threshold = threshold,
+            RemoteCommand::View(view) => *style = view,
+            RemoteCommand::Preset(request) => presets.send(request),
+        }
+    }
+}
+
+fn publish_status(
+    server: Option<Res<RemoteServer>>,
+    camera: Res<DepthCamera>,
+    connection: Res<Connection>,
+    tilt: Res<TiltState>,
+    (tracking, style): (Res<TrackingSettings>, Res<DepthStyle>),
+    attract: Res<AttractMode>,
+    presets: Res<Presets>,
+) {
+    let server = match server {
+        Some(server) => server,
+        None => return,
+    };
+    let health = camera.health();
+    let status = json!({
+        "running": camera.is_running(),
+        "frames": {
+            "depth": camera.depth_frames_received(),
+            "video": camera.video_frames_received(),
+        },
+        "health": {
+            "missed": health.missed_frames,
+            "restarts": health.restarts,
+            "jitter_ms": health.jitter_ms,
+        },
+        "streaming": connection.is_streaming(),
+        "attempt": connection.attempt(),
+        "error": connection.last_error().map(|err| err.to_string()),
+        "tilt": {
+            "angle": tilt.angle,
+            "pitch": tilt.pitch,
+            "roll": tilt.roll,
+            "status": format!("{:?}", tilt.status),
+            "target": tilt.target,
+        },
+        "threshold": tracking.threshold,
+        "view": *style,
+        "attract": attract.is_active(),
+        "preset": presets.active,
+        "presets": presets.list.iter().map(|preset| &preset.name).collect::<Vec<_>>(),
+    });
+    *server.shared.status.lock().unwrap() = status.to_string();
+}
+
+/// One Prometheus metric, `kind` being `gauge` or `counter`.
+fn metric(out: &mut String, name: &str, kind: &str, help: &str, value: f64) {
+    let _ = writeln!(
+        out,
+        "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}"
+    );
+}
+
+fn publish_metrics(
+    server: Option<Res<RemoteServer>>,
+    camera: Res<DepthCamera>,
+    (state, diagnostics, time): (Res<State<KinectState>>, Res<Diagnostics>, Res<Time>),
+    depth_query: Query<&CurrentDepth, With<MainKinect>>,
+    (proximity, analytics, attract): (Res<Proximity>, Res<Analytics>, Res<AttractMode>),
+) {
+    let server = match server {
+        Some(server) => server,
+        None => return,
+    };
+    let health = camera.health();
+    let depth_fps = diagnostics
+        .get(health::DEPTH_FPS)
+        .and_then(|fps| fps.value())
+        .unwrap_or(0.0);
+    let depth_age = depth_query
+        .get_single()
+        .map_or(0.0, |depth| time.elapsed_seconds_f64() - depth.received_at);
+
+    let mut out = String::new();
+    let counters = [
+        (
+            "kinect_depth_frames_total",
+            "Depth frames delivered by the device.",
+            health.depth_frames,
+        ),
+        (
+            "kinect_video_frames_total",
+            "Video frames delivered by the device.",
+            camera.video_frames_received(),
+        ),
+        (
+            "kinect_depth_missed_frames_total",
+            "Depth frames lost on the way, incomplete ones included.",
+            health.missed_frames,
+        ),
+        (
+            "kinect_restarts_total",
+            "Times the streams were reopened.",
+            health.restarts,
+        ),
+        (
+            "kinect_sessions_total",
+            "Visitor sessions that ended.",
+            analytics.sessions.len() as u64,
+        ),
+    ];
+    for (name, help, value) in counters {
+        metric(&mut out, name, "counter", help, value as f64);
+    }
+    let gauges = [
+        (
+            "kinect_depth_fps",
+            "Depth frames per second over the last second.",
+            depth_fps,
+        ),
+        (
+            "kinect_depth_jitter_seconds",
+            "Spread of the time between depth frames.",
+            health.jitter_ms as f64 / 1000.0,
+        ),
+        (
+            "kinect_depth_age_seconds",
+            "Time since the latest depth frame arrived.",
+            depth_age,
+        ),
+        (
+            "kinect_frame_seconds",
+            "Duration of the last app frame.",
+            time.delta_seconds_f64(),
+        ),
+        (
+            "kinect_running",
+            "1 while the device is open.",
+            camera.is_running() as u8 as f64,
+        ),
+        (
+            "kinect_visitor_present",
+            "1 while someone stands out from the background.",
+            proximity.meters.is_some() as u8 as f64,
+        ),
+        (
+            "kinect_visitor_distance_meters",
+            "Distance of the nearest visitor, 0 without one.",
+            proximity.meters.unwrap_or(0.0) as f64,
+        ),
+        (
+            "kinect_attract_active",
+            "1 while in attract mode.",
+            attract.is_active() as u8 as f64,
+        ),
+    ];
+    for (name, help, value) in gauges {
+        metric(&mut out, name, "gauge", help, value);
+    }
+    let _ = writeln!(
+        out,
+        "# HELP kinect_state The connection state, 1 for the current one.\n# TYPE kinect_state gauge"
+    );
+    for (label, variant) in [
+        ("connecting", KinectState::Connecting),
+        ("streaming", KinectState::Streaming),
+        ("disconnected", KinectState::Disconnected),
+    ] {
+        let current = (*state.current() == variant) as u8;
+        let _ = writeln!(out, "kinect_state{{state=\"{label}\"}} {current}");
+    }
+    *server.shared.metrics.lock().unwrap() = out;
+}
+
+fn publish_previews(
+    server: Option<Res<RemoteServer>>,
+    settings: Res<VideoSettings>,
+    time: Res<Time>,
+    mut timer: ResMut<PreviewTimer>,
+    depth_query: Query<&CurrentDepth, With<MainKinect>>,
+    video_query: Query<&CurrentVideo>,
+    images: Res<Assets<Image>>,
+) {
+    let server = match server {
+        Some(server) => server,
+        None => return,
+    };
+    if !timer.0.tick(time.delta()).just_finished() {
+        return;
+    }
+
+    if let Some(image) = depth_query
+        .get_single()
+        .ok()
+        .and_then(|depth| images.get(&depth.handle))
+    {
+        let size = image.texture_descriptor.size;
+        publish(&server.shared.depth, size.width, size.height, |rgba| {
+            rgba.extend_from_slice(&image.data)
+        });
+    }
+
+    if let Some(image) = video_query
+        .get_single()
+        .ok()
+        .and_then(|video| images.get(&video.handle))
+    {
+        let (width, height) = settings.resolution.size();
+        publish(&server.shared.video, width, height, |rgba| {
+            settings.image_to_rgba(&image.data, rgba)
+        });
+    }
+}
+
+fn publish(frame: &Mutex<PreviewFrame>, width: u32, height: u32, fill: impl FnOnce(&mut Vec<u8>)) {
+    let mut frame = frame.lock().unwrap();
+    frame.seq += 1;
+    frame.width = width;
+    frame.height = height;
+    frame.rgba.clear();
+    fill(&mut frame.rgba);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(text: &str) -> Request {
+        Request::read(&mut text.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn requests_need_the_token_and_the_same_origin() {
+        let post = request(
+            "POST /tilt?degrees=5&token=secret HTTP/1.1\r\nHost: kiosk:8080\r\n\
+             Origin: http://kiosk:8080\r\n\r\n",
+        );
+        assert_eq!(post.method, "POST");
+        assert_eq!(parse_command(&post), Ok(RemoteCommand::Tilt(5.0)));
+        assert!(!post.is_cross_origin());
+        assert!(post.has_token(Some("secret")) && !post.has_token(Some("secrets")));
+
+        let bearer = request("GET /status HTTP/1.1\r\nauthorization: Bearer secret\r\n\r\n");
+        assert!(bearer.has_token(Some("secret")));
+        assert!(!request("GET /status HTTP/1.1\r\n\r\n").has_token(Some("secret")));
+        assert!(request("GET /status HTTP/1.1\r\n\r\n").has_token(None));
+
+        let other = request(
+            "POST /pause HTTP/1.1\r\nHost: kiosk:8080\r\nOrigin: http://evil.example\r\n\r\n",
+        );
+        assert!(other.is_cross_origin());
+    }
+}
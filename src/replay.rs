@@ -0,0 +1,250 @@
+//! Sessions of depth frames run through the tracking code outside the app,
+//! for checking that changes to it keep tracking steady. A session is
+//! recorded with `--dataset` (the `depth/` PNGs) or rendered from a
+//! [`crate::simulation`] scene; the functions here track through one and
+//! measure the result, and the tests below hold the tracking to:
+//!
+//! * a still scene gives a still crosshair, within [`MAX_STILL_JITTER`]
+//! * the hands never trade places while they are apart
+//! * a blob moving smoothly is tracked without jumps
+
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+
+use bevy::prelude::*;
+
+use crate::coords::{DEPTH_HEIGHT, DEPTH_WIDTH};
+use crate::simulation::{Scene, SimulatedKinect};
+use crate::skeleton::{self, Joint, Skeleton};
+
+/// Most the close blob center may wander on a still scene, in depth pixels.
+pub const MAX_STILL_JITTER: f32 = 2.0;
+
+/// Depth frames in order, 640x480 raw readings each.
+pub struct Session {
+    pub frames: Vec<Vec<u16>>,
+}
+
+impl Session {
+    /// The depth frames of a `--dataset` directory, in the order taken.
+    pub fn load_dataset(dir: &Path) -> io::Result<Session> {
+        let mut paths: Vec<_> = fs::read_dir(dir.join("depth"))?
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "png"))
+            .collect();
+        paths.sort();
+        let frames = paths
+            .iter()
+            .map(|path| read_depth_png(path))
+            .collect::<io::Result<_>>()?;
+        Ok(Session { frames })
+    }
+
+    /// `seconds` of a scene at the Kinect's 30 Hz.
+    pub fn render(scene: Scene, seconds: f32) -> Session {
+        let mut kinect = SimulatedKinect::new(scene);
+        let frames = (0..(seconds * 30.0) as usize)
+            .map(|frame| kinect.render_depth(frame as f32 / 30.0))
+            .collect();
+        Session { frames }
+    }
+
+    /// Close blob center in each frame, as the crosshair follows it.
+    pub fn close_blob(&self, threshold: u16) -> Vec<Option<Vec2>> {
+        self.frames
+            .iter()
+            .map(|frame| {
+                crate::close_blob_bounds(frame, DEPTH_WIDTH, threshold)
+                    .map(|bounds| bounds.center())
+            })
+            .collect()
+    }
+
+    /// Skeleton in each frame, with everything closer than `threshold` as
+    /// the person.
+    pub fn skeletons(&self, threshold: u16) -> Vec<Skeleton> {
+        self.frames
+            .iter()
+            .map(|frame| {
+                let mask: Vec<bool> = frame.iter().map(|raw| *raw < threshold).collect();
+                skeleton::silhouette_skeleton(&mask, frame).unwrap_or_default()
+            })
+            .collect()
+    }
+}
+
+fn read_depth_png(path: &Path) -> io::Result<Vec<u16>> {
+    let invalid = |err: png::DecodingError| io::Error::new(io::ErrorKind::InvalidData, err);
+    let mut reader = png::Decoder::new(File::open(path)?)
+        .read_info()
+        .map_err(invalid)?;
+    let mut bytes = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut bytes).map_err(invalid)?;
+    if (info.width as usize, info.height as usize) != (DEPTH_WIDTH, DEPTH_HEIGHT)
+        || info.bit_depth != png::BitDepth::Sixteen
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} is not a 16 bit 640x480 depth frame", path.display()),
+        ));
+    }
+    Ok(bytes[..info.buffer_size()]
+        .chunks_exact(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+        .collect())
+}
+
+/// Furthest any point of a track gets from its mean, ignoring frames without
+/// one.
+pub fn jitter(track: &[Option<Vec2>]) -> f32 {
+    let points: Vec<Vec2> = track.iter().flatten().copied().collect();
+    if points.is_empty() {
+        return 0.0;
+    }
+    let mean = points.iter().sum::<Vec2>() / points.len() as f32;
+    points
+        .iter()
+        .map(|point| point.distance(mean))
+        .fold(0.0, f32::max)
+}
+
+/// Longest step between consecutive frames that both have a point.
+pub fn largest_step(track: &[Option<Vec2>]) -> f32 {
+    track
+        .windows(2)
+        .filter_map(|pair| Some(pair[0]?.distance(pair[1]?)))
+        .fold(0.0, f32::max)
+}
+
+/// Frames where the hands traded sides from the frame before, counting only
+/// frames with both hands at least `apart` meters from each other.
+pub fn hand_swaps(skeletons: &[Skeleton], apart: f32) -> usize {
+    let sides: Vec<Option<bool>> = skeletons
+        .iter()
+        .map(|skeleton| {
+            let left = skeleton.get(Joint::HandLeft)?.position;
+            let right = skeleton.get(Joint::HandRight)?.position;
+            // their left is on the sensor's right
+            ((left.x - right.x).abs() >= apart).then_some(left.x > right.x)
+        })
+        .collect();
+    sides
+        .windows(2)
+        .filter(|pair| matches!(pair, [Some(a), Some(b)] if a != b))
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coords;
+    use crate::simulation::{Ellipsoid, Keyframe, Plane};
+
+    fn back_wall() -> Plane {
+        Plane {
+            point: Vec3::new(0.0, 0.0, 4.0),
+            normal: Vec3::NEG_Z,
+            color: Vec3::ONE,
+        }
+    }
+
+    fn still(radii: Vec3, center: Vec3) -> Ellipsoid {
+        moving(radii, &[(0.0, center)])
+    }
+
+    fn moving(radii: Vec3, path: &[(f32, Vec3)]) -> Ellipsoid {
+        Ellipsoid {
+            radii,
+            path: path
+                .iter()
+                .map(|(at, center)| Keyframe {
+                    at: *at,
+                    center: *center,
+                })
+                .collect(),
+            looped: true,
+            color: Vec3::ONE,
+        }
+    }
+
+    #[test]
+    fn still_scenes_give_a_still_crosshair() {
+        let threshold = coords::meters_to_raw_depth(1.5);
+        for x in [-0.4, 0.0, 0.3] {
+            for z in [0.8, 1.2] {
+                let scene = Scene {
+                    planes: vec![back_wall()],
+                    ellipsoids: vec![still(Vec3::splat(0.1), Vec3::new(x, 0.0, z))],
+                    noise_at_1m: 0.005,
+                };
+                let track = Session::render(scene, 2.0).close_blob(threshold);
+                assert!(
+                    track.iter().all(Option::is_some),
+                    "lost the blob at {x} {z}"
+                );
+                let jitter = jitter(&track);
+                assert!(jitter <= MAX_STILL_JITTER, "jitter {jitter} at {x} {z}");
+            }
+        }
+    }
+
+    #[test]
+    fn hands_apart_never_trade_places() {
+        let threshold = coords::meters_to_raw_depth(3.0);
+        for reach in [0.45, 0.6] {
+            // waving out of step, one up while the other is down
+            let hand = |side: f32, phase: f32| {
+                let up = Vec3::new(side * reach, 0.3, 2.3);
+                let down = Vec3::new(side * reach, -0.1, 2.3);
+                let (first, second) = if phase > 0.0 { (up, down) } else { (down, up) };
+                moving(
+                    Vec3::splat(0.06),
+                    &[(0.0, first), (1.0, second), (2.0, first)],
+                )
+            };
+            let scene = Scene {
+                planes: vec![back_wall()],
+                ellipsoids: vec![
+                    still(Vec3::new(0.2, 0.8, 0.15), Vec3::new(0.0, -0.2, 2.4)),
+                    hand(1.0, 1.0),
+                    hand(-1.0, -1.0),
+                ],
+                noise_at_1m: 0.005,
+            };
+            let skeletons = Session::render(scene, 2.0).skeletons(threshold);
+            assert!(skeletons.iter().all(Skeleton::is_tracked));
+            assert_eq!(hand_swaps(&skeletons, 0.3), 0, "reach {reach}");
+        }
+    }
+
+    #[test]
+    fn the_sweep_is_followed_across_the_view() {
+        let threshold = coords::meters_to_raw_depth(1.5);
+        let track = Session::render(Scene::sweep(), 2.0).close_blob(threshold);
+        assert!(track.iter().all(Option::is_some));
+        let (first, last) = (track[0].unwrap(), track[track.len() - 1].unwrap());
+        assert!(first.x < 150.0 && last.x > 490.0, "{first} -> {last}");
+    }
+
+    #[test]
+    fn smooth_motion_is_tracked_without_jumps() {
+        let threshold = coords::meters_to_raw_depth(1.5);
+        let scene = Scene {
+            planes: vec![back_wall()],
+            ellipsoids: vec![moving(
+                Vec3::splat(0.1),
+                &[
+                    (0.0, Vec3::new(-0.3, 0.0, 1.0)),
+                    (1.5, Vec3::new(0.3, 0.1, 1.0)),
+                    (3.0, Vec3::new(-0.3, 0.0, 1.0)),
+                ],
+            )],
+            noise_at_1m: 0.005,
+        };
+        let track = Session::render(scene, 3.0).close_blob(threshold);
+        // 0.6 m in 45 frames at 1 m is about 8 pixels a frame
+        let step = largest_step(&track);
+        assert!(step < 12.0, "jumped {step} pixels");
+    }
+}
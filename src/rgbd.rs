@@ -0,0 +1,97 @@
+//! Color and depth of the same pixel, with the depth registered onto the
+//! color camera ([`DepthFormat::Registered`]). [`Rgbd`] holds the 640x480
+//! video with the distance at every pixel, refreshed with each depth frame,
+//! for apps that cut out, relight or texture what the sensor sees.
+//!
+//! Without registration the depth camera sits a few centimeters beside the
+//! color one and sees a slightly different picture, so [`Rgbd`] stays empty.
+
+use bevy::prelude::*;
+
+use crate::coords::{self, DEPTH_HEIGHT, DEPTH_WIDTH};
+use crate::video::{CurrentVideo, VideoResolution, VideoSettings};
+use crate::{CurrentDepth, DepthFormat, KinectConfig, MainKinect};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RgbdPixel {
+    pub rgba: [u8; 4],
+    /// `None` where there is no reading
+    pub meters: Option<f32>,
+}
+
+/// Video and depth pixel by pixel, row by row, empty until registered depth
+/// and medium resolution video arrive.
+#[derive(Resource, Clone, Debug, Default)]
+pub struct Rgbd {
+    pub pixels: Vec<RgbdPixel>,
+}
+
+impl Rgbd {
+    pub fn get(&self, x: usize, y: usize) -> Option<&RgbdPixel> {
+        if x >= DEPTH_WIDTH {
+            return None;
+        }
+        self.pixels.get(y * DEPTH_WIDTH + x)
+    }
+}
+
+/// Pairs each RGBA pixel with the reading at the same place.
+pub fn combine(rgba: &[u8], depth: &[u16]) -> Vec<RgbdPixel> {
+    rgba.chunks_exact(4)
+        .zip(depth)
+        .map(|(color, raw)| RgbdPixel {
+            rgba: [color[0], color[1], color[2], color[3]],
+            meters: coords::raw_depth_to_meters(*raw),
+        })
+        .collect()
+}
+
+pub struct RgbdPlugin;
+
+impl Plugin for RgbdPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Rgbd>()
+            .add_startup_system(check_registration)
+            .add_system(update_rgbd.after(crate::KinectSet::Acquire));
+    }
+}
+
+fn check_registration(config: Res<KinectConfig>, video: Res<VideoSettings>) {
+    if config.depth_format == DepthFormat::Registered && video.resolution != VideoResolution::Medium
+    {
+        warn!("Depth is registered to medium resolution video only, no RGBA-D view");
+    }
+}
+
+fn update_rgbd(
+    config: Res<KinectConfig>,
+    video_settings: Res<VideoSettings>,
+    depth_query: Query<&CurrentDepth, (With<MainKinect>, Changed<CurrentDepth>)>,
+    video_query: Query<&CurrentVideo>,
+    images: Res<Assets<Image>>,
+    mut rgbd: ResMut<Rgbd>,
+    mut video: Local<Vec<u8>>,
+) {
+    if config.depth_format != DepthFormat::Registered
+        || video_settings.resolution != VideoResolution::Medium
+    {
+        return;
+    }
+    let depth = match depth_query.get_single() {
+        Ok(depth) if depth.depth_array.len() == DEPTH_WIDTH * DEPTH_HEIGHT => depth,
+        _ => return,
+    };
+    let source = match video_query
+        .get_single()
+        .ok()
+        .and_then(|current| images.get(&current.handle))
+    {
+        Some(image) => image,
+        None => return,
+    };
+    video_settings.image_to_rgba(&source.data, &mut video);
+    if video.len() != DEPTH_WIDTH * DEPTH_HEIGHT * 4 {
+        return;
+    }
+    rgbd.pixels = combine(&video, &depth.depth_array);
+}
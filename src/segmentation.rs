@@ -0,0 +1,287 @@
+//! Person mask for the green-screen view. Depth alone tells people from the
+//! background well, but has holes wherever the IR pattern doesn't come back:
+//! hair, thin limbs, edges. With [`SegmentationSettings::rgb`] set the mask
+//! follows an RGB model there instead, its person probability at the matching
+//! color pixel; without it holes are background.
+//!
+//! The RGB model is a person-segmentation network loaded from a `.onnx` file
+//! (the `onnx` feature), or background subtraction against a slowly updated
+//! average of the video, which is also what stands in when the network can't
+//! be loaded. Either only gets frames while something shows the mask: the
+//! green-screen view, or booth clips and recordings of the green screen.
+
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use bevy::prelude::*;
+
+use crate::booth::BoothSettings;
+use crate::composite::FrameSource;
+use crate::coords;
+use crate::inference::{self, Frame, FrameModel, FrameModelAppExt, ModelSwitch, Tensor};
+#[cfg(feature = "onnx")]
+use crate::onnx::OnnxModel;
+#[cfg(feature = "record")]
+use crate::recording::{Recording, RecordingSettings};
+use crate::video::VideoSettings;
+use crate::{CurrentDepth, DepthStyle, MainKinect, TrackingSettings, NO_DEPTH};
+
+/// How fast the background follows the video, per processed frame.
+const BACKGROUND_RATE: f32 = 0.02;
+/// Mean color difference (0..1) still counted as background.
+const BACKGROUND_TOLERANCE: f32 = 0.06;
+
+/// What fills the holes in the depth mask.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum RgbSegmentation {
+    /// nothing, holes are background
+    #[default]
+    Off,
+    /// background subtraction
+    Background,
+    /// a network taking `[1, 3, height, width]` RGB in 0..1 at the video's
+    /// resolution, as [`inference::video_tensor`] makes it, and returning
+    /// person probabilities `[1, c, h, w]`, the last channel being the
+    /// person's
+    Model(PathBuf),
+}
+
+impl FromStr for RgbSegmentation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(RgbSegmentation::Off),
+            "background" => Ok(RgbSegmentation::Background),
+            _ if s.ends_with(".onnx") => Ok(RgbSegmentation::Model(s.into())),
+            _ => Err(format!(
+                "unknown segmentation '{s}', expected off, background or a .onnx file"
+            )),
+        }
+    }
+}
+
+#[derive(Resource, Clone, Debug, Default)]
+pub struct SegmentationSettings {
+    pub rgb: RgbSegmentation,
+}
+
+/// Person probability per color pixel, shaped `[1, 1, height, width]`.
+#[derive(Resource)]
+pub struct PersonProbability(pub Tensor);
+
+/// Per depth pixel, whether it shows a person.
+#[derive(Resource, Default)]
+pub struct PersonMask(pub Vec<bool>);
+
+pub struct SegmentationPlugin;
+
+impl Plugin for SegmentationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SegmentationSettings>()
+            .init_resource::<PersonMask>()
+            .add_system(fuse_person_mask);
+        let video_size = app
+            .world
+            .get_resource::<VideoSettings>()
+            .copied()
+            .unwrap_or_default()
+            .resolution
+            .size();
+        let model = match app.world.resource::<SegmentationSettings>().rgb.clone() {
+            RgbSegmentation::Off => return,
+            RgbSegmentation::Background => background_model(),
+            RgbSegmentation::Model(path) => {
+                network_model(&path, video_size).unwrap_or_else(|err| {
+                    warn!("{err}, using background subtraction");
+                    background_model()
+                })
+            }
+        };
+        app.insert_resource(ModelSwitch::<PersonProbability>::new(false))
+            .add_frame_model(model)
+            .add_system(switch_rgb_model);
+        #[cfg(feature = "record")]
+        app.add_system(switch_rgb_model_for_recording.after(switch_rgb_model));
+    }
+}
+
+/// Feeds the model only while the mask is on show.
+fn switch_rgb_model(
+    style: Res<DepthStyle>,
+    booth: Res<BoothSettings>,
+    mut switch: ResMut<ModelSwitch<PersonProbability>>,
+) {
+    switch.on = *style == DepthStyle::GreenScreen
+        || (booth.dir.is_some() && booth.source == FrameSource::GreenScreen);
+}
+
+#[cfg(feature = "record")]
+fn switch_rgb_model_for_recording(
+    settings: Res<RecordingSettings>,
+    recording: Res<Recording>,
+    mut switch: ResMut<ModelSwitch<PersonProbability>>,
+) {
+    if recording.is_recording() && settings.source == FrameSource::GreenScreen {
+        switch.on = true;
+    }
+}
+
+#[cfg(feature = "onnx")]
+fn network_model(
+    path: &Path,
+    (width, height): (u32, u32),
+) -> Result<FrameModel<PersonProbability>, String> {
+    let (width, height) = (width as usize, height as usize);
+    let model = OnnxModel::load(path, &[1, 3, height, width])?;
+    Ok(FrameModel {
+        every: 2,
+        // the video mode may change after loading, the network keeps its size
+        preprocess: Box::new(move |frame| video_tensor_at(frame, width, height)),
+        model: Box::new(model),
+        postprocess: Box::new(|output| PersonProbability(person_channel(output))),
+    })
+}
+
+#[cfg(not(feature = "onnx"))]
+fn network_model(path: &Path, _: (u32, u32)) -> Result<FrameModel<PersonProbability>, String> {
+    Err(format!("{} needs the onnx feature", path.display()))
+}
+
+/// [`inference::video_tensor`] at a fixed size, the nearest video pixel for
+/// each.
+#[cfg_attr(not(feature = "onnx"), allow(dead_code))]
+fn video_tensor_at(frame: &Frame, width: usize, height: usize) -> Tensor {
+    let video = inference::video_tensor(frame);
+    let (from_height, from_width) = (video.shape[2], video.shape[3]);
+    if (from_width, from_height) == (width, height) || from_width == 0 {
+        return video;
+    }
+    let (plane, from_plane) = (width * height, from_width * from_height);
+    let mut data = vec![0.0; 3 * plane];
+    for y in 0..height {
+        let fy = y * from_height / height;
+        for x in 0..width {
+            let fx = x * from_width / width;
+            for c in 0..3 {
+                data[c * plane + y * width + x] = video.data[c * from_plane + fy * from_width + fx];
+            }
+        }
+    }
+    Tensor::new(vec![1, 3, height, width], data)
+}
+
+/// The last channel of a `[.., height, width]` output as `[1, 1, height,
+/// width]`; with background and person scores that is the person's.
+#[cfg_attr(not(feature = "onnx"), allow(dead_code))]
+fn person_channel(output: Tensor) -> Tensor {
+    let (height, width) = match output.shape[..] {
+        [.., height, width] => (height, width),
+        _ => (0, 0),
+    };
+    let start = output.data.len() - height * width;
+    Tensor::new(vec![1, 1, height, width], output.data[start..].to_vec())
+}
+
+fn background_model() -> FrameModel<PersonProbability> {
+    let mut background: Vec<f32> = Vec::new();
+    FrameModel {
+        every: 2,
+        preprocess: Box::new(inference::video_tensor),
+        model: Box::new(move |input: Tensor| {
+            let (height, width) = (input.shape[2], input.shape[3]);
+            let plane = width * height;
+            if background.len() != input.data.len() {
+                background = input.data.clone();
+            }
+
+            let mut probability = Vec::with_capacity(plane);
+            for i in 0..plane {
+                let difference = (0..3)
+                    .map(|c| (input.data[c * plane + i] - background[c * plane + i]).abs())
+                    .sum::<f32>()
+                    / 3.0;
+                let p = ((difference - BACKGROUND_TOLERANCE) * 10.0).clamp(0.0, 1.0);
+                // only learn what looks like background, people standing
+                // still would fade into it otherwise
+                if p < 0.5 {
+                    for c in 0..3 {
+                        let b = &mut background[c * plane + i];
+                        *b += (input.data[c * plane + i] - *b) * BACKGROUND_RATE;
+                    }
+                }
+                probability.push(p);
+            }
+            Ok(Tensor::new(vec![1, 1, height, width], probability))
+        }),
+        postprocess: Box::new(PersonProbability),
+    }
+}
+
+fn fuse_person_mask(
+    depth_query: Query<&CurrentDepth, (With<MainKinect>, Changed<CurrentDepth>)>,
+    tracking: Res<TrackingSettings>,
+    probability: Option<Res<PersonProbability>>,
+    switch: Option<Res<ModelSwitch<PersonProbability>>>,
+    mut mask: ResMut<PersonMask>,
+) {
+    // the last result from before the model was switched off is stale
+    let probability = probability.filter(|_| switch.is_some_and(|switch| switch.on));
+    let depth = match depth_query.get_single() {
+        Ok(depth) if depth.is_medium() => &depth.depth_array,
+        _ => return,
+    };
+    // holes get looked up as if they were as far as the foreground can be
+    let hole_meters = coords::raw_depth_to_meters(tracking.threshold).unwrap_or(1.0);
+
+    mask.0.clear();
+    for (i, raw) in depth.iter().enumerate() {
+        let person = if *raw != NO_DEPTH {
+            *raw < tracking.threshold
+        } else {
+            let pixel = Vec2::new(
+                (i % coords::DEPTH_WIDTH) as f32,
+                (i / coords::DEPTH_WIDTH) as f32,
+            );
+            let color = coords::depth_pixel_to_color_pixel(pixel, hole_meters);
+            match &probability {
+                Some(probability) => sample(&probability.0, color) > 0.5,
+                None => false,
+            }
+        };
+        mask.0.push(person);
+    }
+}
+
+/// Value of a `[1, 1, height, width]` tensor at a pixel of the 640x480 color
+/// image, scaled to whatever resolution the tensor has.
+fn sample(tensor: &Tensor, pixel: Vec2) -> f32 {
+    let (height, width) = (tensor.shape[2], tensor.shape[3]);
+    let scale = width as f32 / coords::DEPTH_WIDTH as f32;
+    let (x, y) = ((pixel.x * scale) as isize, (pixel.y * scale) as isize);
+    if x < 0 || y < 0 || x as usize >= width || y as usize >= height {
+        return 0.0;
+    }
+    tensor.data[y as usize * width + x as usize]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn network_output_gives_the_person_channel() {
+        assert_eq!("off".parse(), Ok(RgbSegmentation::Off));
+        assert_eq!(
+            "people.onnx".parse(),
+            Ok(RgbSegmentation::Model("people.onnx".into()))
+        );
+        assert!("people".parse::<RgbSegmentation>().is_err());
+
+        // background, then person scores for a 2x1 picture
+        let output = Tensor::new(vec![1, 2, 1, 2], vec![0.9, 0.2, 0.1, 0.8]);
+        let person = person_channel(output);
+        assert_eq!(person.shape, [1, 1, 1, 2]);
+        assert_eq!(person.data, [0.1, 0.8]);
+    }
+}
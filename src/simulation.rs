@@ -0,0 +1,427 @@
+//! A Kinect made up, for working on gestures and tracking without one.
+//! `--simulate <scene.ron>` swaps the device for [`SimulatedKinect`], which
+//! renders depth and video of a scene of planes and ellipsoids moving along
+//! keyframed paths, standing in for the floor, walls, people and hands:
+//!
+//! ```ron
+//! (
+//!     planes: [
+//!         // floor and back wall, room space: meters from the sensor,
+//!         // y up, z forward
+//!         (point: (0.0, -1.0, 0.0), normal: (0.0, 1.0, 0.0)),
+//!         (point: (0.0, 0.0, 4.0), normal: (0.0, 0.0, -1.0), color: (0.5, 0.6, 0.7)),
+//!     ],
+//!     ellipsoids: [
+//!         // someone walking across, and back since paths loop
+//!         (radii: (0.25, 0.85, 0.15), path: [
+//!             (at: 0.0, center: (-1.5, -0.15, 2.5)),
+//!             (at: 6.0, center: (1.5, -0.15, 2.5)),
+//!             (at: 12.0, center: (-1.5, -0.15, 2.5)),
+//!         ]),
+//!         // a hand pushing towards the sensor
+//!         (radii: (0.05, 0.05, 0.05), color: (0.9, 0.7, 0.6), path: [
+//!             (at: 0.0, center: (0.0, 0.1, 1.8)),
+//!             (at: 1.5, center: (0.0, 0.1, 1.2)),
+//!             (at: 3.0, center: (0.0, 0.1, 1.8)),
+//!         ]),
+//!     ],
+//!     noise_at_1m: 0.003,
+//! )
+//! ```
+//!
+//! `--simulate sweep` needs no file: [`Scene::sweep`] has a ball sweeping
+//! across in front of a wall, enough to see the close blob tracked.
+//!
+//! Frames come at the Kinect's 30 Hz, depth as the raw readings it would
+//! give, and tilting the sensor tilts the view. Video is RGB at medium
+//! resolution only.
+
+use std::fs;
+use std::path::Path;
+
+use bevy::prelude::{Quat, Vec2, Vec3};
+// std's panics in the browser
+use bevy::utils::Instant;
+use serde::Deserialize;
+
+use crate::backend::DepthCameraBackend;
+use crate::consumer::FrameConsumers;
+use crate::coords::{self, COLOR_INTRINSICS, DEPTH_HEIGHT, DEPTH_INTRINSICS, DEPTH_WIDTH};
+use crate::device::{DepthFrame, KinectError, VideoFrame};
+use crate::motor::{Led, MotorState, TiltStatus};
+use crate::video::{VideoFormat, VideoResolution, VideoSettings};
+use crate::NO_DEPTH;
+
+const FRAME_SECONDS: f64 = 1.0 / 30.0;
+/// The motor's end stops.
+const TILT_LIMIT: f64 = 31.0;
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Scene {
+    pub planes: Vec<Plane>,
+    pub ellipsoids: Vec<Ellipsoid>,
+    /// spread of the readings 1 m away in meters, growing with the square
+    /// of the distance like the real sensor's
+    pub noise_at_1m: f32,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Plane {
+    pub point: Vec3,
+    pub normal: Vec3,
+    #[serde(default = "default_color")]
+    pub color: Vec3,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Ellipsoid {
+    pub radii: Vec3,
+    /// where it is when, one keyframe to stand still
+    pub path: Vec<Keyframe>,
+    /// start over after the last keyframe
+    #[serde(default = "default_looped")]
+    pub looped: bool,
+    #[serde(default = "default_color")]
+    pub color: Vec3,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub struct Keyframe {
+    /// seconds from the start
+    pub at: f32,
+    pub center: Vec3,
+}
+
+fn default_color() -> Vec3 {
+    Vec3::splat(0.8)
+}
+
+fn default_looped() -> bool {
+    true
+}
+
+impl Scene {
+    pub fn load(path: &Path) -> Result<Scene, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|err| format!("can't read scene {}: {err}", path.display()))?;
+        ron::from_str(&text).map_err(|err| format!("bad scene {}: {err}", path.display()))
+    }
+
+    /// A ball the size of a fist 1 m away sweeping left and right across
+    /// the view every 4 s, over a floor and in front of a wall.
+    pub fn sweep() -> Scene {
+        let at = |x: f32| Vec3::new(x, 0.05, 1.0);
+        Scene {
+            planes: vec![
+                Plane {
+                    point: Vec3::new(0.0, -1.0, 0.0),
+                    normal: Vec3::Y,
+                    color: default_color(),
+                },
+                Plane {
+                    point: Vec3::new(0.0, 0.0, 3.5),
+                    normal: Vec3::NEG_Z,
+                    color: Vec3::new(0.5, 0.6, 0.7),
+                },
+            ],
+            ellipsoids: vec![Ellipsoid {
+                radii: Vec3::splat(0.1),
+                path: vec![
+                    Keyframe {
+                        at: 0.0,
+                        center: at(-0.45),
+                    },
+                    Keyframe {
+                        at: 2.0,
+                        center: at(0.45),
+                    },
+                    Keyframe {
+                        at: 4.0,
+                        center: at(-0.45),
+                    },
+                ],
+                looped: true,
+                color: Vec3::new(0.9, 0.3, 0.2),
+            }],
+            noise_at_1m: 0.003,
+        }
+    }
+
+    /// Distance along `direction` from the sensor to the first surface, and
+    /// its color. `direction` is in room space.
+    fn hit(&self, direction: Vec3, seconds: f32) -> Option<(f32, Vec3)> {
+        let planes = self.planes.iter().filter_map(|plane| {
+            let along = direction.dot(plane.normal);
+            if along.abs() < 1e-6 {
+                return None;
+            }
+            let t = plane.point.dot(plane.normal) / along;
+            (t > 0.0).then_some((t, plane.color))
+        });
+        let ellipsoids = self.ellipsoids.iter().filter_map(|ellipsoid| {
+            // a unit sphere once scaled by the radii
+            let origin = -ellipsoid.center_at(seconds) / ellipsoid.radii;
+            let direction = direction / ellipsoid.radii;
+            let a = direction.length_squared();
+            let b = 2.0 * origin.dot(direction);
+            let c = origin.length_squared() - 1.0;
+            let discriminant = b * b - 4.0 * a * c;
+            if discriminant < 0.0 {
+                return None;
+            }
+            let t = (-b - discriminant.sqrt()) / (2.0 * a);
+            (t > 0.0).then_some((t, ellipsoid.color))
+        });
+        planes
+            .chain(ellipsoids)
+            .min_by(|(a, _), (b, _)| a.total_cmp(b))
+    }
+}
+
+impl Ellipsoid {
+    pub fn center_at(&self, seconds: f32) -> Vec3 {
+        let (first, last) = match (self.path.first(), self.path.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Vec3::ZERO,
+        };
+        let t = if self.looped && last.at > 0.0 {
+            seconds.rem_euclid(last.at)
+        } else {
+            seconds
+        };
+        if t <= first.at {
+            return first.center;
+        }
+        match self.path.windows(2).find(|pair| t < pair[1].at) {
+            Some(pair) => {
+                let share = (t - pair[0].at) / (pair[1].at - pair[0].at);
+                pair[0].center.lerp(pair[1].center, share)
+            }
+            None => last.center,
+        }
+    }
+}
+
+/// Renders a [`Scene`] as the Kinect would see it.
+pub struct SimulatedKinect {
+    scene: Scene,
+    video: VideoSettings,
+    started: Option<Instant>,
+    exit: Option<Result<(), KinectError>>,
+    next_depth: f64,
+    next_video: f64,
+    depth_frames: u64,
+    video_frames: u64,
+    tilt_degrees: f64,
+    noise_seed: u32,
+    consumers: FrameConsumers,
+}
+
+impl SimulatedKinect {
+    pub fn new(scene: Scene) -> SimulatedKinect {
+        SimulatedKinect {
+            scene,
+            video: VideoSettings::default(),
+            started: None,
+            exit: None,
+            next_depth: 0.0,
+            next_video: 0.0,
+            depth_frames: 0,
+            video_frames: 0,
+            tilt_degrees: 0.0,
+            noise_seed: 1,
+            consumers: FrameConsumers::default(),
+        }
+    }
+
+    /// Seconds into the scene, if open.
+    fn elapsed(&self) -> Option<f64> {
+        Some(self.started?.elapsed().as_secs_f64())
+    }
+
+    /// Sensor space to room space.
+    fn tilt_rotation(&self) -> Quat {
+        Quat::from_rotation_x(-(self.tilt_degrees as f32).to_radians())
+    }
+
+    /// Depth of the scene at a moment, raw readings row by row.
+    pub fn render_depth(&mut self, seconds: f32) -> Vec<u16> {
+        let rotation = self.tilt_rotation();
+        let mut depth = Vec::with_capacity(DEPTH_WIDTH * DEPTH_HEIGHT);
+        for i in 0..DEPTH_WIDTH * DEPTH_HEIGHT {
+            let pixel = Vec2::new((i % DEPTH_WIDTH) as f32, (i / DEPTH_WIDTH) as f32);
+            // z = 1 in sensor space, so the distance along it is the depth
+            let ray = rotation * DEPTH_INTRINSICS.unproject(pixel, 1.0);
+            depth.push(match self.scene.hit(ray, seconds) {
+                Some((meters, _)) => {
+                    let spread = self.scene.noise_at_1m * meters * meters;
+                    coords::meters_to_raw_depth(meters + spread * self.noise())
+                }
+                None => NO_DEPTH,
+            });
+        }
+        depth
+    }
+
+    /// The scene in color at a moment, 640x480 RGB, a little darker with
+    /// distance so shapes stand out.
+    pub fn render_video(&self, seconds: f32) -> Vec<u8> {
+        let rotation = self.tilt_rotation();
+        let (width, height) = VideoResolution::Medium.size();
+        let mut data = Vec::with_capacity((width * height * 3) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let pixel = Vec2::new(x as f32, y as f32);
+                let ray = rotation * COLOR_INTRINSICS.unproject(pixel, 1.0);
+                let color = match self.scene.hit(ray, seconds) {
+                    Some((meters, color)) => color / (1.0 + 0.15 * meters),
+                    None => Vec3::ZERO,
+                };
+                let rgb = (color.clamp(Vec3::ZERO, Vec3::ONE) * 255.0).round();
+                data.extend_from_slice(&[rgb.x as u8, rgb.y as u8, rgb.z as u8]);
+            }
+        }
+        data
+    }
+
+    /// Roughly normal, -1..1 mostly, from a xorshift.
+    fn noise(&mut self) -> f32 {
+        let mut uniform = || {
+            self.noise_seed ^= self.noise_seed << 13;
+            self.noise_seed ^= self.noise_seed >> 17;
+            self.noise_seed ^= self.noise_seed << 5;
+            self.noise_seed as f32 / u32::MAX as f32
+        };
+        uniform() + uniform() + uniform() - 1.5
+    }
+}
+
+impl DepthCameraBackend for SimulatedKinect {
+    fn open(&mut self) {
+        if self.started.is_some() {
+            return;
+        }
+        if self.video.format != VideoFormat::Rgb || self.video.resolution != VideoResolution::Medium
+        {
+            self.exit = Some(Err(KinectError::Open(
+                "the simulation only renders rgb video at medium resolution".into(),
+            )));
+            return;
+        }
+        self.started = Some(Instant::now());
+        self.next_depth = 0.0;
+        self.next_video = 0.0;
+    }
+
+    fn close(&mut self) -> Result<(), KinectError> {
+        self.started = None;
+        Ok(())
+    }
+
+    fn is_running(&self) -> bool {
+        self.started.is_some() || self.exit.is_some()
+    }
+
+    fn take_exit(&mut self) -> Option<Result<(), KinectError>> {
+        self.exit.take()
+    }
+
+    fn configure(&mut self, video: VideoSettings) {
+        self.video = video;
+    }
+
+    fn set_consumers(&mut self, consumers: FrameConsumers) {
+        self.consumers = consumers;
+    }
+
+    fn next_frame(&mut self) -> Option<DepthFrame> {
+        let seconds = self.elapsed()?;
+        if seconds < self.next_depth {
+            return None;
+        }
+        self.next_depth = seconds + FRAME_SECONDS;
+        self.depth_frames += 1;
+        let data = self.render_depth(seconds as f32);
+        self.consumers.depth(&data);
+        Some(DepthFrame::new(data, self.depth_frames))
+    }
+
+    fn next_video_frame(&mut self) -> Option<VideoFrame> {
+        let seconds = self.elapsed()?;
+        if seconds < self.next_video {
+            return None;
+        }
+        self.next_video = seconds + FRAME_SECONDS;
+        self.video_frames += 1;
+        let data = self.render_video(seconds as f32);
+        self.consumers.video(&data);
+        Some(VideoFrame { data })
+    }
+
+    fn depth_frames_received(&self) -> u64 {
+        self.depth_frames
+    }
+
+    fn video_frames_received(&self) -> u64 {
+        self.video_frames
+    }
+
+    fn tilt(&mut self, degrees: f64) -> Result<(), KinectError> {
+        self.tilt_degrees = degrees.clamp(-TILT_LIMIT, TILT_LIMIT);
+        Ok(())
+    }
+
+    fn tilt_state(&mut self) -> Result<MotorState, KinectError> {
+        // at rest the accelerometer reads +g straight up in sensor space
+        let up = self.tilt_rotation().inverse() * Vec3::Y;
+        Ok(MotorState {
+            tilt_degrees: self.tilt_degrees,
+            status: if self.tilt_degrees.abs() >= TILT_LIMIT {
+                TiltStatus::Limit
+            } else {
+                TiltStatus::Stopped
+            },
+            accel: up * 9.81,
+        })
+    }
+
+    fn led(&mut self, _led: Led) -> Result<(), KinectError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The example at the top of this file.
+    fn example() -> Scene {
+        let doc = include_str!("simulation.rs");
+        let start = doc.find("//! ```ron").unwrap();
+        let end = start + doc[start..].find("//! ```\n").unwrap();
+        let ron: String = doc[start..end]
+            .lines()
+            .skip(1)
+            .map(|line| line.trim_start_matches("//!"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        ron::from_str(&ron).unwrap()
+    }
+
+    #[test]
+    fn the_hand_comes_closer_and_goes_back() {
+        let mut kinect = SimulatedKinect::new(example());
+        // through the hand all along its path
+        let pixel = DEPTH_INTRINSICS.project(Vec3::new(0.0, 0.1, 1.5));
+        let i = pixel.y.round() as usize * DEPTH_WIDTH + pixel.x.round() as usize;
+        let meters_at = |kinect: &mut SimulatedKinect, seconds| {
+            coords::raw_depth_to_meters(kinect.render_depth(seconds)[i]).unwrap()
+        };
+        let start = meters_at(&mut kinect, 0.0);
+        let pushed = meters_at(&mut kinect, 1.5);
+        let back = meters_at(&mut kinect, 3.0);
+        assert!(pushed < start - 0.4, "{start} -> {pushed}");
+        assert!((back - start).abs() < 0.05, "{start} -> {back}");
+    }
+}
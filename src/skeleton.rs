@@ -0,0 +1,274 @@
+//! Skeleton of the person in front of the sensor, in sensor space.
+//!
+//! The built-in estimate is read off the [`PersonMask`] silhouette: head at
+//! the top, shoulders and hips where the body is widest at those heights,
+//! hands and feet at the outermost points, elbows and knees half way. It
+//! only works for one person facing the sensor and has no idea about arms in
+//! front of the body, which its confidences say. A pose network publishing a
+//! [`Skeleton`] through [`crate::inference`] can replace it, everything built
+//! on top only looks at the resource.
+
+use bevy::prelude::*;
+use serde::Serialize;
+
+use crate::coords::{self, WorldConvention, DEPTH_HEIGHT, DEPTH_WIDTH};
+use crate::segmentation::PersonMask;
+use crate::{CurrentDepth, MainKinect, NO_DEPTH};
+
+/// Fewer mask pixels than this is nobody, or only part of somebody.
+const MIN_PIXELS: usize = 2000;
+
+/// Body parts, left and right as the person sees it: facing the sensor,
+/// their left is on the sensor's right.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Joint {
+    Head,
+    Neck,
+    ShoulderLeft,
+    ShoulderRight,
+    ElbowLeft,
+    ElbowRight,
+    HandLeft,
+    HandRight,
+    Pelvis,
+    HipLeft,
+    HipRight,
+    KneeLeft,
+    KneeRight,
+    FootLeft,
+    FootRight,
+}
+
+impl Joint {
+    pub const ALL: [Joint; 15] = [
+        Joint::Head,
+        Joint::Neck,
+        Joint::ShoulderLeft,
+        Joint::ShoulderRight,
+        Joint::ElbowLeft,
+        Joint::ElbowRight,
+        Joint::HandLeft,
+        Joint::HandRight,
+        Joint::Pelvis,
+        Joint::HipLeft,
+        Joint::HipRight,
+        Joint::KneeLeft,
+        Joint::KneeRight,
+        Joint::FootLeft,
+        Joint::FootRight,
+    ];
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TrackedJoint {
+    /// sensor space, meters
+    pub position: Vec3,
+    /// 0..1, how much to trust the position
+    pub confidence: f32,
+}
+
+/// Joints of the tracked person, all `None` while nobody is tracked.
+#[derive(Resource, Clone, Debug, Default)]
+pub struct Skeleton {
+    joints: [Option<TrackedJoint>; Joint::ALL.len()],
+}
+
+impl Skeleton {
+    pub fn get(&self, joint: Joint) -> Option<TrackedJoint> {
+        self.joints[joint as usize]
+    }
+
+    pub fn set(&mut self, joint: Joint, tracked: Option<TrackedJoint>) {
+        self.joints[joint as usize] = tracked;
+    }
+
+    pub fn is_tracked(&self) -> bool {
+        self.joints.iter().any(Option::is_some)
+    }
+
+    /// The same skeleton with positions in `convention` instead of sensor
+    /// space, for handing out. Don't feed it back in.
+    pub fn in_world(&self, convention: &WorldConvention) -> Skeleton {
+        let mut world = self.clone();
+        for tracked in world.joints.iter_mut().flatten() {
+            tracked.position = convention.world_point(tracked.position);
+        }
+        world
+    }
+}
+
+pub struct SkeletonPlugin;
+
+impl Plugin for SkeletonPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Skeleton>()
+            .add_system(estimate_skeleton);
+    }
+}
+
+pub fn estimate_skeleton(
+    mask: Res<PersonMask>,
+    depth_query: Query<&CurrentDepth, With<MainKinect>>,
+    mut skeleton: ResMut<Skeleton>,
+) {
+    if !mask.is_changed() {
+        return;
+    }
+    let depth = match depth_query.get_single() {
+        Ok(depth) if depth.depth_array.len() == mask.0.len() => &depth.depth_array,
+        _ => return,
+    };
+    *skeleton = silhouette_skeleton(&mask.0, depth).unwrap_or_default();
+}
+
+/// Row by row lookups in the person mask.
+struct Rows<'a> {
+    mask: &'a [bool],
+}
+
+impl Rows<'_> {
+    fn at(&self, y: usize) -> &[bool] {
+        &self.mask[y * DEPTH_WIDTH..(y + 1) * DEPTH_WIDTH]
+    }
+
+    /// Leftmost and rightmost person pixel, `None` if the row has none.
+    fn extent(&self, y: usize) -> Option<(usize, usize)> {
+        let row = self.at(y);
+        Some((row.iter().position(|p| *p)?, row.iter().rposition(|p| *p)?))
+    }
+
+    /// The run of person pixels through `x`, or the closest one to it.
+    fn span_around(&self, y: usize, x: usize) -> Option<(usize, usize)> {
+        let row = self.at(y);
+        let x = (0..DEPTH_WIDTH)
+            .filter(|i| row[*i])
+            .min_by_key(|i| i.abs_diff(x))?;
+        let left = (0..=x).rev().take_while(|i| row[*i]).last()?;
+        let right = (x..DEPTH_WIDTH).take_while(|i| row[*i]).last()?;
+        Some((left, right))
+    }
+}
+
+pub(crate) fn silhouette_skeleton(mask: &[bool], depth: &[u16]) -> Option<Skeleton> {
+    let pixels = mask.iter().filter(|p| **p).count();
+    if pixels < MIN_PIXELS {
+        return None;
+    }
+    let rows = Rows { mask };
+    let top = (0..DEPTH_HEIGHT).find(|y| rows.extent(*y).is_some())?;
+    let bottom = (0..DEPTH_HEIGHT)
+        .rev()
+        .find(|y| rows.extent(*y).is_some())?;
+    let height = (bottom - top) as f32;
+    let row = |fraction: f32| (top + (height * fraction) as usize).min(bottom);
+    let center_x = mask
+        .iter()
+        .enumerate()
+        .filter(|(_, p)| **p)
+        .map(|(i, _)| i % DEPTH_WIDTH)
+        .sum::<usize>()
+        / pixels;
+
+    // people are at about one distance, what holes and missing joints get
+    let mut readings: Vec<u16> = mask
+        .iter()
+        .zip(depth)
+        .filter(|(p, raw)| **p && **raw != NO_DEPTH)
+        .map(|(_, raw)| *raw)
+        .collect();
+    if readings.is_empty() {
+        return None;
+    }
+    let middle = readings.len() / 2;
+    let body_meters = coords::raw_depth_to_meters(*readings.select_nth_unstable(middle).1)?;
+
+    let mut skeleton = Skeleton::default();
+    let mut place = |joint: Joint, pixel: Vec2, confidence: f32| {
+        let (x, y) = (pixel.x as usize, pixel.y as usize);
+        let meters = coords::raw_depth_to_meters(depth[y * DEPTH_WIDTH + x])
+            .filter(|meters| (meters - body_meters).abs() < 0.5)
+            .unwrap_or(body_meters);
+        skeleton.set(
+            joint,
+            Some(TrackedJoint {
+                position: coords::depth_pixel_to_sensor(pixel, meters),
+                confidence,
+            }),
+        );
+    };
+    let middle_of =
+        |(left, right): (usize, usize), y: usize| Vec2::new((left + right) as f32 / 2.0, y as f32);
+
+    let head_y = row(0.06);
+    let head = rows.span_around(head_y, center_x)?;
+    place(Joint::Head, middle_of(head, head_y), 0.8);
+    let neck_y = row(0.17);
+    let neck = rows.span_around(neck_y, center_x)?;
+    place(Joint::Neck, middle_of(neck, neck_y), 0.6);
+
+    let shoulder_y = row(0.2);
+    let (torso_left, torso_right) = rows.span_around(shoulder_y, center_x)?;
+    let shoulder_left = Vec2::new(torso_right as f32, shoulder_y as f32);
+    let shoulder_right = Vec2::new(torso_left as f32, shoulder_y as f32);
+    place(Joint::ShoulderLeft, shoulder_left, 0.6);
+    place(Joint::ShoulderRight, shoulder_right, 0.6);
+
+    // outermost points above the hips, on either side of the body
+    let arm_rows = top..=row(0.55);
+    let hand_right = arm_rows
+        .clone()
+        .filter_map(|y| {
+            rows.extent(y)
+                .map(|(left, _)| Vec2::new(left as f32, y as f32))
+        })
+        .min_by(|a, b| a.x.total_cmp(&b.x))?;
+    let hand_left = arm_rows
+        .filter_map(|y| {
+            rows.extent(y)
+                .map(|(_, right)| Vec2::new(right as f32, y as f32))
+        })
+        .max_by(|a, b| a.x.total_cmp(&b.x))?;
+    // a hand next to the body can't be told from it
+    let reach = height * 0.05;
+    let hand_confidence = |hand: Vec2, shoulder: Vec2| {
+        if (hand.x - shoulder.x).abs() > reach {
+            0.6
+        } else {
+            0.2
+        }
+    };
+    place(
+        Joint::HandLeft,
+        hand_left,
+        hand_confidence(hand_left, shoulder_left),
+    );
+    place(
+        Joint::HandRight,
+        hand_right,
+        hand_confidence(hand_right, shoulder_right),
+    );
+    place(Joint::ElbowLeft, shoulder_left.lerp(hand_left, 0.5), 0.3);
+    place(Joint::ElbowRight, shoulder_right.lerp(hand_right, 0.5), 0.3);
+
+    let hip_y = row(0.5);
+    let hips = rows.span_around(hip_y, center_x)?;
+    let pelvis = middle_of(hips, hip_y);
+    // hip joints sit a bit inside the outline
+    let hip_left = pelvis.lerp(Vec2::new(hips.1 as f32, hip_y as f32), 0.6);
+    let hip_right = pelvis.lerp(Vec2::new(hips.0 as f32, hip_y as f32), 0.6);
+    place(Joint::Pelvis, pelvis, 0.6);
+    place(Joint::HipLeft, hip_left, 0.5);
+    place(Joint::HipRight, hip_right, 0.5);
+
+    let foot_y = row(0.98);
+    let (feet_right, feet_left) = rows.extent(foot_y)?;
+    let foot_left = Vec2::new(feet_left as f32, foot_y as f32);
+    let foot_right = Vec2::new(feet_right as f32, foot_y as f32);
+    place(Joint::FootLeft, foot_left, 0.5);
+    place(Joint::FootRight, foot_right, 0.5);
+    place(Joint::KneeLeft, hip_left.lerp(foot_left, 0.5), 0.3);
+    place(Joint::KneeRight, hip_right.lerp(foot_right, 0.5), 0.3);
+
+    Some(skeleton)
+}
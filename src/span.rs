@@ -0,0 +1,210 @@
+//! Spreads the [`Canvas`] over several displays for projection walls. Each
+//! display gets a borderless fullscreen window of its own showing one
+//! vertical slice of the canvas, left to right in the order the monitors are
+//! given. A per-display offset nudges the slice so neighbouring projectors
+//! line up. The primary window keeps showing the whole canvas.
+
+use std::str::FromStr;
+
+use bevy::prelude::*;
+use bevy::render::camera::RenderTarget;
+use bevy::render::mesh::Indices;
+use bevy::render::render_resource::PrimitiveTopology;
+use bevy::render::view::RenderLayers;
+use bevy::sprite::MaterialMesh2dBundle;
+use bevy::window::{CreateWindow, MonitorSelection, WindowId, WindowMode, WindowPosition};
+
+use crate::display::Canvas;
+use crate::presentation::OUTPUT_LAYER;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SpanDisplay {
+    pub monitor: usize,
+    /// shift of the slice on that display, in pixels, x right and y up
+    pub offset: Vec2,
+}
+
+#[derive(Resource, Clone, Debug, Default)]
+pub struct SpanSettings {
+    pub displays: Vec<SpanDisplay>,
+}
+
+impl SpanSettings {
+    pub fn is_enabled(&self) -> bool {
+        !self.displays.is_empty()
+    }
+
+    /// `<index>:<x>,<y>`, the offset of the display at `index`.
+    pub fn set_offset(&mut self, arg: &str) -> Result<(), String> {
+        let invalid = || format!("invalid display offset '{arg}', expected <index>:<x>,<y>");
+        let (index, offset) = arg.split_once(':').ok_or_else(invalid)?;
+        let (x, y) = offset.split_once(',').ok_or_else(invalid)?;
+        let index: usize = index.parse().map_err(|_| invalid())?;
+        let offset = Vec2::new(
+            x.trim().parse().map_err(|_| invalid())?,
+            y.trim().parse().map_err(|_| invalid())?,
+        );
+        let display = self
+            .displays
+            .get_mut(index)
+            .ok_or_else(|| format!("no display {index} to offset, check --span"))?;
+        display.offset = offset;
+        Ok(())
+    }
+}
+
+impl FromStr for SpanSettings {
+    type Err = String;
+
+    /// Comma separated monitor indices.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let displays = s
+            .split(',')
+            .map(|monitor| {
+                monitor
+                    .trim()
+                    .parse()
+                    .map(|monitor| SpanDisplay {
+                        monitor,
+                        offset: Vec2::ZERO,
+                    })
+                    .map_err(|_| format!("invalid monitor '{monitor}' in '{s}'"))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(SpanSettings { displays })
+    }
+}
+
+#[derive(Component)]
+struct SpanOutput {
+    window: WindowId,
+    index: usize,
+    mesh: Handle<Mesh>,
+    size: Vec2,
+}
+
+pub struct SpanPlugin;
+
+impl Plugin for SpanPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SpanSettings>()
+            .add_startup_system_to_stage(StartupStage::PostStartup, spawn_span_outputs)
+            .add_system(fit_span_outputs);
+    }
+}
+
+fn spawn_span_outputs(
+    mut commands: Commands,
+    settings: Res<SpanSettings>,
+    canvas: Option<Res<Canvas>>,
+    mut create_window: EventWriter<CreateWindow>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let canvas = match canvas {
+        Some(canvas) => canvas,
+        None => return,
+    };
+    let material = materials.add(ColorMaterial::from(canvas.image.clone()));
+
+    for (index, display) in settings.displays.iter().enumerate() {
+        let window = WindowId::new();
+        create_window.send(CreateWindow {
+            id: window,
+            descriptor: WindowDescriptor {
+                title: format!("Bevy Kinect {}/{}", index + 1, settings.displays.len()),
+                mode: WindowMode::BorderlessFullscreen,
+                monitor: MonitorSelection::Index(display.monitor),
+                position: WindowPosition::Centered,
+                decorations: false,
+                cursor_visible: false,
+                ..default()
+            },
+        });
+
+        // every output gets a layer of its own so cameras only see their slice
+        let layer = RenderLayers::layer(OUTPUT_LAYER + 1 + index as u8);
+        let mesh = meshes.add(slice_quad(
+            index,
+            settings.displays.len(),
+            Vec2::ONE,
+            Vec2::ZERO,
+        ));
+        commands.spawn((
+            MaterialMesh2dBundle {
+                mesh: mesh.clone().into(),
+                material: material.clone(),
+                ..default()
+            },
+            layer,
+            SpanOutput {
+                window,
+                index,
+                mesh,
+                size: Vec2::ONE,
+            },
+        ));
+        commands.spawn((
+            Camera2dBundle {
+                camera: Camera {
+                    target: RenderTarget::Window(window),
+                    priority: 2 + index as isize,
+                    ..default()
+                },
+                ..default()
+            },
+            UiCameraConfig { show_ui: false },
+            layer,
+        ));
+    }
+}
+
+fn fit_span_outputs(
+    windows: Res<Windows>,
+    settings: Res<SpanSettings>,
+    mut output_query: Query<&mut SpanOutput>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    for mut output in output_query.iter_mut() {
+        let window = match windows.get(output.window) {
+            Some(window) => window,
+            None => continue,
+        };
+        let size = Vec2::new(window.width(), window.height());
+        if output.size == size && !settings.is_changed() {
+            continue;
+        }
+        output.size = size;
+        if let Some(mesh) = meshes.get_mut(&output.mesh) {
+            let offset = settings.displays[output.index].offset;
+            *mesh = slice_quad(output.index, settings.displays.len(), size, offset);
+        }
+    }
+}
+
+/// Window-filling quad showing slice `index` of `count`.
+fn slice_quad(index: usize, count: usize, size: Vec2, offset: Vec2) -> Mesh {
+    let (u0, u1) = (
+        index as f32 / count as f32,
+        (index + 1) as f32 / count as f32,
+    );
+    let (min, max) = (offset - size / 2.0, offset + size / 2.0);
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(
+        Mesh::ATTRIBUTE_POSITION,
+        vec![
+            [min.x, max.y, 0.0],
+            [max.x, max.y, 0.0],
+            [max.x, min.y, 0.0],
+            [min.x, min.y, 0.0],
+        ],
+    );
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, vec![[0.0, 0.0, 1.0]; 4]);
+    mesh.insert_attribute(
+        Mesh::ATTRIBUTE_UV_0,
+        vec![[u0, 0.0], [u1, 0.0], [u1, 1.0], [u0, 1.0]],
+    );
+    mesh.set_indices(Some(Indices::U32(vec![0, 3, 1, 1, 3, 2])));
+    mesh
+}
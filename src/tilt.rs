@@ -0,0 +1,323 @@
+//! Tilt readout: polls motor and accelerometer, draws a small dial in the
+//! corner and keeps a [`KinectSensor`] entity posed like the real device.
+//! The accelerometer is read every frame into [`KinectAccelerometer`], the
+//! motor's angle a few times a second into [`TiltState`].
+
+use bevy::prelude::*;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+
+use crate::backend::DepthCamera;
+use crate::motor::TiltStatus;
+use crate::presentation::DebugUi;
+
+const DIAL_SIZE: u32 = 96;
+const POLL_INTERVAL: f32 = 0.1;
+/// Seconds the gravity estimate takes to follow a new pose.
+const GRAVITY_SMOOTHING: f32 = 0.5;
+/// How far off gravity in m/s², beyond the sensor's noise, counts as a bump.
+const BUMP_THRESHOLD: f32 = 1.5;
+/// Degrees from the target the motor counts as there, about what it reads.
+const SETTLE_TOLERANCE: f32 = 2.0;
+
+/// End stops of the tilt motor in degrees.
+pub const TILT_LIMIT: f32 = 31.0;
+
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct TiltState {
+    /// angle the motor reports, relative to its base
+    pub angle: f32,
+    /// true pitch from the accelerometer, positive when looking up
+    pub pitch: f32,
+    /// sideways lean from the accelerometer, positive when leaning right
+    pub roll: f32,
+    pub status: TiltStatus,
+    /// the angle last asked for with a tilt command, until the motor has
+    /// got there or given up at an end stop
+    pub target: Option<f32>,
+}
+
+impl TiltState {
+    /// Not on its way anywhere, the view holds still. [`TiltSettled`] goes
+    /// out when a move ends.
+    pub fn is_settled(&self) -> bool {
+        self.target.is_none()
+    }
+}
+
+/// The motor has stopped after a tilt command.
+#[derive(Clone, Copy, Debug)]
+pub struct TiltSettled {
+    pub angle: f32,
+    /// it stopped short at an end stop or against something
+    pub at_limit: bool,
+}
+
+/// The accelerometer, in m/s² in the sensor's frame. At rest it reads
+/// gravity's push back, 9.81 m/s² straight up.
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct KinectAccelerometer {
+    /// the latest reading
+    pub accel: Vec3,
+    /// the readings averaged over about half a second, up as seen by the
+    /// sensor once it has settled, for leveling the floor
+    pub gravity: Vec3,
+}
+
+impl KinectAccelerometer {
+    /// Whether the latest reading is well off the settled one, the sensor
+    /// being knocked or carried around.
+    pub fn bumped(&self) -> bool {
+        self.accel.distance(self.gravity) > BUMP_THRESHOLD
+    }
+}
+
+/// Stands in for the physical sensor. Its rotation follows the measured
+/// pitch and roll, so anything attached to it lines up with the real view.
+#[derive(Component)]
+pub struct KinectSensor;
+
+#[derive(Component)]
+struct TiltDial {
+    handle: Handle<Image>,
+}
+
+#[derive(Component)]
+struct TiltText;
+
+#[derive(Resource)]
+struct TiltPollTimer(Timer);
+
+pub struct TiltPlugin;
+
+impl Plugin for TiltPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TiltState>()
+            .add_event::<TiltSettled>()
+            .init_resource::<KinectAccelerometer>()
+            .insert_resource(TiltPollTimer(Timer::from_seconds(
+                POLL_INTERVAL,
+                TimerMode::Repeating,
+            )))
+            .add_startup_system(spawn_tilt_dial)
+            .add_system(poll_tilt)
+            .add_system(draw_tilt_dial)
+            .add_system(update_sensor_pose);
+    }
+}
+
+fn spawn_tilt_dial(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    asset_server: Res<AssetServer>,
+) {
+    commands.spawn((KinectSensor, TransformBundle::default()));
+
+    let handle = images.add(Image::new_fill(
+        Extent3d {
+            width: DIAL_SIZE,
+            height: DIAL_SIZE,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[0, 0, 0, 0],
+        TextureFormat::Rgba8UnormSrgb,
+    ));
+
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Px(8.0),
+                    right: Val::Px(8.0),
+                    ..default()
+                },
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            ..default()
+        })
+        .insert(DebugUi)
+        .with_children(|parent| {
+            parent
+                .spawn(ImageBundle {
+                    style: Style {
+                        size: Size::new(Val::Px(DIAL_SIZE as f32), Val::Px(DIAL_SIZE as f32)),
+                        ..default()
+                    },
+                    image: UiImage(handle.clone()),
+                    ..default()
+                })
+                .insert(TiltDial { handle });
+
+            parent
+                .spawn(TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font: asset_server.load("fonts/Hack-Regular.ttf"),
+                        font_size: 14.0,
+                        color: Color::WHITE,
+                    },
+                ))
+                .insert(TiltText);
+        });
+}
+
+fn poll_tilt(
+    mut camera: ResMut<DepthCamera>,
+    time: Res<Time>,
+    mut timer: ResMut<TiltPollTimer>,
+    (mut tilt, mut accelerometer): (ResMut<TiltState>, ResMut<KinectAccelerometer>),
+    mut settled: EventWriter<TiltSettled>,
+) {
+    let poll_motor = timer.0.tick(time.delta()).just_finished();
+    let state = match camera.tilt_state() {
+        Ok(state) => state,
+        Err(_) => return,
+    };
+    let accel = state.accel;
+    accelerometer.gravity = if accelerometer.gravity == Vec3::ZERO {
+        accel
+    } else {
+        let follow = (time.delta_seconds() / GRAVITY_SMOOTHING).min(1.0);
+        accelerometer.gravity.lerp(accel, follow)
+    };
+    accelerometer.accel = accel;
+
+    if poll_motor {
+        // at rest the accelerometer reads +g straight up in sensor space
+        let mut new_tilt = TiltState {
+            angle: state.tilt_degrees as f32,
+            pitch: accel.z.atan2(accel.y).to_degrees(),
+            roll: (-accel.x).atan2(accel.y).to_degrees(),
+            status: state.status,
+            target: tilt.target,
+        };
+        if let Some(target) = new_tilt.target {
+            let at_limit = new_tilt.status == TiltStatus::Limit;
+            // the motor reads stopped for a moment before it gets going, so
+            // that alone doesn't mean it's there
+            let arrived = at_limit || (new_tilt.angle - target).abs() <= SETTLE_TOLERANCE;
+            if arrived && new_tilt.status != TiltStatus::Moving {
+                new_tilt.target = None;
+                settled.send(TiltSettled {
+                    angle: new_tilt.angle,
+                    at_limit,
+                });
+            }
+        }
+        // only write through the change detection when something moved
+        if (new_tilt.angle - tilt.angle).abs() > 0.1
+            || (new_tilt.pitch - tilt.pitch).abs() > 0.1
+            || (new_tilt.roll - tilt.roll).abs() > 0.1
+            || new_tilt.status != tilt.status
+            || new_tilt.target != tilt.target
+        {
+            *tilt = new_tilt;
+        }
+    }
+}
+
+fn draw_tilt_dial(
+    tilt: Res<TiltState>,
+    dial_query: Query<&TiltDial>,
+    mut text_query: Query<&mut Text, With<TiltText>>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    if !tilt.is_changed() {
+        return;
+    }
+
+    if let Ok(dial) = dial_query.get_single() {
+        if let Some(image) = images.get_mut(&dial.handle) {
+            let mut canvas = Canvas::new(&mut image.data, DIAL_SIZE as usize);
+            canvas.clear();
+            canvas.circle(44.0, [255, 255, 255, 80]);
+            canvas.needle(0.0, 44.0, [255, 255, 255, 80]);
+            canvas.needle(TILT_LIMIT, 44.0, [255, 80, 80, 160]);
+            canvas.needle(-TILT_LIMIT, 44.0, [255, 80, 80, 160]);
+            if let Some(target) = tilt.target {
+                canvas.needle(target, 44.0, [255, 255, 255, 140]);
+            }
+            canvas.needle(tilt.pitch, 40.0, [255, 210, 0, 255]);
+            canvas.needle(tilt.angle, 32.0, [255, 255, 255, 255]);
+        }
+    }
+
+    if let Ok(mut text) = text_query.get_single_mut() {
+        let moving = match tilt.status {
+            TiltStatus::Stopped => "",
+            TiltStatus::Limit => " (limit)",
+            TiltStatus::Moving => " (moving)",
+        };
+        let target = match tilt.target {
+            Some(target) => format!(" → {target:+.0}°"),
+            None => String::new(),
+        };
+        text.sections[0].value = format!(
+            "tilt {:+.1}°{}{}\npitch {:+.1}°",
+            tilt.angle, target, moving, tilt.pitch
+        );
+    }
+}
+
+fn update_sensor_pose(
+    tilt: Res<TiltState>,
+    mut sensor_query: Query<&mut Transform, With<KinectSensor>>,
+) {
+    if !tilt.is_changed() {
+        return;
+    }
+    for mut transform in sensor_query.iter_mut() {
+        transform.rotation = Quat::from_rotation_z(-tilt.roll.to_radians())
+            * Quat::from_rotation_x(tilt.pitch.to_radians());
+    }
+}
+
+/// Tiny software rasterizer for the dial, angles in degrees from the
+/// horizontal, needles start at the center.
+struct Canvas<'a> {
+    data: &'a mut [u8],
+    size: usize,
+}
+
+impl<'a> Canvas<'a> {
+    fn new(data: &'a mut [u8], size: usize) -> Self {
+        Canvas { data, size }
+    }
+
+    fn clear(&mut self) {
+        self.data.fill(0);
+    }
+
+    fn plot(&mut self, x: f32, y: f32, color: [u8; 4]) {
+        if x < 0.0 || y < 0.0 || x >= self.size as f32 || y >= self.size as f32 {
+            return;
+        }
+        let i = (y as usize * self.size + x as usize) * 4;
+        self.data[i..i + 4].copy_from_slice(&color);
+    }
+
+    fn circle(&mut self, radius: f32, color: [u8; 4]) {
+        let center = self.size as f32 / 2.0;
+        let steps = (radius * std::f32::consts::TAU) as usize * 2;
+        for i in 0..steps {
+            let a = i as f32 / steps as f32 * std::f32::consts::TAU;
+            self.plot(center + radius * a.cos(), center + radius * a.sin(), color);
+        }
+    }
+
+    fn needle(&mut self, degrees: f32, length: f32, color: [u8; 4]) {
+        let center = self.size as f32 / 2.0;
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let mut r = 0.0;
+        while r <= length {
+            // image rows grow downwards, so up is -y
+            let (x, y) = (center + r * cos, center - r * sin);
+            self.plot(x, y, color);
+            self.plot(x, y + 1.0, color);
+            r += 0.5;
+        }
+    }
+}
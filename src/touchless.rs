@@ -0,0 +1,243 @@
+//! Touchless navigation of Bevy UI. The closest thing to the sensor, usually
+//! a hand held out towards it, points at [`Button`]s like a mouse would:
+//!
+//! * pointing at a button sets its [`Interaction`] to `Hovered`
+//! * pushing towards the sensor clicks the hovered button, `Clicked` for a
+//!   frame
+//! * swiping left or right moves the focus to the previous or next button,
+//!   in reading order, for menus that are easier to step through
+//!
+//! The gestures are sent as [`HandGesture`] events too. Menus need no code of
+//! their own, systems see the same [`Interaction`] changes as with a mouse.
+
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+use bevy::ui::UiSystem;
+
+use crate::coords::{self, DisplayRect, DEPTH_HEIGHT, DEPTH_WIDTH};
+use crate::pipeline::{self, DepthPipelineApp, DepthStage};
+use crate::{BlobMotion, CloseBlob, CurrentDepth, MainKinect, NO_DEPTH};
+
+/// Seconds a gesture has to happen in.
+const GESTURE_WINDOW: f64 = 0.4;
+/// How much closer the hand has to come for a push, in meters.
+const PUSH_METERS: f32 = 0.12;
+/// Sideways travel for a swipe, in depth pixels.
+const SWIPE_PIXELS: f32 = 160.0;
+/// Depth pixels around the blob center looked at for the hand's distance.
+const HAND_RADIUS: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HandGesture {
+    Push,
+    SwipeLeft,
+    SwipeRight,
+}
+
+/// Where the hand points in UI coordinates (origin top left), and the button
+/// it has focused.
+#[derive(Resource, Default)]
+pub struct HandCursor {
+    pub position: Option<Vec2>,
+    pub focused: Option<Entity>,
+}
+
+#[derive(Clone, Copy)]
+struct HandSample {
+    at: f64,
+    pixel: Vec2,
+    meters: f32,
+}
+
+pub struct TouchlessPlugin;
+
+impl Plugin for TouchlessPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<HandCursor>()
+            .add_event::<HandGesture>()
+            .add_depth_stage(
+                DepthStage::consumer(pipeline::DetectHandGestures, detect_hand_gestures)
+                    .after(pipeline::TrackCloseBlob),
+            )
+            .add_system_to_stage(CoreStage::PreUpdate, drive_ui_focus.after(UiSystem::Focus));
+    }
+}
+
+fn detect_hand_gestures(
+    depth_query: Query<&CurrentDepth, (With<MainKinect>, Changed<CurrentDepth>)>,
+    blob: Res<CloseBlob>,
+    mut tracker: Local<GestureTracker>,
+    mut gestures: EventWriter<HandGesture>,
+) {
+    let depth = match depth_query.get_single() {
+        Ok(depth) if depth.is_medium() => depth,
+        _ => return,
+    };
+    if let Some(gesture) = tracker.update(depth, blob.0) {
+        gestures.send(gesture);
+    }
+}
+
+/// The last [`GESTURE_WINDOW`] of one hand, turned into gestures.
+#[derive(Default)]
+pub(crate) struct GestureTracker {
+    history: VecDeque<HandSample>,
+}
+
+impl GestureTracker {
+    /// The hand at `pixel` in a depth frame, `None` while it's gone.
+    pub(crate) fn update(
+        &mut self,
+        depth: &CurrentDepth,
+        pixel: Option<Vec2>,
+    ) -> Option<HandGesture> {
+        let history = &mut self.history;
+        let sample = pixel.and_then(|pixel| {
+            hand_meters(&depth.depth_array, pixel).map(|meters| HandSample {
+                at: depth.received_at,
+                pixel,
+                meters,
+            })
+        });
+        let sample = match sample {
+            Some(sample) => sample,
+            None => {
+                history.clear();
+                return None;
+            }
+        };
+        if history.back().map(|last| last.at) == Some(sample.at) {
+            return None;
+        }
+        while matches!(history.front(), Some(first) if sample.at - first.at > GESTURE_WINDOW) {
+            history.pop_front();
+        }
+        history.push_back(sample);
+
+        let first = history[0];
+        let travel = sample.pixel - first.pixel;
+        let gesture =
+            if first.meters - sample.meters > PUSH_METERS && travel.length() < SWIPE_PIXELS / 2.0 {
+                HandGesture::Push
+            } else if travel.x.abs() > SWIPE_PIXELS && travel.x.abs() > travel.y.abs() * 2.0 {
+                if travel.x < 0.0 {
+                    HandGesture::SwipeLeft
+                } else {
+                    HandGesture::SwipeRight
+                }
+            } else {
+                return None;
+            };
+        // one gesture per movement
+        history.clear();
+        Some(gesture)
+    }
+}
+
+/// Closest reading around the blob center, the blob itself can be patchy.
+pub fn hand_meters(depth: &[u16], center: Vec2) -> Option<f32> {
+    let (x, y) = (center.x as usize, center.y as usize);
+    let rows = y.saturating_sub(HAND_RADIUS)..(y + HAND_RADIUS + 1).min(DEPTH_HEIGHT);
+    rows.flat_map(|row| {
+        let columns = x.saturating_sub(HAND_RADIUS)..(x + HAND_RADIUS + 1).min(DEPTH_WIDTH);
+        columns.map(move |column| depth[row * DEPTH_WIDTH + column])
+    })
+    .filter(|raw| *raw != NO_DEPTH)
+    .min()
+    .and_then(coords::raw_depth_to_meters)
+}
+
+fn drive_ui_focus(
+    motion: Res<BlobMotion>,
+    rect: Res<DisplayRect>,
+    windows: Res<Windows>,
+    mut gestures: EventReader<HandGesture>,
+    mut cursor: ResMut<HandCursor>,
+    mut button_query: Query<
+        (
+            Entity,
+            &Node,
+            &GlobalTransform,
+            &ComputedVisibility,
+            &mut Interaction,
+        ),
+        With<Button>,
+    >,
+    mut pointed_before: Local<Option<Entity>>,
+) {
+    let window = windows.primary();
+    cursor.position = motion.pointer.map(|pixel| {
+        let screen = rect.image_to_screen(pixel);
+        Vec2::new(screen.x, window.height() - screen.y)
+    });
+
+    let mut buttons: Vec<(Entity, Vec2, Vec2)> = button_query
+        .iter()
+        .filter(|(_, _, _, visibility, _)| visibility.is_visible())
+        .map(|(entity, node, transform, _, _)| {
+            (entity, transform.translation().truncate(), node.size())
+        })
+        .collect();
+    // reading order, top to bottom then left to right
+    buttons.sort_by(|a, b| (a.1.y, a.1.x).partial_cmp(&(b.1.y, b.1.x)).unwrap());
+
+    let pointed = cursor.position.and_then(|position| {
+        buttons
+            .iter()
+            .find(|(_, center, size)| {
+                let offset = (position - *center).abs();
+                offset.x < size.x / 2.0 && offset.y < size.y / 2.0
+            })
+            .map(|(entity, _, _)| *entity)
+    });
+    let before = cursor.focused;
+    if cursor.position.is_none() {
+        cursor.focused = None;
+    } else if pointed.is_some() && pointed != *pointed_before {
+        cursor.focused = pointed;
+    }
+    *pointed_before = pointed;
+
+    let mut push = false;
+    for gesture in gestures.iter() {
+        let step: isize = match gesture {
+            HandGesture::Push => {
+                push = true;
+                continue;
+            }
+            HandGesture::SwipeLeft => -1,
+            HandGesture::SwipeRight => 1,
+        };
+        if buttons.is_empty() || cursor.position.is_none() {
+            continue;
+        }
+        let index = cursor
+            .focused
+            .and_then(|focused| buttons.iter().position(|(entity, ..)| *entity == focused));
+        let next = match index {
+            Some(index) => (index as isize + step).rem_euclid(buttons.len() as isize) as usize,
+            None if step > 0 => 0,
+            None => buttons.len() - 1,
+        };
+        cursor.focused = Some(buttons[next].0);
+    }
+
+    for (entity, _, _, _, mut interaction) in button_query.iter_mut() {
+        let wanted = if Some(entity) == cursor.focused {
+            if push {
+                Interaction::Clicked
+            } else {
+                Interaction::Hovered
+            }
+        } else if Some(entity) == before {
+            // let go of what the hand had, leave the rest to the mouse
+            Interaction::None
+        } else {
+            continue;
+        };
+        if *interaction != wanted {
+            *interaction = wanted;
+        }
+    }
+}
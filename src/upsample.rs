@@ -0,0 +1,154 @@
+//! Depth super-resolution by joint bilateral upsampling. Every output pixel
+//! averages the nearby depth readings, weighted by how close they are and by
+//! how alike the video looks at both places, so edges in the upscaled map
+//! follow the sharper color image instead of the blocky depth.
+//!
+//! It runs as a [`FrameModel`](crate::inference::FrameModel), off the main
+//! thread, so the upscaled map trails the live one by a frame or two.
+
+use bevy::prelude::*;
+
+use crate::coords::{self, DEPTH_HEIGHT, DEPTH_WIDTH};
+use crate::inference::{Frame, FrameModel, FrameModelAppExt, Tensor};
+use crate::NO_DEPTH;
+
+/// Depth pixels looked at around each output pixel, in every direction.
+const RADIUS: isize = 2;
+/// Falloff with distance, in depth pixels.
+const SPATIAL_SIGMA: f32 = 1.0;
+/// Falloff with color difference, 0..1 per channel.
+const COLOR_SIGMA: f32 = 0.1;
+/// Distance used to look up the color of a hole.
+const HOLE_METERS: f32 = 1.5;
+
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct UpsampleSettings {
+    /// output size over depth size, 1 is off
+    pub factor: usize,
+}
+
+impl Default for UpsampleSettings {
+    fn default() -> Self {
+        UpsampleSettings { factor: 1 }
+    }
+}
+
+/// Raw depth at `factor` times the depth resolution, [`NO_DEPTH`] where
+/// nothing was close enough to go by.
+#[derive(Resource)]
+pub struct UpsampledDepth {
+    pub factor: usize,
+    pub data: Vec<u16>,
+}
+
+pub struct UpsamplePlugin;
+
+impl Plugin for UpsamplePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<UpsampleSettings>();
+        let factor = app.world.resource::<UpsampleSettings>().factor;
+        if factor > 1 {
+            app.add_frame_model(upsample_model(factor));
+        }
+    }
+}
+
+fn upsample_model(factor: usize) -> FrameModel<UpsampledDepth> {
+    FrameModel {
+        every: 2,
+        preprocess: Box::new(move |frame| guided_tensor(frame, factor)),
+        model: Box::new(move |input: Tensor| Ok(joint_bilateral(&input, factor))),
+        postprocess: Box::new(move |output| UpsampledDepth {
+            factor,
+            data: output
+                .data
+                .iter()
+                .map(|raw| if *raw < 0.0 { NO_DEPTH } else { *raw as u16 })
+                .collect(),
+        }),
+    }
+}
+
+/// `[1, 4, height, width]` at the output size: raw depth of the depth pixel
+/// each output pixel falls in (-1 without a reading), then the RGB seen there.
+fn guided_tensor(frame: &Frame, factor: usize) -> Tensor {
+    let (width, height) = (DEPTH_WIDTH * factor, DEPTH_HEIGHT * factor);
+    let plane = width * height;
+    let mut data = vec![0.0; 4 * plane];
+    let (video_width, video_height) = (frame.video_size.0 as usize, frame.video_size.1 as usize);
+    let has_video = frame.video.len() == video_width * video_height * 4;
+    let scale = video_width as f32 / DEPTH_WIDTH as f32;
+
+    for y in 0..height {
+        for x in 0..width {
+            let i = y * width + x;
+            let raw = frame.depth[(y / factor) * DEPTH_WIDTH + x / factor];
+            data[i] = if raw == NO_DEPTH { -1.0 } else { raw as f32 };
+            if !has_video {
+                continue;
+            }
+
+            let pixel = (Vec2::new(x as f32, y as f32) + 0.5) / factor as f32 - 0.5;
+            let meters = coords::raw_depth_to_meters(raw).unwrap_or(HOLE_METERS);
+            let color = coords::depth_pixel_to_color_pixel(pixel, meters) * scale;
+            let (cx, cy) = (color.x as isize, color.y as isize);
+            if cx < 0 || cy < 0 || cx as usize >= video_width || cy as usize >= video_height {
+                continue;
+            }
+            let at = (cy as usize * video_width + cx as usize) * 4;
+            for channel in 0..3 {
+                data[(channel + 1) * plane + i] = frame.video[at + channel] as f32 / 255.0;
+            }
+        }
+    }
+    Tensor::new(vec![1, 4, height, width], data)
+}
+
+/// Upscaled raw depth shaped `[1, 1, height, width]`, -1 for no reading.
+fn joint_bilateral(input: &Tensor, factor: usize) -> Tensor {
+    let (height, width) = (input.shape[2], input.shape[3]);
+    let plane = width * height;
+    let color = |i: usize| {
+        Vec3::new(
+            input.data[plane + i],
+            input.data[2 * plane + i],
+            input.data[3 * plane + i],
+        )
+    };
+
+    let mut output = Vec::with_capacity(plane);
+    for y in 0..height {
+        for x in 0..width {
+            let i = y * width + x;
+            let center = (Vec2::new(x as f32, y as f32) + 0.5) / factor as f32 - 0.5;
+            let guide = color(i);
+            let (mut weights, mut sum) = (0.0, 0.0);
+
+            for dy in -RADIUS..=RADIUS {
+                for dx in -RADIUS..=RADIUS {
+                    let qx = center.x.round() as isize + dx;
+                    let qy = center.y.round() as isize + dy;
+                    if qx < 0 || qy < 0 || qx >= DEPTH_WIDTH as isize || qy >= DEPTH_HEIGHT as isize
+                    {
+                        continue;
+                    }
+                    // the top-left output pixel of depth pixel q stands in for it
+                    let j = qy as usize * factor * width + qx as usize * factor;
+                    let raw = input.data[j];
+                    if raw < 0.0 {
+                        continue;
+                    }
+                    let spatial = (Vec2::new(qx as f32, qy as f32) - center).length_squared();
+                    let range = (color(j) - guide).length_squared();
+                    let weight = (-spatial / (2.0 * SPATIAL_SIGMA * SPATIAL_SIGMA)
+                        - range / (2.0 * COLOR_SIGMA * COLOR_SIGMA))
+                        .exp();
+                    weights += weight;
+                    sum += weight * raw;
+                }
+            }
+            output.push(if weights > 1e-6 { sum / weights } else { -1.0 });
+        }
+    }
+    Tensor::new(vec![1, 1, height, width], output)
+}
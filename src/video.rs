@@ -0,0 +1,635 @@
+use std::str::FromStr;
+
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::render::render_resource::{
+    AsBindGroup, Extent3d, ShaderRef, TextureDimension, TextureFormat,
+};
+use bevy::sprite::{Material2d, Material2dPlugin, MaterialMesh2dBundle};
+#[cfg(feature = "freenect")]
+use freenectrs::freenect::{FreenectResolution, FreenectVideoFormat};
+
+use crate::backend::DepthCamera;
+use crate::capture::{CaptureSettings, FrameGate};
+use crate::display::ViewSprite;
+
+/// Width of the area the video is drawn into, same as the depth image.
+const DISPLAY_WIDTH: f32 = 640.0;
+const DISPLAY_HEIGHT: f32 = 480.0;
+
+/// `High` is 1280x1024 at roughly 10 fps and only exists for RGB and Bayer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VideoResolution {
+    Medium,
+    High,
+}
+
+impl VideoResolution {
+    #[cfg(feature = "freenect")]
+    pub fn to_freenect(self) -> FreenectResolution {
+        match self {
+            VideoResolution::Medium => FreenectResolution::Medium,
+            VideoResolution::High => FreenectResolution::High,
+        }
+    }
+
+    pub fn size(self) -> (u32, u32) {
+        match self {
+            VideoResolution::Medium => (640, 480),
+            VideoResolution::High => (1280, 1024),
+        }
+    }
+}
+
+impl FromStr for VideoResolution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "medium" => Ok(VideoResolution::Medium),
+            "high" => Ok(VideoResolution::High),
+            _ => Err(format!("unknown video resolution `{s}`")),
+        }
+    }
+}
+
+/// Video modes the Kinect can stream. `Bayer` and `YuvRaw` hand us the sensor
+/// data untouched, the other two are converted to RGB by libfreenect.
+///
+/// Over USB the RGB mode is Bayer too, one byte per pixel at 30 fps, and the
+/// YUV modes two bytes at 15 fps, so they take the same bandwidth; what YUV
+/// saves is the demosaicing.
+///
+/// `Ir` is the depth camera's own 8-bit infrared picture instead of color,
+/// which works in the dark and lines up with depth pixel for pixel. It shows
+/// the emitter's dot pattern unless the emitter is off.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VideoFormat {
+    Rgb,
+    Bayer,
+    YuvRgb,
+    YuvRaw,
+    Ir,
+}
+
+impl VideoFormat {
+    #[cfg(feature = "freenect")]
+    pub fn to_freenect(self) -> FreenectVideoFormat {
+        match self {
+            VideoFormat::Rgb => FreenectVideoFormat::Rgb,
+            VideoFormat::Bayer => FreenectVideoFormat::Bayer,
+            VideoFormat::YuvRgb => FreenectVideoFormat::YuvRgb,
+            VideoFormat::YuvRaw => FreenectVideoFormat::YuvRaw,
+            VideoFormat::Ir => FreenectVideoFormat::IR8,
+        }
+    }
+
+    /// Size of one frame in bytes as delivered by libfreenect. Medium IR
+    /// frames are 640x488, this only covers the top 480 rows.
+    pub fn frame_len(self, resolution: VideoResolution) -> usize {
+        let (width, height) = resolution.size();
+        let pixels = (width * height) as usize;
+        match self {
+            VideoFormat::Rgb | VideoFormat::YuvRgb => pixels * 3,
+            VideoFormat::Bayer | VideoFormat::Ir => pixels,
+            VideoFormat::YuvRaw => pixels * 2,
+        }
+    }
+
+    pub fn is_raw(self) -> bool {
+        matches!(self, VideoFormat::Bayer | VideoFormat::YuvRaw)
+    }
+
+    pub fn supports(self, resolution: VideoResolution) -> bool {
+        resolution == VideoResolution::Medium
+            || matches!(
+                self,
+                VideoFormat::Rgb | VideoFormat::Bayer | VideoFormat::Ir
+            )
+    }
+}
+
+impl FromStr for VideoFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rgb" => Ok(VideoFormat::Rgb),
+            "bayer" => Ok(VideoFormat::Bayer),
+            "yuv-rgb" => Ok(VideoFormat::YuvRgb),
+            "yuv-raw" => Ok(VideoFormat::YuvRaw),
+            "ir" => Ok(VideoFormat::Ir),
+            _ => Err(format!("unknown video format `{s}`")),
+        }
+    }
+}
+
+/// How Bayer frames are turned into color on the CPU. `Bilinear` averages
+/// the neighbours of each missing color and leaves colored fringes along
+/// sharp edges, `EdgeAware` interpolates green along edges rather than
+/// across them and the other two from their difference to green, which
+/// takes about twice as long. The shader is always bilinear.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Demosaic {
+    #[default]
+    Bilinear,
+    EdgeAware,
+}
+
+impl Demosaic {
+    pub fn to_rgba(self, raw: &[u8], width: usize, height: usize, out: &mut Vec<u8>) {
+        match self {
+            Demosaic::Bilinear => bayer_to_rgba(raw, width, height, out),
+            Demosaic::EdgeAware => bayer_to_rgba_edge_aware(raw, width, height, out),
+        }
+    }
+}
+
+impl FromStr for Demosaic {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bilinear" => Ok(Demosaic::Bilinear),
+            "edge-aware" => Ok(Demosaic::EdgeAware),
+            _ => Err(format!("unknown demosaic `{s}`")),
+        }
+    }
+}
+
+/// Where raw frames get converted to RGB. Only matters for the raw formats.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VideoConversion {
+    Cpu,
+    Gpu,
+}
+
+#[derive(Resource, Clone, Copy, Debug, PartialEq)]
+pub struct VideoSettings {
+    pub format: VideoFormat,
+    pub resolution: VideoResolution,
+    pub conversion: VideoConversion,
+    pub demosaic: Demosaic,
+}
+
+impl Default for VideoSettings {
+    fn default() -> Self {
+        VideoSettings {
+            format: VideoFormat::Rgb,
+            resolution: VideoResolution::Medium,
+            conversion: VideoConversion::Cpu,
+            demosaic: Demosaic::default(),
+        }
+    }
+}
+
+impl VideoSettings {
+    /// Rate the hardware delivers the selected mode at.
+    pub fn native_fps(&self) -> f32 {
+        match (self.format, self.resolution) {
+            (_, VideoResolution::High) => 10.0,
+            (VideoFormat::YuvRgb | VideoFormat::YuvRaw, _) => 15.0,
+            _ => 30.0,
+        }
+    }
+
+    fn uses_gpu(&self) -> bool {
+        self.format.is_raw() && self.conversion == VideoConversion::Gpu
+    }
+
+    /// RGBA pixels of a frame as the device delivered it.
+    pub fn frame_to_rgba(&self, frame: &[u8], out: &mut Vec<u8>) {
+        match self.format {
+            VideoFormat::Rgb | VideoFormat::YuvRgb => rgb_to_rgba(frame, out),
+            VideoFormat::Bayer => {
+                let (width, height) = self.resolution.size();
+                self.demosaic
+                    .to_rgba(frame, width as usize, height as usize, out)
+            }
+            VideoFormat::YuvRaw => uyvy_to_rgba(frame, out),
+            VideoFormat::Ir => gray_to_rgba(frame, out),
+        }
+    }
+
+    /// RGBA pixels of the [`CurrentVideo`] image, converting the raw frames
+    /// that are left to the GPU otherwise.
+    pub fn image_to_rgba(&self, data: &[u8], out: &mut Vec<u8>) {
+        if !self.uses_gpu() {
+            out.clear();
+            out.extend_from_slice(data);
+        } else if self.format == VideoFormat::YuvRaw {
+            uyvy_to_rgba(data, out);
+        } else {
+            let (width, height) = self.resolution.size();
+            self.demosaic
+                .to_rgba(data, width as usize, height as usize, out);
+        }
+    }
+
+    /// Size and offset of the video quad. The high-res mode covers the same
+    /// view as 640x480 plus 64 extra rows at the bottom, so it gets scaled to
+    /// the display width and pinned to the top to stay lined up with depth.
+    fn display_rect(&self) -> (Vec2, Vec3) {
+        let (width, height) = self.resolution.size();
+        let size = Vec2::new(DISPLAY_WIDTH, DISPLAY_WIDTH * height as f32 / width as f32);
+        let offset = Vec3::new(0.0, (DISPLAY_HEIGHT - size.y) / 2.0, 0.0);
+        (size, offset)
+    }
+}
+
+/// The latest IR frame as a single channel texture, for shaders and
+/// calibration tools that want the intensities rather than the gray
+/// [`CurrentVideo`] picture. Only there with [`VideoFormat::Ir`].
+#[derive(Resource, Clone, Debug)]
+pub struct IrImage(pub Handle<Image>);
+
+/// The latest Bayer frame as the sensor delivered it, one byte per pixel in
+/// the GRBG pattern, for computer vision that does its own demosaicing or
+/// none. Only there with [`VideoFormat::Bayer`]; [`Demosaic::to_rgba`]
+/// converts it.
+#[derive(Resource, Clone, Debug, Default)]
+pub struct BayerFrame {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u8>,
+}
+
+#[derive(Component)]
+pub struct CurrentVideo {
+    pub handle: Handle<Image>,
+    material: Option<Handle<RawVideoMaterial>>,
+}
+
+/// Converts raw Bayer / UYVY frames on the GPU. The raw bytes are uploaded
+/// as-is and `raw_video.wgsl` does the demosaicing or color conversion.
+#[derive(AsBindGroup, TypeUuid, Debug, Clone)]
+#[uuid = "17ad7d2d-4214-4b32-8713-b28b3be635bb"]
+pub struct RawVideoMaterial {
+    /// 0 = Bayer (GRBG), 1 = UYVY
+    #[uniform(0)]
+    format: u32,
+    #[texture(1)]
+    raw: Handle<Image>,
+}
+
+impl Material2d for RawVideoMaterial {
+    fn fragment_shader() -> ShaderRef {
+        "shaders/raw_video.wgsl".into()
+    }
+}
+
+pub struct VideoPlugin;
+
+impl Plugin for VideoPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<VideoSettings>()
+            .add_plugin(Material2dPlugin::<RawVideoMaterial>::default())
+            .add_startup_system(spawn_video)
+            .add_system(respawn_video.before(crate::KinectSet::Acquire))
+            .add_system(read_video_data.label(crate::KinectSet::Acquire));
+    }
+}
+
+fn spawn_video(
+    mut commands: Commands,
+    settings: Res<VideoSettings>,
+    mut images: ResMut<Assets<Image>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<RawVideoMaterial>>,
+) {
+    let (width, height) = settings.resolution.size();
+    let (size, offset) = settings.display_rect();
+
+    if settings.format == VideoFormat::Ir {
+        commands.insert_resource(IrImage(images.add(Image::new_fill(
+            Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            &[0],
+            TextureFormat::R8Unorm,
+        ))));
+    }
+    if settings.format == VideoFormat::Bayer {
+        commands.insert_resource(BayerFrame {
+            width: width as usize,
+            height: height as usize,
+            data: vec![0; (width * height) as usize],
+        });
+    }
+
+    if !settings.uses_gpu() {
+        let handle = images.add(Image::new_fill(
+            Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            &[0, 0, 0, 255],
+            TextureFormat::Rgba8UnormSrgb,
+        ));
+
+        commands
+            .spawn(SpriteBundle {
+                sprite: Sprite {
+                    custom_size: Some(size),
+                    ..default()
+                },
+                texture: handle.clone(),
+                transform: Transform::from_translation(offset),
+                ..default()
+            })
+            .insert(CurrentVideo {
+                handle,
+                material: None,
+            })
+            .insert(ViewSprite {
+                offset: offset.truncate(),
+            });
+        return;
+    }
+
+    // UYVY packs two pixels into four bytes, so it goes up as a half-width
+    // RGBA texture; Bayer is a single channel per pixel.
+    let (texture_width, pixel, format) = match settings.format {
+        VideoFormat::YuvRaw => (width / 2, &[128, 0, 128, 0][..], TextureFormat::Rgba8Unorm),
+        _ => (width, &[0][..], TextureFormat::R8Unorm),
+    };
+    let handle = images.add(Image::new_fill(
+        Extent3d {
+            width: texture_width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        pixel,
+        format,
+    ));
+    let material = materials.add(RawVideoMaterial {
+        format: (settings.format == VideoFormat::YuvRaw).into(),
+        raw: handle.clone(),
+    });
+
+    commands
+        .spawn(MaterialMesh2dBundle {
+            mesh: meshes.add(Mesh::from(shape::Quad::new(size))).into(),
+            material: material.clone(),
+            transform: Transform::from_translation(offset),
+            ..default()
+        })
+        .insert(CurrentVideo {
+            handle,
+            material: Some(material),
+        })
+        .insert(ViewSprite {
+            offset: offset.truncate(),
+        });
+}
+
+/// A new video mode, from [`KinectCommand::Configure`](crate::device::KinectCommand),
+/// needs a view of its own size and format.
+fn respawn_video(
+    mut commands: Commands,
+    settings: Res<VideoSettings>,
+    images: ResMut<Assets<Image>>,
+    meshes: ResMut<Assets<Mesh>>,
+    materials: ResMut<Assets<RawVideoMaterial>>,
+    views: Query<Entity, With<CurrentVideo>>,
+) {
+    if !settings.is_changed() || settings.is_added() {
+        return;
+    }
+    for view in &views {
+        commands.entity(view).despawn();
+    }
+    commands.remove_resource::<IrImage>();
+    commands.remove_resource::<BayerFrame>();
+    spawn_video(commands, settings, images, meshes, materials);
+}
+
+fn read_video_data(
+    mut camera: ResMut<DepthCamera>,
+    (settings, capture): (Res<VideoSettings>, Res<CaptureSettings>),
+    mut gate: Local<FrameGate>,
+    video_query: Query<&CurrentVideo>,
+    mut images: ResMut<Assets<Image>>,
+    mut materials: ResMut<Assets<RawVideoMaterial>>,
+    (ir_image, bayer): (Option<Res<IrImage>>, Option<ResMut<BayerFrame>>),
+) {
+    let video = match video_query.get_single() {
+        Ok(video) => video,
+        Err(_) => return,
+    };
+
+    if let Some(frame) = camera.next_video_frame() {
+        if !gate.accept(capture.video, settings.native_fps()) {
+            return;
+        }
+        let frame = &frame.data;
+
+        if let Some(image) = images.get_mut(&video.handle) {
+            if settings.uses_gpu() {
+                image.data.clear();
+                image.data.extend_from_slice(frame);
+            } else {
+                settings.frame_to_rgba(frame, &mut image.data);
+            }
+        }
+        if let Some(image) = ir_image.and_then(|ir| images.get_mut(&ir.0)) {
+            image.data.clear();
+            image.data.extend_from_slice(frame);
+        }
+        if let Some(mut bayer) = bayer {
+            bayer.data.clear();
+            bayer.data.extend_from_slice(frame);
+        }
+
+        // materials cache their bind group, touch it so the new texture data is picked up
+        if let Some(material) = &video.material {
+            materials.get_mut(material);
+        }
+    }
+}
+
+pub fn rgb_to_rgba(rgb: &[u8], out: &mut Vec<u8>) {
+    out.clear();
+    out.reserve(rgb.len() / 3 * 4);
+    for pixel in rgb.chunks_exact(3) {
+        out.extend_from_slice(&[pixel[0], pixel[1], pixel[2], 255]);
+    }
+}
+
+pub fn gray_to_rgba(gray: &[u8], out: &mut Vec<u8>) {
+    out.clear();
+    out.reserve(gray.len() * 4);
+    for value in gray {
+        out.extend_from_slice(&[*value, *value, *value, 255]);
+    }
+}
+
+/// Bilinear demosaic of the Kinect's GRBG Bayer pattern:
+///
+/// ```text
+/// G R G R ...
+/// B G B G ...
+/// ```
+pub fn bayer_to_rgba(raw: &[u8], width: usize, height: usize, out: &mut Vec<u8>) {
+    out.clear();
+    out.reserve(width * height * 4);
+
+    // mirror at the borders so neighbours keep the right color
+    let at = |x: isize, y: isize| -> u16 {
+        let x = reflect(x, width as isize);
+        let y = reflect(y, height as isize);
+        raw[y * width + x] as u16
+    };
+
+    for y in 0..height as isize {
+        for x in 0..width as isize {
+            let c = at(x, y);
+            let horizontal = (at(x - 1, y) + at(x + 1, y)) / 2;
+            let vertical = (at(x, y - 1) + at(x, y + 1)) / 2;
+            let cross = (at(x - 1, y) + at(x + 1, y) + at(x, y - 1) + at(x, y + 1)) / 4;
+            let diagonal =
+                (at(x - 1, y - 1) + at(x + 1, y - 1) + at(x - 1, y + 1) + at(x + 1, y + 1)) / 4;
+
+            let (r, g, b) = match (y & 1, x & 1) {
+                (0, 0) => (horizontal, c, vertical),
+                (0, _) => (c, cross, diagonal),
+                (_, 0) => (diagonal, cross, c),
+                _ => (vertical, c, horizontal),
+            };
+            out.extend_from_slice(&[r as u8, g as u8, b as u8, 255]);
+        }
+    }
+}
+
+/// Demosaic of the same pattern that keeps edges sharp. Green is
+/// interpolated along whichever direction changes least, corrected by the
+/// curvature of the pixel's own color, then red and blue are filled in from
+/// their difference to green, which varies much less across an edge than
+/// the colors themselves.
+pub fn bayer_to_rgba_edge_aware(raw: &[u8], width: usize, height: usize, out: &mut Vec<u8>) {
+    let at = |x: isize, y: isize| -> i32 {
+        raw[reflect(y, height as isize) * width + reflect(x, width as isize)] as i32
+    };
+
+    let mut green = Vec::with_capacity(width * height);
+    for y in 0..height as isize {
+        for x in 0..width as isize {
+            let c = at(x, y);
+            if (x + y) & 1 == 0 {
+                green.push(c);
+                continue;
+            }
+            let curve_h = 2 * c - at(x - 2, y) - at(x + 2, y);
+            let curve_v = 2 * c - at(x, y - 2) - at(x, y + 2);
+            let grad_h = (at(x - 1, y) - at(x + 1, y)).abs() + curve_h.abs();
+            let grad_v = (at(x, y - 1) - at(x, y + 1)).abs() + curve_v.abs();
+            let h = (2 * (at(x - 1, y) + at(x + 1, y)) + curve_h) / 4;
+            let v = (2 * (at(x, y - 1) + at(x, y + 1)) + curve_v) / 4;
+            green.push(match grad_h.cmp(&grad_v) {
+                std::cmp::Ordering::Less => h,
+                std::cmp::Ordering::Greater => v,
+                std::cmp::Ordering::Equal => (h + v) / 2,
+            });
+        }
+    }
+    let g = |x: isize, y: isize| -> i32 {
+        green[reflect(y, height as isize) * width + reflect(x, width as isize)]
+    };
+    // a color less green, averaged over the given neighbours
+    let diff = |points: &[(isize, isize)]| -> i32 {
+        points.iter().map(|&(x, y)| at(x, y) - g(x, y)).sum::<i32>() / points.len() as i32
+    };
+
+    out.clear();
+    out.reserve(width * height * 4);
+    for y in 0..height as isize {
+        for x in 0..width as isize {
+            let green = g(x, y);
+            let horizontal = diff(&[(x - 1, y), (x + 1, y)]);
+            let vertical = diff(&[(x, y - 1), (x, y + 1)]);
+            let diagonal = diff(&[
+                (x - 1, y - 1),
+                (x + 1, y - 1),
+                (x - 1, y + 1),
+                (x + 1, y + 1),
+            ]);
+            let own = at(x, y) - green;
+            let (r, b) = match (y & 1, x & 1) {
+                (0, 0) => (horizontal, vertical),
+                (0, _) => (own, diagonal),
+                (_, 0) => (diagonal, own),
+                _ => (vertical, horizontal),
+            };
+            out.extend_from_slice(&[
+                (green + r).clamp(0, 255) as u8,
+                green.clamp(0, 255) as u8,
+                (green + b).clamp(0, 255) as u8,
+                255,
+            ]);
+        }
+    }
+}
+
+fn reflect(i: isize, len: isize) -> usize {
+    if i < 0 {
+        (-i) as usize
+    } else if i >= len {
+        (2 * len - 2 - i) as usize
+    } else {
+        i as usize
+    }
+}
+
+/// Converts packed UYVY (what libfreenect calls `YUV_RAW`) to RGBA.
+pub fn uyvy_to_rgba(raw: &[u8], out: &mut Vec<u8>) {
+    out.clear();
+    out.reserve(raw.len() * 2);
+    for chunk in raw.chunks_exact(4) {
+        let (u, y0, v, y1) = (chunk[0], chunk[1], chunk[2], chunk[3]);
+        out.extend_from_slice(&yuv_to_rgba(y0, u, v));
+        out.extend_from_slice(&yuv_to_rgba(y1, u, v));
+    }
+}
+
+fn yuv_to_rgba(y: u8, u: u8, v: u8) -> [u8; 4] {
+    let y = y as f32;
+    let u = u as f32 - 128.0;
+    let v = v as f32 - 128.0;
+    // float -> u8 casts saturate, so no clamping needed
+    [
+        (y + 1.402 * v) as u8,
+        (y - 0.344 * u - 0.714 * v) as u8,
+        (y + 1.772 * u) as u8,
+        255,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edge_aware_demosaic_keeps_edges_gray() {
+        // a gray scene, black on the left and white on the right
+        let (width, height) = (16, 8);
+        let raw: Vec<u8> = (0..width * height)
+            .map(|i| if i % width < width / 2 { 0 } else { 200 })
+            .collect();
+        let gray = |rgba: &[u8]| {
+            rgba.chunks_exact(4)
+                .all(|pixel| pixel[0] == pixel[1] && pixel[1] == pixel[2])
+        };
+
+        let mut out = Vec::new();
+        bayer_to_rgba(&raw, width, height, &mut out);
+        assert!(!gray(&out));
+        bayer_to_rgba_edge_aware(&raw, width, height, &mut out);
+        assert!(gray(&out));
+    }
+}
@@ -0,0 +1,134 @@
+//! Stream watchdog. While the Kinect is streaming, each stream has to keep
+//! delivering frames; when one goes quiet for too long a [`StreamStalled`]
+//! event goes out, which makes the reconnect supervisor restart the device,
+//! and the incident is logged (and appended to a file, if one is set) so
+//! flaky setups show up in the morning.
+
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+
+use crate::analytics::unix_now;
+use crate::backend::DepthCamera;
+use crate::reconnect::Connection;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stream {
+    Depth,
+    Video,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct StreamStalled {
+    pub stream: Stream,
+    /// how long the stream had been quiet
+    pub seconds: f32,
+}
+
+#[derive(Resource, Clone, Debug)]
+pub struct WatchdogSettings {
+    /// seconds without a frame before a stream counts as stalled
+    pub depth_timeout: f32,
+    /// video is slower, 10 fps in high resolution
+    pub video_timeout: f32,
+    /// file incidents are appended to
+    pub log: Option<PathBuf>,
+}
+
+impl Default for WatchdogSettings {
+    fn default() -> Self {
+        WatchdogSettings {
+            depth_timeout: 3.0,
+            video_timeout: 5.0,
+            log: None,
+        }
+    }
+}
+
+#[derive(Default)]
+struct StreamClock {
+    frames: u64,
+    last_frame_at: f64,
+}
+
+pub struct WatchdogPlugin;
+
+impl Plugin for WatchdogPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<WatchdogSettings>()
+            .add_event::<StreamStalled>()
+            .add_system(watch_streams)
+            .add_system(log_incidents);
+    }
+}
+
+fn watch_streams(
+    camera: Res<DepthCamera>,
+    connection: Res<Connection>,
+    settings: Res<WatchdogSettings>,
+    time: Res<Time>,
+    mut clocks: Local<[StreamClock; 2]>,
+    mut stalled: EventWriter<StreamStalled>,
+) {
+    let now = time.elapsed_seconds_f64();
+    let watched = [
+        (
+            Stream::Depth,
+            camera.depth_frames_received(),
+            settings.depth_timeout,
+        ),
+        (
+            Stream::Video,
+            camera.video_frames_received(),
+            settings.video_timeout,
+        ),
+    ];
+
+    for (clock, (stream, frames, timeout)) in clocks.iter_mut().zip(watched) {
+        if stream == Stream::Video && !camera.streams_video() {
+            continue;
+        }
+        // only a streaming device is expected to deliver
+        if !connection.is_streaming() || !camera.is_running() || frames != clock.frames {
+            clock.frames = frames;
+            clock.last_frame_at = now;
+            continue;
+        }
+
+        let quiet = now - clock.last_frame_at;
+        if quiet > timeout as f64 {
+            stalled.send(StreamStalled {
+                stream,
+                seconds: quiet as f32,
+            });
+            clock.last_frame_at = now;
+        }
+    }
+}
+
+fn log_incidents(settings: Res<WatchdogSettings>, mut stalled: EventReader<StreamStalled>) {
+    for incident in stalled.iter() {
+        warn!(
+            "{:?} stream sent nothing for {:.1}s",
+            incident.stream, incident.seconds
+        );
+        if let Some(path) = &settings.log {
+            if let Err(err) = append_incident(path, incident) {
+                error!("Unable to write to {}: {err}", path.display());
+            }
+        }
+    }
+}
+
+fn append_incident(path: &Path, incident: &StreamStalled) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(
+        file,
+        "{:.0}\t{:?} stalled\t{:.1}s",
+        unix_now(),
+        incident.stream,
+        incident.seconds
+    )
+}
@@ -0,0 +1,174 @@
+//! How full a few boxes in front of the sensor are, for inputs that ramp up
+//! instead of switching: the further someone leans into a zone, the higher
+//! its [`ZoneOccupancy`]. Zones are boxes in sensor space; what counts is
+//! what stands out from the learned background, as for
+//! [`proximity`](crate::proximity).
+//!
+//! The sensor only sees surfaces, so a zone is as occupied as the part of it
+//! hidden behind foreground surfaces: 0 when nobody is in it, close to 1
+//! when someone fills it up to the side facing the sensor.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use bevy::prelude::*;
+
+use crate::proximity::Background;
+use crate::{coords, CurrentDepth, MainKinect};
+
+/// A box in sensor space, in meters.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Zone {
+    pub name: String,
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Zone {
+    /// The part of the ray through depth pixel `pixel` inside the zone, as
+    /// the depth it enters and leaves at.
+    fn crossing(&self, pixel: Vec2) -> Option<(f32, f32)> {
+        let direction = coords::depth_pixel_to_sensor(pixel, 1.0);
+        let (mut enter, mut leave) = (0.0f32, f32::MAX);
+        for axis in 0..3 {
+            if direction[axis] == 0.0 {
+                if self.min[axis] > 0.0 || self.max[axis] < 0.0 {
+                    return None;
+                }
+                continue;
+            }
+            let (a, b) = (
+                self.min[axis] / direction[axis],
+                self.max[axis] / direction[axis],
+            );
+            enter = enter.max(a.min(b));
+            leave = leave.min(a.max(b));
+        }
+        (enter < leave).then_some((enter, leave))
+    }
+}
+
+impl FromStr for Zone {
+    type Err = String;
+
+    /// `name:x0,y0,z0,x1,y1,z1`, two opposite corners.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad = || format!("bad zone '{s}', expected <name>:<x0>,<y0>,<z0>,<x1>,<y1>,<z1>");
+        let (name, corners) = s.split_once(':').ok_or_else(bad)?;
+        let values: Vec<f32> = corners
+            .split(',')
+            .map(|value| value.trim().parse::<f32>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| bad())?;
+        match values[..] {
+            [x0, y0, z0, x1, y1, z1] if !name.is_empty() => {
+                let (a, b) = (Vec3::new(x0, y0, z0), Vec3::new(x1, y1, z1));
+                Ok(Zone {
+                    name: name.to_string(),
+                    min: a.min(b),
+                    max: a.max(b),
+                })
+            }
+            _ => Err(bad()),
+        }
+    }
+}
+
+#[derive(Resource, Clone, Debug, Default)]
+pub struct ZoneSettings {
+    pub zones: Vec<Zone>,
+}
+
+/// Share of each zone's volume behind foreground surfaces, from 0 to 1, by
+/// zone name.
+#[derive(Resource, Clone, Debug, Default)]
+pub struct ZoneOccupancy(pub HashMap<String, f32>);
+
+impl ZoneOccupancy {
+    pub fn get(&self, zone: &str) -> f32 {
+        self.0.get(zone).copied().unwrap_or(0.0)
+    }
+}
+
+pub struct ZonesPlugin;
+
+impl Plugin for ZonesPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ZoneSettings>()
+            .init_resource::<ZoneOccupancy>()
+            .add_system(measure_zones.after(crate::KinectSet::Acquire));
+    }
+}
+
+fn measure_zones(
+    depth_query: Query<&CurrentDepth, (With<MainKinect>, Changed<CurrentDepth>)>,
+    settings: Res<ZoneSettings>,
+    mut occupancy: ResMut<ZoneOccupancy>,
+    mut background: Local<Background>,
+) {
+    let depth = match depth_query.get_single() {
+        Ok(depth) if depth.is_medium() => depth,
+        _ => return,
+    };
+    if settings.zones.is_empty() {
+        return;
+    }
+    let foreground = background.foreground(&depth.depth_array);
+    occupancy.0 = settings
+        .zones
+        .iter()
+        .map(|zone| (zone.name.clone(), occupied(zone, &foreground)))
+        .collect();
+}
+
+/// Adds up the zone's volume along each pixel's ray, each slice of a ray
+/// weighing as the square of its depth, like the area a pixel covers.
+fn occupied(zone: &Zone, foreground: &[Option<f32>]) -> f32 {
+    let volume = |from: f32, to: f32| (to.powi(3) - from.powi(3)) / 3.0;
+    let (mut hidden, mut total) = (0.0, 0.0);
+    for (i, meters) in foreground.iter().enumerate() {
+        let pixel = Vec2::new(
+            (i % coords::DEPTH_WIDTH) as f32,
+            (i / coords::DEPTH_WIDTH) as f32,
+        );
+        let (enter, leave) = match zone.crossing(pixel) {
+            Some(crossing) => crossing,
+            None => continue,
+        };
+        total += volume(enter, leave);
+        if let Some(meters) = meters {
+            if *meters < leave {
+                hidden += volume(meters.max(enter), leave);
+            }
+        }
+    }
+    if total > 0.0 {
+        hidden / total
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DEPTH_HEIGHT, DEPTH_WIDTH};
+
+    #[test]
+    fn leaning_in_fills_the_zone() {
+        let zone: Zone = "button:-0.5,-0.5,2,0.5,0.5,1".parse().unwrap();
+        assert_eq!(zone.min, Vec3::new(-0.5, -0.5, 1.0));
+        assert!("button:1,2,3".parse::<Zone>().is_err());
+
+        // a wall of foreground at `meters` across the whole view
+        let at = |meters: Option<f32>| {
+            let foreground = vec![meters; DEPTH_WIDTH * DEPTH_HEIGHT];
+            occupied(&zone, &foreground)
+        };
+        assert_eq!(at(None), 0.0);
+        assert_eq!(at(Some(2.5)), 0.0);
+        assert!((at(Some(0.5)) - 1.0).abs() < 1e-3);
+        let (back, front) = (at(Some(1.6)), at(Some(1.2)));
+        assert!(0.0 < back && back < front && front < 1.0);
+    }
+}